@@ -0,0 +1,126 @@
+//! Alternate wire names for intents.
+//!
+//! Older clients may send an action under a name the current intent type no
+//! longer has, e.g. `{"type":"chat_message","text":"hi"}` instead of
+//! `{"type":"intent","action":"message","text":"hi"}`. Register a
+//! deserializer for each legacy `type` and decode with
+//! [`IntentAliasRegistry::decode`]: messages that parse normally are
+//! returned as-is, and ones rejected for an unknown variant are retried
+//! against the aliases.
+
+use crate::ClientWire;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Builds an intent from a message sent under an alias.
+pub type AliasDeserializer<I> = fn(Value) -> Result<I, serde_json::Error>;
+
+/// Error decoding a message through the alias registry.
+#[derive(Debug, thiserror::Error)]
+pub enum AliasError {
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("message has no type")]
+    MissingType,
+    #[error("unknown message type: {0}")]
+    Unknown(String),
+}
+
+/// Legacy message types mapped to intents.
+pub struct IntentAliasRegistry<I> {
+    aliases: HashMap<String, AliasDeserializer<I>>,
+}
+
+impl<I> Default for IntentAliasRegistry<I> {
+    fn default() -> Self {
+        Self {
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+impl<I> IntentAliasRegistry<I> {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept messages whose `type` is `alias`, built by `deserializer`.
+    ///
+    /// The deserializer sees the whole message, `type` included.
+    pub fn register(mut self, alias: &str, deserializer: AliasDeserializer<I>) -> Self {
+        self.aliases.insert(alias.to_string(), deserializer);
+        self
+    }
+
+    /// Build an intent from a message sent under an alias.
+    pub fn resolve(&self, raw: &Value) -> Result<I, AliasError> {
+        let ty = raw
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or(AliasError::MissingType)?;
+        let deserializer = self
+            .aliases
+            .get(ty)
+            .ok_or_else(|| AliasError::Unknown(ty.to_string()))?;
+        Ok(deserializer(raw.clone())?)
+    }
+
+    /// Decode a client message, falling back to the aliases for unknown types.
+    pub fn decode(&self, text: &str) -> Result<ClientWire<I>, AliasError>
+    where
+        I: DeserializeOwned,
+    {
+        match serde_json::from_str(text) {
+            Ok(wire) => Ok(wire),
+            Err(e) if e.to_string().contains("unknown variant") => {
+                let raw: Value = serde_json::from_str(text)?;
+                self.resolve(&raw).map(ClientWire::Intent)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(tag = "action", rename_all = "snake_case")]
+    enum Intent {
+        Message { text: String },
+    }
+
+    fn legacy_message(raw: Value) -> Result<Intent, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct Legacy {
+            text: String,
+        }
+        let legacy: Legacy = serde_json::from_value(raw)?;
+        Ok(Intent::Message { text: legacy.text })
+    }
+
+    #[test]
+    fn current_and_legacy_names_decode_alike() {
+        let aliases = IntentAliasRegistry::new().register("chat_message", legacy_message);
+        let expected = Intent::Message { text: "hi".into() };
+
+        let current = aliases
+            .decode(r#"{"type":"intent","action":"message","text":"hi"}"#)
+            .unwrap();
+        assert!(matches!(current, ClientWire::Intent(i) if i == expected));
+
+        let legacy = aliases
+            .decode(r#"{"type":"chat_message","text":"hi"}"#)
+            .unwrap();
+        assert!(matches!(legacy, ClientWire::Intent(i) if i == expected));
+
+        assert!(matches!(
+            aliases.decode(r#"{"type":"shout"}"#),
+            Err(AliasError::Unknown(ty)) if ty == "shout"
+        ));
+    }
+}
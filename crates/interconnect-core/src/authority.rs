@@ -4,10 +4,21 @@
 //! (WebSocket, HTTP, etc.) calls into the Authority to process
 //! intents, generate snapshots, and handle transfers.
 
-use crate::Identity;
+use crate::{
+    AuthorityEvent, Capabilities, ClientPrediction, ConnectionQuality, ConnectionState, ErrorCode,
+    Identity, IdentityError, LocalTransferResult, Manifest, PassportUpdate,
+    PassportValidationError, QueryError, QueryPage, Redactor, SessionEncoding, SnapshotBudget,
+    Timestamp, TransferError, TransferSnapshot, TypeNameOnly, canonical_bytes,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
 
 /// A connected session.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
     /// Unique session ID.
     pub id: u64,
@@ -15,12 +26,120 @@ pub struct Session {
     pub identity: Identity,
     /// Display name.
     pub name: String,
+    /// Connection quality, kept current by the transport.
+    ///
+    /// Local to this server: not carried in tokens or passports.
+    #[serde(skip)]
+    pub quality: ConnectionQuality,
+    /// What the client last reported about its prediction, kept current by
+    /// the transport. Local to this server, like `quality`.
+    #[serde(skip)]
+    pub prediction: ClientPrediction,
+    /// How the connection encodes wire messages, negotiated at connect.
+    /// Local to this server, like `quality`.
+    #[serde(skip)]
+    pub encoding: SessionEncoding,
+    /// The client's software version, if it reported one at connect (see
+    /// [`Authority::translate_snapshot_for_version`]). Local to this
+    /// server, like `quality`.
+    #[serde(skip)]
+    pub client_version: Option<String>,
+    /// Where the connection is in its lifecycle, kept current by the
+    /// transport through [`Session::enter_state`]. Local to this server,
+    /// like `quality`.
+    #[serde(skip, default = "syncing")]
+    pub state: ConnectionState,
+    /// When the session entered `state`.
+    #[serde(skip, default = "Instant::now")]
+    pub state_entered_at: Instant,
+}
+
+fn syncing() -> ConnectionState {
+    ConnectionState::Syncing
 }
 
 impl Session {
     /// Create a new session.
     pub fn new(id: u64, identity: Identity, name: String) -> Self {
-        Self { id, identity, name }
+        Self {
+            id,
+            identity,
+            name,
+            quality: ConnectionQuality::default(),
+            prediction: ClientPrediction::default(),
+            encoding: SessionEncoding::default(),
+            client_version: None,
+            state: ConnectionState::Syncing,
+            state_entered_at: Instant::now(),
+        }
+    }
+
+    /// Move to `state`, restarting [`time_in_state`](Self::time_in_state).
+    pub fn enter_state(&mut self, state: ConnectionState) {
+        self.state = state;
+        self.state_entered_at = Instant::now();
+    }
+
+    /// How long the session has been in its current state, e.g. to find
+    /// sessions stuck `Syncing`.
+    pub fn time_in_state(&self) -> Duration {
+        self.state_entered_at.elapsed()
+    }
+
+    /// How far ahead the client is predicting: the snapshot it has
+    /// predicted up to, or its last confirmed one if it doesn't predict.
+    pub fn acknowledged_prediction_seq(&self) -> u64 {
+        self.prediction.predicted_seq()
+    }
+
+    /// Whether this is a guest session (see [`IdentityKind::Anonymous`]).
+    ///
+    /// [`IdentityKind::Anonymous`]: crate::IdentityKind::Anonymous
+    pub fn is_anonymous(&self) -> bool {
+        self.identity.is_anonymous()
+    }
+}
+
+/// Connect-time context from the transport, such as request headers.
+///
+/// Unlike [`Session`] it isn't kept or carried anywhere: the authority sees
+/// it once, at connect, and stores what it needs. Keys are lowercased, so
+/// lookups match HTTP headers in any case.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectInfo {
+    hints: BTreeMap<String, String>,
+}
+
+impl ConnectInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a hint, replacing any under the same key.
+    pub fn insert(&mut self, key: &str, value: impl Into<String>) {
+        self.hints.insert(key.to_ascii_lowercase(), value.into());
+    }
+
+    /// With a hint added.
+    pub fn with_hint(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// The hint under `key`, if the transport provided one.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.hints
+            .get(&key.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Every hint, by key.
+    pub fn hints(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.hints.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hints.is_empty()
     }
 }
 
@@ -31,6 +150,8 @@ pub struct ImportResult<P> {
     pub passport: P,
     /// Items/data that were rejected.
     pub rejected: Vec<Rejection>,
+    /// Items/data that were accepted in modified form.
+    pub transformed: Vec<Transform>,
 }
 
 /// A rejection from import policy.
@@ -38,6 +159,8 @@ pub struct ImportResult<P> {
 pub struct Rejection {
     /// What was rejected.
     pub item: String,
+    /// Machine-readable rejection code (app-defined).
+    pub code: Option<String>,
     /// Why it was rejected.
     pub reason: String,
 }
@@ -46,9 +169,244 @@ impl Rejection {
     pub fn new(item: impl Into<String>, reason: impl Into<String>) -> Self {
         Self {
             item: item.into(),
+            code: None,
             reason: reason.into(),
         }
     }
+
+    /// Attach a machine-readable code.
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+}
+
+/// An authority's state failed its own consistency checks.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("invariant violated: {description}")]
+pub struct InvariantViolation {
+    /// What is inconsistent.
+    pub description: String,
+    /// State needed to debug it (offending ids, counts).
+    pub context: serde_json::Value,
+}
+
+impl InvariantViolation {
+    pub fn new(description: impl Into<String>, context: serde_json::Value) -> Self {
+        Self {
+            description: description.into(),
+            context,
+        }
+    }
+}
+
+pub(crate) type HmacSha256 = Hmac<sha2::Sha256>;
+
+/// A signed session, for reconnecting to any instance that shares the key.
+///
+/// Unlike a resume token, the server keeps nothing: the token carries the
+/// session and an HMAC-SHA256 over it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionToken {
+    /// The serialized session.
+    pub payload: Vec<u8>,
+    /// HMAC-SHA256 over `payload` and `expires_at`.
+    pub signature: Vec<u8>,
+    /// Unix time in milliseconds after which the token is refused.
+    pub expires_at: u64,
+}
+
+impl SessionToken {
+    /// How long tokens from the default `generate_session_token` last.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+    /// Sign a session, valid for `ttl`.
+    pub fn sign(session: &Session, signing_key: &[u8], ttl: Duration) -> Self {
+        let payload = canonical_bytes(session).expect("session serializes");
+        let expires_at = Timestamp::now().saturating_add(ttl).as_millis();
+        let signature = Self::mac(&payload, expires_at, signing_key)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        Self {
+            payload,
+            signature,
+            expires_at,
+        }
+    }
+
+    /// The signed session, if the signature holds and the token is unexpired.
+    pub fn verify(&self, signing_key: &[u8]) -> Option<Session> {
+        if Timestamp::now().as_millis() > self.expires_at {
+            return None;
+        }
+        Self::mac(&self.payload, self.expires_at, signing_key)
+            .verify_slice(&self.signature)
+            .ok()?;
+        serde_json::from_slice(&self.payload).ok()
+    }
+
+    fn mac(payload: &[u8], expires_at: u64, signing_key: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(signing_key).expect("HMAC takes keys of any length");
+        mac.update(payload);
+        mac.update(&expires_at.to_be_bytes());
+        mac
+    }
+}
+
+/// A session's state, exported for live migration to another process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedSession {
+    /// The session as the transport knew it.
+    pub session: Session,
+    /// App-defined per-session state.
+    pub authority_state: Vec<u8>,
+}
+
+/// Why exported sessions couldn't be imported.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ImportSessionError {
+    #[error("this authority can't import sessions")]
+    Unsupported,
+    #[error("session {session_id} has unreadable state: {reason}")]
+    InvalidState { session_id: u64, reason: String },
+    #[error("session {session_id} already exists")]
+    Duplicate { session_id: u64 },
+}
+
+/// What the transport does with a passport that fails to decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassportDecodeAction {
+    /// Refuse the connection with a protocol error.
+    Reject,
+    /// Connect the session as a fresh join, without the passport.
+    ConnectFresh,
+}
+
+/// Passports larger than this go through [`Authority::on_large_passport`]
+/// before they are decoded.
+pub const LARGE_PASSPORT_BYTES: usize = 64 * 1024;
+
+/// What the transport does with a passport over [`LARGE_PASSPORT_BYTES`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LargePassportAction {
+    /// Decode it as usual.
+    Accept,
+    /// Refuse the connection with a `passport_too_large` error.
+    Reject { reason: String },
+    /// Keep only the first `max_bytes`. What's left rarely decodes, so
+    /// this usually ends in [`Authority::on_passport_decode_error`].
+    Truncate { max_bytes: usize },
+}
+
+/// What the transport does when a session asks to transfer to the server
+/// it's already on.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LoopbackAction {
+    /// Carry out the transfer anyway; the client reconnects here.
+    Allow,
+    /// Refuse with a `transfer_loopback` error.
+    #[default]
+    Reject,
+    /// Transfer to `url` instead.
+    Redirect { url: String },
+}
+
+/// How soon the transport applies an intent when several are waiting.
+///
+/// Ordered lowest first, so `Critical > Low`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum IntentPriority {
+    /// Background work that can wait behind everything else.
+    Low,
+    /// Regular user intents.
+    #[default]
+    Normal,
+    /// VIP users.
+    High,
+    /// Admin operations and system events.
+    Critical,
+}
+
+/// Whether a client's optimistic application of an intent stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptimisticOutcome {
+    /// The client predicted right; it keeps its local state.
+    Confirmed,
+    /// The client predicted wrong; it rolls back to a fresh snapshot.
+    Corrected,
+}
+
+/// What the transport does after an authority returns an error.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AuthorityErrorAction {
+    /// Send the session the error and carry on.
+    #[default]
+    SendErrorAndContinue,
+    /// Send the error, then disconnect the session with `reason`.
+    KickSession { reason: String },
+    /// Send the error, then refuse every session's intents for a while
+    /// (e.g. until a database reconnects).
+    PauseAuthority { for_duration: Duration },
+    /// Send the error, then shut the server down gracefully.
+    ShutdownAuthority,
+}
+
+/// A pause ([`AuthorityErrorAction::PauseAuthority`]) that has ended.
+#[derive(Debug, Clone)]
+pub struct RecoveryAttempt<E> {
+    /// The error that paused the authority.
+    pub error: E,
+    /// How long intents were refused.
+    pub paused_for: Duration,
+    /// When the pause ended and intents were taken again.
+    pub recovered_at: Instant,
+}
+
+impl<E: std::fmt::Display> RecoveryAttempt<E> {
+    /// The same attempt with the error as text, e.g. for an observer that
+    /// doesn't know the authority's error type.
+    pub fn described(&self) -> RecoveryAttempt<String> {
+        RecoveryAttempt {
+            error: self.error.to_string(),
+            paused_for: self.paused_for,
+            recovered_at: self.recovered_at,
+        }
+    }
+}
+
+/// What the transport does about a malformed client message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireErrorAction {
+    /// Drop the message.
+    Ignore,
+    /// Drop the message and send the client an error.
+    WarnClient { message: String },
+    /// Close the connection.
+    Disconnect,
+}
+
+/// An item accepted in modified form by import policy.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    /// What was transformed.
+    pub item: String,
+    /// The value as it arrived.
+    pub from: String,
+    /// The value as accepted.
+    pub to: String,
+}
+
+impl Transform {
+    pub fn new(item: impl Into<String>, from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            item: item.into(),
+            from: from.into(),
+            to: to.into(),
+        }
+    }
 }
 
 impl<P> ImportResult<P> {
@@ -57,12 +415,134 @@ impl<P> ImportResult<P> {
         Self {
             passport,
             rejected: Vec::new(),
+            transformed: Vec::new(),
         }
     }
 
     /// Create a result with some rejections.
     pub fn with_rejections(passport: P, rejected: Vec<Rejection>) -> Self {
-        Self { passport, rejected }
+        Self {
+            passport,
+            rejected,
+            transformed: Vec::new(),
+        }
+    }
+
+    /// Build a result with mixed outcomes.
+    ///
+    /// `passport` is the sanitized passport; the builder records what was
+    /// rejected or transformed on the way.
+    pub fn builder(passport: P) -> ImportResultBuilder<P> {
+        ImportResultBuilder {
+            result: Self::accept(passport),
+        }
+    }
+
+    /// Whether the passport was accepted unchanged.
+    pub fn is_clean(&self) -> bool {
+        self.rejected.is_empty() && self.transformed.is_empty()
+    }
+}
+
+/// Result of importing a party that transferred together.
+///
+/// Every member ends up in exactly one list. A party is atomic when
+/// [`is_complete`](Self::is_complete) or when nobody was accepted; anything
+/// else is a partial arrival the transport reports member by member.
+#[derive(Debug, Clone)]
+pub struct PartyImportResult<P> {
+    /// Members let in, by session ID, in party order.
+    pub accepted: Vec<(u64, ImportResult<P>)>,
+    /// Members turned away, by session ID, in party order.
+    pub refused: Vec<(u64, Rejection)>,
+}
+
+impl<P> Default for PartyImportResult<P> {
+    fn default() -> Self {
+        Self {
+            accepted: Vec::new(),
+            refused: Vec::new(),
+        }
+    }
+}
+
+impl<P> PartyImportResult<P> {
+    /// Import members one at a time with `import`, refusing any it fails.
+    ///
+    /// Sessions and passports are paired by position.
+    pub fn import_each<E: std::fmt::Display>(
+        sessions: &[Session],
+        passports: Vec<P>,
+        mut import: impl FnMut(&Session, P) -> Result<ImportResult<P>, E>,
+    ) -> Self {
+        let mut result = Self::default();
+        for (session, passport) in sessions.iter().zip(passports) {
+            match import(session, passport) {
+                Ok(imported) => result.accepted.push((session.id, imported)),
+                Err(e) => result
+                    .refused
+                    .push((session.id, Rejection::new(&session.name, e.to_string()))),
+            }
+        }
+        result
+    }
+
+    /// Whether every member was accepted.
+    pub fn is_complete(&self) -> bool {
+        self.refused.is_empty()
+    }
+
+    /// Whether some members were accepted and others refused.
+    pub fn is_partial(&self) -> bool {
+        !self.accepted.is_empty() && !self.refused.is_empty()
+    }
+}
+
+/// Fluent builder for [`ImportResult`].
+#[derive(Debug, Clone)]
+pub struct ImportResultBuilder<P> {
+    result: ImportResult<P>,
+}
+
+impl<P> ImportResultBuilder<P> {
+    /// Record a rejected item.
+    pub fn reject(mut self, item: impl Into<String>, reason: impl Into<String>) -> Self {
+        self.result.rejected.push(Rejection::new(item, reason));
+        self
+    }
+
+    /// Record a rejected item with a machine-readable code.
+    pub fn reject_code(
+        mut self,
+        item: impl Into<String>,
+        code: impl Into<String>,
+        reason: impl Into<String>,
+    ) -> Self {
+        let rejection = Rejection::new(item, reason).with_code(code);
+        self.result.rejected.push(rejection);
+        self
+    }
+
+    /// Record an item accepted in modified form.
+    pub fn transform(
+        mut self,
+        item: impl Into<String>,
+        from: impl Into<String>,
+        to: impl Into<String>,
+    ) -> Self {
+        self.result.transformed.push(Transform::new(item, from, to));
+        self
+    }
+
+    /// Apply `record` only if `condition` holds, so outcomes that depend on
+    /// the passport stay in one chain.
+    pub fn when(self, condition: bool, record: impl FnOnce(Self) -> Self) -> Self {
+        if condition { record(self) } else { self }
+    }
+
+    /// Finish building.
+    pub fn build(self) -> ImportResult<P> {
+        self.result
     }
 }
 
@@ -85,9 +565,43 @@ pub trait Authority: Send + Sync {
     /// Error type.
     type Error: std::error::Error + Send + Sync + 'static;
 
+    /// Check an identity before its session connects or transfers in.
+    ///
+    /// Runs before `on_connect` and `on_transfer_in`; an error refuses the
+    /// connection. The default accepts any identity.
+    fn validate_identity(&self, _identity: &Identity) -> Result<(), IdentityError> {
+        Ok(())
+    }
+
+    /// [`validate_identity`](Self::validate_identity), with the transport's
+    /// [`ConnectInfo`].
+    ///
+    /// Transports that have connect-time hints call this instead. The
+    /// default ignores them.
+    fn validate_connect(
+        &self,
+        identity: &Identity,
+        _info: &ConnectInfo,
+    ) -> Result<(), IdentityError> {
+        self.validate_identity(identity)
+    }
+
     /// Called when a new session connects (without transfer).
     fn on_connect(&mut self, session: &Session) -> Result<(), Self::Error>;
 
+    /// [`on_connect`](Self::on_connect), with the transport's
+    /// [`ConnectInfo`] (e.g. to gate features by client version).
+    ///
+    /// Transports that have connect-time hints call this instead. The
+    /// default ignores them.
+    fn on_connect_with_info(
+        &mut self,
+        session: &Session,
+        _info: &ConnectInfo,
+    ) -> Result<(), Self::Error> {
+        self.on_connect(session)
+    }
+
     /// Called when a session transfers in from another server.
     ///
     /// Apply your import policy and return the sanitized passport.
@@ -97,108 +611,2278 @@ pub trait Authority: Send + Sync {
         passport: Self::Passport,
     ) -> Result<ImportResult<Self::Passport>, Self::Error>;
 
-    /// Called when a session disconnects.
-    fn on_disconnect(&mut self, session: &Session);
+    /// Called once [`on_transfer_in`](Self::on_transfer_in) has imported a
+    /// session that brought its view at the origin (the transfer's
+    /// [`snapshot_context`](crate::TransferSnapshot::snapshot_context)).
+    ///
+    /// Use it to restore what the session was doing there: its current
+    /// room, an open quest. Sessions from servers that send a bare passport
+    /// never get here. The default ignores it.
+    fn on_transfer_context(&mut self, _session: &Session, _context: Self::Snapshot) {}
 
-    /// Handle an intent from a session.
-    fn handle_intent(
+    /// Called when a party transfers in together (see [`Transfer::bundle`]).
+    ///
+    /// `sessions` and `passports` are paired by position. The default runs
+    /// each member through [`on_transfer_in`](Self::on_transfer_in) and
+    /// refuses the ones it fails, so the party may arrive partially.
+    /// Override to make it all-or-nothing: check every member first, and
+    /// return an error to refuse the whole party without importing anyone.
+    ///
+    /// [`Transfer::bundle`]: crate::Transfer::bundle
+    fn on_party_transfer_in(
         &mut self,
-        session: &Session,
-        intent: Self::Intent,
-    ) -> Result<(), Self::Error>;
+        sessions: &[Session],
+        passports: Vec<Self::Passport>,
+    ) -> Result<PartyImportResult<Self::Passport>, Self::Error> {
+        Ok(PartyImportResult::import_each(
+            sessions,
+            passports,
+            |session, passport| self.on_transfer_in(session, passport),
+        ))
+    }
 
-    /// Generate a snapshot for a specific session.
+    /// Called when a transferring session's passport can't be decoded.
     ///
-    /// This allows relevancy filtering - you can customize what each session sees.
-    fn snapshot_for(&self, session: &Session) -> Self::Snapshot;
+    /// This is a broken transfer (a bug or version skew between servers),
+    /// not a policy rejection. The default refuses the connection rather
+    /// than silently treating it as a fresh join.
+    fn on_passport_decode_error(
+        &mut self,
+        _session: &Session,
+        _raw: &[u8],
+        _error: &serde_json::Error,
+    ) -> PassportDecodeAction {
+        PassportDecodeAction::Reject
+    }
 
-    /// Generate a passport for a session that's transferring out.
-    fn emit_passport(&self, session: &Session) -> Self::Passport;
+    /// Called with the size of a passport over [`LARGE_PASSPORT_BYTES`],
+    /// before it is decoded.
+    ///
+    /// Passports over the transport's own hard limit are refused without
+    /// asking. The default rejects.
+    fn on_large_passport(&mut self, _session: &Session, size: usize) -> LargePassportAction {
+        LargePassportAction::Reject {
+            reason: format!("Passport of {size} bytes is over {LARGE_PASSPORT_BYTES}"),
+        }
+    }
 
-    /// Check if a transfer destination is valid.
-    fn validate_destination(&self, destination: &str) -> bool;
-}
+    /// Check an arriving passport before [`on_transfer_in`](Self::on_transfer_in).
+    ///
+    /// A passport that fails is refused with every error reported and
+    /// never reaches `on_transfer_in`. The default accepts everything; a
+    /// [`PassportValidationChain`](crate::PassportValidationChain) makes an
+    /// easy override.
+    fn validate_passport(
+        &self,
+        _session: &Session,
+        _passport: &Self::Passport,
+    ) -> Result<(), Vec<PassportValidationError>> {
+        Ok(())
+    }
 
-/// A simpler trait for authorities that don't need per-session snapshots.
-pub trait SimpleAuthority: Send + Sync {
-    type Intent;
-    type Snapshot;
-    type Passport;
-    type Error: std::error::Error + Send + Sync + 'static;
+    /// Called when a session disconnects.
+    fn on_disconnect(&mut self, session: &Session);
 
-    /// Called when a new session connects.
-    fn on_connect(&mut self, session: &Session) -> Result<(), Self::Error>;
+    /// Called when many sessions leave at once, e.g. every session at
+    /// shutdown. The transport tells the clients with one
+    /// `ServerWire::RoomClosing` each and sends no snapshots in between.
+    ///
+    /// The transport also calls it with a single session when it knows
+    /// more than that the connection closed, e.g. after a transfer out
+    /// ([`DisconnectReason::Transferred`]).
+    ///
+    /// The default calls [`on_disconnect`](Self::on_disconnect) for each;
+    /// override it to tear the room down in one pass.
+    fn on_disconnect_batch(&mut self, sessions: &[Session], _reason: DisconnectReason) {
+        for session in sessions {
+            self.on_disconnect(session);
+        }
+    }
 
-    /// Called when a session transfers in.
-    fn on_transfer_in(
-        &mut self,
-        session: &Session,
-        passport: Self::Passport,
-    ) -> Result<ImportResult<Self::Passport>, Self::Error>;
+    /// Called when a client goes to the background (`ClientWire::Pause`).
+    ///
+    /// The session stays connected; the transport holds its snapshots
+    /// until it resumes. Use this to e.g. mark the user away.
+    fn on_session_paused(&mut self, _session: &Session) {}
 
-    /// Called when a session disconnects.
-    fn on_disconnect(&mut self, session: &Session);
+    /// Called when a paused client comes back (`ClientWire::Resume`), before
+    /// held snapshots are delivered.
+    fn on_session_resumed(&mut self, _session: &Session) {}
 
-    /// Handle an intent.
-    fn handle_intent(
+    /// Handle an intent from a session.
+    fn handle_intent(&mut self, session: &Session, intent: Self::Intent)
+    -> Result<(), Self::Error>;
+
+    /// How urgently to apply `intent` when intents are queued.
+    ///
+    /// Higher priorities jump the queue, so a flood of low-priority
+    /// intents can't starve admin operations. The default is
+    /// [`IntentPriority::Normal`] for everything.
+    fn intent_priority(&self, _session: &Session, _intent: &Self::Intent) -> IntentPriority {
+        IntentPriority::Normal
+    }
+
+    /// JSON Schema every intent must satisfy before
+    /// [`handle_intent`](Self::handle_intent) sees it.
+    ///
+    /// Deserializing only checks an intent's shape; a schema can also bound
+    /// its values (no negative quantities, coordinates inside the map).
+    /// Transports that validate reject failures with
+    /// [`ErrorCode::IntentRejected`](crate::ErrorCode::IntentRejected). The
+    /// default has no schema.
+    fn intent_schema(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Handle an intent the client already applied locally, on top of
+    /// snapshot `base_seq` (`ClientWire::TrackedIntent` with `base_seq`).
+    ///
+    /// Return [`OptimisticOutcome::Corrected`] when the client can't have
+    /// predicted the result, e.g. the server clamped a value, or another
+    /// session's change landed after `base_seq`. `latest_seq` is the last
+    /// snapshot sent to this session. The client is sent a corrective
+    /// snapshot and rolls back. An error always corrects. The default
+    /// applies the intent with [`handle_intent`](Self::handle_intent) and
+    /// confirms it.
+    fn handle_optimistic_intent(
         &mut self,
         session: &Session,
         intent: Self::Intent,
-    ) -> Result<(), Self::Error>;
+        _base_seq: u64,
+        _latest_seq: u64,
+    ) -> Result<OptimisticOutcome, Self::Error> {
+        self.handle_intent(session, intent)?;
+        Ok(OptimisticOutcome::Confirmed)
+    }
 
-    /// Generate a snapshot (same for all sessions).
-    fn snapshot(&self) -> Self::Snapshot;
+    /// Called once the transport has acked a tracked intent
+    /// (`ClientWire::TrackedIntent` with `require_ack`).
+    ///
+    /// `intent_seq` is the client's `request_id`. Retries of an intent
+    /// already applied are acked again without calling this.
+    fn on_intent_ack(&mut self, _session: &Session, _intent_seq: u64) {}
 
-    /// Generate a passport for transfer.
-    fn emit_passport(&self, session: &Session) -> Self::Passport;
+    /// Generate a snapshot for a specific session.
+    ///
+    /// This allows relevancy filtering - you can customize what each session sees.
+    /// `session.quality` allows the same for detail: send less to slow
+    /// connections.
+    fn snapshot_for(&self, session: &Session) -> Self::Snapshot;
 
-    /// Check if a destination is valid.
-    fn validate_destination(&self, destination: &str) -> bool;
-}
+    /// Reduce a run of snapshots to one, e.g. those queued for a paused
+    /// session (see `PauseBuffer` in the server crate).
+    ///
+    /// `history` is oldest first; `None` only when it's empty. Snapshots
+    /// are full state, so the default keeps the last; override to merge
+    /// state the last one doesn't carry on its own.
+    fn coalesce_snapshots(&self, mut history: Vec<Self::Snapshot>) -> Option<Self::Snapshot> {
+        history.pop()
+    }
 
-// Blanket implementation: SimpleAuthority -> Authority
-impl<T> Authority for T
-where
-    T: SimpleAuthority,
-{
-    type Intent = T::Intent;
-    type Snapshot = T::Snapshot;
-    type Passport = T::Passport;
-    type Error = T::Error;
+    /// Redact a snapshot in place just before it's sent to a session.
+    ///
+    /// Where `snapshot_for` decides what a session sees, this hides fields
+    /// within it (e.g. other users' private messages). The transport calls
+    /// it per recipient, so a snapshot shared by several sessions is
+    /// redacted separately for each.
+    fn redact_snapshot(&self, _session: &Session, _snapshot: &mut Self::Snapshot) {}
 
-    fn on_connect(&mut self, session: &Session) -> Result<(), Self::Error> {
-        SimpleAuthority::on_connect(self, session)
+    /// Downgrade a snapshot for a client running older software, so it
+    /// still deserializes there (e.g. drop a field added after
+    /// `client_version` shipped).
+    ///
+    /// The transport calls it after redaction for sessions that reported a
+    /// version (see [`Session::client_version`]), keeping version checks out
+    /// of `snapshot_for`. The default sends the snapshot as is.
+    fn translate_snapshot_for_version(
+        &self,
+        snapshot: Self::Snapshot,
+        _client_version: &str,
+    ) -> Self::Snapshot {
+        snapshot
     }
 
-    fn on_transfer_in(
-        &mut self,
-        session: &Session,
-        passport: Self::Passport,
-    ) -> Result<ImportResult<Self::Passport>, Self::Error> {
-        SimpleAuthority::on_transfer_in(self, session, passport)
+    /// How relevant an entity is to a session, from 0.0 (irrelevant) to
+    /// 1.0 (fully relevant).
+    ///
+    /// A [`RelevanceFilter`](crate::RelevanceFilter) drops entities scoring
+    /// below its threshold from `snapshot_for`. Spatial games typically
+    /// score by distance through [`RelevanceConfig::score`](crate::RelevanceConfig::score).
+    fn compute_relevance_score(&self, _session: &Session, _entity_id: u64) -> f32 {
+        1.0
     }
 
-    fn on_disconnect(&mut self, session: &Session) {
-        SimpleAuthority::on_disconnect(self, session)
+    /// A snapshot from `source_session_id`'s point of view, for a session
+    /// spectating it.
+    ///
+    /// When this returns `Some`, the transport sends it to the spectator
+    /// instead of the spectator's own `snapshot_for`. The default doesn't
+    /// support spectating.
+    fn clone_session_state(
+        &self,
+        _source_session_id: u64,
+        _spectator_session_id: u64,
+    ) -> Option<Self::Snapshot> {
+        None
     }
 
-    fn handle_intent(
-        &mut self,
-        session: &Session,
-        intent: Self::Intent,
-    ) -> Result<(), Self::Error> {
-        SimpleAuthority::handle_intent(self, session, intent)
+    /// Produce a delta from `base`, the snapshot the session last acked
+    /// (sequence `base_seq`), to its current state.
+    ///
+    /// Deltas use the snapshot type; the app decides what a partial
+    /// snapshot means and how its client applies one. Return `None` to send
+    /// the full snapshot instead (the default).
+    fn snapshot_delta_from(
+        &self,
+        _session: &Session,
+        _base_seq: u64,
+        _base: &Self::Snapshot,
+    ) -> Option<Self::Snapshot> {
+        None
     }
 
-    fn snapshot_for(&self, _session: &Session) -> Self::Snapshot {
-        SimpleAuthority::snapshot(self)
+    /// Compute a delta directly from the events since the last one.
+    ///
+    /// For event-sourced authorities, whose log says exactly what changed.
+    /// The encoding is app-defined. Return `None` (the default) to fall back
+    /// to diffing snapshots. See [`DeltaAuthority`](crate::DeltaAuthority).
+    fn snapshot_delta_from_events(
+        &self,
+        _events: &[AuthorityEvent<Self::Intent, Self::Snapshot, Self::Passport>],
+    ) -> Option<Vec<u8>> {
+        None
     }
 
-    fn emit_passport(&self, session: &Session) -> Self::Passport {
-        SimpleAuthority::emit_passport(self, session)
+    /// Answer one page of a read-only query (see [`QueryPage`]).
+    ///
+    /// `cursor` is `None` for the first page, then whatever the previous
+    /// page returned. The default answers nothing.
+    fn query_page(
+        &self,
+        _session: &Session,
+        _query: &serde_json::Value,
+        _cursor: Option<&str>,
+    ) -> Result<QueryPage, QueryError> {
+        Err(QueryError::Unsupported)
     }
 
-    fn validate_destination(&self, destination: &str) -> bool {
-        SimpleAuthority::validate_destination(self, destination)
+    /// Generate a passport for a session that's transferring out.
+    fn emit_passport(&self, session: &Session) -> Self::Passport;
+
+    /// Generate a passport for a session transferring out to `destination`.
+    ///
+    /// Destinations may want different contents: a different schema, a
+    /// narrower permission scope, no premium items on a free server, or an
+    /// access token only that server accepts. The default ignores
+    /// `destination` and calls [`emit_passport`](Self::emit_passport).
+    fn emit_passport_for_destination(
+        &self,
+        session: &Session,
+        _destination: &str,
+    ) -> Self::Passport {
+        self.emit_passport(session)
+    }
+
+    /// Generate a passport for a session whose destination already holds
+    /// `previous` (at `previous_version`), e.g. one bouncing between two
+    /// servers.
+    ///
+    /// Return a [`PassportUpdate::patch`] when little has changed. The
+    /// default always sends the full passport.
+    ///
+    /// The transports in this workspace don't call this: they don't know
+    /// what a destination holds, so their transfers always carry a full
+    /// passport. It's for applications that track that per peer themselves
+    /// and resolve updates on arrival with a [`PassportCache`].
+    ///
+    /// [`PassportCache`]: crate::PassportCache
+    fn emit_incremental_passport(
+        &self,
+        session: &Session,
+        previous_version: u32,
+        _previous: &Self::Passport,
+    ) -> PassportUpdate<Self::Passport> {
+        PassportUpdate::full(
+            self.emit_passport(session),
+            previous_version.saturating_add(1),
+        )
+    }
+
+    /// Generate the full transfer payload for a session that's transferring
+    /// out to `destination`.
+    ///
+    /// The default pairs [`snapshot_for`](Self::snapshot_for) with
+    /// [`emit_passport_for_destination`](Self::emit_passport_for_destination).
+    fn emit_transfer_snapshot(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> TransferSnapshot<Self::Snapshot, Self::Passport> {
+        TransferSnapshot {
+            snapshot_context: self.snapshot_for(session),
+            passport: self.emit_passport_for_destination(session, destination),
+        }
+    }
+
+    /// Generate passports for sessions transferring out together, in the
+    /// same order.
+    ///
+    /// The default emits each member's passport on its own; override to
+    /// add what only makes sense for the group (a shared party ID, loot
+    /// split between members).
+    fn emit_party_passport(&self, sessions: &[Session]) -> Vec<Self::Passport> {
+        sessions
+            .iter()
+            .map(|session| self.emit_passport(session))
+            .collect()
+    }
+
+    /// Check if a transfer destination is valid.
+    fn validate_destination(&self, destination: &str) -> bool;
+
+    /// A session asked to transfer to this server.
+    ///
+    /// The transport calls this when the destination is the manifest's own
+    /// [`endpoint`](crate::Manifest::endpoint), before
+    /// [`validate_destination`](Self::validate_destination); carrying out
+    /// such a transfer would only reconnect the client here. The default
+    /// rejects it.
+    fn on_transfer_loopback(&mut self, _session: &Session, _destination: &str) -> LoopbackAction {
+        LoopbackAction::Reject
+    }
+
+    /// A session asked to transfer to `destination`, which this authority
+    /// may host itself (e.g. a room of a [`RouterAuthority`]).
+    ///
+    /// Return `Some` once the session has moved there under its own ID (or
+    /// failed to): it keeps its connection, and the transport tells the
+    /// client with `LocalTransferComplete` rather than a transfer
+    /// directive. The default returns `None`, and the transfer goes through
+    /// the client.
+    ///
+    /// [`RouterAuthority`]: crate::RouterAuthority
+    fn on_local_transfer(
+        &mut self,
+        _session: &Session,
+        _destination: &str,
+    ) -> Option<Result<LocalTransferResult, TransferError<Self::Error>>> {
+        None
+    }
+
+    /// Estimate the encoded size of a session's transfer payload, in bytes:
+    /// its passport and the snapshot context sent along with it.
+    ///
+    /// The transport checks this before a transfer so oversized passports can
+    /// be caught before they stall the connection. The default emits both
+    /// and measures their JSON encoding; override with a structural
+    /// estimate if emitting is expensive.
+    fn passport_size_estimate(&self, session: &Session) -> usize
+    where
+        Self::Snapshot: Serialize,
+        Self::Passport: Serialize,
+    {
+        let transfer = TransferSnapshot {
+            snapshot_context: self.snapshot_for(session),
+            passport: self.emit_passport(session),
+        };
+        serde_json::to_vec(&transfer).map_or(0, |bytes| bytes.len())
+    }
+
+    /// Deterministic hash of the authority's state, for tests that check
+    /// state is exactly as expected (see [`FingerprintAssert`]).
+    ///
+    /// Equal state gives equal fingerprints regardless of map iteration
+    /// order (see [`fingerprint`]). The default hashes the snapshot an
+    /// observer with no identity would see; override if that view hides
+    /// state you need covered.
+    ///
+    /// [`FingerprintAssert`]: crate::FingerprintAssert
+    /// [`fingerprint`]: crate::fingerprint
+    fn authority_fingerprint(&self) -> u64
+    where
+        Self::Snapshot: Serialize,
+    {
+        let observer = Session::new(0, Identity::local("fingerprint"), String::new());
+        crate::fingerprint(&self.snapshot_for(&observer))
+    }
+
+    /// Short label for an intent, for metrics and logs.
+    ///
+    /// The default is the full Rust type name; override to return a
+    /// per-variant label (e.g. `"message"`).
+    fn intent_type_name(_intent: &Self::Intent) -> &'static str {
+        std::any::type_name::<Self::Intent>()
+    }
+
+    /// An intent as JSON, for logs and debugging; `null` if it doesn't
+    /// serialize.
+    ///
+    /// Intents can carry secrets, so log
+    /// [`redacted_intent_to_json`](Self::redacted_intent_to_json) anywhere
+    /// that outlives a debugging session.
+    fn intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value
+    where
+        Self::Intent: Serialize,
+    {
+        serde_json::to_value(intent).unwrap_or_default()
+    }
+
+    /// An intent as something safe to log: its [`Redactor`] projection,
+    /// which observers and audit hooks get in place of the intent.
+    ///
+    /// The default keeps only the type name ([`TypeNameOnly`]). Override to
+    /// project through the authority's own redactor, or to mask fields with
+    /// [`RedactPaths`]:
+    ///
+    /// ```ignore
+    /// fn redacted_intent_to_json(&self, intent: &AccountIntent) -> Value {
+    ///     RedactPaths(&["SetPassword.password", "Pay.card.*"]).redact(intent)
+    /// }
+    /// ```
+    ///
+    /// [`RedactPaths`]: crate::RedactPaths
+    fn redacted_intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value {
+        TypeNameOnly.redact(intent)
+    }
+
+    /// Who is present, for transports to send as joins and leaves.
+    ///
+    /// A roster in every snapshot is resent in full on every join; return
+    /// it here instead (and leave it out of the snapshot) and clients get
+    /// `ServerWire::Presence` updates with only what changed (see
+    /// [`Roster`](crate::Roster)). The default reports none.
+    fn presence(&self) -> Option<Vec<Identity>> {
+        None
+    }
+
+    /// What a snapshot is about, e.g. `"presence"` or `"game-state"`.
+    ///
+    /// Transports can send each topic at its own rate, so presence may lag
+    /// while game state goes out at once. The default tags nothing.
+    fn snapshot_topic(_snapshot: &Self::Snapshot) -> Option<&'static str> {
+        None
+    }
+
+    /// Pre-flight check on where a transfer comes from.
+    ///
+    /// Called before the passport is decoded, so policy such as "no
+    /// transfers from untrusted clusters" costs nothing. Returning `false`
+    /// refuses the transfer without calling `on_transfer_in`. Transports
+    /// only pass manifests they can trust (e.g. [signed](Manifest::verify)
+    /// by the federation's key), never one taken on a client's word.
+    fn can_accept_transfer_from(&self, _src_manifest: &Manifest) -> bool {
+        true
+    }
+
+    /// Decide what a session may do.
+    ///
+    /// `default` comes from the transport's policy (e.g. reduced
+    /// capabilities for anonymous sessions). Override to grant or revoke
+    /// per session.
+    fn capabilities_for(&self, _session: &Session, default: Capabilities) -> Capabilities {
+        default
+    }
+
+    /// Called when a snapshot would push a session over its [`SnapshotBudget`].
+    ///
+    /// The transport still delivers the snapshot, late. Use this to reduce
+    /// detail for the session so later snapshots fit.
+    fn on_budget_exceeded(
+        &mut self,
+        _session: &Session,
+        _snapshot_size: usize,
+        _budget: &SnapshotBudget,
+    ) {
+    }
+
+    /// Called once a batch of transfers from one peer server has settled.
+    ///
+    /// The transport groups `on_transfer_in` calls from the same source
+    /// within a time window (e.g. a bulk migration) and reports the totals
+    /// here. `elapsed` spans the first to the last transfer in the batch.
+    fn on_peer_transfer_complete(
+        &mut self,
+        _src_identity: &Identity,
+        _accepted: u64,
+        _rejected: u64,
+        _elapsed: Duration,
+    ) {
+    }
+
+    /// Called when an intent fails, to decide what happens next.
+    ///
+    /// Most errors are the session's fault and only need reporting, which
+    /// is the default. Errors that mean the authority itself is unwell
+    /// (storage down, state corrupted) can pause or stop it instead.
+    fn on_authority_error(&self, _error: &Self::Error) -> AuthorityErrorAction {
+        AuthorityErrorAction::SendErrorAndContinue
+    }
+
+    /// Called when a pause from [`AuthorityErrorAction::PauseAuthority`]
+    /// is over, before intents are handled again.
+    ///
+    /// Use it to clear error state, reconnect storage, or record how long
+    /// the outage lasted. The default does nothing.
+    fn on_authority_recovered(&mut self, _previous_error: &Self::Error) {}
+
+    /// The [`ErrorCode`] a failed intent is reported with.
+    ///
+    /// Map errors onto the HTTP-like codes (`NotFound`, `Conflict`,
+    /// `Forbidden`, ...) so transports that speak HTTP can answer with the
+    /// matching [`status_code`](ErrorCode::status_code). The default is
+    /// [`ErrorCode::IntentError`].
+    fn error_code(&self, _error: &Self::Error) -> ErrorCode {
+        ErrorCode::IntentError
+    }
+
+    /// Called when a client message fails to decode.
+    ///
+    /// `session` is `None` before the client has authenticated. Track
+    /// repeat offenders here and escalate; the default ignores the message.
+    fn on_wire_error(
+        &mut self,
+        _session: Option<&Session>,
+        _raw: &str,
+        _error: &serde_json::Error,
+    ) -> WireErrorAction {
+        WireErrorAction::Ignore
+    }
+
+    /// Check the authority's internal consistency.
+    ///
+    /// Implement this to catch state that has drifted (a user without a
+    /// session, a negative count). Debug transports call it after every
+    /// `handle_intent`; otherwise it runs on demand or periodically.
+    fn assert_invariants(&self) -> Result<(), InvariantViolation> {
+        Ok(())
+    }
+
+    /// Serialize every session's state, for handing off to a new process.
+    ///
+    /// The default exports nothing.
+    fn export_sessions(&self) -> Vec<ExportedSession> {
+        Vec::new()
+    }
+
+    /// Restore sessions exported by another process.
+    ///
+    /// The transport calls this before accepting connections, so migrated
+    /// clients arrive at an authority that already knows them. The default
+    /// refuses any sessions, since it can't restore them.
+    fn import_sessions(
+        &mut self,
+        sessions: Vec<ExportedSession>,
+    ) -> Result<(), ImportSessionError> {
+        if sessions.is_empty() {
+            Ok(())
+        } else {
+            Err(ImportSessionError::Unsupported)
+        }
+    }
+
+    /// Issue a token the session can reconnect with, here or on any
+    /// instance sharing `signing_key`.
+    ///
+    /// The default signs the bare session for [`SessionToken::DEFAULT_TTL`].
+    fn generate_session_token(&self, session: &Session, signing_key: &[u8]) -> SessionToken {
+        SessionToken::sign(session, signing_key, SessionToken::DEFAULT_TTL)
+    }
+
+    /// Rebuild the session from a reconnect token, or `None` to refuse it.
+    fn verify_session_token(&self, token: &SessionToken, signing_key: &[u8]) -> Option<Session> {
+        token.verify(signing_key)
+    }
+
+    /// Type of snapshots this authority sends, advertised in the manifest.
+    ///
+    /// Lets clients detect that they connected to the wrong kind of server
+    /// before the first snapshot fails to deserialize. The default names
+    /// [`Self::Snapshot`] at version 0; bump the version when its encoding
+    /// changes.
+    fn expected_snapshot_type(&self) -> WireType {
+        WireType::of::<Self::Snapshot>()
+    }
+
+    /// Type of intents this authority accepts, advertised in the manifest.
+    fn expected_intent_type(&self) -> WireType {
+        WireType::of::<Self::Intent>()
+    }
+}
+
+/// A simpler trait for authorities that don't need per-session snapshots.
+pub trait SimpleAuthority: Send + Sync {
+    type Intent;
+    type Snapshot;
+    type Passport;
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Check an identity before it connects (see [`Authority::validate_identity`]).
+    fn validate_identity(&self, _identity: &Identity) -> Result<(), IdentityError> {
+        Ok(())
+    }
+
+    /// Check an identity with transport hints (see
+    /// [`Authority::validate_connect`]).
+    fn validate_connect(
+        &self,
+        identity: &Identity,
+        _info: &ConnectInfo,
+    ) -> Result<(), IdentityError> {
+        SimpleAuthority::validate_identity(self, identity)
+    }
+
+    /// Called when a new session connects.
+    fn on_connect(&mut self, session: &Session) -> Result<(), Self::Error>;
+
+    /// Connect with transport hints (see [`Authority::on_connect_with_info`]).
+    fn on_connect_with_info(
+        &mut self,
+        session: &Session,
+        _info: &ConnectInfo,
+    ) -> Result<(), Self::Error> {
+        SimpleAuthority::on_connect(self, session)
+    }
+
+    /// Called when a session transfers in.
+    fn on_transfer_in(
+        &mut self,
+        session: &Session,
+        passport: Self::Passport,
+    ) -> Result<ImportResult<Self::Passport>, Self::Error>;
+
+    /// Restore a transferred session's view at the origin (see
+    /// [`Authority::on_transfer_context`]).
+    fn on_transfer_context(&mut self, _session: &Session, _context: Self::Snapshot) {}
+
+    /// Import a party together (see [`Authority::on_party_transfer_in`]).
+    fn on_party_transfer_in(
+        &mut self,
+        sessions: &[Session],
+        passports: Vec<Self::Passport>,
+    ) -> Result<PartyImportResult<Self::Passport>, Self::Error> {
+        Ok(PartyImportResult::import_each(
+            sessions,
+            passports,
+            |session, passport| self.on_transfer_in(session, passport),
+        ))
+    }
+
+    /// A passport failed to decode (see [`Authority::on_passport_decode_error`]).
+    fn on_passport_decode_error(
+        &mut self,
+        _session: &Session,
+        _raw: &[u8],
+        _error: &serde_json::Error,
+    ) -> PassportDecodeAction {
+        PassportDecodeAction::Reject
+    }
+
+    /// A passport is over [`LARGE_PASSPORT_BYTES`] (see [`Authority::on_large_passport`]).
+    fn on_large_passport(&mut self, _session: &Session, size: usize) -> LargePassportAction {
+        LargePassportAction::Reject {
+            reason: format!("Passport of {size} bytes is over {LARGE_PASSPORT_BYTES}"),
+        }
+    }
+
+    /// Check an arriving passport (see [`Authority::validate_passport`]).
+    fn validate_passport(
+        &self,
+        _session: &Session,
+        _passport: &Self::Passport,
+    ) -> Result<(), Vec<PassportValidationError>> {
+        Ok(())
+    }
+
+    /// Called when a session disconnects.
+    fn on_disconnect(&mut self, session: &Session);
+
+    /// Many sessions left at once (see [`Authority::on_disconnect_batch`]).
+    fn on_disconnect_batch(&mut self, sessions: &[Session], _reason: DisconnectReason) {
+        for session in sessions {
+            self.on_disconnect(session);
+        }
+    }
+
+    /// A client went to the background (see [`Authority::on_session_paused`]).
+    fn on_session_paused(&mut self, _session: &Session) {}
+
+    /// A paused client came back (see [`Authority::on_session_resumed`]).
+    fn on_session_resumed(&mut self, _session: &Session) {}
+
+    /// Handle an intent.
+    fn handle_intent(&mut self, session: &Session, intent: Self::Intent)
+    -> Result<(), Self::Error>;
+
+    /// Queue priority of an intent (see [`Authority::intent_priority`]).
+    fn intent_priority(&self, _session: &Session, _intent: &Self::Intent) -> IntentPriority {
+        IntentPriority::Normal
+    }
+
+    /// Schema intents must satisfy (see [`Authority::intent_schema`]).
+    fn intent_schema(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Handle an intent the client applied optimistically (see
+    /// [`Authority::handle_optimistic_intent`]).
+    fn handle_optimistic_intent(
+        &mut self,
+        session: &Session,
+        intent: Self::Intent,
+        _base_seq: u64,
+        _latest_seq: u64,
+    ) -> Result<OptimisticOutcome, Self::Error> {
+        self.handle_intent(session, intent)?;
+        Ok(OptimisticOutcome::Confirmed)
+    }
+
+    /// A tracked intent was acked (see [`Authority::on_intent_ack`]).
+    fn on_intent_ack(&mut self, _session: &Session, _intent_seq: u64) {}
+
+    /// Generate a snapshot (same for all sessions).
+    fn snapshot(&self) -> Self::Snapshot;
+
+    /// A session's snapshot (see [`Authority::snapshot_for`]).
+    ///
+    /// Override to scale detail to `session.quality`.
+    fn snapshot_for(&self, _session: &Session) -> Self::Snapshot {
+        self.snapshot()
+    }
+
+    /// Reduce a run of snapshots to one (see [`Authority::coalesce_snapshots`]).
+    fn coalesce_snapshots(&self, mut history: Vec<Self::Snapshot>) -> Option<Self::Snapshot> {
+        history.pop()
+    }
+
+    /// Hide fields from a session (see [`Authority::redact_snapshot`]).
+    fn redact_snapshot(&self, _session: &Session, _snapshot: &mut Self::Snapshot) {}
+
+    /// Downgrade a snapshot for an older client (see
+    /// [`Authority::translate_snapshot_for_version`]).
+    fn translate_snapshot_for_version(
+        &self,
+        snapshot: Self::Snapshot,
+        _client_version: &str,
+    ) -> Self::Snapshot {
+        snapshot
+    }
+
+    /// An entity's relevance to a session (see [`Authority::compute_relevance_score`]).
+    fn compute_relevance_score(&self, _session: &Session, _entity_id: u64) -> f32 {
+        1.0
+    }
+
+    /// Another session's view for a spectator (see [`Authority::clone_session_state`]).
+    fn clone_session_state(
+        &self,
+        _source_session_id: u64,
+        _spectator_session_id: u64,
+    ) -> Option<Self::Snapshot> {
+        None
+    }
+
+    /// Diff against the session's last acked snapshot (see [`Authority::snapshot_delta_from`]).
+    fn snapshot_delta_from(
+        &self,
+        _session: &Session,
+        _base_seq: u64,
+        _base: &Self::Snapshot,
+    ) -> Option<Self::Snapshot> {
+        None
+    }
+
+    /// Event-sourced deltas (see [`Authority::snapshot_delta_from_events`]).
+    fn snapshot_delta_from_events(
+        &self,
+        _events: &[AuthorityEvent<Self::Intent, Self::Snapshot, Self::Passport>],
+    ) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Paginated queries (see [`Authority::query_page`]).
+    fn query_page(
+        &self,
+        _session: &Session,
+        _query: &serde_json::Value,
+        _cursor: Option<&str>,
+    ) -> Result<QueryPage, QueryError> {
+        Err(QueryError::Unsupported)
+    }
+
+    /// Generate a passport for transfer.
+    fn emit_passport(&self, session: &Session) -> Self::Passport;
+
+    /// Passport for a particular destination (see [`Authority::emit_passport_for_destination`]).
+    fn emit_passport_for_destination(
+        &self,
+        session: &Session,
+        _destination: &str,
+    ) -> Self::Passport {
+        self.emit_passport(session)
+    }
+
+    /// Patch on a passport the destination holds (see [`Authority::emit_incremental_passport`]).
+    fn emit_incremental_passport(
+        &self,
+        session: &Session,
+        previous_version: u32,
+        _previous: &Self::Passport,
+    ) -> PassportUpdate<Self::Passport> {
+        PassportUpdate::full(
+            self.emit_passport(session),
+            previous_version.saturating_add(1),
+        )
+    }
+
+    /// Generate the full transfer payload (see [`Authority::emit_transfer_snapshot`]).
+    fn emit_transfer_snapshot(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> TransferSnapshot<Self::Snapshot, Self::Passport> {
+        TransferSnapshot {
+            snapshot_context: self.snapshot(),
+            passport: self.emit_passport_for_destination(session, destination),
+        }
+    }
+
+    /// Generate passports for a party (see [`Authority::emit_party_passport`]).
+    fn emit_party_passport(&self, sessions: &[Session]) -> Vec<Self::Passport> {
+        sessions
+            .iter()
+            .map(|session| self.emit_passport(session))
+            .collect()
+    }
+
+    /// Check if a destination is valid.
+    fn validate_destination(&self, destination: &str) -> bool;
+
+    /// A transfer to this server (see [`Authority::on_transfer_loopback`]).
+    fn on_transfer_loopback(&mut self, _session: &Session, _destination: &str) -> LoopbackAction {
+        LoopbackAction::Reject
+    }
+
+    /// Move a session without reconnecting (see [`Authority::on_local_transfer`]).
+    fn on_local_transfer(
+        &mut self,
+        _session: &Session,
+        _destination: &str,
+    ) -> Option<Result<LocalTransferResult, TransferError<Self::Error>>> {
+        None
+    }
+
+    /// Estimate the encoded transfer size (see [`Authority::passport_size_estimate`]).
+    fn passport_size_estimate(&self, session: &Session) -> usize
+    where
+        Self::Snapshot: Serialize,
+        Self::Passport: Serialize,
+    {
+        let transfer = TransferSnapshot {
+            snapshot_context: self.snapshot(),
+            passport: self.emit_passport(session),
+        };
+        serde_json::to_vec(&transfer).map_or(0, |bytes| bytes.len())
+    }
+
+    /// Hash of the full snapshot (see [`Authority::authority_fingerprint`]).
+    fn authority_fingerprint(&self) -> u64
+    where
+        Self::Snapshot: Serialize,
+    {
+        crate::fingerprint(&self.snapshot())
+    }
+
+    /// Short label for an intent (see [`Authority::intent_type_name`]).
+    fn intent_type_name(_intent: &Self::Intent) -> &'static str {
+        std::any::type_name::<Self::Intent>()
+    }
+
+    /// An intent as JSON (see [`Authority::intent_to_json`]).
+    fn intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value
+    where
+        Self::Intent: Serialize,
+    {
+        serde_json::to_value(intent).unwrap_or_default()
+    }
+
+    /// An intent as something safe to log (see
+    /// [`Authority::redacted_intent_to_json`]).
+    fn redacted_intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value {
+        TypeNameOnly.redact(intent)
+    }
+
+    /// Who is present (see [`Authority::presence`]).
+    fn presence(&self) -> Option<Vec<Identity>> {
+        None
+    }
+
+    /// What a snapshot is about (see [`Authority::snapshot_topic`]).
+    fn snapshot_topic(_snapshot: &Self::Snapshot) -> Option<&'static str> {
+        None
+    }
+
+    /// Pre-flight check on a transfer's source (see [`Authority::can_accept_transfer_from`]).
+    fn can_accept_transfer_from(&self, _src_manifest: &Manifest) -> bool {
+        true
+    }
+
+    /// Decide what a session may do (see [`Authority::capabilities_for`]).
+    fn capabilities_for(&self, _session: &Session, default: Capabilities) -> Capabilities {
+        default
+    }
+
+    /// A session went over its snapshot budget (see [`Authority::on_budget_exceeded`]).
+    fn on_budget_exceeded(
+        &mut self,
+        _session: &Session,
+        _snapshot_size: usize,
+        _budget: &SnapshotBudget,
+    ) {
+    }
+
+    /// Summary of a batch of peer transfers (see [`Authority::on_peer_transfer_complete`]).
+    fn on_peer_transfer_complete(
+        &mut self,
+        _src_identity: &Identity,
+        _accepted: u64,
+        _rejected: u64,
+        _elapsed: Duration,
+    ) {
+    }
+
+    /// Decide what follows a failed intent (see [`Authority::on_authority_error`]).
+    fn on_authority_error(&self, _error: &Self::Error) -> AuthorityErrorAction {
+        AuthorityErrorAction::SendErrorAndContinue
+    }
+
+    /// A pause is over (see [`Authority::on_authority_recovered`]).
+    fn on_authority_recovered(&mut self, _previous_error: &Self::Error) {}
+
+    /// How a failed intent is reported (see [`Authority::error_code`]).
+    fn error_code(&self, _error: &Self::Error) -> ErrorCode {
+        ErrorCode::IntentError
+    }
+
+    /// A client message failed to decode (see [`Authority::on_wire_error`]).
+    fn on_wire_error(
+        &mut self,
+        _session: Option<&Session>,
+        _raw: &str,
+        _error: &serde_json::Error,
+    ) -> WireErrorAction {
+        WireErrorAction::Ignore
+    }
+
+    /// Consistency checks (see [`Authority::assert_invariants`]).
+    fn assert_invariants(&self) -> Result<(), InvariantViolation> {
+        Ok(())
+    }
+
+    /// Session export (see [`Authority::export_sessions`]).
+    fn export_sessions(&self) -> Vec<ExportedSession> {
+        Vec::new()
+    }
+
+    /// Session import (see [`Authority::import_sessions`]).
+    fn import_sessions(
+        &mut self,
+        sessions: Vec<ExportedSession>,
+    ) -> Result<(), ImportSessionError> {
+        if sessions.is_empty() {
+            Ok(())
+        } else {
+            Err(ImportSessionError::Unsupported)
+        }
+    }
+
+    /// Reconnect tokens (see [`Authority::generate_session_token`]).
+    fn generate_session_token(&self, session: &Session, signing_key: &[u8]) -> SessionToken {
+        SessionToken::sign(session, signing_key, SessionToken::DEFAULT_TTL)
+    }
+
+    /// Reconnect token checks (see [`Authority::verify_session_token`]).
+    fn verify_session_token(&self, token: &SessionToken, signing_key: &[u8]) -> Option<Session> {
+        token.verify(signing_key)
+    }
+
+    /// Snapshot type advertised in the manifest (see [`Authority::expected_snapshot_type`]).
+    fn expected_snapshot_type(&self) -> WireType {
+        WireType::of::<Self::Snapshot>()
+    }
+
+    /// Intent type advertised in the manifest (see [`Authority::expected_intent_type`]).
+    fn expected_intent_type(&self) -> WireType {
+        WireType::of::<Self::Intent>()
+    }
+}
+
+// Blanket implementation: SimpleAuthority -> Authority
+impl<T> Authority for T
+where
+    T: SimpleAuthority,
+{
+    type Intent = T::Intent;
+    type Snapshot = T::Snapshot;
+    type Passport = T::Passport;
+    type Error = T::Error;
+
+    fn validate_identity(&self, identity: &Identity) -> Result<(), IdentityError> {
+        SimpleAuthority::validate_identity(self, identity)
+    }
+
+    fn validate_connect(
+        &self,
+        identity: &Identity,
+        info: &ConnectInfo,
+    ) -> Result<(), IdentityError> {
+        SimpleAuthority::validate_connect(self, identity, info)
+    }
+
+    fn on_connect(&mut self, session: &Session) -> Result<(), Self::Error> {
+        SimpleAuthority::on_connect(self, session)
+    }
+
+    fn on_connect_with_info(
+        &mut self,
+        session: &Session,
+        info: &ConnectInfo,
+    ) -> Result<(), Self::Error> {
+        SimpleAuthority::on_connect_with_info(self, session, info)
+    }
+
+    fn on_transfer_in(
+        &mut self,
+        session: &Session,
+        passport: Self::Passport,
+    ) -> Result<ImportResult<Self::Passport>, Self::Error> {
+        SimpleAuthority::on_transfer_in(self, session, passport)
+    }
+
+    fn on_transfer_context(&mut self, session: &Session, context: Self::Snapshot) {
+        SimpleAuthority::on_transfer_context(self, session, context)
+    }
+
+    fn on_party_transfer_in(
+        &mut self,
+        sessions: &[Session],
+        passports: Vec<Self::Passport>,
+    ) -> Result<PartyImportResult<Self::Passport>, Self::Error> {
+        SimpleAuthority::on_party_transfer_in(self, sessions, passports)
+    }
+
+    fn on_passport_decode_error(
+        &mut self,
+        session: &Session,
+        raw: &[u8],
+        error: &serde_json::Error,
+    ) -> PassportDecodeAction {
+        SimpleAuthority::on_passport_decode_error(self, session, raw, error)
+    }
+
+    fn on_large_passport(&mut self, session: &Session, size: usize) -> LargePassportAction {
+        SimpleAuthority::on_large_passport(self, session, size)
+    }
+
+    fn validate_passport(
+        &self,
+        session: &Session,
+        passport: &Self::Passport,
+    ) -> Result<(), Vec<PassportValidationError>> {
+        SimpleAuthority::validate_passport(self, session, passport)
+    }
+
+    fn on_disconnect(&mut self, session: &Session) {
+        SimpleAuthority::on_disconnect(self, session)
+    }
+
+    fn on_disconnect_batch(&mut self, sessions: &[Session], reason: DisconnectReason) {
+        SimpleAuthority::on_disconnect_batch(self, sessions, reason)
+    }
+
+    fn on_session_paused(&mut self, session: &Session) {
+        SimpleAuthority::on_session_paused(self, session)
+    }
+
+    fn on_session_resumed(&mut self, session: &Session) {
+        SimpleAuthority::on_session_resumed(self, session)
+    }
+
+    fn handle_intent(
+        &mut self,
+        session: &Session,
+        intent: Self::Intent,
+    ) -> Result<(), Self::Error> {
+        SimpleAuthority::handle_intent(self, session, intent)
+    }
+
+    fn intent_priority(&self, session: &Session, intent: &Self::Intent) -> IntentPriority {
+        SimpleAuthority::intent_priority(self, session, intent)
+    }
+
+    fn intent_schema(&self) -> Option<serde_json::Value> {
+        SimpleAuthority::intent_schema(self)
+    }
+
+    fn handle_optimistic_intent(
+        &mut self,
+        session: &Session,
+        intent: Self::Intent,
+        base_seq: u64,
+        latest_seq: u64,
+    ) -> Result<OptimisticOutcome, Self::Error> {
+        SimpleAuthority::handle_optimistic_intent(self, session, intent, base_seq, latest_seq)
+    }
+
+    fn on_intent_ack(&mut self, session: &Session, intent_seq: u64) {
+        SimpleAuthority::on_intent_ack(self, session, intent_seq)
+    }
+
+    fn snapshot_for(&self, session: &Session) -> Self::Snapshot {
+        SimpleAuthority::snapshot_for(self, session)
+    }
+
+    fn coalesce_snapshots(&self, history: Vec<Self::Snapshot>) -> Option<Self::Snapshot> {
+        SimpleAuthority::coalesce_snapshots(self, history)
+    }
+
+    fn redact_snapshot(&self, session: &Session, snapshot: &mut Self::Snapshot) {
+        SimpleAuthority::redact_snapshot(self, session, snapshot)
+    }
+
+    fn translate_snapshot_for_version(
+        &self,
+        snapshot: Self::Snapshot,
+        client_version: &str,
+    ) -> Self::Snapshot {
+        SimpleAuthority::translate_snapshot_for_version(self, snapshot, client_version)
+    }
+
+    fn compute_relevance_score(&self, session: &Session, entity_id: u64) -> f32 {
+        SimpleAuthority::compute_relevance_score(self, session, entity_id)
+    }
+
+    fn clone_session_state(
+        &self,
+        source_session_id: u64,
+        spectator_session_id: u64,
+    ) -> Option<Self::Snapshot> {
+        SimpleAuthority::clone_session_state(self, source_session_id, spectator_session_id)
+    }
+
+    fn snapshot_delta_from(
+        &self,
+        session: &Session,
+        base_seq: u64,
+        base: &Self::Snapshot,
+    ) -> Option<Self::Snapshot> {
+        SimpleAuthority::snapshot_delta_from(self, session, base_seq, base)
+    }
+
+    fn snapshot_delta_from_events(
+        &self,
+        events: &[AuthorityEvent<Self::Intent, Self::Snapshot, Self::Passport>],
+    ) -> Option<Vec<u8>> {
+        SimpleAuthority::snapshot_delta_from_events(self, events)
+    }
+
+    fn query_page(
+        &self,
+        session: &Session,
+        query: &serde_json::Value,
+        cursor: Option<&str>,
+    ) -> Result<QueryPage, QueryError> {
+        SimpleAuthority::query_page(self, session, query, cursor)
+    }
+
+    fn emit_passport(&self, session: &Session) -> Self::Passport {
+        SimpleAuthority::emit_passport(self, session)
+    }
+
+    fn emit_passport_for_destination(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> Self::Passport {
+        SimpleAuthority::emit_passport_for_destination(self, session, destination)
+    }
+
+    fn emit_incremental_passport(
+        &self,
+        session: &Session,
+        previous_version: u32,
+        previous: &Self::Passport,
+    ) -> PassportUpdate<Self::Passport> {
+        SimpleAuthority::emit_incremental_passport(self, session, previous_version, previous)
+    }
+
+    fn emit_transfer_snapshot(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> TransferSnapshot<Self::Snapshot, Self::Passport> {
+        SimpleAuthority::emit_transfer_snapshot(self, session, destination)
+    }
+
+    fn emit_party_passport(&self, sessions: &[Session]) -> Vec<Self::Passport> {
+        SimpleAuthority::emit_party_passport(self, sessions)
+    }
+
+    fn validate_destination(&self, destination: &str) -> bool {
+        SimpleAuthority::validate_destination(self, destination)
+    }
+
+    fn on_transfer_loopback(&mut self, session: &Session, destination: &str) -> LoopbackAction {
+        SimpleAuthority::on_transfer_loopback(self, session, destination)
+    }
+
+    fn on_local_transfer(
+        &mut self,
+        session: &Session,
+        destination: &str,
+    ) -> Option<Result<LocalTransferResult, TransferError<Self::Error>>> {
+        SimpleAuthority::on_local_transfer(self, session, destination)
+    }
+
+    fn passport_size_estimate(&self, session: &Session) -> usize
+    where
+        Self::Snapshot: Serialize,
+        Self::Passport: Serialize,
+    {
+        SimpleAuthority::passport_size_estimate(self, session)
+    }
+
+    fn authority_fingerprint(&self) -> u64
+    where
+        Self::Snapshot: Serialize,
+    {
+        SimpleAuthority::authority_fingerprint(self)
+    }
+
+    fn intent_type_name(intent: &Self::Intent) -> &'static str {
+        <T as SimpleAuthority>::intent_type_name(intent)
+    }
+
+    fn presence(&self) -> Option<Vec<Identity>> {
+        SimpleAuthority::presence(self)
+    }
+
+    fn snapshot_topic(snapshot: &Self::Snapshot) -> Option<&'static str> {
+        <T as SimpleAuthority>::snapshot_topic(snapshot)
+    }
+
+    fn intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value
+    where
+        Self::Intent: Serialize,
+    {
+        SimpleAuthority::intent_to_json(self, intent)
+    }
+
+    fn redacted_intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value {
+        SimpleAuthority::redacted_intent_to_json(self, intent)
+    }
+
+    fn can_accept_transfer_from(&self, src_manifest: &Manifest) -> bool {
+        SimpleAuthority::can_accept_transfer_from(self, src_manifest)
+    }
+
+    fn capabilities_for(&self, session: &Session, default: Capabilities) -> Capabilities {
+        SimpleAuthority::capabilities_for(self, session, default)
+    }
+
+    fn on_budget_exceeded(
+        &mut self,
+        session: &Session,
+        snapshot_size: usize,
+        budget: &SnapshotBudget,
+    ) {
+        SimpleAuthority::on_budget_exceeded(self, session, snapshot_size, budget)
+    }
+
+    fn on_peer_transfer_complete(
+        &mut self,
+        src_identity: &Identity,
+        accepted: u64,
+        rejected: u64,
+        elapsed: Duration,
+    ) {
+        SimpleAuthority::on_peer_transfer_complete(self, src_identity, accepted, rejected, elapsed)
+    }
+
+    fn on_authority_error(&self, error: &Self::Error) -> AuthorityErrorAction {
+        SimpleAuthority::on_authority_error(self, error)
+    }
+
+    fn on_authority_recovered(&mut self, previous_error: &Self::Error) {
+        SimpleAuthority::on_authority_recovered(self, previous_error)
+    }
+
+    fn error_code(&self, error: &Self::Error) -> ErrorCode {
+        SimpleAuthority::error_code(self, error)
+    }
+
+    fn on_wire_error(
+        &mut self,
+        session: Option<&Session>,
+        raw: &str,
+        error: &serde_json::Error,
+    ) -> WireErrorAction {
+        SimpleAuthority::on_wire_error(self, session, raw, error)
+    }
+
+    fn assert_invariants(&self) -> Result<(), InvariantViolation> {
+        SimpleAuthority::assert_invariants(self)
+    }
+
+    fn export_sessions(&self) -> Vec<ExportedSession> {
+        SimpleAuthority::export_sessions(self)
+    }
+
+    fn import_sessions(
+        &mut self,
+        sessions: Vec<ExportedSession>,
+    ) -> Result<(), ImportSessionError> {
+        SimpleAuthority::import_sessions(self, sessions)
+    }
+
+    fn generate_session_token(&self, session: &Session, signing_key: &[u8]) -> SessionToken {
+        SimpleAuthority::generate_session_token(self, session, signing_key)
+    }
+
+    fn verify_session_token(&self, token: &SessionToken, signing_key: &[u8]) -> Option<Session> {
+        SimpleAuthority::verify_session_token(self, token, signing_key)
+    }
+
+    fn expected_snapshot_type(&self) -> WireType {
+        SimpleAuthority::expected_snapshot_type(self)
+    }
+
+    fn expected_intent_type(&self) -> WireType {
+        SimpleAuthority::expected_intent_type(self)
+    }
+}
+
+/// Why a session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisconnectReason {
+    /// The client closed the connection.
+    Closed,
+    /// The session transferred to another server.
+    Transferred,
+    /// The reconnect grace window elapsed without a resume.
+    GraceExpired,
+    /// The connection failed.
+    Error,
+    /// The server closed the room, e.g. to shut down.
+    RoomClosed,
+    /// The client didn't take its initial state in time, e.g. it stopped
+    /// reading (see [`Session::time_in_state`]).
+    SyncTimeout,
+}
+
+/// What happened in a [`ConnectionEvent`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEventKind {
+    /// A fresh session connected.
+    Connected,
+    /// A session ended.
+    Disconnected { reason: DisconnectReason },
+    /// A session arrived from another server.
+    TransferredIn { from: String },
+    /// A session left for another server.
+    TransferredOut { to: String },
+}
+
+/// An entry in the connection audit log.
+#[derive(Debug, Clone)]
+pub struct ConnectionEvent {
+    /// The session concerned.
+    pub session_id: u64,
+    /// Hash of the session's identity (the log doesn't keep identities).
+    pub identity_hash: String,
+    /// What happened.
+    pub event: ConnectionEventKind,
+    /// When it happened.
+    pub timestamp: Instant,
+}
+
+/// Rolling buffer of connection events.
+#[derive(Debug, Clone)]
+pub struct ConnectionEventLog {
+    capacity: usize,
+    entries: VecDeque<ConnectionEvent>,
+}
+
+impl ConnectionEventLog {
+    /// Keep the most recent `capacity` events.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Record an event for a session, dropping the oldest when full.
+    pub fn record(&mut self, session: &Session, event: ConnectionEventKind) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(ConnectionEvent {
+            session_id: session.id,
+            identity_hash: identity_hash(&session.identity),
+            event,
+            timestamp: Instant::now(),
+        });
+    }
+
+    /// Events at or after `since`, oldest first.
+    pub fn events_since(&self, since: Instant) -> impl Iterator<Item = &ConnectionEvent> {
+        self.entries.iter().filter(move |e| e.timestamp >= since)
+    }
+
+    /// Number of events held.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the log is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn identity_hash(identity: &Identity) -> String {
+    let mut hasher = DefaultHasher::new();
+    identity.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Wraps an authority and keeps a [`ConnectionEventLog`] of its sessions.
+///
+/// Connects, disconnects, and transfers-in are recorded as the transport
+/// calls the usual hooks. The hooks don't say where a passport came from
+/// or why a session ended, so the transport supplies those beforehand with
+/// [`set_transfer_source`](Self::set_transfer_source) and
+/// [`set_disconnect_reason`](Self::set_disconnect_reason). Transfers out
+/// are recorded with [`record_transfer_out`](Self::record_transfer_out).
+pub struct RecordingAuthority<A> {
+    inner: A,
+    log: ConnectionEventLog,
+    transfer_sources: HashMap<u64, String>,
+    disconnect_reasons: HashMap<u64, DisconnectReason>,
+}
+
+impl<A: Authority> RecordingAuthority<A> {
+    /// Wrap `inner`, keeping the last `capacity` events.
+    pub fn new(inner: A, capacity: usize) -> Self {
+        Self {
+            inner,
+            log: ConnectionEventLog::new(capacity),
+            transfer_sources: HashMap::new(),
+            disconnect_reasons: HashMap::new(),
+        }
+    }
+
+    /// The wrapped authority.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The wrapped authority, mutably.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// The event log.
+    pub fn log(&self) -> &ConnectionEventLog {
+        &self.log
+    }
+
+    /// Events at or after `since`, oldest first.
+    pub fn list_connection_events(&self, since: Instant) -> Vec<ConnectionEvent> {
+        self.log.events_since(since).cloned().collect()
+    }
+
+    /// Where the next `on_transfer_in` for this session came from.
+    pub fn set_transfer_source(&mut self, session_id: u64, from: impl Into<String>) {
+        self.transfer_sources.insert(session_id, from.into());
+    }
+
+    /// Why the next `on_disconnect` for this session happens.
+    /// Unmarked disconnects are recorded as [`DisconnectReason::Closed`].
+    pub fn set_disconnect_reason(&mut self, session_id: u64, reason: DisconnectReason) {
+        self.disconnect_reasons.insert(session_id, reason);
+    }
+
+    /// Record a session leaving for another server.
+    pub fn record_transfer_out(&mut self, session: &Session, to: impl Into<String>) {
+        let to = to.into();
+        self.log
+            .record(session, ConnectionEventKind::TransferredOut { to });
+    }
+}
+
+impl<A: Authority> Authority for RecordingAuthority<A> {
+    type Intent = A::Intent;
+    type Snapshot = A::Snapshot;
+    type Passport = A::Passport;
+    type Error = A::Error;
+
+    fn validate_identity(&self, identity: &Identity) -> Result<(), IdentityError> {
+        self.inner.validate_identity(identity)
+    }
+
+    fn validate_connect(
+        &self,
+        identity: &Identity,
+        info: &ConnectInfo,
+    ) -> Result<(), IdentityError> {
+        self.inner.validate_connect(identity, info)
+    }
+
+    fn on_connect(&mut self, session: &Session) -> Result<(), Self::Error> {
+        self.inner.on_connect(session)?;
+        self.log.record(session, ConnectionEventKind::Connected);
+        Ok(())
+    }
+
+    fn on_connect_with_info(
+        &mut self,
+        session: &Session,
+        info: &ConnectInfo,
+    ) -> Result<(), Self::Error> {
+        self.inner.on_connect_with_info(session, info)?;
+        self.log.record(session, ConnectionEventKind::Connected);
+        Ok(())
+    }
+
+    fn on_transfer_in(
+        &mut self,
+        session: &Session,
+        passport: Self::Passport,
+    ) -> Result<ImportResult<Self::Passport>, Self::Error> {
+        let from = self
+            .transfer_sources
+            .remove(&session.id)
+            .unwrap_or_default();
+        let result = self.inner.on_transfer_in(session, passport)?;
+        self.log
+            .record(session, ConnectionEventKind::TransferredIn { from });
+        Ok(result)
+    }
+
+    fn on_transfer_context(&mut self, session: &Session, context: Self::Snapshot) {
+        self.inner.on_transfer_context(session, context)
+    }
+
+    fn on_party_transfer_in(
+        &mut self,
+        sessions: &[Session],
+        passports: Vec<Self::Passport>,
+    ) -> Result<PartyImportResult<Self::Passport>, Self::Error> {
+        let sources: HashMap<u64, String> = sessions
+            .iter()
+            .filter_map(|s| Some((s.id, self.transfer_sources.remove(&s.id)?)))
+            .collect();
+        let result = self.inner.on_party_transfer_in(sessions, passports)?;
+        for session in sessions {
+            if result.accepted.iter().any(|(id, _)| *id == session.id) {
+                let from = sources.get(&session.id).cloned().unwrap_or_default();
+                self.log
+                    .record(session, ConnectionEventKind::TransferredIn { from });
+            }
+        }
+        Ok(result)
+    }
+
+    fn on_passport_decode_error(
+        &mut self,
+        session: &Session,
+        raw: &[u8],
+        error: &serde_json::Error,
+    ) -> PassportDecodeAction {
+        self.inner.on_passport_decode_error(session, raw, error)
+    }
+
+    fn on_large_passport(&mut self, session: &Session, size: usize) -> LargePassportAction {
+        self.inner.on_large_passport(session, size)
+    }
+
+    fn validate_passport(
+        &self,
+        session: &Session,
+        passport: &Self::Passport,
+    ) -> Result<(), Vec<PassportValidationError>> {
+        self.inner.validate_passport(session, passport)
+    }
+
+    fn on_disconnect(&mut self, session: &Session) {
+        let reason = self
+            .disconnect_reasons
+            .remove(&session.id)
+            .unwrap_or(DisconnectReason::Closed);
+        self.inner.on_disconnect(session);
+        self.log
+            .record(session, ConnectionEventKind::Disconnected { reason });
+    }
+
+    fn on_disconnect_batch(&mut self, sessions: &[Session], reason: DisconnectReason) {
+        self.inner.on_disconnect_batch(sessions, reason);
+        for session in sessions {
+            self.disconnect_reasons.remove(&session.id);
+            self.log
+                .record(session, ConnectionEventKind::Disconnected { reason });
+        }
+    }
+
+    fn on_session_paused(&mut self, session: &Session) {
+        self.inner.on_session_paused(session)
+    }
+
+    fn on_session_resumed(&mut self, session: &Session) {
+        self.inner.on_session_resumed(session)
+    }
+
+    fn handle_intent(
+        &mut self,
+        session: &Session,
+        intent: Self::Intent,
+    ) -> Result<(), Self::Error> {
+        self.inner.handle_intent(session, intent)
+    }
+
+    fn intent_priority(&self, session: &Session, intent: &Self::Intent) -> IntentPriority {
+        self.inner.intent_priority(session, intent)
+    }
+
+    fn intent_schema(&self) -> Option<serde_json::Value> {
+        self.inner.intent_schema()
+    }
+
+    fn handle_optimistic_intent(
+        &mut self,
+        session: &Session,
+        intent: Self::Intent,
+        base_seq: u64,
+        latest_seq: u64,
+    ) -> Result<OptimisticOutcome, Self::Error> {
+        self.inner
+            .handle_optimistic_intent(session, intent, base_seq, latest_seq)
+    }
+
+    fn on_intent_ack(&mut self, session: &Session, intent_seq: u64) {
+        self.inner.on_intent_ack(session, intent_seq)
+    }
+
+    fn snapshot_for(&self, session: &Session) -> Self::Snapshot {
+        self.inner.snapshot_for(session)
+    }
+
+    fn coalesce_snapshots(&self, history: Vec<Self::Snapshot>) -> Option<Self::Snapshot> {
+        self.inner.coalesce_snapshots(history)
+    }
+
+    fn redact_snapshot(&self, session: &Session, snapshot: &mut Self::Snapshot) {
+        self.inner.redact_snapshot(session, snapshot)
+    }
+
+    fn translate_snapshot_for_version(
+        &self,
+        snapshot: Self::Snapshot,
+        client_version: &str,
+    ) -> Self::Snapshot {
+        self.inner
+            .translate_snapshot_for_version(snapshot, client_version)
+    }
+
+    fn compute_relevance_score(&self, session: &Session, entity_id: u64) -> f32 {
+        self.inner.compute_relevance_score(session, entity_id)
+    }
+
+    fn clone_session_state(
+        &self,
+        source_session_id: u64,
+        spectator_session_id: u64,
+    ) -> Option<Self::Snapshot> {
+        self.inner
+            .clone_session_state(source_session_id, spectator_session_id)
+    }
+
+    fn snapshot_delta_from(
+        &self,
+        session: &Session,
+        base_seq: u64,
+        base: &Self::Snapshot,
+    ) -> Option<Self::Snapshot> {
+        self.inner.snapshot_delta_from(session, base_seq, base)
+    }
+
+    fn snapshot_delta_from_events(
+        &self,
+        events: &[AuthorityEvent<Self::Intent, Self::Snapshot, Self::Passport>],
+    ) -> Option<Vec<u8>> {
+        self.inner.snapshot_delta_from_events(events)
+    }
+
+    fn query_page(
+        &self,
+        session: &Session,
+        query: &serde_json::Value,
+        cursor: Option<&str>,
+    ) -> Result<QueryPage, QueryError> {
+        self.inner.query_page(session, query, cursor)
+    }
+
+    fn emit_passport(&self, session: &Session) -> Self::Passport {
+        self.inner.emit_passport(session)
+    }
+
+    fn emit_passport_for_destination(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> Self::Passport {
+        self.inner
+            .emit_passport_for_destination(session, destination)
+    }
+
+    fn emit_incremental_passport(
+        &self,
+        session: &Session,
+        previous_version: u32,
+        previous: &Self::Passport,
+    ) -> PassportUpdate<Self::Passport> {
+        self.inner
+            .emit_incremental_passport(session, previous_version, previous)
+    }
+
+    fn emit_transfer_snapshot(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> TransferSnapshot<Self::Snapshot, Self::Passport> {
+        self.inner.emit_transfer_snapshot(session, destination)
+    }
+
+    fn emit_party_passport(&self, sessions: &[Session]) -> Vec<Self::Passport> {
+        self.inner.emit_party_passport(sessions)
+    }
+
+    fn validate_destination(&self, destination: &str) -> bool {
+        self.inner.validate_destination(destination)
+    }
+
+    fn on_transfer_loopback(&mut self, session: &Session, destination: &str) -> LoopbackAction {
+        self.inner.on_transfer_loopback(session, destination)
+    }
+
+    fn on_local_transfer(
+        &mut self,
+        session: &Session,
+        destination: &str,
+    ) -> Option<Result<LocalTransferResult, TransferError<Self::Error>>> {
+        self.inner.on_local_transfer(session, destination)
+    }
+
+    fn passport_size_estimate(&self, session: &Session) -> usize
+    where
+        Self::Snapshot: Serialize,
+        Self::Passport: Serialize,
+    {
+        self.inner.passport_size_estimate(session)
+    }
+
+    fn authority_fingerprint(&self) -> u64
+    where
+        Self::Snapshot: Serialize,
+    {
+        self.inner.authority_fingerprint()
+    }
+
+    fn intent_type_name(intent: &Self::Intent) -> &'static str {
+        A::intent_type_name(intent)
+    }
+
+    fn presence(&self) -> Option<Vec<Identity>> {
+        self.inner.presence()
+    }
+
+    fn snapshot_topic(snapshot: &Self::Snapshot) -> Option<&'static str> {
+        A::snapshot_topic(snapshot)
+    }
+
+    fn intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value
+    where
+        Self::Intent: Serialize,
+    {
+        self.inner.intent_to_json(intent)
+    }
+
+    fn redacted_intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value {
+        self.inner.redacted_intent_to_json(intent)
+    }
+
+    fn can_accept_transfer_from(&self, src_manifest: &Manifest) -> bool {
+        self.inner.can_accept_transfer_from(src_manifest)
+    }
+
+    fn capabilities_for(&self, session: &Session, default: Capabilities) -> Capabilities {
+        self.inner.capabilities_for(session, default)
+    }
+
+    fn on_budget_exceeded(
+        &mut self,
+        session: &Session,
+        snapshot_size: usize,
+        budget: &SnapshotBudget,
+    ) {
+        self.inner
+            .on_budget_exceeded(session, snapshot_size, budget)
+    }
+
+    fn on_peer_transfer_complete(
+        &mut self,
+        src_identity: &Identity,
+        accepted: u64,
+        rejected: u64,
+        elapsed: Duration,
+    ) {
+        self.inner
+            .on_peer_transfer_complete(src_identity, accepted, rejected, elapsed)
+    }
+
+    fn on_authority_error(&self, error: &Self::Error) -> AuthorityErrorAction {
+        self.inner.on_authority_error(error)
+    }
+
+    fn on_authority_recovered(&mut self, previous_error: &Self::Error) {
+        self.inner.on_authority_recovered(previous_error)
+    }
+
+    fn error_code(&self, error: &Self::Error) -> ErrorCode {
+        self.inner.error_code(error)
+    }
+
+    fn on_wire_error(
+        &mut self,
+        session: Option<&Session>,
+        raw: &str,
+        error: &serde_json::Error,
+    ) -> WireErrorAction {
+        self.inner.on_wire_error(session, raw, error)
+    }
+
+    fn assert_invariants(&self) -> Result<(), InvariantViolation> {
+        self.inner.assert_invariants()
+    }
+
+    fn export_sessions(&self) -> Vec<ExportedSession> {
+        self.inner.export_sessions()
+    }
+
+    fn import_sessions(
+        &mut self,
+        sessions: Vec<ExportedSession>,
+    ) -> Result<(), ImportSessionError> {
+        self.inner.import_sessions(sessions)
+    }
+
+    fn generate_session_token(&self, session: &Session, signing_key: &[u8]) -> SessionToken {
+        self.inner.generate_session_token(session, signing_key)
+    }
+
+    fn verify_session_token(&self, token: &SessionToken, signing_key: &[u8]) -> Option<Session> {
+        self.inner.verify_session_token(token, signing_key)
+    }
+
+    fn expected_snapshot_type(&self) -> WireType {
+        self.inner.expected_snapshot_type()
+    }
+
+    fn expected_intent_type(&self) -> WireType {
+        self.inner.expected_intent_type()
+    }
+}
+
+/// A wire type as the manifest advertises it: a name, and a schema version
+/// to bump when its encoding changes under the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WireType {
+    pub name: &'static str,
+    pub version: u32,
+}
+
+impl WireType {
+    /// `T` by its [`type_name`](std::any::type_name), at version 0.
+    ///
+    /// Type names can change between compiler versions; where client and
+    /// server are built apart, name the type with [`new`](Self::new).
+    pub fn of<T: ?Sized>() -> Self {
+        Self::new(std::any::type_name::<T>(), 0)
+    }
+
+    pub const fn new(name: &'static str, version: u32) -> Self {
+        Self { name, version }
+    }
+}
+
+/// Hex SHA-256 of a wire type's name and version, for the manifest.
+///
+/// The same on any build and toolchain that names the type the same.
+pub fn type_hash(ty: WireType) -> String {
+    let digest = sha2::Sha256::new()
+        .chain_update(ty.name)
+        .chain_update([0])
+        .chain_update(ty.version.to_le_bytes())
+        .finalize();
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Add, Refused, Tallies, TestPassport, TestRoom};
+
+    fn session() -> Session {
+        Session::new(1, Identity::local("alice"), "alice".into())
+    }
+
+    #[test]
+    fn entering_a_state_restarts_its_clock() {
+        let mut session = session();
+        assert_eq!(session.state, ConnectionState::Syncing);
+        std::thread::sleep(Duration::from_millis(5));
+        let syncing = session.time_in_state();
+        assert!(syncing >= Duration::from_millis(5));
+
+        session.enter_state(ConnectionState::Live);
+        assert_eq!(session.state, ConnectionState::Live);
+        assert!(session.time_in_state() < syncing);
+    }
+
+    #[test]
+    fn passport_size_estimate_measures_json() {
+        let mut room = TestRoom::new();
+        let empty = Authority::passport_size_estimate(&room, &session());
+        let transfer = Authority::emit_transfer_snapshot(&room, &session(), "ws://elsewhere");
+        let expected = serde_json::to_vec(&transfer).unwrap();
+        assert_eq!(empty, expected.len());
+
+        room.items.push("x".repeat(1000));
+        assert!(Authority::passport_size_estimate(&room, &session()) > empty + 1000);
+    }
+
+    #[test]
+    fn transfer_snapshot_pairs_view_and_passport() {
+        let mut room = TestRoom::new();
+        room.items.push("sword".into());
+        Authority::handle_intent(&mut room, &session(), Add { by: 2 }).unwrap();
+        let ts = Authority::emit_transfer_snapshot(&room, &session(), "ws://elsewhere");
+        let (context, passport) = crate::split_transfer_snapshot(ts);
+        assert_eq!(context, vec![(1, 2)]);
+        assert_eq!(passport.name, "alice");
+        assert_eq!(passport.items, ["sword"]);
+    }
+
+    #[test]
+    fn destination_passport_defaults_to_plain_passport() {
+        let mut room = TestRoom::new();
+        room.items.push("sword".into());
+        let plain = Authority::emit_passport(&room, &session());
+        let to = Authority::emit_passport_for_destination(&room, &session(), "ws://free.example");
+        assert_eq!(to.name, plain.name);
+        assert_eq!(to.items, plain.items);
+    }
+
+    #[test]
+    fn intent_type_name_defaults_to_type_name() {
+        let name = <TestRoom as Authority>::intent_type_name(&Add { by: 1 });
+        assert_eq!(name, std::any::type_name::<Add>());
+    }
+
+    #[test]
+    fn redacted_intents_default_to_the_type_name() {
+        let room = TestRoom::new();
+        let intent = Add { by: 1 };
+        assert_eq!(
+            Authority::intent_to_json(&room, &intent),
+            serde_json::json!({ "by": 1 })
+        );
+        assert_eq!(
+            Authority::redacted_intent_to_json(&room, &intent),
+            serde_json::json!(std::any::type_name::<Add>())
+        );
+    }
+
+    #[test]
+    fn import_result_builder_collects_outcomes() {
+        let result = ImportResult::builder(())
+            .reject("sword", "not allowed here")
+            .reject_code("shield", "too_heavy", "weighs too much")
+            .transform("name", "a-very-long-name", "a-very")
+            .when(false, |b| b.reject("bow", "skipped"))
+            .build();
+        assert!(!result.is_clean());
+        assert_eq!(result.rejected.len(), 2);
+        assert_eq!(result.rejected[0].code, None);
+        assert_eq!(result.rejected[1].code.as_deref(), Some("too_heavy"));
+        assert_eq!(result.transformed[0].to, "a-very");
+        assert!(ImportResult::accept(()).is_clean());
+    }
+
+    #[test]
+    fn transfers_accepted_from_any_source_by_default() {
+        let room = TestRoom::new();
+        let source = Manifest {
+            identity: Identity::local("elsewhere"),
+            name: "elsewhere".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        };
+        assert!(Authority::can_accept_transfer_from(&room, &source));
+    }
+
+    #[test]
+    fn redaction_defaults_to_no_op() {
+        let mut room = TestRoom::new();
+        Authority::handle_intent(&mut room, &session(), Add { by: 2 }).unwrap();
+        let mut snapshot = Authority::snapshot_for(&room, &session());
+        Authority::redact_snapshot(&room, &session(), &mut snapshot);
+        assert_eq!(snapshot, room.tallies());
+    }
+
+    #[test]
+    fn coalescing_keeps_the_last_snapshot_by_default() {
+        let room = TestRoom::new();
+        let history = vec![vec![(1, 1)], vec![(1, 1), (2, 1)]];
+        assert_eq!(
+            Authority::coalesce_snapshots(&room, history),
+            Some(vec![(1, 1), (2, 1)])
+        );
+        assert_eq!(Authority::coalesce_snapshots(&room, Vec::new()), None);
+    }
+
+    #[test]
+    fn version_translation_defaults_to_no_op() {
+        let room = RecordingAuthority::new(TestRoom::new(), 1);
+        let snapshot = room.translate_snapshot_for_version(vec![(1, 2)], "1.0.0");
+        assert_eq!(snapshot, vec![(1, 2)]);
+    }
+
+    #[test]
+    fn recording_authority_logs_lifecycle() {
+        let mut room = RecordingAuthority::new(TestRoom::new(), 2);
+        let start = Instant::now();
+        let alice = session();
+        room.on_connect(&alice).unwrap();
+        room.set_disconnect_reason(alice.id, DisconnectReason::Transferred);
+        room.record_transfer_out(&alice, "ws://b");
+        room.on_disconnect(&alice);
+
+        // Capacity 2: the connect has rolled off.
+        let events: Vec<_> = room
+            .list_connection_events(start)
+            .into_iter()
+            .map(|e| e.event)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ConnectionEventKind::TransferredOut {
+                    to: "ws://b".into()
+                },
+                ConnectionEventKind::Disconnected {
+                    reason: DisconnectReason::Transferred
+                },
+            ]
+        );
+        assert_eq!(room.log().events_since(Instant::now()).count(), 0);
+    }
+
+    #[test]
+    fn batch_disconnects_record_the_shared_reason() {
+        let mut room = RecordingAuthority::new(TestRoom::new(), 8);
+        let start = Instant::now();
+        let sessions: Vec<_> = ["alice", "bob"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| Session::new(i as u64 + 1, Identity::local(name), name.into()))
+            .collect();
+        room.set_disconnect_reason(1, DisconnectReason::Transferred);
+        room.on_disconnect_batch(&sessions, DisconnectReason::RoomClosed);
+
+        let reasons: Vec<_> = room
+            .list_connection_events(start)
+            .into_iter()
+            .map(|e| (e.session_id, e.event))
+            .collect();
+        let closed = ConnectionEventKind::Disconnected {
+            reason: DisconnectReason::RoomClosed,
+        };
+        assert_eq!(reasons, [(1, closed.clone()), (2, closed)]);
+    }
+
+    #[test]
+    fn party_imports_report_refused_members() {
+        let room = TestRoom {
+            refused: vec!["bob".into()],
+            ..TestRoom::new()
+        };
+        let mut room = RecordingAuthority::new(room, 8);
+        let start = Instant::now();
+        let party: Vec<_> = ["alice", "bob", "carol"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| Session::new(i as u64 + 1, Identity::local(name), name.into()))
+            .collect();
+        let passports = Authority::emit_party_passport(&room, &party);
+        assert_eq!(passports[1].name, "bob");
+        for session in &party {
+            room.set_transfer_source(session.id, "ws://a");
+        }
+
+        let result = room.on_party_transfer_in(&party, passports).unwrap();
+        assert!(result.is_partial());
+        let accepted: Vec<_> = result.accepted.iter().map(|(id, _)| *id).collect();
+        assert_eq!(accepted, [1, 3]);
+        assert_eq!(result.refused[0].0, 2);
+        assert_eq!(result.refused[0].1.item, "bob");
+
+        // Only members that arrived are logged as transferred in.
+        let arrived = room
+            .list_connection_events(start)
+            .into_iter()
+            .filter(|e| matches!(e.event, ConnectionEventKind::TransferredIn { .. }))
+            .count();
+        assert_eq!(arrived, 2);
+
+        let clean = Authority::emit_party_passport(&room, &party[..1]);
+        let result = room.on_party_transfer_in(&party[..1], clean).unwrap();
+        assert!(result.is_complete() && !result.is_partial());
+    }
+
+    #[test]
+    fn authority_errors_reported_and_continued_by_default() {
+        let room = RecordingAuthority::new(TestRoom::new(), 1);
+        assert_eq!(
+            room.on_authority_error(&Refused),
+            AuthorityErrorAction::SendErrorAndContinue
+        );
+        assert_eq!(
+            AuthorityErrorAction::default(),
+            AuthorityErrorAction::SendErrorAndContinue
+        );
+    }
+
+    #[test]
+    fn wire_errors_ignored_by_default() {
+        let mut room = TestRoom::new();
+        let error = serde_json::from_str::<String>("{").unwrap_err();
+        let action = Authority::on_wire_error(&mut room, Some(&session()), "{", &error);
+        assert_eq!(action, WireErrorAction::Ignore);
+    }
+
+    #[test]
+    fn corrupt_passports_rejected_by_default() {
+        let mut room = TestRoom::new();
+        let raw = b"{\"name\":";
+        let error = serde_json::from_slice::<TestPassport>(raw).unwrap_err();
+        let action = Authority::on_passport_decode_error(&mut room, &session(), raw, &error);
+        assert_eq!(action, PassportDecodeAction::Reject);
+    }
+
+    #[test]
+    fn large_passports_rejected_by_default() {
+        let mut room = TestRoom::new();
+        let action = Authority::on_large_passport(&mut room, &session(), 100_000);
+        assert!(matches!(action, LargePassportAction::Reject { .. }));
+    }
+
+    #[test]
+    fn connect_info_falls_back_to_plain_connect() {
+        let info = ConnectInfo::new().with_hint("X-Client-Version", "2.3.0");
+        assert_eq!(info.get("x-client-version"), Some("2.3.0"));
+
+        let mut room = RecordingAuthority::new(TestRoom::new(), 4);
+        room.validate_connect(&session().identity, &info).unwrap();
+        room.on_connect_with_info(&session(), &info).unwrap();
+        assert_eq!(room.log().len(), 1);
+    }
+
+    #[test]
+    fn loopback_transfers_rejected_by_default() {
+        let mut room = RecordingAuthority::new(TestRoom::new(), 1);
+        let action = room.on_transfer_loopback(&session(), "ws://localhost:8001");
+        assert_eq!(action, LoopbackAction::Reject);
+    }
+
+    #[test]
+    fn invariants_hold_by_default() {
+        let room = RecordingAuthority::new(TestRoom::new(), 4);
+        assert_eq!(room.assert_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn sessions_and_queries_unsupported_by_default() {
+        let mut room = TestRoom::new();
+        assert!(Authority::export_sessions(&room).is_empty());
+        assert_eq!(
+            Authority::query_page(&room, &session(), &serde_json::Value::Null, None),
+            Err(QueryError::Unsupported)
+        );
+        assert_eq!(Authority::import_sessions(&mut room, Vec::new()), Ok(()));
+        let exported = ExportedSession {
+            session: session(),
+            authority_state: Vec::new(),
+        };
+        assert_eq!(
+            Authority::import_sessions(&mut room, vec![exported]),
+            Err(ImportSessionError::Unsupported)
+        );
+    }
+
+    #[test]
+    fn session_tokens_verify_only_with_their_key() {
+        let room = TestRoom::new();
+        let token = Authority::generate_session_token(&room, &session(), b"key");
+        let restored = Authority::verify_session_token(&room, &token, b"key").unwrap();
+        assert_eq!(restored.id, session().id);
+        assert_eq!(restored.identity, session().identity);
+        assert!(Authority::verify_session_token(&room, &token, b"other").is_none());
+
+        let mut tampered = token.clone();
+        tampered.expires_at += 1;
+        assert!(tampered.verify(b"key").is_none());
+
+        let expired = SessionToken::sign(&session(), b"key", Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(expired.verify(b"key").is_none());
+    }
+
+    #[test]
+    fn wire_types_default_to_associated_types() {
+        let room = TestRoom::new();
+        let snapshot = Authority::expected_snapshot_type(&room);
+        assert_eq!(snapshot, WireType::of::<Tallies>());
+        assert_eq!(type_hash(snapshot), type_hash(WireType::of::<Tallies>()));
+        assert_ne!(
+            type_hash(snapshot),
+            type_hash(Authority::expected_intent_type(&room))
+        );
+        // A new version of the same type is another type on the wire
+        let bumped = WireType::new(snapshot.name, 1);
+        assert_ne!(type_hash(snapshot), type_hash(bumped));
+        // Pinned: clients built elsewhere must get the same hash
+        assert_eq!(
+            type_hash(WireType::new("counter::Tallies", 0)),
+            "7a65d030cca0741890a9db5440a74f6861845b879eaefd3fb668de3b7983db4e"
+        );
     }
 }
@@ -0,0 +1,30 @@
+//! Per-session snapshot throughput limits.
+
+/// How many snapshot bytes a session may receive over time.
+///
+/// A token bucket: `burst_bytes` can go out at once, refilled at
+/// `bytes_per_second`. The transport enforces it; the authority hears about
+/// overruns through `on_budget_exceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotBudget {
+    /// Sustained rate, in bytes per second.
+    pub bytes_per_second: u64,
+    /// Bucket size: bytes that may be sent in one burst.
+    pub burst_bytes: u64,
+}
+
+impl SnapshotBudget {
+    /// A budget that never runs out.
+    pub const fn unlimited() -> Self {
+        Self {
+            bytes_per_second: u64::MAX,
+            burst_bytes: u64::MAX,
+        }
+    }
+}
+
+impl Default for SnapshotBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
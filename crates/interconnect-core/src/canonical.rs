@@ -0,0 +1,218 @@
+//! Canonical bytes for signing.
+//!
+//! A signature only checks out if the signer and the verifier hash the same
+//! bytes, and serde_json doesn't promise that: map entries come out in
+//! insertion order (or a `HashMap`'s whim), and `1.0` and `1` print
+//! differently. [`canonical_bytes`] writes JSON with object keys sorted and
+//! each number in one form, so values equal as JSON encode identically
+//! wherever they're produced.
+
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+/// `value` as canonical JSON.
+///
+/// - Object keys are sorted by their UTF-8 bytes.
+/// - Integral numbers print as integers (`1.0` as `1`, `-0.0` as `0`);
+///   others print in their shortest round-trip form (`0.1`, `1e+300`).
+/// - No whitespace.
+///
+/// Non-finite floats become `null`, as serde_json has them. Verify
+/// against the bytes as received where you can: serde_json's parser may
+/// round a float's last digit, so re-encoding a parsed value can differ.
+pub fn canonical_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, serde_json::Error> {
+    let value = serde_json::to_value(value)?;
+    let mut out = Vec::new();
+    write_value(&value, &mut out)?;
+    Ok(out)
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>) -> Result<(), serde_json::Error> {
+    match value {
+        Value::Number(number) => write_number(number, out)?,
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_value(item, out)?;
+            }
+            out.push(b']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+            out.push(b'{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, key)?;
+                out.push(b':');
+                write_value(item, out)?;
+            }
+            out.push(b'}');
+        }
+        // Null, booleans and strings have one form already
+        scalar => serde_json::to_writer(&mut *out, scalar)?,
+    }
+    Ok(())
+}
+
+fn write_number(number: &Number, out: &mut Vec<u8>) -> Result<(), serde_json::Error> {
+    // Integral floats within the integer types print as the integer would
+    const MIN: f64 = i64::MIN as f64;
+    const MAX: f64 = u64::MAX as f64;
+    match number.as_f64() {
+        Some(float) if number.is_f64() && float.fract() == 0.0 && (MIN..MAX).contains(&float) => {
+            out.extend_from_slice((float as i128).to_string().as_bytes());
+            Ok(())
+        }
+        _ => serde_json::to_writer(out, number),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serializer;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn edge_cases_have_one_form() {
+        let value = serde_json::json!({
+            "b": 1.0,
+            "a": [-0.0, 0.1, 1e300, 2.5e-8, -3, 18446744073709551615u64],
+            "é": "\"quoted\"\n",
+        });
+        assert_eq!(
+            String::from_utf8(canonical_bytes(&value).unwrap()).unwrap(),
+            r#"{"a":[0,0.1,1e+300,2.5e-8,-3,18446744073709551615],"b":1,"é":"\"quoted\"\n"}"#
+        );
+
+        let hashed: HashMap<&str, u32> = [("z", 1), ("a", 2), ("m", 3)].into();
+        let sorted: BTreeMap<&str, u32> = [("m", 3), ("z", 1), ("a", 2)].into();
+        assert_eq!(
+            canonical_bytes(&hashed).unwrap(),
+            canonical_bytes(&sorted).unwrap()
+        );
+    }
+
+    /// A JSON tree that serializes in many equivalent ways.
+    #[derive(Debug)]
+    enum Node {
+        Null,
+        Bool(bool),
+        Int(i64),
+        Float(f64),
+        Text(String),
+        List(Vec<Node>),
+        Map(Vec<(String, Node)>),
+    }
+
+    /// `node` serialized with its maps in a seed-chosen order and, where
+    /// it makes no difference to the value, integers as floats and zero as
+    /// negative zero.
+    struct Variant<'a> {
+        node: &'a Node,
+        seed: u64,
+    }
+
+    impl Serialize for Variant<'_> {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            let as_float = self.seed % 2 == 1;
+            let child = |i: usize, node| Variant {
+                node,
+                seed: mix(self.seed ^ i as u64),
+            };
+            match self.node {
+                Node::Null => s.serialize_unit(),
+                Node::Bool(b) => s.serialize_bool(*b),
+                Node::Int(i) if as_float && i.unsigned_abs() < 1 << 53 => {
+                    s.serialize_f64(*i as f64)
+                }
+                Node::Int(i) => s.serialize_i64(*i),
+                Node::Float(f) if as_float && *f == 0.0 => s.serialize_f64(-0.0),
+                Node::Float(f) => s.serialize_f64(*f),
+                Node::Text(text) => s.serialize_str(text),
+                Node::List(items) => {
+                    s.collect_seq(items.iter().enumerate().map(|(i, node)| child(i, node)))
+                }
+                Node::Map(entries) => {
+                    let mut order: Vec<usize> = (0..entries.len()).collect();
+                    let mut state = self.seed | 1;
+                    for i in (1..order.len()).rev() {
+                        order.swap(i, (next(&mut state) % (i as u64 + 1)) as usize);
+                    }
+                    s.collect_map(
+                        order
+                            .into_iter()
+                            .map(|i| (&entries[i].0, child(i, &entries[i].1))),
+                    )
+                }
+            }
+        }
+    }
+
+    fn mix(seed: u64) -> u64 {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        next(&mut state)
+    }
+
+    /// xorshift64*
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn random_node(state: &mut u64, depth: u32) -> Node {
+        const FLOATS: [f64; 6] = [0.0, 0.1, -2.5, 1e300, 5e-324, 123456789.125];
+        const WORDS: [&str; 6] = ["", "a", "b", "é", "\"\\\n", "😀"];
+        let pick = next(state) % if depth == 0 { 5 } else { 7 };
+        match pick {
+            0 => Node::Null,
+            1 => Node::Bool(next(state).is_multiple_of(2)),
+            2 => Node::Int(next(state) as i64 >> (next(state) % 64)),
+            3 => Node::Float(match next(state) % 2 {
+                0 => FLOATS[(next(state) % 6) as usize],
+                _ => Some(f64::from_bits(next(state)))
+                    .filter(|f| f.is_finite())
+                    .unwrap_or(0.0),
+            }),
+            4 => Node::Text(WORDS[(next(state) % 6) as usize].repeat((next(state) % 3) as usize)),
+            5 => Node::List(
+                (0..next(state) % 4)
+                    .map(|_| random_node(state, depth - 1))
+                    .collect(),
+            ),
+            _ => Node::Map(
+                (0..next(state) % 5)
+                    .map(|i| (format!("k{}", i), random_node(state, depth - 1)))
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn equal_values_encode_identically() {
+        let mut state = 0x5eed;
+        for _ in 0..500 {
+            let node = random_node(&mut state, 4);
+            let first = canonical_bytes(&Variant {
+                node: &node,
+                seed: 2,
+            })
+            .unwrap();
+            for seed in [3, next(&mut state), next(&mut state)] {
+                let other = canonical_bytes(&Variant { node: &node, seed }).unwrap();
+                assert_eq!(first, other, "{node:?}");
+            }
+            // Still JSON (serde_json's parser may round the last digit of
+            // a float, so the parsed value isn't compared)
+            assert!(serde_json::from_slice::<Value>(&first).is_ok());
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! What a session is allowed to do.
+
+use std::time::{Duration, Instant};
+
+/// Per-session capabilities, enforced by the transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// May request a transfer to another server.
+    pub transfer: bool,
+    /// May subscribe to the admin event stream.
+    pub admin: bool,
+    /// Minimum time between intents, if rate-limited.
+    pub min_intent_interval: Option<Duration>,
+}
+
+impl Capabilities {
+    /// Everything a player may do, no rate limit.
+    pub const fn full() -> Self {
+        Self {
+            transfer: true,
+            admin: false,
+            min_intent_interval: None,
+        }
+    }
+
+    /// [`full`](Self::full), plus the admin event stream. Grant it from
+    /// [`Authority::capabilities_for`](crate::Authority::capabilities_for).
+    pub const fn admin() -> Self {
+        Self {
+            admin: true,
+            ..Self::full()
+        }
+    }
+
+    /// Reduced set for guests: no transfers, at most one intent per second.
+    pub const fn guest() -> Self {
+        Self {
+            transfer: false,
+            admin: false,
+            min_intent_interval: Some(Duration::from_secs(1)),
+        }
+    }
+
+    /// Whether an intent at `now` respects the rate limit, given the time
+    /// of the previous accepted intent.
+    pub fn intent_allowed(&self, last: Option<Instant>, now: Instant) -> bool {
+        match (self.min_intent_interval, last) {
+            (Some(interval), Some(last)) => now.duration_since(last) >= interval,
+            _ => true,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guest_intents_are_spaced() {
+        let now = Instant::now();
+        let guest = Capabilities::guest();
+        assert!(guest.intent_allowed(None, now));
+        assert!(!guest.intent_allowed(Some(now), now + Duration::from_millis(500)));
+        assert!(guest.intent_allowed(Some(now), now + Duration::from_secs(1)));
+        assert!(Capabilities::full().intent_allowed(Some(now), now));
+    }
+}
@@ -0,0 +1,24 @@
+//! Server-state checkpoints for crash recovery.
+//!
+//! Client snapshots show a session what it may see; a checkpoint is the
+//! authority's full state, written periodically so a restarted server can
+//! pick up where it left off. Where checkpoints are kept is the transport's
+//! concern (see `interconnect-server`'s `FileCheckpointStore`).
+
+/// An authority whose state can be saved and restored.
+///
+/// Restoring goes into a freshly constructed authority, so anything that
+/// isn't state (configuration, memory budgets, peers) comes from its
+/// constructor as usual.
+pub trait Persistable {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Serialize the state worth keeping across a restart.
+    ///
+    /// Connected sessions usually aren't: their connections end with the
+    /// process.
+    fn checkpoint(&self) -> Result<Vec<u8>, Self::Error>;
+
+    /// Replace the state with a checkpoint's.
+    fn restore(&mut self, checkpoint: &[u8]) -> Result<(), Self::Error>;
+}
@@ -0,0 +1,875 @@
+//! Client-side connection logic.
+//!
+//! [`ClientStateMachine`] decodes server messages, tracks the
+//! [`ConnectionState`], and calls into an app-provided
+//! [`ClientConnectionHandler`]. It does no I/O: feed it what the socket
+//! receives and send whatever reply it returns.
+
+use crate::{
+    ClientWire, ConnectionState, LifecycleEvent, Manifest, PresenceDelta, Roster, ServerWire,
+    SessionToken, SystemCategory, WireType, decode_batch,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Something the server told the client besides state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemEvent {
+    /// An informational message.
+    Message {
+        message: String,
+        category: Option<SystemCategory>,
+    },
+    /// Connect to `destination`, presenting `passport`.
+    Transfer {
+        destination: String,
+        passport: Vec<u8>,
+    },
+    /// Connect to `destination`, presenting `token` as the ticket.
+    TransferTicket { destination: String, token: String },
+    /// Moved to `destination` on the same server; the connection stays up.
+    LocalTransfer {
+        destination: String,
+        session_id: u64,
+    },
+    /// Present `token` to resume this session after a dropped connection.
+    ResumeToken { token: String },
+    /// Present `token` as the `reconnect_token` in a later `Auth`.
+    ReconnectToken { token: SessionToken },
+    /// The server is closing the room; the connection ends next.
+    RoomClosing { message: String },
+}
+
+impl SystemEvent {
+    /// The event `msg` carries, if it's one of these.
+    pub fn from_server_wire<S>(msg: &ServerWire<S>) -> Option<Self> {
+        let event = match msg {
+            ServerWire::System { message, category } => Self::Message {
+                message: message.clone(),
+                category: *category,
+            },
+            ServerWire::Transfer {
+                destination,
+                passport,
+            } => Self::Transfer {
+                destination: destination.clone(),
+                passport: passport.clone(),
+            },
+            ServerWire::TransferTicket { destination, token } => Self::TransferTicket {
+                destination: destination.clone(),
+                token: token.clone(),
+            },
+            ServerWire::LocalTransferComplete {
+                destination,
+                session_id,
+            } => Self::LocalTransfer {
+                destination: destination.clone(),
+                session_id: *session_id,
+            },
+            ServerWire::ResumeToken { token } => Self::ResumeToken {
+                token: token.clone(),
+            },
+            ServerWire::ReconnectToken { token } => Self::ReconnectToken {
+                token: token.clone(),
+            },
+            ServerWire::RoomClosing { message } => Self::RoomClosing {
+                message: message.clone(),
+            },
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// App code reacting to a server connection.
+///
+/// All methods default to no-ops; implement the ones you need.
+pub trait ClientConnectionHandler {
+    /// The server sent its manifest.
+    fn on_manifest_received(&mut self, _manifest: &Manifest) {}
+
+    /// The manifest advertises other types than the ones given to
+    /// [`ClientStateMachine::with_types`]: this is another app's server,
+    /// and its snapshots won't mean what the client expects.
+    fn on_type_mismatch(&mut self, _manifest: &Manifest) {}
+
+    /// The server sent a full snapshot.
+    fn on_snapshot_received(&mut self, _seq: u64, _data: serde_json::Value) {}
+
+    /// The server sent a snapshot while its authority isn't taking intents;
+    /// render it read-only (e.g. dimmed). The default treats it as a plain
+    /// snapshot.
+    fn on_stale_snapshot_received(&mut self, seq: u64, data: serde_json::Value) {
+        self.on_snapshot_received(seq, data);
+    }
+
+    /// The server sent a snapshot because the client's prediction diverged;
+    /// reconcile to it. The default treats it as a plain snapshot.
+    fn on_correction_received(&mut self, seq: u64, data: serde_json::Value) {
+        self.on_snapshot_received(seq, data);
+    }
+
+    /// The server sent changes since snapshot `base_seq`.
+    fn on_delta_received(&mut self, _seq: u64, _base_seq: u64, _data: serde_json::Value) {}
+
+    /// The server sent a system message, transfer, or token.
+    fn on_event_received(&mut self, _event: SystemEvent) {}
+
+    /// The server reported an error.
+    fn on_error_received(&mut self, _code: &str, _message: &str) {}
+
+    /// Every page of a query started with [`ClientStateMachine::query`]
+    /// has arrived.
+    fn on_query_result(&mut self, _id: u64, _items: Vec<serde_json::Value>) {}
+
+    /// A tracked intent was applied (see [`IntentTracker`]).
+    fn on_intent_acked(&mut self, _request_id: u64, _seq: u64) {}
+
+    /// An optimistic intent came out as predicted (see
+    /// [`IntentTracker::track_optimistic`]); keep the local state.
+    fn on_intent_confirmed(&mut self, _request_id: u64, _seq: u64) {}
+
+    /// A lifecycle event from the admin stream (see `ClientWire::Subscribe`).
+    fn on_admin_event(&mut self, _event: LifecycleEvent) {}
+
+    /// Who joined and left changed; `roster` is who is present now (see
+    /// [`ClientStateMachine::roster`]).
+    fn on_presence_changed(&mut self, _roster: &Roster, _delta: &PresenceDelta) {}
+
+    /// An optimistic intent came out differently; roll back to `snapshot`,
+    /// which is snapshot `seq`.
+    fn on_intent_corrected(&mut self, _request_id: u64, _seq: u64, _snapshot: serde_json::Value) {}
+}
+
+/// Intents sent with `ClientWire::TrackedIntent`, kept until acked.
+///
+/// Resend [`retries`](Self::retries) after a reconnect, and
+/// [`due_retries`](Self::due_retries) periodically; the server applies each
+/// request ID once, so retrying is safe.
+#[derive(Debug)]
+pub struct IntentTracker<I> {
+    next_id: u64,
+    pending: BTreeMap<u64, Pending<I>>,
+}
+
+/// A tracked intent waiting for its ack.
+#[derive(Debug)]
+struct Pending<I> {
+    intent: I,
+    /// The snapshot it was applied on locally, if it was.
+    base_seq: Option<u64>,
+    /// The snapshot it must still apply to, if any.
+    if_seq: Option<u64>,
+    sent: Instant,
+}
+
+impl<I: Clone> Pending<I> {
+    fn wire(&self, request_id: u64) -> ClientWire<I> {
+        ClientWire::TrackedIntent {
+            request_id,
+            require_ack: true,
+            base_seq: self.base_seq,
+            if_seq: self.if_seq,
+            intent: self.intent.clone(),
+        }
+    }
+}
+
+impl<I> Default for IntentTracker<I> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<I: Clone> IntentTracker<I> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track an intent, returning the message to send.
+    pub fn track(&mut self, intent: I) -> ClientWire<I> {
+        self.send(intent, None, None)
+    }
+
+    /// Track an intent already applied locally on top of snapshot
+    /// `base_seq`, returning the message to send.
+    ///
+    /// The server answers `IntentConfirmed` or `IntentCorrected` rather than
+    /// `IntentAck`; pass either's `request_id` to [`acked`](Self::acked).
+    pub fn track_optimistic(&mut self, intent: I, base_seq: u64) -> ClientWire<I> {
+        self.send(intent, Some(base_seq), None)
+    }
+
+    /// Track an intent that may only apply while snapshot `if_seq` is the
+    /// latest, returning the message to send.
+    ///
+    /// If the state moved on, the server answers with a
+    /// [`ErrorCode::Conflict`](crate::ErrorCode::Conflict) error naming the
+    /// request; drop the intent with [`acked`](Self::acked) and rebase.
+    pub fn track_if_seq(&mut self, intent: I, if_seq: u64) -> ClientWire<I> {
+        self.send(intent, None, Some(if_seq))
+    }
+
+    fn send(&mut self, intent: I, base_seq: Option<u64>, if_seq: Option<u64>) -> ClientWire<I> {
+        let request_id = self.next_id;
+        self.next_id += 1;
+        let pending = Pending {
+            intent,
+            base_seq,
+            if_seq,
+            sent: Instant::now(),
+        };
+        let wire = pending.wire(request_id);
+        self.pending.insert(request_id, pending);
+        wire
+    }
+
+    /// The server acked `request_id`; returns the intent if it was pending.
+    pub fn acked(&mut self, request_id: u64) -> Option<I> {
+        self.pending
+            .remove(&request_id)
+            .map(|pending| pending.intent)
+    }
+
+    /// Messages resending every unacked intent, oldest first.
+    pub fn retries(&mut self) -> Vec<ClientWire<I>> {
+        self.due_retries(Duration::ZERO)
+    }
+
+    /// Messages resending intents unacked for longer than `timeout`,
+    /// oldest first. Each counts as sent again from now.
+    pub fn due_retries(&mut self, timeout: Duration) -> Vec<ClientWire<I>> {
+        let now = Instant::now();
+        self.pending
+            .iter_mut()
+            .filter(|(_, pending)| now.duration_since(pending.sent) >= timeout)
+            .map(|(&request_id, pending)| {
+                pending.sent = now;
+                pending.wire(request_id)
+            })
+            .collect()
+    }
+
+    /// Intents still waiting for an ack.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// What to do after a query page arrives.
+#[derive(Debug)]
+pub enum QueryProgress<I> {
+    /// Send this to ask for the next page.
+    Next(ClientWire<I>),
+    /// That was the last page; here is the whole result.
+    Done(Vec<serde_json::Value>),
+}
+
+/// Reassembles paginated query results, one page request at a time.
+#[derive(Debug, Default)]
+pub struct QueryReassembler {
+    next_id: u64,
+    /// query ID -> (query, items so far)
+    queries: HashMap<u64, (serde_json::Value, Vec<serde_json::Value>)>,
+}
+
+impl QueryReassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a query, returning the request for its first page.
+    pub fn start<I>(&mut self, query: serde_json::Value) -> ClientWire<I> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queries.insert(id, (query.clone(), Vec::new()));
+        ClientWire::Query {
+            id,
+            query,
+            cursor: None,
+        }
+    }
+
+    /// Add a page. Returns `None` for queries this reassembler didn't start
+    /// (or already finished).
+    pub fn accept<I>(
+        &mut self,
+        id: u64,
+        data: Vec<serde_json::Value>,
+        cursor: Option<String>,
+    ) -> Option<QueryProgress<I>> {
+        let (query, items) = self.queries.get_mut(&id)?;
+        items.extend(data);
+        match cursor {
+            Some(cursor) => Some(QueryProgress::Next(ClientWire::Query {
+                id,
+                query: query.clone(),
+                cursor: Some(cursor),
+            })),
+            None => {
+                let (_, items) = self.queries.remove(&id)?;
+                Some(QueryProgress::Done(items))
+            }
+        }
+    }
+
+    /// Stop collecting a query, e.g. after the server reported an error.
+    pub fn cancel(&mut self, id: u64) {
+        self.queries.remove(&id);
+    }
+
+    /// Queries still waiting on pages.
+    pub fn in_flight(&self) -> usize {
+        self.queries.len()
+    }
+}
+
+/// Wire-level framing for one connection.
+#[derive(Debug)]
+pub struct ClientStateMachine<H> {
+    handler: H,
+    state: ConnectionState,
+    last_seq: Option<u64>,
+    queries: QueryReassembler,
+    roster: Roster,
+    /// The snapshot and intent types manifests are checked against.
+    type_check: Option<(WireType, WireType)>,
+}
+
+impl<H: ClientConnectionHandler> ClientStateMachine<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            state: ConnectionState::Connecting,
+            last_seq: None,
+            queries: QueryReassembler::new(),
+            roster: Roster::new(),
+            type_check: None,
+        }
+    }
+
+    /// Check each manifest against these snapshot and intent types (see
+    /// [`Manifest::check_types`]), answering a mismatch with `TypeMismatch`
+    /// so the server can log it.
+    pub fn with_types(mut self, snapshot: WireType, intent: WireType) -> Self {
+        self.type_check = Some((snapshot, intent));
+        self
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Where the connection is in its lifecycle.
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Sequence number of the latest snapshot or delta.
+    pub fn last_seq(&self) -> Option<u64> {
+        self.last_seq
+    }
+
+    /// Who is present, from the server's `Presence` updates.
+    pub fn roster(&self) -> &Roster {
+        &self.roster
+    }
+
+    /// Start a paginated query, returning the request to send.
+    ///
+    /// Later pages are requested through the replies from [`handle`]; the
+    /// handler gets the whole result in `on_query_result`.
+    ///
+    /// [`handle`]: Self::handle
+    pub fn query<I>(&mut self, query: serde_json::Value) -> ClientWire<I> {
+        self.queries.start(query)
+    }
+
+    /// Go to the background, returning the message to send.
+    ///
+    /// The server holds snapshots until [`resume`](Self::resume).
+    pub fn pause<I>(&mut self) -> ClientWire<I> {
+        if self.state == ConnectionState::Live {
+            self.state = ConnectionState::Paused;
+        }
+        ClientWire::Pause
+    }
+
+    /// Come back from the background, returning the message to send.
+    pub fn resume<I>(&mut self) -> ClientWire<I> {
+        if self.state == ConnectionState::Paused {
+            self.state = ConnectionState::Live;
+        }
+        ClientWire::Resume
+    }
+
+    /// Decode and handle a text frame.
+    pub fn handle_text<I>(
+        &mut self,
+        text: &str,
+    ) -> Result<Option<ClientWire<I>>, serde_json::Error> {
+        let msg = serde_json::from_str(text)?;
+        Ok(self.handle(msg))
+    }
+
+    /// Decode and handle a text frame holding one message or a batch (see
+    /// [`encode_batch`](crate::encode_batch)), returning every reply.
+    pub fn handle_frame<I>(&mut self, text: &str) -> Result<Vec<ClientWire<I>>, serde_json::Error> {
+        if !text.trim_start().starts_with('[') {
+            return Ok(self.handle_text(text)?.into_iter().collect());
+        }
+        let msgs: Vec<ServerWire<serde_json::Value>> = decode_batch(text.as_bytes())?;
+        Ok(msgs
+            .into_iter()
+            .filter_map(|msg| self.handle(msg))
+            .collect())
+    }
+
+    /// Handle a server message, returning the reply to send, if any.
+    pub fn handle<I>(&mut self, msg: ServerWire<serde_json::Value>) -> Option<ClientWire<I>> {
+        match msg {
+            ServerWire::Manifest(manifest) => {
+                if self.state.can_receive_manifest() {
+                    self.state = ConnectionState::Syncing;
+                }
+                // A new connection sends the whole roster as joins
+                self.roster.clear();
+                self.handler.on_manifest_received(&manifest);
+                let mismatch = self
+                    .type_check
+                    .and_then(|(snapshot, intent)| manifest.check_types(snapshot, intent));
+                if mismatch.is_some() {
+                    self.handler.on_type_mismatch(&manifest);
+                    return mismatch;
+                }
+            }
+            ServerWire::Snapshot { seq, data, stale } => {
+                self.last_seq = Some(seq);
+                if stale {
+                    self.state = ConnectionState::Ghost;
+                    self.handler.on_stale_snapshot_received(seq, data);
+                } else {
+                    self.state = ConnectionState::Live;
+                    self.handler.on_snapshot_received(seq, data);
+                }
+            }
+            ServerWire::Correction { seq, data } => {
+                self.state = ConnectionState::Live;
+                self.last_seq = Some(seq);
+                self.handler.on_correction_received(seq, data);
+            }
+            ServerWire::Delta {
+                seq,
+                base_seq,
+                data,
+            } => {
+                self.last_seq = Some(seq);
+                self.handler.on_delta_received(seq, base_seq, data);
+            }
+            ServerWire::Transfer {
+                destination,
+                passport,
+            } => self.handler.on_event_received(SystemEvent::Transfer {
+                destination,
+                passport,
+            }),
+            ServerWire::TransferTicket { destination, token } => self
+                .handler
+                .on_event_received(SystemEvent::TransferTicket { destination, token }),
+            ServerWire::LocalTransferComplete {
+                destination,
+                session_id,
+            } => self.handler.on_event_received(SystemEvent::LocalTransfer {
+                destination,
+                session_id,
+            }),
+            ServerWire::Error { code, message } => self.handler.on_error_received(&code, &message),
+            ServerWire::System { message, category } => self
+                .handler
+                .on_event_received(SystemEvent::Message { message, category }),
+            ServerWire::ResumeToken { token } => self
+                .handler
+                .on_event_received(SystemEvent::ResumeToken { token }),
+            ServerWire::ReconnectToken { token } => self
+                .handler
+                .on_event_received(SystemEvent::ReconnectToken { token }),
+            ServerWire::RoomClosing { message } => self
+                .handler
+                .on_event_received(SystemEvent::RoomClosing { message }),
+            ServerWire::IntentAck { request_id, seq } => {
+                self.handler.on_intent_acked(request_id, seq)
+            }
+            ServerWire::Admin { event } => self.handler.on_admin_event(event),
+            ServerWire::Presence { joined, left } => {
+                let delta = PresenceDelta { joined, left };
+                self.roster.apply(delta.clone());
+                self.handler.on_presence_changed(&self.roster, &delta);
+            }
+            ServerWire::IntentConfirmed { request_id, seq } => {
+                self.handler.on_intent_confirmed(request_id, seq)
+            }
+            ServerWire::IntentCorrected {
+                request_id,
+                seq,
+                corrective_snapshot,
+            } => {
+                self.last_seq = Some(seq);
+                self.handler
+                    .on_intent_corrected(request_id, seq, corrective_snapshot);
+            }
+            ServerWire::QueryPage {
+                id, data, cursor, ..
+            } => match self.queries.accept(id, data, cursor)? {
+                QueryProgress::Next(request) => return Some(request),
+                QueryProgress::Done(items) => self.handler.on_query_result(id, items),
+            },
+            ServerWire::Ping { nonce } => return Some(ClientWire::Pong { nonce }),
+            ServerWire::Pong => {}
+        }
+        None
+    }
+
+    /// The connection dropped. A live (or paused) view stays readable as a
+    /// ghost.
+    pub fn disconnected(&mut self) {
+        self.state = if matches!(self.state, ConnectionState::Live | ConnectionState::Paused) {
+            ConnectionState::Ghost
+        } else {
+            ConnectionState::Connecting
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Identity;
+
+    #[derive(Default)]
+    struct Recorder {
+        manifests: Vec<String>,
+        snapshots: Vec<u64>,
+        events: Vec<SystemEvent>,
+        errors: Vec<String>,
+        results: Vec<(u64, Vec<serde_json::Value>)>,
+        confirmed: Vec<u64>,
+        corrected: Vec<(u64, serde_json::Value)>,
+        mismatched: bool,
+    }
+
+    impl ClientConnectionHandler for Recorder {
+        fn on_manifest_received(&mut self, manifest: &Manifest) {
+            self.manifests.push(manifest.name.to_string());
+        }
+
+        fn on_type_mismatch(&mut self, _manifest: &Manifest) {
+            self.mismatched = true;
+        }
+
+        fn on_snapshot_received(&mut self, seq: u64, _data: serde_json::Value) {
+            self.snapshots.push(seq);
+        }
+
+        fn on_event_received(&mut self, event: SystemEvent) {
+            self.events.push(event);
+        }
+
+        fn on_error_received(&mut self, code: &str, _message: &str) {
+            self.errors.push(code.to_string());
+        }
+
+        fn on_query_result(&mut self, id: u64, items: Vec<serde_json::Value>) {
+            self.results.push((id, items));
+        }
+
+        fn on_intent_confirmed(&mut self, request_id: u64, _seq: u64) {
+            self.confirmed.push(request_id);
+        }
+
+        fn on_intent_corrected(&mut self, request_id: u64, _seq: u64, snapshot: serde_json::Value) {
+            self.corrected.push((request_id, snapshot));
+        }
+    }
+
+    fn text(msg: ServerWire<serde_json::Value>) -> String {
+        serde_json::to_string(&msg).unwrap()
+    }
+
+    #[test]
+    fn manifests_are_checked_against_the_client_types() {
+        let names = WireType::of::<Vec<String>>();
+        let words = WireType::of::<String>();
+        let manifest = Manifest {
+            identity: Identity::local("server"),
+            name: "lobby".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: Some(crate::type_hash(names)),
+            intent_type: Some(crate::type_hash(words)),
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        };
+        let manifest = text(ServerWire::Manifest(manifest.into()));
+
+        let mut client = ClientStateMachine::new(Recorder::default()).with_types(names, words);
+        assert!(client.handle_text::<String>(&manifest).unwrap().is_none());
+        assert!(!client.handler().mismatched);
+
+        let number = WireType::of::<u64>();
+        let mut client = ClientStateMachine::new(Recorder::default()).with_types(number, words);
+        let reply = client.handle_text::<String>(&manifest).unwrap();
+        assert!(
+            matches!(reply, Some(ClientWire::TypeMismatch { .. })),
+            "{reply:?}"
+        );
+        assert!(client.handler().mismatched);
+    }
+
+    #[test]
+    fn connection_goes_live_on_first_snapshot() {
+        let mut client = ClientStateMachine::new(Recorder::default());
+        let manifest = Manifest {
+            identity: Identity::local("server"),
+            name: "lobby".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        };
+
+        client
+            .handle_text::<()>(&text(ServerWire::Manifest(manifest.into())))
+            .unwrap();
+        assert_eq!(client.state(), ConnectionState::Syncing);
+
+        let snapshot = ServerWire::snapshot(3, serde_json::json!({ "users": [] }));
+        client.handle_text::<()>(&text(snapshot)).unwrap();
+        assert_eq!(client.state(), ConnectionState::Live);
+        assert_eq!(client.last_seq(), Some(3));
+
+        client
+            .handle_text::<()>(&text(ServerWire::system("hello")))
+            .unwrap();
+        client
+            .handle_text::<()>(&text(ServerWire::error("rate_limited", "slow down")))
+            .unwrap();
+
+        let handler = client.handler();
+        assert_eq!(handler.manifests, ["lobby"]);
+        assert_eq!(handler.snapshots, [3]);
+        assert_eq!(
+            handler.events,
+            [SystemEvent::Message {
+                message: "hello".into(),
+                category: None
+            }]
+        );
+        assert_eq!(handler.errors, ["rate_limited"]);
+
+        assert!(matches!(client.pause::<()>(), ClientWire::Pause));
+        assert_eq!(client.state(), ConnectionState::Paused);
+        client.resume::<()>();
+        assert_eq!(client.state(), ConnectionState::Live);
+
+        client.disconnected();
+        assert_eq!(client.state(), ConnectionState::Ghost);
+    }
+
+    #[test]
+    fn stale_snapshots_are_read_only() {
+        let mut client = ClientStateMachine::new(Recorder::default());
+        let stale = ServerWire::Snapshot {
+            seq: 1,
+            data: serde_json::json!({}),
+            stale: true,
+        };
+        client.handle_text::<()>(&text(stale)).unwrap();
+        assert_eq!(client.state(), ConnectionState::Ghost);
+        assert!(!client.state().is_writable());
+        // Handled as a plain snapshot by default
+        assert_eq!(client.handler().snapshots, [1]);
+
+        let fresh = ServerWire::snapshot(2, serde_json::json!({}));
+        assert!(!text(fresh.clone()).contains("stale"));
+        client.handle_text::<()>(&text(fresh)).unwrap();
+        assert_eq!(client.state(), ConnectionState::Live);
+    }
+
+    #[test]
+    fn query_pages_are_reassembled() {
+        let mut client = ClientStateMachine::new(Recorder::default());
+        let ClientWire::Query { id, cursor, .. } =
+            client.query::<()>(serde_json::json!({ "history": {} }))
+        else {
+            panic!("expected a query");
+        };
+        assert_eq!(cursor, None);
+
+        let first = ServerWire::QueryPage {
+            id,
+            page: 0,
+            total_pages: 2,
+            data: vec![serde_json::json!("a")],
+            cursor: Some("1".into()),
+        };
+        let next = client.handle::<()>(first);
+        assert!(matches!(
+            next,
+            Some(ClientWire::Query { cursor: Some(ref c), .. }) if c == "1"
+        ));
+
+        let last = ServerWire::QueryPage {
+            id,
+            page: 1,
+            total_pages: 2,
+            data: vec![serde_json::json!("b")],
+            cursor: None,
+        };
+        assert!(client.handle::<()>(last).is_none());
+        let results = &client.handler().results;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, id);
+        assert_eq!(results[0].1, ["a", "b"]);
+    }
+
+    #[test]
+    fn batched_frames_are_unpacked() {
+        let mut client = ClientStateMachine::new(Recorder::default());
+        let batch = crate::encode_batch(&[
+            ServerWire::<serde_json::Value>::system("one"),
+            ServerWire::Ping { nonce: 4 },
+            ServerWire::system("two"),
+        ])
+        .unwrap();
+        let replies = client
+            .handle_frame::<()>(std::str::from_utf8(&batch).unwrap())
+            .unwrap();
+        assert!(matches!(replies[..], [ClientWire::Pong { nonce: 4 }]));
+        assert_eq!(client.handler().events.len(), 2);
+    }
+
+    #[test]
+    fn unacked_intents_are_retried() {
+        let mut tracker = IntentTracker::new();
+        let first = tracker.track("buy sword");
+        tracker.track("buy shield");
+        let ClientWire::TrackedIntent { request_id, .. } = first else {
+            panic!("expected a tracked intent");
+        };
+
+        assert_eq!(tracker.acked(request_id), Some("buy sword"));
+        assert_eq!(tracker.acked(request_id), None);
+        assert!(tracker.due_retries(Duration::from_secs(60)).is_empty());
+        let retries = tracker.retries();
+        assert!(matches!(
+            retries[..],
+            [ClientWire::TrackedIntent {
+                intent: "buy shield",
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn retries_keep_their_precondition() {
+        let mut tracker = IntentTracker::new();
+        tracker.track_if_seq("rename", 3);
+        assert!(matches!(
+            tracker.retries()[..],
+            [ClientWire::TrackedIntent {
+                if_seq: Some(3),
+                ..
+            }]
+        ));
+        assert_eq!(tracker.acked(0), Some("rename"));
+    }
+
+    #[test]
+    fn optimistic_intents_roll_back_only_on_correction() {
+        let mut tracker = IntentTracker::new();
+        tracker.track_optimistic("move left", 4);
+        tracker.track_optimistic("move right", 4);
+        // Retries keep the base they were predicted from.
+        assert!(matches!(
+            tracker.retries()[..],
+            [
+                ClientWire::TrackedIntent {
+                    base_seq: Some(4),
+                    ..
+                },
+                ClientWire::TrackedIntent {
+                    base_seq: Some(4),
+                    ..
+                }
+            ]
+        ));
+
+        let mut client = ClientStateMachine::new(Recorder::default());
+        client.handle::<()>(ServerWire::IntentConfirmed {
+            request_id: 0,
+            seq: 5,
+        });
+        client.handle::<()>(ServerWire::IntentCorrected {
+            request_id: 1,
+            seq: 6,
+            corrective_snapshot: serde_json::json!({"x": 0}),
+        });
+        assert_eq!(client.handler().confirmed, [0]);
+        assert_eq!(
+            client.handler().corrected,
+            [(1, serde_json::json!({"x": 0}))]
+        );
+        assert_eq!(client.last_seq(), Some(6));
+        assert_eq!(tracker.acked(0), Some("move left"));
+        assert_eq!(tracker.acked(1), Some("move right"));
+        assert_eq!(tracker.pending(), 0);
+    }
+
+    #[test]
+    fn corrections_default_to_snapshots() {
+        let mut client = ClientStateMachine::new(Recorder::default());
+        client.handle::<()>(ServerWire::Correction {
+            seq: 4,
+            data: serde_json::json!({"x": 1}),
+        });
+        assert_eq!(client.handler().snapshots, [4]);
+        assert_eq!(client.last_seq(), Some(4));
+        assert_eq!(client.state(), ConnectionState::Live);
+    }
+
+    #[test]
+    fn pings_are_answered() {
+        let mut client = ClientStateMachine::new(Recorder::default());
+        let reply = client.handle::<()>(ServerWire::Ping { nonce: 7 });
+        assert!(matches!(reply, Some(ClientWire::Pong { nonce: 7 })));
+    }
+
+    #[test]
+    fn presence_updates_build_the_roster() {
+        let mut server = Roster::new();
+        let mut client = ClientStateMachine::new(Recorder::default());
+        let (a, b) = (Identity::local("a"), Identity::local("b"));
+
+        let joined = server.update([a.clone(), b.clone()]).unwrap();
+        client
+            .handle_text::<()>(&text(ServerWire::presence(joined)))
+            .unwrap();
+        assert_eq!(client.roster().len(), 2);
+
+        let left = server.update([b]).unwrap();
+        assert!(text(ServerWire::presence(left.clone())).contains("left"));
+        client.handle::<()>(ServerWire::presence(left));
+        assert_eq!(client.roster(), &server);
+    }
+}
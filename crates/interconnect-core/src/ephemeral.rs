@@ -0,0 +1,77 @@
+//! Values that expire on their own.
+//!
+//! Typing indicators and transient notifications shouldn't need an explicit
+//! clear. Wrap them in [`Ephemeral`] and filter with [`unexpired`] when
+//! building a snapshot. The expiry travels with the value, so clients can
+//! drop it on time even if no further snapshot arrives.
+
+use crate::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A value with an absolute expiry time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Ephemeral<T> {
+    /// The wrapped value.
+    pub value: T,
+    /// When the value expires.
+    pub expires_at: Timestamp,
+}
+
+impl<T> Ephemeral<T> {
+    /// Wrap a value that expires `ttl` from now.
+    pub fn new(value: T, ttl: Duration) -> Self {
+        Self {
+            value,
+            expires_at: Timestamp::now().saturating_add(ttl),
+        }
+    }
+
+    /// Wrap a value with an explicit expiry.
+    pub fn until(value: T, expires_at: Timestamp) -> Self {
+        Self { value, expires_at }
+    }
+
+    /// Whether the value has expired.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(Timestamp::now())
+    }
+
+    /// Whether the value has expired as of `now`.
+    pub fn is_expired_at(&self, now: Timestamp) -> bool {
+        now >= self.expires_at
+    }
+}
+
+/// Iterate the items that haven't expired yet.
+pub fn unexpired<'a, T: 'a>(
+    items: impl IntoIterator<Item = &'a Ephemeral<T>>,
+) -> impl Iterator<Item = &'a Ephemeral<T>> {
+    let now = Timestamp::now();
+    items
+        .into_iter()
+        .filter(move |item| !item.is_expired_at(now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_at_deadline() {
+        let item = Ephemeral::until("typing", Timestamp::from_millis(1_000));
+        assert!(!item.is_expired_at(Timestamp::from_millis(999)));
+        assert!(item.is_expired_at(Timestamp::from_millis(1_000)));
+    }
+
+    #[test]
+    fn unexpired_filters_past_items() {
+        let items = vec![
+            Ephemeral::until("old", Timestamp::from_millis(0)),
+            Ephemeral::new("fresh", Duration::from_secs(60)),
+        ];
+        let live: Vec<_> = unexpired(&items).map(|e| e.value).collect();
+        assert_eq!(live, ["fresh"]);
+    }
+}
@@ -0,0 +1,532 @@
+//! Event retention for event-sourced authorities.
+//!
+//! [`DeltaAuthority`] keeps the [`AuthorityEvent`]s applied since the last
+//! delta, so an authority that overrides
+//! [`Authority::snapshot_delta_from_events`] can describe exactly what
+//! changed instead of having its snapshots diffed.
+
+use crate::{
+    Authority, AuthorityErrorAction, Capabilities, ConnectInfo, DisconnectReason, ErrorCode,
+    ExportedSession, Identity, IdentityError, ImportResult, ImportSessionError, IntentPriority,
+    InvariantViolation, LargePassportAction, LocalTransferResult, LoopbackAction, Manifest,
+    OptimisticOutcome, PartyImportResult, PassportDecodeAction, PassportUpdate,
+    PassportValidationError, QueryError, QueryPage, Session, SessionToken, SnapshotBudget,
+    TransferError, TransferSnapshot, WireErrorAction, WireType,
+};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Something that changed an authority's state.
+#[derive(Debug, Clone)]
+pub enum AuthorityEvent<I, S, P> {
+    /// A session connected.
+    Connected { session: Session },
+    /// A session transferred in with this (sanitized) passport.
+    TransferredIn { session: Session, passport: P },
+    /// A session left.
+    Disconnected { session: Session },
+    /// An intent was applied.
+    Intent { session: Session, intent: I },
+    /// State was replaced wholesale (e.g. loaded from a checkpoint).
+    Restored { snapshot: S },
+}
+
+/// An authority that retains the events it applies, for event-based deltas.
+///
+/// With `retain_events` off (the default) it only forwards.
+pub struct DeltaAuthority<A: Authority> {
+    inner: A,
+    retain_events: bool,
+    events: Vec<AuthorityEvent<A::Intent, A::Snapshot, A::Passport>>,
+}
+
+impl<A: Authority> DeltaAuthority<A> {
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            retain_events: false,
+            events: Vec::new(),
+        }
+    }
+
+    /// Keep events until the next [`take_delta`](Self::take_delta).
+    pub fn retain_events(mut self, retain: bool) -> Self {
+        self.retain_events = retain;
+        self
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Events retained since the last delta.
+    pub fn events(&self) -> &[AuthorityEvent<A::Intent, A::Snapshot, A::Passport>] {
+        &self.events
+    }
+
+    /// Record an event the wrapper can't see, such as a `Restored`.
+    pub fn push_event(&mut self, event: AuthorityEvent<A::Intent, A::Snapshot, A::Passport>) {
+        if self.retain_events {
+            self.events.push(event);
+        }
+    }
+
+    /// The delta for the retained events, clearing them.
+    ///
+    /// `None` means the authority can't compute one (or nothing was
+    /// retained); diff snapshots instead.
+    pub fn take_delta(&mut self) -> Option<Vec<u8>> {
+        if self.events.is_empty() {
+            return None;
+        }
+        let delta = self.inner.snapshot_delta_from_events(&self.events);
+        self.events.clear();
+        delta
+    }
+
+    fn record(
+        &mut self,
+        event: impl FnOnce() -> AuthorityEvent<A::Intent, A::Snapshot, A::Passport>,
+    ) {
+        if self.retain_events {
+            self.events.push(event());
+        }
+    }
+}
+
+impl<A: Authority> Authority for DeltaAuthority<A>
+where
+    A::Intent: Clone + Send + Sync,
+    A::Snapshot: Send + Sync,
+    A::Passport: Clone + Send + Sync,
+{
+    type Intent = A::Intent;
+    type Snapshot = A::Snapshot;
+    type Passport = A::Passport;
+    type Error = A::Error;
+
+    fn validate_identity(&self, identity: &Identity) -> Result<(), IdentityError> {
+        self.inner.validate_identity(identity)
+    }
+
+    fn validate_connect(
+        &self,
+        identity: &Identity,
+        info: &ConnectInfo,
+    ) -> Result<(), IdentityError> {
+        self.inner.validate_connect(identity, info)
+    }
+
+    fn on_connect(&mut self, session: &Session) -> Result<(), Self::Error> {
+        self.inner.on_connect(session)?;
+        self.record(|| AuthorityEvent::Connected {
+            session: session.clone(),
+        });
+        Ok(())
+    }
+
+    fn on_connect_with_info(
+        &mut self,
+        session: &Session,
+        info: &ConnectInfo,
+    ) -> Result<(), Self::Error> {
+        self.inner.on_connect_with_info(session, info)?;
+        self.record(|| AuthorityEvent::Connected {
+            session: session.clone(),
+        });
+        Ok(())
+    }
+
+    fn on_transfer_in(
+        &mut self,
+        session: &Session,
+        passport: Self::Passport,
+    ) -> Result<ImportResult<Self::Passport>, Self::Error> {
+        let result = self.inner.on_transfer_in(session, passport)?;
+        self.record(|| AuthorityEvent::TransferredIn {
+            session: session.clone(),
+            passport: result.passport.clone(),
+        });
+        Ok(result)
+    }
+
+    fn on_transfer_context(&mut self, session: &Session, context: Self::Snapshot) {
+        self.inner.on_transfer_context(session, context)
+    }
+
+    fn on_party_transfer_in(
+        &mut self,
+        sessions: &[Session],
+        passports: Vec<Self::Passport>,
+    ) -> Result<PartyImportResult<Self::Passport>, Self::Error> {
+        let result = self.inner.on_party_transfer_in(sessions, passports)?;
+        for (id, imported) in &result.accepted {
+            if let Some(session) = sessions.iter().find(|s| s.id == *id) {
+                self.record(|| AuthorityEvent::TransferredIn {
+                    session: session.clone(),
+                    passport: imported.passport.clone(),
+                });
+            }
+        }
+        Ok(result)
+    }
+
+    fn on_passport_decode_error(
+        &mut self,
+        session: &Session,
+        raw: &[u8],
+        error: &serde_json::Error,
+    ) -> PassportDecodeAction {
+        self.inner.on_passport_decode_error(session, raw, error)
+    }
+
+    fn on_large_passport(&mut self, session: &Session, size: usize) -> LargePassportAction {
+        self.inner.on_large_passport(session, size)
+    }
+
+    fn validate_passport(
+        &self,
+        session: &Session,
+        passport: &Self::Passport,
+    ) -> Result<(), Vec<PassportValidationError>> {
+        self.inner.validate_passport(session, passport)
+    }
+
+    fn on_disconnect(&mut self, session: &Session) {
+        self.inner.on_disconnect(session);
+        self.record(|| AuthorityEvent::Disconnected {
+            session: session.clone(),
+        });
+    }
+
+    fn on_disconnect_batch(&mut self, sessions: &[Session], reason: DisconnectReason) {
+        self.inner.on_disconnect_batch(sessions, reason);
+        for session in sessions {
+            self.record(|| AuthorityEvent::Disconnected {
+                session: session.clone(),
+            });
+        }
+    }
+
+    fn on_session_paused(&mut self, session: &Session) {
+        self.inner.on_session_paused(session)
+    }
+
+    fn on_session_resumed(&mut self, session: &Session) {
+        self.inner.on_session_resumed(session)
+    }
+
+    fn handle_intent(
+        &mut self,
+        session: &Session,
+        intent: Self::Intent,
+    ) -> Result<(), Self::Error> {
+        if !self.retain_events {
+            return self.inner.handle_intent(session, intent);
+        }
+        self.inner.handle_intent(session, intent.clone())?;
+        self.events.push(AuthorityEvent::Intent {
+            session: session.clone(),
+            intent,
+        });
+        Ok(())
+    }
+
+    fn intent_priority(&self, session: &Session, intent: &Self::Intent) -> IntentPriority {
+        self.inner.intent_priority(session, intent)
+    }
+
+    fn intent_schema(&self) -> Option<serde_json::Value> {
+        self.inner.intent_schema()
+    }
+
+    fn handle_optimistic_intent(
+        &mut self,
+        session: &Session,
+        intent: Self::Intent,
+        base_seq: u64,
+        latest_seq: u64,
+    ) -> Result<OptimisticOutcome, Self::Error> {
+        if !self.retain_events {
+            return self
+                .inner
+                .handle_optimistic_intent(session, intent, base_seq, latest_seq);
+        }
+        let outcome =
+            self.inner
+                .handle_optimistic_intent(session, intent.clone(), base_seq, latest_seq)?;
+        self.events.push(AuthorityEvent::Intent {
+            session: session.clone(),
+            intent,
+        });
+        Ok(outcome)
+    }
+
+    fn on_intent_ack(&mut self, session: &Session, intent_seq: u64) {
+        self.inner.on_intent_ack(session, intent_seq)
+    }
+
+    fn snapshot_for(&self, session: &Session) -> Self::Snapshot {
+        self.inner.snapshot_for(session)
+    }
+
+    fn coalesce_snapshots(&self, history: Vec<Self::Snapshot>) -> Option<Self::Snapshot> {
+        self.inner.coalesce_snapshots(history)
+    }
+
+    fn redact_snapshot(&self, session: &Session, snapshot: &mut Self::Snapshot) {
+        self.inner.redact_snapshot(session, snapshot)
+    }
+
+    fn translate_snapshot_for_version(
+        &self,
+        snapshot: Self::Snapshot,
+        client_version: &str,
+    ) -> Self::Snapshot {
+        self.inner
+            .translate_snapshot_for_version(snapshot, client_version)
+    }
+
+    fn compute_relevance_score(&self, session: &Session, entity_id: u64) -> f32 {
+        self.inner.compute_relevance_score(session, entity_id)
+    }
+
+    fn clone_session_state(
+        &self,
+        source_session_id: u64,
+        spectator_session_id: u64,
+    ) -> Option<Self::Snapshot> {
+        self.inner
+            .clone_session_state(source_session_id, spectator_session_id)
+    }
+
+    fn snapshot_delta_from(
+        &self,
+        session: &Session,
+        base_seq: u64,
+        base: &Self::Snapshot,
+    ) -> Option<Self::Snapshot> {
+        self.inner.snapshot_delta_from(session, base_seq, base)
+    }
+
+    fn snapshot_delta_from_events(
+        &self,
+        events: &[AuthorityEvent<Self::Intent, Self::Snapshot, Self::Passport>],
+    ) -> Option<Vec<u8>> {
+        self.inner.snapshot_delta_from_events(events)
+    }
+
+    fn query_page(
+        &self,
+        session: &Session,
+        query: &serde_json::Value,
+        cursor: Option<&str>,
+    ) -> Result<QueryPage, QueryError> {
+        self.inner.query_page(session, query, cursor)
+    }
+
+    fn emit_passport(&self, session: &Session) -> Self::Passport {
+        self.inner.emit_passport(session)
+    }
+
+    fn emit_passport_for_destination(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> Self::Passport {
+        self.inner
+            .emit_passport_for_destination(session, destination)
+    }
+
+    fn emit_incremental_passport(
+        &self,
+        session: &Session,
+        previous_version: u32,
+        previous: &Self::Passport,
+    ) -> PassportUpdate<Self::Passport> {
+        self.inner
+            .emit_incremental_passport(session, previous_version, previous)
+    }
+
+    fn emit_transfer_snapshot(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> TransferSnapshot<Self::Snapshot, Self::Passport> {
+        self.inner.emit_transfer_snapshot(session, destination)
+    }
+
+    fn emit_party_passport(&self, sessions: &[Session]) -> Vec<Self::Passport> {
+        self.inner.emit_party_passport(sessions)
+    }
+
+    fn validate_destination(&self, destination: &str) -> bool {
+        self.inner.validate_destination(destination)
+    }
+
+    fn on_transfer_loopback(&mut self, session: &Session, destination: &str) -> LoopbackAction {
+        self.inner.on_transfer_loopback(session, destination)
+    }
+
+    fn on_local_transfer(
+        &mut self,
+        session: &Session,
+        destination: &str,
+    ) -> Option<Result<LocalTransferResult, TransferError<Self::Error>>> {
+        self.inner.on_local_transfer(session, destination)
+    }
+
+    fn passport_size_estimate(&self, session: &Session) -> usize
+    where
+        Self::Snapshot: Serialize,
+        Self::Passport: Serialize,
+    {
+        self.inner.passport_size_estimate(session)
+    }
+
+    fn authority_fingerprint(&self) -> u64
+    where
+        Self::Snapshot: Serialize,
+    {
+        self.inner.authority_fingerprint()
+    }
+
+    fn intent_type_name(intent: &Self::Intent) -> &'static str {
+        A::intent_type_name(intent)
+    }
+
+    fn presence(&self) -> Option<Vec<Identity>> {
+        self.inner.presence()
+    }
+
+    fn snapshot_topic(snapshot: &Self::Snapshot) -> Option<&'static str> {
+        A::snapshot_topic(snapshot)
+    }
+
+    fn intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value
+    where
+        Self::Intent: Serialize,
+    {
+        self.inner.intent_to_json(intent)
+    }
+
+    fn redacted_intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value {
+        self.inner.redacted_intent_to_json(intent)
+    }
+
+    fn can_accept_transfer_from(&self, src_manifest: &Manifest) -> bool {
+        self.inner.can_accept_transfer_from(src_manifest)
+    }
+
+    fn capabilities_for(&self, session: &Session, default: Capabilities) -> Capabilities {
+        self.inner.capabilities_for(session, default)
+    }
+
+    fn on_budget_exceeded(
+        &mut self,
+        session: &Session,
+        snapshot_size: usize,
+        budget: &SnapshotBudget,
+    ) {
+        self.inner
+            .on_budget_exceeded(session, snapshot_size, budget)
+    }
+
+    fn on_peer_transfer_complete(
+        &mut self,
+        src_identity: &Identity,
+        accepted: u64,
+        rejected: u64,
+        elapsed: Duration,
+    ) {
+        self.inner
+            .on_peer_transfer_complete(src_identity, accepted, rejected, elapsed)
+    }
+
+    fn on_authority_error(&self, error: &Self::Error) -> AuthorityErrorAction {
+        self.inner.on_authority_error(error)
+    }
+
+    fn on_authority_recovered(&mut self, previous_error: &Self::Error) {
+        self.inner.on_authority_recovered(previous_error)
+    }
+
+    fn error_code(&self, error: &Self::Error) -> ErrorCode {
+        self.inner.error_code(error)
+    }
+
+    fn on_wire_error(
+        &mut self,
+        session: Option<&Session>,
+        raw: &str,
+        error: &serde_json::Error,
+    ) -> WireErrorAction {
+        self.inner.on_wire_error(session, raw, error)
+    }
+
+    fn assert_invariants(&self) -> Result<(), InvariantViolation> {
+        self.inner.assert_invariants()
+    }
+
+    fn export_sessions(&self) -> Vec<ExportedSession> {
+        self.inner.export_sessions()
+    }
+
+    fn import_sessions(
+        &mut self,
+        sessions: Vec<ExportedSession>,
+    ) -> Result<(), ImportSessionError> {
+        self.inner.import_sessions(sessions)
+    }
+
+    fn generate_session_token(&self, session: &Session, signing_key: &[u8]) -> SessionToken {
+        self.inner.generate_session_token(session, signing_key)
+    }
+
+    fn verify_session_token(&self, token: &SessionToken, signing_key: &[u8]) -> Option<Session> {
+        self.inner.verify_session_token(token, signing_key)
+    }
+
+    fn expected_snapshot_type(&self) -> WireType {
+        self.inner.expected_snapshot_type()
+    }
+
+    fn expected_intent_type(&self) -> WireType {
+        self.inner.expected_intent_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Add, TestRoom};
+
+    fn session() -> Session {
+        Session::new(1, Identity::local("alice"), "alice".into())
+    }
+
+    #[test]
+    fn deltas_come_from_retained_events() {
+        let mut room = DeltaAuthority::new(TestRoom::new()).retain_events(true);
+        room.on_connect(&session()).unwrap();
+        room.handle_intent(&session(), Add { by: 2 }).unwrap();
+        room.handle_intent(&session(), Add { by: 3 }).unwrap();
+        assert_eq!(room.events().len(), 3);
+
+        assert_eq!(room.take_delta(), Some(5u32.to_be_bytes().to_vec()));
+        assert!(room.events().is_empty());
+        assert_eq!(room.take_delta(), None);
+    }
+
+    #[test]
+    fn events_not_retained_by_default() {
+        let mut room = DeltaAuthority::new(TestRoom::new());
+        room.handle_intent(&session(), Add { by: 2 }).unwrap();
+        assert!(room.events().is_empty());
+        assert_eq!(room.inner().total(), 2);
+    }
+}
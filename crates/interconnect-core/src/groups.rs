@@ -0,0 +1,170 @@
+//! Named session groups for routing broadcasts.
+//!
+//! One authority can host several broadcast domains (rooms, channels,
+//! teams). The authority decides who is in which [`Groups`] and queues
+//! messages in an [`Outbox`]; the transport delivers each message to the
+//! sessions it targets.
+
+use crate::Session;
+use std::collections::{BTreeSet, HashMap};
+
+/// Group membership, keyed by session ID.
+#[derive(Debug, Clone, Default)]
+pub struct Groups {
+    members: HashMap<String, BTreeSet<u64>>,
+}
+
+impl Groups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Manage `session`'s memberships.
+    pub fn context(&mut self, session: &Session) -> SessionContext<'_> {
+        SessionContext {
+            session_id: session.id,
+            groups: self,
+        }
+    }
+
+    /// Sessions in `group`, in ID order.
+    pub fn members(&self, group: &str) -> impl Iterator<Item = u64> + '_ {
+        self.members.get(group).into_iter().flatten().copied()
+    }
+
+    /// Groups `session_id` belongs to.
+    pub fn groups_of(&self, session_id: u64) -> Vec<&str> {
+        let mut groups: Vec<&str> = self
+            .members
+            .iter()
+            .filter(|(_, members)| members.contains(&session_id))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        groups.sort_unstable();
+        groups
+    }
+
+    /// Drop a session from every group (call from `on_disconnect`).
+    pub fn remove_session(&mut self, session_id: u64) {
+        self.members.retain(|_, members| {
+            members.remove(&session_id);
+            !members.is_empty()
+        });
+    }
+}
+
+/// One session's view of [`Groups`].
+pub struct SessionContext<'a> {
+    session_id: u64,
+    groups: &'a mut Groups,
+}
+
+impl SessionContext<'_> {
+    /// Add the session to `group`. Returns `false` if it was already in it.
+    pub fn join_group(&mut self, group: impl Into<String>) -> bool {
+        self.groups
+            .members
+            .entry(group.into())
+            .or_default()
+            .insert(self.session_id)
+    }
+
+    /// Remove the session from `group`. Returns `false` if it wasn't in it.
+    pub fn leave_group(&mut self, group: &str) -> bool {
+        let Some(members) = self.groups.members.get_mut(group) else {
+            return false;
+        };
+        let left = members.remove(&self.session_id);
+        if members.is_empty() {
+            self.groups.members.remove(group);
+        }
+        left
+    }
+
+    pub fn in_group(&self, group: &str) -> bool {
+        self.groups
+            .members
+            .get(group)
+            .is_some_and(|members| members.contains(&self.session_id))
+    }
+}
+
+/// Who a queued message is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipients {
+    /// Every connected session.
+    All,
+    /// Members of a group at delivery time.
+    Group(String),
+    /// One session.
+    Session(u64),
+}
+
+/// Messages queued by the authority for the transport to deliver.
+#[derive(Debug)]
+pub struct Outbox<M> {
+    queue: Vec<(Recipients, M)>,
+}
+
+impl<M> Default for Outbox<M> {
+    fn default() -> Self {
+        Self { queue: Vec::new() }
+    }
+}
+
+impl<M> Outbox<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn broadcast(&mut self, msg: M) {
+        self.queue.push((Recipients::All, msg));
+    }
+
+    pub fn broadcast_group(&mut self, group: impl Into<String>, msg: M) {
+        self.queue.push((Recipients::Group(group.into()), msg));
+    }
+
+    pub fn send_to(&mut self, session_id: u64, msg: M) {
+        self.queue.push((Recipients::Session(session_id), msg));
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Take the queued messages, oldest first.
+    pub fn drain(&mut self) -> impl Iterator<Item = (Recipients, M)> + '_ {
+        self.queue.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Identity;
+
+    #[test]
+    fn sessions_join_and_leave_groups() {
+        let alice = Session::new(1, Identity::local("alice"), "alice".into());
+        let bob = Session::new(2, Identity::local("bob"), "bob".into());
+        let mut groups = Groups::new();
+
+        assert!(groups.context(&alice).join_group("lobby"));
+        assert!(!groups.context(&alice).join_group("lobby"));
+        groups.context(&alice).join_group("games");
+        groups.context(&bob).join_group("lobby");
+        assert_eq!(groups.members("lobby").collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(groups.groups_of(1), ["games", "lobby"]);
+
+        assert!(groups.context(&bob).leave_group("lobby"));
+        assert!(!groups.context(&bob).in_group("lobby"));
+        groups.remove_session(1);
+        assert_eq!(groups.members("lobby").count(), 0);
+        assert!(groups.groups_of(1).is_empty());
+    }
+}
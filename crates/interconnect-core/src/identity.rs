@@ -6,17 +6,41 @@
 //! - `local:name` - Trust the connection (dev/LAN)
 //! - `url:user@server` - Server vouches for user
 //! - `ed25519:fingerprint` - Cryptographic (user holds key)
+//! - `anon:id` - Guest; no claim about who the user is
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
+/// The kind of identity, derived from its scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdentityKind {
+    /// `local:` - trust the connection.
+    Local,
+    /// `url:` - vouched for by a server.
+    Url,
+    /// `ed25519:` - cryptographic.
+    Ed25519,
+    /// `anon:` - guest.
+    Anonymous,
+    /// Any other scheme.
+    Other,
+}
+
+/// Payload characters kept by [`Identity::short_id`].
+const SHORT_ID_LEN: usize = 8;
+
 /// An identity in the form `scheme:payload`.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+///
+/// Metadata is local decoration: it isn't part of the wire form and two
+/// identities with the same scheme and payload are equal regardless of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(try_from = "String", into = "String")]
 pub struct Identity {
     scheme: String,
     payload: String,
+    metadata: serde_json::Value,
 }
 
 impl Identity {
@@ -25,6 +49,7 @@ impl Identity {
         Self {
             scheme: scheme.into(),
             payload: payload.into(),
+            metadata: serde_json::Value::Null,
         }
     }
 
@@ -33,11 +58,26 @@ impl Identity {
         Self::new("local", name)
     }
 
+    /// Create a local identity carrying display metadata.
+    ///
+    /// A string `display_name` field overrides [`Identity::display_name`].
+    pub fn local_with_metadata(name: &str, metadata: serde_json::Value) -> Self {
+        Self {
+            metadata,
+            ..Self::local(name)
+        }
+    }
+
     /// Create a URL-based (server-vouched) identity.
     pub fn url(user_at_server: impl Into<String>) -> Self {
         Self::new("url", user_at_server)
     }
 
+    /// Create an anonymous (guest) identity.
+    pub fn anonymous(id: impl Into<String>) -> Self {
+        Self::new("anon", id)
+    }
+
     /// The scheme (e.g., "local", "url", "ed25519").
     pub fn scheme(&self) -> &str {
         &self.scheme
@@ -48,10 +88,75 @@ impl Identity {
         &self.payload
     }
 
+    /// Local metadata (`Null` unless set; never sent over the wire).
+    pub fn metadata(&self) -> &serde_json::Value {
+        &self.metadata
+    }
+
+    /// A human-readable name: the metadata's `display_name` if set,
+    /// otherwise the user part of a `url:` identity, a [`short_id`] for
+    /// key fingerprints, and the payload for the rest.
+    ///
+    /// [`short_id`]: Identity::short_id
+    pub fn display_name(&self) -> String {
+        if let Some(name) = self.metadata.get("display_name").and_then(|v| v.as_str()) {
+            return name.to_string();
+        }
+        match self.kind() {
+            IdentityKind::Url => self
+                .payload
+                .split_once('@')
+                .map_or(self.payload.as_str(), |(user, _)| user)
+                .to_string(),
+            IdentityKind::Ed25519 => self.short_id(),
+            _ => self.payload.clone(),
+        }
+    }
+
+    /// `scheme:payload` with the payload cut to its first few characters,
+    /// for logs.
+    pub fn short_id(&self) -> String {
+        match self.payload.char_indices().nth(SHORT_ID_LEN) {
+            Some((end, _)) => format!("{}:{}…", self.scheme, &self.payload[..end]),
+            None => self.to_string(),
+        }
+    }
+
     /// Check if this is a local (unverified) identity.
     pub fn is_local(&self) -> bool {
         self.scheme == "local"
     }
+
+    /// The kind of identity.
+    pub fn kind(&self) -> IdentityKind {
+        match self.scheme.as_str() {
+            "local" => IdentityKind::Local,
+            "url" => IdentityKind::Url,
+            "ed25519" => IdentityKind::Ed25519,
+            "anon" => IdentityKind::Anonymous,
+            _ => IdentityKind::Other,
+        }
+    }
+
+    /// Check if this is an anonymous (guest) identity.
+    pub fn is_anonymous(&self) -> bool {
+        self.kind() == IdentityKind::Anonymous
+    }
+}
+
+impl PartialEq for Identity {
+    fn eq(&self, other: &Self) -> bool {
+        self.scheme == other.scheme && self.payload == other.payload
+    }
+}
+
+impl Eq for Identity {}
+
+impl Hash for Identity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.scheme.hash(state);
+        self.payload.hash(state);
+    }
 }
 
 impl fmt::Display for Identity {
@@ -72,10 +177,7 @@ impl FromStr for Identity {
             return Err(IdentityParseError::EmptyScheme);
         }
 
-        Ok(Self {
-            scheme: scheme.to_string(),
-            payload: payload.to_string(),
-        })
+        Ok(Self::new(scheme, payload))
     }
 }
 
@@ -87,12 +189,116 @@ impl TryFrom<String> for Identity {
     }
 }
 
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Identity {
+    fn schema_name() -> String {
+        "Identity".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::r#gen::SchemaGenerator) -> schemars::schema::Schema {
+        // Sent as its `scheme:payload` string
+        schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            string: Some(Box::new(schemars::schema::StringValidation {
+                pattern: Some("^[^:]+:".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 impl From<Identity> for String {
     fn from(id: Identity) -> Self {
         id.to_string()
     }
 }
 
+/// A server's name, normalized so spelling variants match.
+///
+/// Surrounding whitespace is trimmed and inner runs collapse to one space;
+/// comparison ignores case, so `"Room"` and `"room "` are the same server.
+/// Displays as given (after trimming) and travels as a plain string.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct ServerName {
+    name: String,
+    /// Case-folded form used for comparison and identity.
+    key: String,
+}
+
+impl ServerName {
+    pub fn new(name: &str) -> Self {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        let key = name.to_lowercase();
+        Self { name, key }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// The server's local identity, the same for every spelling.
+    pub fn identity(&self) -> Identity {
+        Identity::local(&self.key)
+    }
+
+    /// Whether `other` names this server, e.g. a requested destination.
+    pub fn matches(&self, other: &str) -> bool {
+        *self == Self::new(other)
+    }
+}
+
+impl PartialEq for ServerName {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for ServerName {}
+
+impl Hash for ServerName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+    }
+}
+
+impl fmt::Display for ServerName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl From<String> for ServerName {
+    fn from(name: String) -> Self {
+        Self::new(&name)
+    }
+}
+
+impl From<&str> for ServerName {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl From<ServerName> for String {
+    fn from(name: ServerName) -> Self {
+        name.name
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for ServerName {
+    fn schema_name() -> String {
+        "ServerName".to_string()
+    }
+
+    fn json_schema(generator: &mut schemars::r#gen::SchemaGenerator) -> schemars::schema::Schema {
+        <String as schemars::JsonSchema>::json_schema(generator)
+    }
+}
+
 /// Error parsing an identity string.
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum IdentityParseError {
@@ -102,6 +308,48 @@ pub enum IdentityParseError {
     EmptyScheme,
 }
 
+/// Why an identity was refused (see `Authority::validate_identity`).
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum IdentityError {
+    #[error("identity payload must be at least {min_len} characters")]
+    TooShort { min_len: usize },
+    #[error("identity must be {expected}")]
+    InvalidFormat { expected: String },
+    #[error("identity is not trusted")]
+    NotTrusted,
+    #[error("identity is banned")]
+    Banned,
+}
+
+type IdentityCheck = Box<dyn Fn(&Identity) -> Result<(), IdentityError> + Send + Sync>;
+
+/// Runs several identity checks in order, stopping at the first failure.
+#[derive(Default)]
+pub struct CompositeIdentityValidator {
+    checks: Vec<IdentityCheck>,
+}
+
+impl CompositeIdentityValidator {
+    /// Create a validator that accepts everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a check after those already added.
+    pub fn with(
+        mut self,
+        check: impl Fn(&Identity) -> Result<(), IdentityError> + Send + Sync + 'static,
+    ) -> Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    /// Run the checks, returning the first error.
+    pub fn validate(&self, identity: &Identity) -> Result<(), IdentityError> {
+        self.checks.iter().try_for_each(|check| check(identity))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,6 +370,79 @@ mod tests {
         assert!(!id.is_local());
     }
 
+    #[test]
+    fn kind_from_scheme() {
+        assert_eq!(Identity::local("a").kind(), IdentityKind::Local);
+        assert_eq!(Identity::url("a@b").kind(), IdentityKind::Url);
+        assert!(Identity::anonymous("x1").is_anonymous());
+        assert_eq!(Identity::new("custom", "a").kind(), IdentityKind::Other);
+    }
+
+    #[test]
+    fn composite_validator_returns_first_error() {
+        let validator = CompositeIdentityValidator::new()
+            .with(|id| {
+                if id.payload().len() < 3 {
+                    Err(IdentityError::TooShort { min_len: 3 })
+                } else {
+                    Ok(())
+                }
+            })
+            .with(|id| {
+                if id.is_local() {
+                    Err(IdentityError::NotTrusted)
+                } else {
+                    Ok(())
+                }
+            });
+
+        assert!(
+            validator
+                .validate(&Identity::url("alice@example.com"))
+                .is_ok()
+        );
+        assert_eq!(
+            validator.validate(&Identity::local("al")),
+            Err(IdentityError::TooShort { min_len: 3 })
+        );
+        assert_eq!(
+            validator.validate(&Identity::local("alice")),
+            Err(IdentityError::NotTrusted)
+        );
+    }
+
+    #[test]
+    fn display_names() {
+        assert_eq!(Identity::url("alice@example.com").display_name(), "alice");
+        assert_eq!(Identity::local("bob").display_name(), "bob");
+        let key = Identity::new("ed25519", "3f9a1c2b7d4e5f60");
+        assert_eq!(key.display_name(), "ed25519:3f9a1c2b…");
+        assert_eq!(Identity::local("bob").short_id(), "local:bob");
+
+        let named =
+            Identity::local_with_metadata("bob", serde_json::json!({ "display_name": "Bobby" }));
+        assert_eq!(named.display_name(), "Bobby");
+        // Metadata doesn't change who it is
+        assert_eq!(named, Identity::local("bob"));
+        assert_eq!(named.to_string(), "local:bob");
+    }
+
+    #[test]
+    fn server_names_normalize() {
+        let name = ServerName::new("  Room   One ");
+        assert_eq!(name.as_str(), "Room One");
+        assert_eq!(name, ServerName::new("room one"));
+        assert!(name.matches("ROOM one\t"));
+        assert!(!name.matches("Room Two"));
+        assert_eq!(name.identity(), ServerName::new("room one ").identity());
+
+        // A plain string on the wire
+        let json = serde_json::to_string(&name).unwrap();
+        assert_eq!(json, "\"Room One\"");
+        let back: ServerName = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_str(), "Room One");
+    }
+
     #[test]
     fn roundtrip() {
         let id = Identity::local("bob");
@@ -0,0 +1,261 @@
+//! Reusable import policies.
+//!
+//! An [`ImportPolicy`] sanitizes an arriving passport and records what it
+//! rejected or changed. Policies compose with [`Chain`], so an authority's
+//! `on_transfer_in` can call one `apply` and each rule can be tested on its
+//! own. Closures `Fn(&Session, P) -> ImportResult<P>` are policies too.
+
+use crate::{ImportResult, Rejection, Session};
+use std::fmt::Display;
+
+/// Sanitizes a passport on transfer-in.
+pub trait ImportPolicy<P>: Send + Sync {
+    /// Apply the policy, returning the sanitized passport.
+    fn apply(&self, session: &Session, passport: P) -> ImportResult<P>;
+
+    /// Apply `next` after this policy.
+    fn then<B: ImportPolicy<P>>(self, next: B) -> Chain<Self, B>
+    where
+        Self: Sized,
+    {
+        Chain::new(self, next)
+    }
+}
+
+impl<P, F> ImportPolicy<P> for F
+where
+    F: Fn(&Session, P) -> ImportResult<P> + Send + Sync,
+{
+    fn apply(&self, session: &Session, passport: P) -> ImportResult<P> {
+        self(session, passport)
+    }
+}
+
+/// Two policies in sequence; the second sees the first's output.
+///
+/// Rejections and transforms from both are kept, first policy first.
+#[derive(Debug, Clone)]
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Chain<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<P, A: ImportPolicy<P>, B: ImportPolicy<P>> ImportPolicy<P> for Chain<A, B> {
+    fn apply(&self, session: &Session, passport: P) -> ImportResult<P> {
+        let mut first = self.first.apply(session, passport);
+        let second = self.second.apply(session, first.passport);
+        first.rejected.extend(second.rejected);
+        first.transformed.extend(second.transformed);
+        ImportResult {
+            passport: second.passport,
+            ..first
+        }
+    }
+}
+
+/// Keeps only the items of a list whose key is allowed.
+pub struct AllowList<P, T> {
+    items: fn(&mut P) -> &mut Vec<T>,
+    key: fn(&T) -> &str,
+    allowed: Vec<String>,
+}
+
+impl<P, T> AllowList<P, T> {
+    /// Filter the list `items` selects, by the key `key` extracts.
+    pub fn new(
+        items: fn(&mut P) -> &mut Vec<T>,
+        key: fn(&T) -> &str,
+        allowed: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            items,
+            key,
+            allowed: allowed.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<P, T> ImportPolicy<P> for AllowList<P, T> {
+    fn apply(&self, _session: &Session, passport: P) -> ImportResult<P> {
+        retain_items(
+            passport,
+            self.items,
+            self.key,
+            ("not_allowed", "not allowed"),
+            |key| self.allowed.iter().any(|a| a == key),
+        )
+    }
+}
+
+/// Removes the items of a list whose key is denied.
+pub struct DenyList<P, T> {
+    items: fn(&mut P) -> &mut Vec<T>,
+    key: fn(&T) -> &str,
+    denied: Vec<String>,
+}
+
+impl<P, T> DenyList<P, T> {
+    /// Filter the list `items` selects, by the key `key` extracts.
+    pub fn new(
+        items: fn(&mut P) -> &mut Vec<T>,
+        key: fn(&T) -> &str,
+        denied: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            items,
+            key,
+            denied: denied.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<P, T> ImportPolicy<P> for DenyList<P, T> {
+    fn apply(&self, _session: &Session, passport: P) -> ImportResult<P> {
+        retain_items(
+            passport,
+            self.items,
+            self.key,
+            ("denied", "denied"),
+            |key| !self.denied.iter().any(|d| d == key),
+        )
+    }
+}
+
+fn retain_items<P, T>(
+    mut passport: P,
+    items: fn(&mut P) -> &mut Vec<T>,
+    key: fn(&T) -> &str,
+    (code, reason): (&str, &str),
+    keep: impl Fn(&str) -> bool,
+) -> ImportResult<P> {
+    let mut rejected = Vec::new();
+    items(&mut passport).retain(|item| {
+        let key = key(item);
+        let kept = keep(key);
+        if !kept {
+            rejected.push(Rejection::new(key, reason).with_code(code));
+        }
+        kept
+    });
+    ImportResult::with_rejections(passport, rejected)
+}
+
+/// Clamps a numeric field into a range.
+pub struct Clamp<P, N> {
+    item: String,
+    field: fn(&mut P) -> &mut N,
+    min: N,
+    max: N,
+}
+
+impl<P, N> Clamp<P, N> {
+    /// Clamp the field `field` selects to `min..=max`, recording changes
+    /// under `item`.
+    pub fn new(item: impl Into<String>, field: fn(&mut P) -> &mut N, min: N, max: N) -> Self {
+        Self {
+            item: item.into(),
+            field,
+            min,
+            max,
+        }
+    }
+}
+
+impl<P, N> ImportPolicy<P> for Clamp<P, N>
+where
+    N: PartialOrd + Copy + Display + Send + Sync,
+{
+    fn apply(&self, _session: &Session, mut passport: P) -> ImportResult<P> {
+        let value = (self.field)(&mut passport);
+        let from = *value;
+        if from < self.min {
+            *value = self.min;
+        } else if from > self.max {
+            *value = self.max;
+        } else {
+            return ImportResult::accept(passport);
+        }
+        let to = *value;
+        ImportResult::builder(passport)
+            .transform(&self.item, from.to_string(), to.to_string())
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Identity;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Passport {
+        roles: Vec<String>,
+        gold: u64,
+    }
+
+    fn roles(p: &mut Passport) -> &mut Vec<String> {
+        &mut p.roles
+    }
+
+    fn gold(p: &mut Passport) -> &mut u64 {
+        &mut p.gold
+    }
+
+    fn session() -> Session {
+        Session::new(1, Identity::local("alice"), "alice".into())
+    }
+
+    fn arriving() -> Passport {
+        Passport {
+            roles: vec!["admin".into(), "player".into()],
+            gold: 5000,
+        }
+    }
+
+    #[test]
+    fn strip_admin_and_clamp_gold() {
+        let policy =
+            DenyList::new(roles, String::as_str, ["admin"]).then(Clamp::new("gold", gold, 0, 1000));
+
+        let result = policy.apply(&session(), arriving());
+        assert_eq!(
+            result.passport,
+            Passport {
+                roles: vec!["player".into()],
+                gold: 1000,
+            }
+        );
+        assert_eq!(result.rejected[0].item, "admin");
+        assert_eq!(result.rejected[0].code.as_deref(), Some("denied"));
+        assert_eq!(result.transformed[0].from, "5000");
+        assert_eq!(result.transformed[0].to, "1000");
+    }
+
+    #[test]
+    fn allow_list_keeps_only_listed_items() {
+        let policy = AllowList::new(roles, String::as_str, ["player", "moderator"]);
+        let result = policy.apply(&session(), arriving());
+        assert_eq!(result.passport.roles, ["player"]);
+        assert_eq!(result.rejected.len(), 1);
+    }
+
+    #[test]
+    fn closures_are_policies() {
+        let zero_gold = |_: &Session, passport: Passport| {
+            ImportResult::accept(Passport {
+                gold: 0,
+                ..passport
+            })
+        };
+        let result =
+            Chain::new(zero_gold, Clamp::new("gold", gold, 10, 20)).apply(&session(), arriving());
+        assert_eq!(result.passport.gold, 10);
+        assert_eq!(result.transformed.len(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+//! Signing keys that can be rotated without a cutover.
+//!
+//! Passports and reconnect tokens are signed with HMAC-SHA256 under a key
+//! the servers of a federation share. Replacing that key outright would
+//! invalidate everything in flight. An [`IdentityKeyring`] signs with its
+//! current key but still accepts the keys it replaced for an overlap
+//! window, so servers can pick up a new key one at a time while transfers
+//! keep working.
+
+use crate::authority::HmacSha256;
+use crate::{Authority, Session, SessionToken, Timestamp};
+use hmac::Mac;
+use std::fmt;
+use std::time::Duration;
+
+/// A secret signing key, and the ID it's published under.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SigningKey {
+    id: String,
+    secret: Vec<u8>,
+}
+
+impl SigningKey {
+    /// A key the operator names `id` (e.g. `"2026-10"`).
+    ///
+    /// The ID goes out in every signed manifest, so it's assigned rather
+    /// than derived from the secret, which would let anyone test guesses
+    /// at the secret offline. Servers sharing a key give it the same ID.
+    pub fn new(id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            id: id.into(),
+            secret: secret.into(),
+        }
+    }
+
+    /// The ID the key is published under; says nothing about the secret.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    fn mac(&self, message: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(message);
+        mac
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The current signing key and the recent keys it replaced.
+#[derive(Debug, Clone)]
+pub struct IdentityKeyring {
+    current: SigningKey,
+    /// Replaced keys, most recent first, each with when it was replaced.
+    previous: Vec<(SigningKey, Timestamp)>,
+    overlap: Duration,
+}
+
+impl IdentityKeyring {
+    /// Sign with `current`, accepting keys it later replaces for `overlap`.
+    pub fn new(current: SigningKey, overlap: Duration) -> Self {
+        Self {
+            current,
+            previous: Vec::new(),
+            overlap,
+        }
+    }
+
+    /// The key new signatures use.
+    pub fn current(&self) -> &SigningKey {
+        &self.current
+    }
+
+    /// How long a replaced key is still accepted.
+    pub fn overlap(&self) -> Duration {
+        self.overlap
+    }
+
+    /// Sign with `new_key` from now on, accepting the current key for the
+    /// overlap window.
+    pub fn rotate(&mut self, new_key: SigningKey) {
+        self.rotate_at(new_key, Timestamp::now());
+    }
+
+    fn rotate_at(&mut self, new_key: SigningKey, now: Timestamp) {
+        let old = std::mem::replace(&mut self.current, new_key);
+        self.previous.insert(0, (old, now));
+        let overlap = self.overlap;
+        self.previous
+            .retain(|(_, replaced_at)| now < replaced_at.saturating_add(overlap));
+    }
+
+    /// Keys whose signatures are accepted: the current one first, then
+    /// those replaced within the overlap window.
+    pub fn accepted_keys(&self) -> impl Iterator<Item = &SigningKey> {
+        self.accepted_keys_at(Timestamp::now())
+    }
+
+    fn accepted_keys_at(&self, now: Timestamp) -> impl Iterator<Item = &SigningKey> {
+        let overlap = self.overlap;
+        let previous = self
+            .previous
+            .iter()
+            .filter(move |(_, replaced_at)| now < replaced_at.saturating_add(overlap))
+            .map(|(key, _)| key);
+        std::iter::once(&self.current).chain(previous)
+    }
+
+    /// IDs of the accepted keys, current first, for the manifest.
+    pub fn key_ids(&self) -> Vec<String> {
+        self.accepted_keys().map(|key| key.id.clone()).collect()
+    }
+
+    /// Sign `message` with the current key.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.current.mac(message).finalize().into_bytes().to_vec()
+    }
+
+    /// Whether `signature` over `message` is from an accepted key.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        self.verify_at(message, signature, Timestamp::now())
+    }
+
+    fn verify_at(&self, message: &[u8], signature: &[u8], now: Timestamp) -> bool {
+        self.accepted_keys_at(now)
+            .any(|key| key.mac(message).verify_slice(signature).is_ok())
+    }
+
+    /// Issue `authority`'s reconnect token for `session`, signed with the
+    /// current key (see [`Authority::generate_session_token`]).
+    pub fn sign_session_token<A: Authority>(
+        &self,
+        authority: &A,
+        session: &Session,
+    ) -> SessionToken {
+        authority.generate_session_token(session, &self.current.secret)
+    }
+
+    /// The session in a reconnect token `authority` accepts under any
+    /// accepted key (see [`Authority::verify_session_token`]).
+    pub fn verify_session_token<A: Authority>(
+        &self,
+        authority: &A,
+        token: &SessionToken,
+    ) -> Option<Session> {
+        self.accepted_keys()
+            .find_map(|key| authority.verify_session_token(token, &key.secret))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRoom;
+    use crate::{Identity, Passport, Transfer};
+
+    #[test]
+    fn replaced_keys_are_accepted_for_the_overlap() {
+        let mut keyring =
+            IdentityKeyring::new(SigningKey::new("k1", "old secret"), Duration::from_secs(60));
+        let old_signature = keyring.sign(b"passport");
+        let start = Timestamp::from_secs(1_000);
+
+        keyring.rotate_at(SigningKey::new("k2", "new secret"), start);
+        assert_ne!(keyring.sign(b"passport"), old_signature);
+        assert!(keyring.verify_at(b"passport", &old_signature, start));
+        assert!(!keyring.verify_at(b"forged", &old_signature, start));
+        assert_eq!(keyring.accepted_keys_at(start).count(), 2);
+
+        let later = start.saturating_add(Duration::from_secs(60));
+        assert!(!keyring.verify_at(b"passport", &old_signature, later));
+        assert_eq!(keyring.accepted_keys_at(later).count(), 1);
+    }
+
+    #[test]
+    fn transfers_verify_across_a_rotation() {
+        let old = SigningKey::new("k1", "old secret");
+        let mut origin = IdentityKeyring::new(old.clone(), Duration::from_secs(3600));
+        let mut destination = origin.clone();
+
+        // The destination picks up the new key first
+        destination.rotate(SigningKey::new("k2", "new secret"));
+        let passport = Passport::new(Identity::local("alice"), b"inventory".to_vec());
+        let transfer = Transfer::new("ws://destination", passport.sign(&origin));
+        assert!(transfer.verify(&destination));
+
+        origin.rotate(SigningKey::new("k2", "new secret"));
+        assert_eq!(origin.key_ids(), destination.key_ids());
+        assert_eq!(origin.key_ids()[1], old.id());
+
+        let unsigned = Transfer::new(
+            "ws://destination",
+            Passport::new(Identity::local("bob"), Vec::new()),
+        );
+        assert!(!unsigned.verify(&destination));
+    }
+
+    #[test]
+    fn session_tokens_verify_across_a_rotation() {
+        let room = TestRoom::new();
+        let mut keyring =
+            IdentityKeyring::new(SigningKey::new("k1", "old secret"), Duration::from_secs(60));
+        let session = Session::new(1, Identity::local("alice"), "Alice".into());
+        let token = keyring.sign_session_token(&room, &session);
+
+        keyring.rotate(SigningKey::new("k2", "new secret"));
+        let restored = keyring.verify_session_token(&room, &token).unwrap();
+        assert_eq!(restored.name, "Alice");
+
+        let stranger = IdentityKeyring::new(SigningKey::new("k1", "other"), Duration::ZERO);
+        assert!(stranger.verify_session_token(&room, &token).is_none());
+    }
+
+    #[test]
+    fn debug_hides_the_secret() {
+        let key = SigningKey::new("2026-10", "hunter2");
+        assert!(!format!("{key:?}").contains("hunter2"));
+        assert_eq!(key.id(), "2026-10");
+    }
+}
@@ -7,7 +7,7 @@
 //!
 //! 1. Define your types (Intent, Snapshot, Passport)
 //! 2. Implement [`SimpleAuthority`] or [`Authority`]
-//! 3. Use a transport crate to run your server
+//! 3. Use a transport crate to run your server (e.g. `interconnect_server::spawn_authority`)
 //!
 //! # Example
 //!
@@ -34,32 +34,208 @@
 //! }
 //! ```
 
+mod alias;
 mod authority;
+mod budget;
+mod canonical;
+mod capabilities;
+mod checkpoint;
+mod client;
+mod ephemeral;
+mod events;
+mod groups;
 mod identity;
+mod import_policy;
+mod keyring;
 mod message;
+mod middleware;
+mod policy;
+mod presence;
+mod quality;
+mod query;
+mod redact;
+mod relevance;
+mod retention;
+mod router;
+mod scenario;
+pub mod testing;
+mod time;
 mod transfer;
 mod wire;
 
-pub use authority::{Authority, ImportResult, Rejection, Session, SimpleAuthority};
-pub use identity::Identity;
+pub use alias::{AliasDeserializer, AliasError, IntentAliasRegistry};
+pub use authority::{
+    Authority, AuthorityErrorAction, ConnectInfo, ConnectionEvent, ConnectionEventKind,
+    ConnectionEventLog, DisconnectReason, ExportedSession, ImportResult, ImportResultBuilder,
+    ImportSessionError, IntentPriority, InvariantViolation, LARGE_PASSPORT_BYTES,
+    LargePassportAction, LoopbackAction, OptimisticOutcome, PartyImportResult,
+    PassportDecodeAction, RecordingAuthority, RecoveryAttempt, Rejection, Session, SessionToken,
+    SimpleAuthority, Transform, WireErrorAction, WireType, type_hash,
+};
+pub use budget::SnapshotBudget;
+pub use canonical::canonical_bytes;
+pub use capabilities::Capabilities;
+pub use checkpoint::Persistable;
+pub use client::{
+    ClientConnectionHandler, ClientStateMachine, IntentTracker, QueryProgress, QueryReassembler,
+    SystemEvent,
+};
+pub use ephemeral::{Ephemeral, unexpired};
+pub use events::{AuthorityEvent, DeltaAuthority};
+pub use groups::{Groups, Outbox, Recipients, SessionContext};
+pub use identity::{CompositeIdentityValidator, Identity, IdentityError, IdentityKind, ServerName};
+pub use import_policy::{AllowList, Chain, Clamp, DenyList, ImportPolicy};
+pub use keyring::{IdentityKeyring, SigningKey};
 pub use message::{ClientMessage, ServerMessage};
-pub use transfer::{Passport, Transfer};
-pub use wire::{from_json, from_json_str, to_json, to_json_string, ClientWire, ServerWire, Wire};
+pub use middleware::{AuthorityMiddleware, Layered};
+pub use policy::{
+    FieldPresenceValidator, FieldRangeValidator, PassportValidationChain, PassportValidationError,
+    PassportValidator,
+};
+pub use presence::{PresenceDelta, Roster, presence_key};
+pub use quality::{ClientPrediction, ConnectionQuality};
+pub use query::{QueryError, QueryPage};
+pub use redact::{REDACTED, RedactPaths, Redactor, TypeNameOnly};
+pub use relevance::{EntitySnapshot, RelevanceConfig, RelevanceFilter};
+pub use retention::{BudgetAccount, ByteSize, EvictionRequest, MemoryBudget, RingLog};
+pub use router::{LocalTransferResult, RouterAuthority, TransferError};
+pub use scenario::{
+    AuthorityScenario, ScenarioResult, ScenarioTiming, TestSessionScript, load_scenario_from_json,
+    run_scenario,
+};
+pub use testing::{FingerprintAssert, PendingBroadcastQueue, fingerprint};
+pub use time::Timestamp;
+pub use transfer::{
+    Passport, PassportCache, PassportUpdate, Transfer, TransferSnapshot, split_transfer_snapshot,
+};
+#[cfg(feature = "schema")]
+pub use wire::wire_schema;
+pub use wire::{
+    ADMIN_TOPIC, Broadcaster, ClientWire, Delivery, ErrorCode, LifecycleEvent, ServerWire,
+    SessionEncoding, SystemCategory, TagCase, WIRE_SCHEMA_VERSION, Wire, WireConfig, WireEncoding,
+    WireError, decode_batch, encode_batch, from_json, from_json_str, to_json, to_json_string,
+    wire_format_from_content_type,
+};
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 /// Manifest describing a server's capabilities and requirements.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Manifest {
     /// Server's identity (for verification).
     pub identity: Identity,
     /// Human-readable server name.
-    pub name: String,
+    pub name: ServerName,
     /// Substrate hash (if applicable).
     pub substrate: Option<String>,
     /// Additional metadata (app-defined).
     #[serde(default)]
     pub metadata: serde_json::Value,
+    /// Hash of the authority's snapshot type (see [`type_hash`]).
+    #[serde(default)]
+    pub snapshot_type: Option<String>,
+    /// Hash of the authority's intent type (see [`type_hash`]).
+    #[serde(default)]
+    pub intent_type: Option<String>,
+    /// Where clients reach this server (e.g. `ws://host:port`), so
+    /// transfers back to it can be caught (see [`Manifest::is_endpoint`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// IDs of the keys the server signs with and accepts, current first
+    /// (see [`IdentityKeyring::key_ids`]). Peers comparing them can tell a
+    /// key rotation in progress from a mismatched key.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signing_keys: Vec<String>,
+    /// Signature over the rest of the manifest (see [`Manifest::sign`]), so
+    /// a peer can trust it when a client relays it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Vec<u8>>,
+}
+
+impl Manifest {
+    /// Advertise the authority's snapshot and intent types.
+    pub fn with_types<A: Authority>(mut self, authority: &A) -> Self {
+        self.snapshot_type = Some(type_hash(authority.expected_snapshot_type()));
+        self.intent_type = Some(type_hash(authority.expected_intent_type()));
+        self
+    }
+
+    /// Advertise the keyring's accepted keys.
+    pub fn with_keyring(mut self, keyring: &IdentityKeyring) -> Self {
+        self.signing_keys = keyring.key_ids();
+        self
+    }
+
+    /// Sign with `keyring`'s current key. Sign last: changing the manifest
+    /// afterwards breaks the signature.
+    pub fn sign(mut self, keyring: &IdentityKeyring) -> Self {
+        self.signature = Some(keyring.sign(&self.signed_bytes()));
+        self
+    }
+
+    /// Whether the manifest is signed by a key `keyring` accepts.
+    pub fn verify(&self, keyring: &IdentityKeyring) -> bool {
+        self.signature
+            .as_deref()
+            .is_some_and(|signature| keyring.verify(&self.signed_bytes(), signature))
+    }
+
+    /// Everything but the signature, as signed.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = Self {
+            signature: None,
+            ..self.clone()
+        };
+        canonical_bytes(&unsigned).expect("manifests serialize")
+    }
+
+    /// Set the metadata from an app-defined struct.
+    pub fn with_typed_metadata<T: Serialize>(
+        mut self,
+        metadata: &T,
+    ) -> Result<Self, serde_json::Error> {
+        self.metadata = serde_json::to_value(metadata)?;
+        Ok(self)
+    }
+
+    /// Read the metadata as an app-defined struct.
+    pub fn typed_metadata<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        T::deserialize(&self.metadata)
+    }
+
+    /// Whether `destination` is this server's [`endpoint`](Self::endpoint).
+    ///
+    /// Ignores ASCII case and trailing slashes. Always false without an
+    /// endpoint.
+    pub fn is_endpoint(&self, destination: &str) -> bool {
+        let trim = |url: &str| url.trim().trim_end_matches('/').to_string();
+        self.endpoint
+            .as_deref()
+            .is_some_and(|endpoint| trim(endpoint).eq_ignore_ascii_case(&trim(destination)))
+    }
+
+    /// Check the advertised types against the client's.
+    ///
+    /// Returns the `TypeMismatch` message to send if they differ. It carries
+    /// both the snapshot and the intent hashes, whichever differs. Manifests
+    /// without type hashes always pass.
+    pub fn check_types<I>(&self, snapshot: WireType, intent: WireType) -> Option<ClientWire<I>> {
+        let snapshot = type_hash(snapshot);
+        let intent = type_hash(intent);
+        let snapshot_ok = self.snapshot_type.as_ref().is_none_or(|t| *t == snapshot);
+        let intent_ok = self.intent_type.as_ref().is_none_or(|t| *t == intent);
+        if snapshot_ok && intent_ok {
+            return None;
+        }
+        Some(ClientWire::TypeMismatch {
+            expected_snapshot: self.snapshot_type.clone().unwrap_or_default(),
+            got: snapshot,
+            expected_intent: self.intent_type.clone().unwrap_or_default(),
+            got_intent: intent,
+        })
+    }
 }
 
 /// Connection lifecycle state.
@@ -71,6 +247,188 @@ pub enum ConnectionState {
     Syncing,
     /// Normal operation.
     Live,
-    /// Authority lost, read-only mode.
+    /// Backgrounded; the server holds updates until resume.
+    Paused,
+    /// Authority lost (disconnected, or the server marked its snapshots
+    /// stale), read-only mode.
     Ghost,
 }
+
+impl ConnectionState {
+    /// Whether intents may be sent (only while `Live`).
+    pub fn is_writable(&self) -> bool {
+        matches!(self, Self::Live)
+    }
+
+    /// Whether state can be displayed (`Live`, the last state while
+    /// `Paused`, or `Ghost` from the substrate).
+    pub fn is_readable(&self) -> bool {
+        matches!(self, Self::Live | Self::Paused | Self::Ghost)
+    }
+
+    /// Whether a transfer may be requested (only while `Live`).
+    pub fn can_transfer(&self) -> bool {
+        matches!(self, Self::Live)
+    }
+
+    /// Whether a manifest is expected (`Connecting` or `Syncing`).
+    pub fn can_receive_manifest(&self) -> bool {
+        matches!(self, Self::Connecting | Self::Syncing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn connection_state_is_writable() {
+        assert!(!ConnectionState::Connecting.is_writable());
+        assert!(!ConnectionState::Syncing.is_writable());
+        assert!(ConnectionState::Live.is_writable());
+        assert!(!ConnectionState::Paused.is_writable());
+        assert!(!ConnectionState::Ghost.is_writable());
+    }
+
+    #[test]
+    fn connection_state_is_readable() {
+        assert!(!ConnectionState::Connecting.is_readable());
+        assert!(!ConnectionState::Syncing.is_readable());
+        assert!(ConnectionState::Live.is_readable());
+        assert!(ConnectionState::Paused.is_readable());
+        assert!(ConnectionState::Ghost.is_readable());
+    }
+
+    #[test]
+    fn connection_state_can_transfer() {
+        assert!(!ConnectionState::Connecting.can_transfer());
+        assert!(!ConnectionState::Syncing.can_transfer());
+        assert!(ConnectionState::Live.can_transfer());
+        assert!(!ConnectionState::Paused.can_transfer());
+        assert!(!ConnectionState::Ghost.can_transfer());
+    }
+
+    #[test]
+    fn connection_state_can_receive_manifest() {
+        assert!(ConnectionState::Connecting.can_receive_manifest());
+        assert!(ConnectionState::Syncing.can_receive_manifest());
+        assert!(!ConnectionState::Live.can_receive_manifest());
+        assert!(!ConnectionState::Paused.can_receive_manifest());
+        assert!(!ConnectionState::Ghost.can_receive_manifest());
+    }
+
+    #[test]
+    fn manifest_check_types() {
+        let manifest = Manifest {
+            identity: Identity::local("server"),
+            name: "server".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: Some(type_hash(WireType::of::<Vec<String>>())),
+            intent_type: Some(type_hash(WireType::of::<String>())),
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        };
+        let names = WireType::of::<Vec<String>>();
+        let text = WireType::of::<String>();
+        let number = WireType::of::<u64>();
+        assert!(manifest.check_types::<()>(names, text).is_none());
+        assert!(matches!(
+            manifest.check_types::<()>(number, text),
+            Some(ClientWire::TypeMismatch { .. })
+        ));
+
+        // An intent mismatch names both intent hashes
+        let Some(ClientWire::TypeMismatch {
+            expected_snapshot,
+            got,
+            expected_intent,
+            got_intent,
+        }) = manifest.check_types::<()>(names, number)
+        else {
+            panic!("intent types differ");
+        };
+        assert_eq!(expected_snapshot, got);
+        assert_eq!(expected_intent, type_hash(text));
+        assert_eq!(got_intent, type_hash(number));
+    }
+
+    #[test]
+    fn manifest_typed_metadata_roundtrip() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Meta {
+            max_users: usize,
+        }
+
+        let manifest = Manifest {
+            identity: Identity::local("server"),
+            name: "server".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        }
+        .with_typed_metadata(&Meta { max_users: 8 })
+        .unwrap();
+        assert_eq!(manifest.metadata, serde_json::json!({ "max_users": 8 }));
+        assert_eq!(
+            manifest.typed_metadata::<Meta>().unwrap(),
+            Meta { max_users: 8 }
+        );
+        assert!(manifest.typed_metadata::<String>().is_err());
+    }
+
+    #[test]
+    fn signed_manifests_verify_until_changed() {
+        let keyring =
+            IdentityKeyring::new(SigningKey::new("k1", "federation"), Duration::from_secs(60));
+        let manifest = Manifest {
+            identity: Identity::local("server"),
+            name: "server".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        };
+        assert!(!manifest.verify(&keyring));
+
+        let signed = manifest.sign(&keyring);
+        assert!(signed.verify(&keyring));
+        let stranger = IdentityKeyring::new(SigningKey::new("k1", "other"), Duration::ZERO);
+        assert!(!signed.verify(&stranger));
+        let forged = Manifest {
+            identity: Identity::local("elsewhere"),
+            ..signed
+        };
+        assert!(!forged.verify(&keyring));
+    }
+
+    #[test]
+    fn manifest_recognizes_its_endpoint() {
+        let mut manifest = Manifest {
+            identity: Identity::local("server"),
+            name: "server".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        };
+        assert!(!manifest.is_endpoint("ws://localhost:8001"));
+
+        manifest.endpoint = Some("ws://localhost:8001".into());
+        assert!(manifest.is_endpoint("ws://localhost:8001"));
+        assert!(manifest.is_endpoint("WS://LocalHost:8001/"));
+        assert!(!manifest.is_endpoint("ws://localhost:8002"));
+    }
+}
@@ -0,0 +1,494 @@
+//! Middleware around an authority's intent pipeline.
+//!
+//! [`Layered`] wraps an authority and runs each intent through its
+//! middleware before the authority sees it:
+//!
+//! 1. [`before_validate`](AuthorityMiddleware::before_validate) may rewrite
+//!    the intent.
+//! 2. [`validate_intent`](AuthorityMiddleware::validate_intent) may refuse it.
+//! 3. [`before_intent`](AuthorityMiddleware::before_intent) observes it.
+//! 4. The authority's `handle_intent` applies it.
+//!
+//! Each stage runs for every middleware, in the order they were added,
+//! before the next stage starts.
+//!
+//! Rewriting intents is deliberate. Normalization (clamping coordinates,
+//! filtering text, attaching server-side context) belongs in one place
+//! rather than in every handler, so a rewrite in `before_validate` is what
+//! every later stage, and the authority, sees. The client is not told its
+//! intent was changed; if that matters, refuse it in `validate_intent`
+//! instead.
+
+use crate::{
+    Authority, AuthorityErrorAction, AuthorityEvent, Capabilities, ConnectInfo, DisconnectReason,
+    ErrorCode, ExportedSession, Identity, IdentityError, ImportResult, ImportSessionError,
+    IntentPriority, InvariantViolation, LargePassportAction, LocalTransferResult, LoopbackAction,
+    Manifest, OptimisticOutcome, PartyImportResult, PassportDecodeAction, PassportUpdate,
+    PassportValidationError, QueryError, QueryPage, Session, SessionToken, SnapshotBudget,
+    TransferError, TransferSnapshot, WireErrorAction, WireType,
+};
+use serde::Serialize;
+use std::time::Duration;
+
+/// A stage in the intent pipeline of a [`Layered`] authority.
+pub trait AuthorityMiddleware<A: Authority>: Send + Sync {
+    /// Rewrite an intent before validation.
+    ///
+    /// The rewritten intent is what all later stages see.
+    fn before_validate(&mut self, _session: &Session, _intent: &mut A::Intent) {}
+
+    /// Refuse an intent. The default accepts everything.
+    fn validate_intent(&mut self, _session: &Session, _intent: &A::Intent) -> Result<(), A::Error> {
+        Ok(())
+    }
+
+    /// Observe a validated intent just before the authority handles it.
+    fn before_intent(&mut self, _session: &Session, _intent: &A::Intent) {}
+}
+
+/// An authority with middleware on its intent pipeline.
+pub struct Layered<A: Authority> {
+    inner: A,
+    middleware: Vec<Box<dyn AuthorityMiddleware<A>>>,
+}
+
+impl<A: Authority> Layered<A> {
+    /// Wrap `inner` with no middleware.
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Add a middleware after those already added.
+    pub fn layer(mut self, middleware: impl AuthorityMiddleware<A> + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// The wrapped authority.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The wrapped authority, mutably.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Run `intent` through every middleware stage.
+    fn run_middleware(
+        &mut self,
+        session: &Session,
+        intent: &mut A::Intent,
+    ) -> Result<(), A::Error> {
+        for m in &mut self.middleware {
+            m.before_validate(session, intent);
+        }
+        for m in &mut self.middleware {
+            m.validate_intent(session, intent)?;
+        }
+        for m in &mut self.middleware {
+            m.before_intent(session, intent);
+        }
+        Ok(())
+    }
+}
+
+impl<A: Authority> Authority for Layered<A> {
+    type Intent = A::Intent;
+    type Snapshot = A::Snapshot;
+    type Passport = A::Passport;
+    type Error = A::Error;
+
+    fn validate_identity(&self, identity: &Identity) -> Result<(), IdentityError> {
+        self.inner.validate_identity(identity)
+    }
+
+    fn validate_connect(
+        &self,
+        identity: &Identity,
+        info: &ConnectInfo,
+    ) -> Result<(), IdentityError> {
+        self.inner.validate_connect(identity, info)
+    }
+
+    fn on_connect(&mut self, session: &Session) -> Result<(), Self::Error> {
+        self.inner.on_connect(session)
+    }
+
+    fn on_connect_with_info(
+        &mut self,
+        session: &Session,
+        info: &ConnectInfo,
+    ) -> Result<(), Self::Error> {
+        self.inner.on_connect_with_info(session, info)
+    }
+
+    fn on_transfer_in(
+        &mut self,
+        session: &Session,
+        passport: Self::Passport,
+    ) -> Result<ImportResult<Self::Passport>, Self::Error> {
+        self.inner.on_transfer_in(session, passport)
+    }
+
+    fn on_transfer_context(&mut self, session: &Session, context: Self::Snapshot) {
+        self.inner.on_transfer_context(session, context)
+    }
+
+    fn on_party_transfer_in(
+        &mut self,
+        sessions: &[Session],
+        passports: Vec<Self::Passport>,
+    ) -> Result<PartyImportResult<Self::Passport>, Self::Error> {
+        self.inner.on_party_transfer_in(sessions, passports)
+    }
+
+    fn on_passport_decode_error(
+        &mut self,
+        session: &Session,
+        raw: &[u8],
+        error: &serde_json::Error,
+    ) -> PassportDecodeAction {
+        self.inner.on_passport_decode_error(session, raw, error)
+    }
+
+    fn on_large_passport(&mut self, session: &Session, size: usize) -> LargePassportAction {
+        self.inner.on_large_passport(session, size)
+    }
+
+    fn validate_passport(
+        &self,
+        session: &Session,
+        passport: &Self::Passport,
+    ) -> Result<(), Vec<PassportValidationError>> {
+        self.inner.validate_passport(session, passport)
+    }
+
+    fn on_disconnect(&mut self, session: &Session) {
+        self.inner.on_disconnect(session)
+    }
+
+    fn on_disconnect_batch(&mut self, sessions: &[Session], reason: DisconnectReason) {
+        self.inner.on_disconnect_batch(sessions, reason)
+    }
+
+    fn on_session_paused(&mut self, session: &Session) {
+        self.inner.on_session_paused(session)
+    }
+
+    fn on_session_resumed(&mut self, session: &Session) {
+        self.inner.on_session_resumed(session)
+    }
+
+    fn handle_intent(
+        &mut self,
+        session: &Session,
+        mut intent: Self::Intent,
+    ) -> Result<(), Self::Error> {
+        self.run_middleware(session, &mut intent)?;
+        self.inner.handle_intent(session, intent)
+    }
+
+    fn intent_priority(&self, session: &Session, intent: &Self::Intent) -> IntentPriority {
+        self.inner.intent_priority(session, intent)
+    }
+
+    fn intent_schema(&self) -> Option<serde_json::Value> {
+        self.inner.intent_schema()
+    }
+
+    fn handle_optimistic_intent(
+        &mut self,
+        session: &Session,
+        mut intent: Self::Intent,
+        base_seq: u64,
+        latest_seq: u64,
+    ) -> Result<OptimisticOutcome, Self::Error> {
+        self.run_middleware(session, &mut intent)?;
+        self.inner
+            .handle_optimistic_intent(session, intent, base_seq, latest_seq)
+    }
+
+    fn on_intent_ack(&mut self, session: &Session, intent_seq: u64) {
+        self.inner.on_intent_ack(session, intent_seq)
+    }
+
+    fn snapshot_for(&self, session: &Session) -> Self::Snapshot {
+        self.inner.snapshot_for(session)
+    }
+
+    fn coalesce_snapshots(&self, history: Vec<Self::Snapshot>) -> Option<Self::Snapshot> {
+        self.inner.coalesce_snapshots(history)
+    }
+
+    fn redact_snapshot(&self, session: &Session, snapshot: &mut Self::Snapshot) {
+        self.inner.redact_snapshot(session, snapshot)
+    }
+
+    fn translate_snapshot_for_version(
+        &self,
+        snapshot: Self::Snapshot,
+        client_version: &str,
+    ) -> Self::Snapshot {
+        self.inner
+            .translate_snapshot_for_version(snapshot, client_version)
+    }
+
+    fn compute_relevance_score(&self, session: &Session, entity_id: u64) -> f32 {
+        self.inner.compute_relevance_score(session, entity_id)
+    }
+
+    fn clone_session_state(
+        &self,
+        source_session_id: u64,
+        spectator_session_id: u64,
+    ) -> Option<Self::Snapshot> {
+        self.inner
+            .clone_session_state(source_session_id, spectator_session_id)
+    }
+
+    fn snapshot_delta_from(
+        &self,
+        session: &Session,
+        base_seq: u64,
+        base: &Self::Snapshot,
+    ) -> Option<Self::Snapshot> {
+        self.inner.snapshot_delta_from(session, base_seq, base)
+    }
+
+    fn snapshot_delta_from_events(
+        &self,
+        events: &[AuthorityEvent<Self::Intent, Self::Snapshot, Self::Passport>],
+    ) -> Option<Vec<u8>> {
+        self.inner.snapshot_delta_from_events(events)
+    }
+
+    fn query_page(
+        &self,
+        session: &Session,
+        query: &serde_json::Value,
+        cursor: Option<&str>,
+    ) -> Result<QueryPage, QueryError> {
+        self.inner.query_page(session, query, cursor)
+    }
+
+    fn emit_passport(&self, session: &Session) -> Self::Passport {
+        self.inner.emit_passport(session)
+    }
+
+    fn emit_passport_for_destination(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> Self::Passport {
+        self.inner
+            .emit_passport_for_destination(session, destination)
+    }
+
+    fn emit_incremental_passport(
+        &self,
+        session: &Session,
+        previous_version: u32,
+        previous: &Self::Passport,
+    ) -> PassportUpdate<Self::Passport> {
+        self.inner
+            .emit_incremental_passport(session, previous_version, previous)
+    }
+
+    fn emit_transfer_snapshot(
+        &self,
+        session: &Session,
+        destination: &str,
+    ) -> TransferSnapshot<Self::Snapshot, Self::Passport> {
+        self.inner.emit_transfer_snapshot(session, destination)
+    }
+
+    fn emit_party_passport(&self, sessions: &[Session]) -> Vec<Self::Passport> {
+        self.inner.emit_party_passport(sessions)
+    }
+
+    fn validate_destination(&self, destination: &str) -> bool {
+        self.inner.validate_destination(destination)
+    }
+
+    fn on_transfer_loopback(&mut self, session: &Session, destination: &str) -> LoopbackAction {
+        self.inner.on_transfer_loopback(session, destination)
+    }
+
+    fn on_local_transfer(
+        &mut self,
+        session: &Session,
+        destination: &str,
+    ) -> Option<Result<LocalTransferResult, TransferError<Self::Error>>> {
+        self.inner.on_local_transfer(session, destination)
+    }
+
+    fn passport_size_estimate(&self, session: &Session) -> usize
+    where
+        Self::Snapshot: Serialize,
+        Self::Passport: Serialize,
+    {
+        self.inner.passport_size_estimate(session)
+    }
+
+    fn authority_fingerprint(&self) -> u64
+    where
+        Self::Snapshot: Serialize,
+    {
+        self.inner.authority_fingerprint()
+    }
+
+    fn intent_type_name(intent: &Self::Intent) -> &'static str {
+        A::intent_type_name(intent)
+    }
+
+    fn presence(&self) -> Option<Vec<Identity>> {
+        self.inner.presence()
+    }
+
+    fn snapshot_topic(snapshot: &Self::Snapshot) -> Option<&'static str> {
+        A::snapshot_topic(snapshot)
+    }
+
+    fn intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value
+    where
+        Self::Intent: Serialize,
+    {
+        self.inner.intent_to_json(intent)
+    }
+
+    fn redacted_intent_to_json(&self, intent: &Self::Intent) -> serde_json::Value {
+        self.inner.redacted_intent_to_json(intent)
+    }
+
+    fn can_accept_transfer_from(&self, src_manifest: &Manifest) -> bool {
+        self.inner.can_accept_transfer_from(src_manifest)
+    }
+
+    fn capabilities_for(&self, session: &Session, default: Capabilities) -> Capabilities {
+        self.inner.capabilities_for(session, default)
+    }
+
+    fn on_budget_exceeded(
+        &mut self,
+        session: &Session,
+        snapshot_size: usize,
+        budget: &SnapshotBudget,
+    ) {
+        self.inner
+            .on_budget_exceeded(session, snapshot_size, budget)
+    }
+
+    fn on_peer_transfer_complete(
+        &mut self,
+        src_identity: &Identity,
+        accepted: u64,
+        rejected: u64,
+        elapsed: Duration,
+    ) {
+        self.inner
+            .on_peer_transfer_complete(src_identity, accepted, rejected, elapsed)
+    }
+
+    fn on_authority_error(&self, error: &Self::Error) -> AuthorityErrorAction {
+        self.inner.on_authority_error(error)
+    }
+
+    fn on_authority_recovered(&mut self, previous_error: &Self::Error) {
+        self.inner.on_authority_recovered(previous_error)
+    }
+
+    fn error_code(&self, error: &Self::Error) -> ErrorCode {
+        self.inner.error_code(error)
+    }
+
+    fn on_wire_error(
+        &mut self,
+        session: Option<&Session>,
+        raw: &str,
+        error: &serde_json::Error,
+    ) -> WireErrorAction {
+        self.inner.on_wire_error(session, raw, error)
+    }
+
+    fn assert_invariants(&self) -> Result<(), InvariantViolation> {
+        self.inner.assert_invariants()
+    }
+
+    fn export_sessions(&self) -> Vec<ExportedSession> {
+        self.inner.export_sessions()
+    }
+
+    fn import_sessions(
+        &mut self,
+        sessions: Vec<ExportedSession>,
+    ) -> Result<(), ImportSessionError> {
+        self.inner.import_sessions(sessions)
+    }
+
+    fn generate_session_token(&self, session: &Session, signing_key: &[u8]) -> SessionToken {
+        self.inner.generate_session_token(session, signing_key)
+    }
+
+    fn verify_session_token(&self, token: &SessionToken, signing_key: &[u8]) -> Option<Session> {
+        self.inner.verify_session_token(token, signing_key)
+    }
+
+    fn expected_snapshot_type(&self) -> WireType {
+        self.inner.expected_snapshot_type()
+    }
+
+    fn expected_intent_type(&self) -> WireType {
+        self.inner.expected_intent_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Add, Refused, TestRoom};
+
+    struct Double;
+
+    impl AuthorityMiddleware<TestRoom> for Double {
+        fn before_validate(&mut self, _session: &Session, intent: &mut Add) {
+            intent.by *= 2;
+        }
+    }
+
+    struct AtMostTen;
+
+    impl AuthorityMiddleware<TestRoom> for AtMostTen {
+        fn validate_intent(&mut self, _session: &Session, intent: &Add) -> Result<(), Refused> {
+            if intent.by > 10 { Err(Refused) } else { Ok(()) }
+        }
+    }
+
+    #[test]
+    fn rewrites_are_visible_to_validation_and_handler() {
+        let mut room = Layered::new(TestRoom::new()).layer(Double).layer(AtMostTen);
+        let session = Session::new(1, Identity::local("alice"), "alice".into());
+
+        room.handle_intent(&session, Add { by: 3 }).unwrap();
+        // Doubled first, so validation sees 12 and refuses
+        assert!(room.handle_intent(&session, Add { by: 6 }).is_err());
+        assert_eq!(room.inner().applied, [(1, 6)]);
+    }
+
+    #[test]
+    fn optimistic_intents_run_the_same_pipeline() {
+        let mut room = Layered::new(TestRoom::new()).layer(Double).layer(AtMostTen);
+        let session = Session::new(1, Identity::local("alice"), "alice".into());
+
+        let outcome = room.handle_optimistic_intent(&session, Add { by: 3 }, 3, 3);
+        assert_eq!(outcome.ok(), Some(OptimisticOutcome::Confirmed));
+        assert!(
+            room.handle_optimistic_intent(&session, Add { by: 6 }, 3, 3)
+                .is_err()
+        );
+        assert_eq!(room.inner().applied, [(1, 6)]);
+    }
+}
@@ -0,0 +1,234 @@
+//! Passport validation.
+//!
+//! An [`ImportPolicy`](crate::ImportPolicy) repairs a passport; a
+//! [`PassportValidator`] only judges it. Validators run before
+//! `on_transfer_in` (see [`Authority::validate_passport`]), so a passport
+//! missing a name or carrying a level out of range is refused without the
+//! authority ever importing it. A [`PassportValidationChain`] runs several
+//! and reports every failure, not just the first.
+//!
+//! [`Authority::validate_passport`]: crate::Authority::validate_passport
+
+use std::fmt::Display;
+
+/// Why a passport was refused.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{field}: {reason}")]
+pub struct PassportValidationError {
+    /// The offending field.
+    pub field: String,
+    pub reason: String,
+}
+
+impl PassportValidationError {
+    pub fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Checks an arriving passport.
+pub trait PassportValidator<P>: Send + Sync {
+    fn validate_passport(&self, passport: &P) -> Result<(), PassportValidationError>;
+}
+
+impl<P, F> PassportValidator<P> for F
+where
+    F: Fn(&P) -> Result<(), PassportValidationError> + Send + Sync,
+{
+    fn validate_passport(&self, passport: &P) -> Result<(), PassportValidationError> {
+        self(passport)
+    }
+}
+
+/// Validators run in order, collecting every failure.
+pub struct PassportValidationChain<P> {
+    validators: Vec<Box<dyn PassportValidator<P>>>,
+}
+
+impl<P> Default for PassportValidationChain<P> {
+    fn default() -> Self {
+        Self {
+            validators: Vec::new(),
+        }
+    }
+}
+
+impl<P> PassportValidationChain<P> {
+    /// Create a chain that accepts everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a validator after those already added.
+    pub fn with(mut self, validator: impl PassportValidator<P> + 'static) -> Self {
+        self.validators.push(Box::new(validator));
+        self
+    }
+
+    /// Run every validator, returning all their errors.
+    pub fn validate(&self, passport: &P) -> Result<(), Vec<PassportValidationError>> {
+        let errors: Vec<_> = self
+            .validators
+            .iter()
+            .filter_map(|validator| validator.validate_passport(passport).err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Selects a text field of a passport.
+type TextField<P> = fn(&P) -> &str;
+
+/// Requires text fields to be non-empty.
+pub struct FieldPresenceValidator<P> {
+    fields: Vec<(String, TextField<P>)>,
+}
+
+impl<P> Default for FieldPresenceValidator<P> {
+    fn default() -> Self {
+        Self { fields: Vec::new() }
+    }
+}
+
+impl<P> FieldPresenceValidator<P> {
+    /// Require nothing yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require the field `value` selects, reported as `field`.
+    pub fn require(mut self, field: impl Into<String>, value: TextField<P>) -> Self {
+        self.fields.push((field.into(), value));
+        self
+    }
+}
+
+impl<P> PassportValidator<P> for FieldPresenceValidator<P> {
+    /// Fails on the first empty field.
+    fn validate_passport(&self, passport: &P) -> Result<(), PassportValidationError> {
+        match self
+            .fields
+            .iter()
+            .find(|(_, value)| value(passport).trim().is_empty())
+        {
+            Some((field, _)) => Err(PassportValidationError::new(field, "required")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Requires a field to lie within a range.
+///
+/// Unlike [`Clamp`](crate::Clamp), an out-of-range value is refused rather
+/// than corrected.
+pub struct FieldRangeValidator<P, V> {
+    field: String,
+    value: fn(&P) -> V,
+    min: V,
+    max: V,
+}
+
+impl<P, V> FieldRangeValidator<P, V> {
+    /// Require the field `value` selects to be within `min..=max`, reported
+    /// as `field`.
+    pub fn new(field: impl Into<String>, value: fn(&P) -> V, min: V, max: V) -> Self {
+        Self {
+            field: field.into(),
+            value,
+            min,
+            max,
+        }
+    }
+}
+
+impl<P, V> PassportValidator<P> for FieldRangeValidator<P, V>
+where
+    V: Ord + Display + Send + Sync,
+{
+    fn validate_passport(&self, passport: &P) -> Result<(), PassportValidationError> {
+        let value = (self.value)(passport);
+        if value < self.min || value > self.max {
+            return Err(PassportValidationError::new(
+                &self.field,
+                format!("{} is outside {}..={}", value, self.min, self.max),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Passport {
+        name: String,
+        class: String,
+        level: u32,
+    }
+
+    fn chain() -> PassportValidationChain<Passport> {
+        PassportValidationChain::new()
+            .with(
+                FieldPresenceValidator::new()
+                    .require("name", |p: &Passport| p.name.as_str())
+                    .require("class", |p: &Passport| p.class.as_str()),
+            )
+            .with(FieldRangeValidator::new(
+                "level",
+                |p: &Passport| p.level,
+                1,
+                60,
+            ))
+    }
+
+    #[test]
+    fn valid_passports_pass() {
+        let passport = Passport {
+            name: "alice".into(),
+            class: "mage".into(),
+            level: 12,
+        };
+        assert_eq!(chain().validate(&passport), Ok(()));
+    }
+
+    #[test]
+    fn every_failure_is_reported() {
+        let passport = Passport {
+            name: " ".into(),
+            class: "mage".into(),
+            level: 99,
+        };
+        assert_eq!(
+            chain().validate(&passport),
+            Err(vec![
+                PassportValidationError::new("name", "required"),
+                PassportValidationError::new("level", "99 is outside 1..=60"),
+            ])
+        );
+    }
+
+    #[test]
+    fn closures_are_validators() {
+        let chain = PassportValidationChain::new().with(|p: &Passport| {
+            if p.class == "admin" {
+                Err(PassportValidationError::new("class", "not importable"))
+            } else {
+                Ok(())
+            }
+        });
+        let passport = Passport {
+            name: "mallory".into(),
+            class: "admin".into(),
+            level: 1,
+        };
+        assert_eq!(chain.validate(&passport).unwrap_err().len(), 1);
+    }
+}
@@ -0,0 +1,130 @@
+//! Incremental presence.
+//!
+//! Resending the whole roster in every snapshot costs O(room) per join. An
+//! authority that reports [`Authority::presence`] leaves the roster out of
+//! its snapshots instead: the transport diffs successive rosters and sends
+//! `ServerWire::Presence` with just who joined and left, and the client
+//! keeps a [`Roster`] up to date from those.
+//!
+//! [`Authority::presence`]: crate::Authority::presence
+
+use crate::{Identity, fingerprint};
+use std::collections::BTreeMap;
+
+/// Who is present, keyed by [`presence_key`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Roster {
+    members: BTreeMap<String, Identity>,
+}
+
+/// Who joined and left between two rosters.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PresenceDelta {
+    pub joined: BTreeMap<String, Identity>,
+    /// Keys of those who left.
+    pub left: Vec<String>,
+}
+
+impl PresenceDelta {
+    pub fn is_empty(&self) -> bool {
+        self.joined.is_empty() && self.left.is_empty()
+    }
+}
+
+/// The key an identity goes by in presence updates: its fingerprint.
+///
+/// The server picks the keys; clients use the ones they're sent.
+pub fn presence_key(identity: &Identity) -> String {
+    format!("{:016x}", fingerprint(identity))
+}
+
+impl Roster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.members.contains_key(key)
+    }
+
+    /// Members in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Identity)> {
+        self.members
+            .iter()
+            .map(|(key, identity)| (key.as_str(), identity))
+    }
+
+    /// Become `current`, returning who joined and left; `None` if no one did.
+    pub fn update(&mut self, current: impl IntoIterator<Item = Identity>) -> Option<PresenceDelta> {
+        let current: BTreeMap<String, Identity> = current
+            .into_iter()
+            .map(|identity| (presence_key(&identity), identity))
+            .collect();
+        let delta = PresenceDelta {
+            joined: current
+                .iter()
+                .filter(|(key, _)| !self.members.contains_key(*key))
+                .map(|(key, identity)| (key.clone(), identity.clone()))
+                .collect(),
+            left: self
+                .members
+                .keys()
+                .filter(|key| !current.contains_key(*key))
+                .cloned()
+                .collect(),
+        };
+        self.members = current;
+        (!delta.is_empty()).then_some(delta)
+    }
+
+    /// Apply a delta the server sent.
+    pub fn apply(&mut self, delta: PresenceDelta) {
+        for key in &delta.left {
+            self.members.remove(key);
+        }
+        self.members.extend(delta.joined);
+    }
+
+    pub fn clear(&mut self) {
+        self.members.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_sends_only_changes() {
+        let (a, b, c) = (
+            Identity::local("a"),
+            Identity::local("b"),
+            Identity::local("c"),
+        );
+        let mut server = Roster::new();
+        let mut client = Roster::new();
+
+        let first = server.update([a.clone(), b.clone()]).unwrap();
+        assert_eq!(first.joined.len(), 2);
+        assert!(first.left.is_empty());
+        client.apply(first);
+
+        assert_eq!(server.update([b.clone(), a.clone()]), None);
+
+        let next = server.update([b.clone(), c.clone()]).unwrap();
+        assert_eq!(next.joined.keys().collect::<Vec<_>>(), [&presence_key(&c)]);
+        assert_eq!(next.left, [presence_key(&a)]);
+        client.apply(next);
+
+        assert_eq!(client, server);
+        assert!(!client.contains(&presence_key(&a)));
+    }
+}
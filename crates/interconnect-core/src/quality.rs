@@ -0,0 +1,90 @@
+//! Measured connection quality.
+//!
+//! The transport measures each connection and keeps the result on the
+//! [`Session`](crate::Session); the authority reads it in `snapshot_for` to
+//! pick a level of detail, so slow clients degrade instead of falling
+//! behind. What a predicting client reports in its acks is kept alongside.
+
+use std::time::Duration;
+
+/// How well a session's connection is doing, as last measured.
+///
+/// Fields are `None` until the transport has a measurement.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionQuality {
+    /// Smoothed round-trip time.
+    pub rtt: Option<Duration>,
+    /// Estimated throughput to the client, in bytes per second.
+    pub est_bandwidth: Option<u64>,
+    /// Fraction of probes that went unanswered (0.0 to 1.0).
+    pub loss: f32,
+}
+
+impl ConnectionQuality {
+    /// Whether any measurement is worse than the given limits.
+    ///
+    /// Unmeasured fields count as fine, so a new session gets full detail.
+    pub fn is_worse_than(&self, max_rtt: Duration, min_bandwidth: u64, max_loss: f32) -> bool {
+        self.rtt.is_some_and(|rtt| rtt > max_rtt)
+            || self.est_bandwidth.is_some_and(|bw| bw < min_bandwidth)
+            || self.loss > max_loss
+    }
+}
+
+/// Where a client doing client-side prediction stands, from its last
+/// `ClientWire::Ack`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientPrediction {
+    /// The last snapshot that agreed with the client's prediction.
+    pub confirmed_seq: u64,
+    /// How many snapshots past `confirmed_seq` the client predicts; 0 if it
+    /// doesn't predict.
+    pub horizon: u32,
+}
+
+impl ClientPrediction {
+    /// The snapshot the client has predicted up to.
+    pub fn predicted_seq(&self) -> u64 {
+        self.confirmed_seq.saturating_add(u64::from(self.horizon))
+    }
+
+    /// Whether the client's prediction went wrong: it has snapshot
+    /// `acked_seq`, but the last one that agreed is older.
+    pub fn diverged(&self, acked_seq: u64) -> bool {
+        self.horizon > 0 && self.confirmed_seq < acked_seq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prediction_diverges_when_acks_outrun_confirmations() {
+        let predicting = ClientPrediction {
+            confirmed_seq: 10,
+            horizon: 3,
+        };
+        assert_eq!(predicting.predicted_seq(), 13);
+        assert!(!predicting.diverged(10));
+        assert!(predicting.diverged(11));
+        // A client that doesn't predict never diverges
+        assert!(!ClientPrediction::default().diverged(11));
+    }
+
+    #[test]
+    fn unmeasured_connections_are_not_degraded() {
+        let limits = (Duration::from_millis(300), 16_000, 0.2);
+        let fresh = ConnectionQuality::default();
+        assert!(!fresh.is_worse_than(limits.0, limits.1, limits.2));
+
+        let slow = ConnectionQuality {
+            rtt: Some(Duration::from_millis(800)),
+            ..fresh
+        };
+        assert!(slow.is_worse_than(limits.0, limits.1, limits.2));
+
+        let lossy = ConnectionQuality { loss: 0.5, ..fresh };
+        assert!(lossy.is_worse_than(limits.0, limits.1, limits.2));
+    }
+}
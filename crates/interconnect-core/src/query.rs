@@ -0,0 +1,103 @@
+//! Paginated read-only queries.
+//!
+//! A client sends `ClientWire::Query`; the authority answers one page at a
+//! time through [`Authority::query_page`](crate::Authority::query_page).
+//! Each page carries a cursor for the next, so large results (full history)
+//! arrive in chunks the client asks for, without tying up the connection.
+
+use serde::Serialize;
+
+/// One page of a query result, produced by the authority.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPage {
+    /// Zero-based page number.
+    pub page: u32,
+    /// Pages in the whole result.
+    pub total_pages: u32,
+    /// Items on this page.
+    pub items: Vec<serde_json::Value>,
+    /// Cursor for the next page; `None` on the last one.
+    pub next_cursor: Option<String>,
+}
+
+impl QueryPage {
+    /// Page through a slice, using the page number as the cursor.
+    ///
+    /// Suits results that don't shift between requests (append-only logs);
+    /// authorities with mutable results should mint cursors of their own.
+    pub fn paginate<T: Serialize>(
+        items: &[T],
+        page_size: usize,
+        cursor: Option<&str>,
+    ) -> Result<Self, QueryError> {
+        let page_size = page_size.max(1);
+        let total_pages = items.len().div_ceil(page_size).max(1);
+        let page = match cursor {
+            None => 0,
+            Some(c) => c
+                .parse::<usize>()
+                .ok()
+                .filter(|&p| p < total_pages)
+                .ok_or(QueryError::InvalidCursor)?,
+        };
+        let start = page * page_size;
+        let end = (start + page_size).min(items.len());
+        let items = items[start..end]
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()
+            .map_err(|e| QueryError::Invalid(e.to_string()))?;
+        let next = page + 1;
+        Ok(Self {
+            page: page as u32,
+            total_pages: total_pages as u32,
+            items,
+            next_cursor: (next < total_pages).then(|| next.to_string()),
+        })
+    }
+}
+
+/// Why a query couldn't be answered.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QueryError {
+    #[error("this server doesn't answer queries")]
+    Unsupported,
+    #[error("invalid query: {0}")]
+    Invalid(String),
+    #[error("unknown or stale cursor")]
+    InvalidCursor,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paginate_follows_cursors_to_the_end() {
+        let items: Vec<u32> = (0..5).collect();
+
+        let first = QueryPage::paginate(&items, 2, None).unwrap();
+        assert_eq!(first.page, 0);
+        assert_eq!(first.total_pages, 3);
+        assert_eq!(first.items, [serde_json::json!(0), serde_json::json!(1)]);
+
+        let cursor = first.next_cursor.unwrap();
+        let second = QueryPage::paginate(&items, 2, Some(&cursor)).unwrap();
+        let last = QueryPage::paginate(&items, 2, second.next_cursor.as_deref()).unwrap();
+        assert_eq!(last.items, [serde_json::json!(4)]);
+        assert_eq!(last.next_cursor, None);
+
+        assert_eq!(
+            QueryPage::paginate(&items, 2, Some("9")),
+            Err(QueryError::InvalidCursor)
+        );
+    }
+
+    #[test]
+    fn empty_results_have_one_page() {
+        let page = QueryPage::paginate::<u32>(&[], 10, None).unwrap();
+        assert_eq!(page.total_pages, 1);
+        assert!(page.items.is_empty());
+        assert_eq!(page.next_cursor, None);
+    }
+}
@@ -0,0 +1,157 @@
+//! What of an intent is safe to log.
+//!
+//! Intents can carry secrets (`SetPassword { password }`) or personal data,
+//! so audit and tracing hooks log a [`Redactor`]'s projection of an intent
+//! rather than the intent itself, as the authority's
+//! [`redacted_intent_to_json`](crate::Authority::redacted_intent_to_json)
+//! gives it. [`RedactPaths`] masks fields by path, for types whose
+//! definition can't be changed.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Projects an intent onto something safe to log.
+///
+/// The default logs only the intent's type name. Override to keep the
+/// fields worth auditing and drop the rest:
+///
+/// ```ignore
+/// impl Redactor<AccountIntent> for Accounts {
+///     fn redact(&self, intent: &AccountIntent) -> Value {
+///         match intent {
+///             AccountIntent::SetPassword { .. } => json!({ "action": "set_password" }),
+///             AccountIntent::Rename { name } => json!({ "action": "rename", "name": name }),
+///         }
+///     }
+/// }
+/// ```
+pub trait Redactor<I> {
+    /// What to log for `intent`.
+    fn redact(&self, _intent: &I) -> Value {
+        Value::String(std::any::type_name::<I>().to_string())
+    }
+}
+
+/// Keeps only the intent's type name: the default projection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TypeNameOnly;
+
+impl<I> Redactor<I> for TypeNameOnly {}
+
+/// What [`RedactPaths`] replaces masked values with.
+pub const REDACTED: &str = "[redacted]";
+
+/// Logs the intent as JSON with the values at these paths masked.
+///
+/// A path is object keys or array indices separated by `.`, and `*` matches
+/// any key or index: `SetPassword.password`, `Pay.cards.*.number`. Paths
+/// that match nothing are ignored. An intent that doesn't serialize logs as
+/// `null`.
+#[derive(Debug, Clone, Copy)]
+pub struct RedactPaths<'a>(pub &'a [&'a str]);
+
+impl<I: Serialize> Redactor<I> for RedactPaths<'_> {
+    fn redact(&self, intent: &I) -> Value {
+        let mut value = serde_json::to_value(intent).unwrap_or_default();
+        for path in self.0 {
+            mask(&mut value, &path.split('.').collect::<Vec<_>>());
+        }
+        value
+    }
+}
+
+fn mask(value: &mut Value, path: &[&str]) {
+    let Some((step, rest)) = path.split_first() else {
+        *value = Value::String(REDACTED.to_string());
+        return;
+    };
+    let next: Vec<&mut Value> = match (value, *step) {
+        (Value::Object(fields), "*") => fields.values_mut().collect(),
+        (Value::Array(items), "*") => items.iter_mut().collect(),
+        (Value::Object(fields), key) => fields.get_mut(key).into_iter().collect(),
+        (Value::Array(items), index) => index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| items.get_mut(index))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    };
+    for value in next {
+        mask(value, rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[allow(dead_code)] // The password is never read, which is the point.
+    enum AccountIntent {
+        SetPassword { password: String },
+        Rename { name: String },
+    }
+
+    struct Accounts;
+
+    impl Redactor<AccountIntent> for Accounts {
+        fn redact(&self, intent: &AccountIntent) -> Value {
+            match intent {
+                AccountIntent::SetPassword { .. } => json!({ "action": "set_password" }),
+                AccountIntent::Rename { name } => json!({ "action": "rename", "name": name }),
+            }
+        }
+    }
+
+    struct Quiet;
+
+    impl Redactor<String> for Quiet {}
+
+    #[test]
+    fn default_logs_only_the_type_name() {
+        assert_eq!(
+            Quiet.redact(&"hunter2".to_string()),
+            json!(std::any::type_name::<String>())
+        );
+    }
+
+    #[test]
+    fn override_keeps_the_action_and_drops_the_secret() {
+        let logged = Accounts.redact(&AccountIntent::SetPassword {
+            password: "hunter2".into(),
+        });
+        assert_eq!(logged, json!({ "action": "set_password" }));
+        assert!(!logged.to_string().contains("hunter2"));
+
+        let logged = Accounts.redact(&AccountIntent::Rename { name: "a".into() });
+        assert_eq!(logged, json!({ "action": "rename", "name": "a" }));
+    }
+
+    #[test]
+    fn paths_and_wildcards_are_masked() {
+        let intent = json!({
+            "Pay": {
+                "amount": 5,
+                "cards": [{ "number": "4111", "name": "a" }, { "number": "5500" }],
+            },
+            "tags": ["x", "y"],
+        });
+        let paths = RedactPaths(&[
+            "Pay.cards.*.number",
+            "tags.1",
+            "Pay.missing",
+            "SetPassword.password",
+        ]);
+        assert_eq!(
+            paths.redact(&intent),
+            json!({
+                "Pay": {
+                    "amount": 5,
+                    "cards": [{ "number": REDACTED, "name": "a" }, { "number": REDACTED }],
+                },
+                "tags": ["x", REDACTED],
+            })
+        );
+    }
+}
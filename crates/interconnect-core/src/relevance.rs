@@ -0,0 +1,131 @@
+//! Per-session relevance filtering.
+//!
+//! With many entities, each session should only see those near it (or
+//! otherwise relevant to it). The authority scores entities through
+//! [`Authority::compute_relevance_score`]; a [`RelevanceFilter`] applies
+//! the scores to `snapshot_for`, dropping entities below its threshold.
+
+use crate::{Authority, Session};
+
+/// A snapshot made of entities with stable IDs.
+pub trait EntitySnapshot {
+    /// Keep only the entities for which `keep` returns true.
+    fn retain_entities(&mut self, keep: &mut dyn FnMut(u64) -> bool);
+}
+
+/// Entities as (ID, entity) pairs.
+impl<V> EntitySnapshot for Vec<(u64, V)> {
+    fn retain_entities(&mut self, keep: &mut dyn FnMut(u64) -> bool) {
+        self.retain(|(id, _)| keep(*id));
+    }
+}
+
+/// Relevance settings for one authority.
+#[derive(Debug, Clone, Copy)]
+pub struct RelevanceConfig {
+    /// Entities scoring below this are left out of snapshots.
+    pub threshold: f32,
+    /// Maps a distance to a score from 0.0 to 1.0.
+    pub falloff: fn(distance: f32) -> f32,
+}
+
+impl RelevanceConfig {
+    /// The score for an entity `distance` away, per `falloff`.
+    pub fn score(&self, distance: f32) -> f32 {
+        (self.falloff)(distance).clamp(0.0, 1.0)
+    }
+}
+
+impl Default for RelevanceConfig {
+    /// Everything is fully relevant.
+    fn default() -> Self {
+        Self {
+            threshold: 0.0,
+            falloff: |_| 1.0,
+        }
+    }
+}
+
+/// Filters snapshots down to the entities relevant to each session.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RelevanceFilter {
+    config: RelevanceConfig,
+}
+
+impl RelevanceFilter {
+    pub fn new(config: RelevanceConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &RelevanceConfig {
+        &self.config
+    }
+
+    /// `authority.snapshot_for(session)`, without the entities scoring
+    /// below the threshold.
+    pub fn snapshot_for<A>(&self, authority: &A, session: &Session) -> A::Snapshot
+    where
+        A: Authority,
+        A::Snapshot: EntitySnapshot,
+    {
+        let mut snapshot = authority.snapshot_for(session);
+        self.filter(authority, session, &mut snapshot);
+        snapshot
+    }
+
+    /// Drop entities scoring below the threshold from `snapshot`.
+    pub fn filter<A>(&self, authority: &A, session: &Session, snapshot: &mut A::Snapshot)
+    where
+        A: Authority,
+        A::Snapshot: EntitySnapshot,
+    {
+        let threshold = self.config.threshold;
+        snapshot
+            .retain_entities(&mut |id| authority.compute_relevance_score(session, id) >= threshold);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Identity;
+    use crate::testing::{Add, TestRoom};
+
+    /// Tallies from sessions 1, 5 and 50.
+    fn line() -> TestRoom {
+        let mut room = TestRoom {
+            relevance: RelevanceConfig {
+                threshold: 0.5,
+                falloff: |d| 1.0 - d / 20.0,
+            },
+            ..TestRoom::new()
+        };
+        for id in [1, 5, 50] {
+            let session = Session::new(id, Identity::local("a"), "a".into());
+            Authority::handle_intent(&mut room, &session, Add { by: 1 }).unwrap();
+        }
+        room
+    }
+
+    fn ids(snapshot: Vec<(u64, u32)>) -> Vec<u64> {
+        snapshot.into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn distant_entities_are_filtered_out() {
+        let line = line();
+        let filter = RelevanceFilter::new(line.relevance);
+        let near_origin = Session::new(0, Identity::local("alice"), "alice".into());
+        assert_eq!(ids(filter.snapshot_for(&line, &near_origin)), [1, 5]);
+
+        let far_out = Session::new(45, Identity::local("bob"), "bob".into());
+        assert_eq!(ids(filter.snapshot_for(&line, &far_out)), [50]);
+    }
+
+    #[test]
+    fn everything_is_relevant_by_default() {
+        let filter = RelevanceFilter::default();
+        let session = Session::new(0, Identity::local("alice"), "alice".into());
+        assert_eq!(ids(filter.snapshot_for(&line(), &session)), [1, 5, 50]);
+    }
+}
@@ -0,0 +1,481 @@
+//! Bounded in-memory retention.
+//!
+//! A per-room cap (like the chat example's last 100 messages) doesn't bound a
+//! server hosting thousands of rooms. A [`MemoryBudget`] is a global byte cap
+//! shared by many [`RingLog`]s. When the total exceeds the cap, the largest
+//! consumers are asked to evict their oldest entries first, so one busy room
+//! can't push a quiet room's history out.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Approximate memory footprint of a retained value, in bytes.
+pub trait ByteSize {
+    fn byte_size(&self) -> usize;
+}
+
+impl ByteSize for String {
+    fn byte_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl ByteSize for Vec<u8> {
+    fn byte_size(&self) -> usize {
+        self.len()
+    }
+}
+
+/// A request for one consumer to evict bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvictionRequest {
+    /// Consumer that should evict (see [`BudgetAccount::id`]).
+    pub consumer: u64,
+    /// How many bytes it should free.
+    pub bytes: usize,
+}
+
+type EvictionCallback = Arc<dyn Fn(&[EvictionRequest]) + Send + Sync>;
+
+#[derive(Debug, Default)]
+struct Usage {
+    used: usize,
+    pending: usize,
+    /// Held bytes the consumer couldn't evict when asked, left out of
+    /// evictions until it charges more.
+    stuck: usize,
+}
+
+struct BudgetState {
+    cap: usize,
+    used: usize,
+    consumers: HashMap<u64, Usage>,
+    next_id: u64,
+    on_evict: Option<EvictionCallback>,
+}
+
+impl BudgetState {
+    /// Spread `overflow` across consumers, largest first (water-filling).
+    fn assign_evictions(&mut self, overflow: usize) -> Vec<EvictionRequest> {
+        let mut order: Vec<(u64, usize)> = self
+            .consumers
+            .iter()
+            .map(|(id, u)| (*id, u.used.saturating_sub(u.pending + u.stuck)))
+            .filter(|(_, effective)| *effective > 0)
+            .collect();
+        order.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        // Find the level every consumer above it is cut down to.
+        let mut level = 0;
+        let mut count = order.len();
+        let mut sum = 0;
+        for (i, (_, effective)) in order.iter().enumerate() {
+            sum += effective;
+            let next = order.get(i + 1).map_or(0, |(_, e)| *e);
+            if sum >= overflow && (sum - overflow) / (i + 1) >= next {
+                level = (sum - overflow) / (i + 1);
+                count = i + 1;
+                break;
+            }
+        }
+
+        let mut requests = Vec::new();
+        for (id, effective) in &order[..count] {
+            let bytes = effective.saturating_sub(level);
+            if bytes > 0 {
+                if let Some(usage) = self.consumers.get_mut(id) {
+                    usage.pending += bytes;
+                }
+                requests.push(EvictionRequest {
+                    consumer: *id,
+                    bytes,
+                });
+            }
+        }
+        requests
+    }
+}
+
+/// A global byte cap shared across many retention logs.
+///
+/// Cloning is cheap; clones share the same budget.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    state: Arc<Mutex<BudgetState>>,
+}
+
+impl MemoryBudget {
+    /// Create a budget capped at `cap` bytes.
+    pub fn new(cap: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(BudgetState {
+                cap,
+                used: 0,
+                consumers: HashMap::new(),
+                next_id: 1,
+                on_evict: None,
+            })),
+        }
+    }
+
+    /// Call `f` whenever consumers are asked to evict.
+    ///
+    /// Logs honor requests on their next push; use this to poke idle
+    /// consumers (e.g. schedule a [`RingLog::reclaim`]).
+    pub fn with_eviction_callback(
+        self,
+        f: impl Fn(&[EvictionRequest]) + Send + Sync + 'static,
+    ) -> Self {
+        self.lock().on_evict = Some(Arc::new(f));
+        self
+    }
+
+    /// The cap, in bytes.
+    pub fn cap(&self) -> usize {
+        self.lock().cap
+    }
+
+    /// Change the cap. Takes effect on the next charge.
+    pub fn set_cap(&self, cap: usize) {
+        self.lock().cap = cap;
+    }
+
+    /// Bytes currently charged across all consumers.
+    pub fn used(&self) -> usize {
+        self.lock().used
+    }
+
+    /// Number of registered consumers.
+    pub fn consumer_count(&self) -> usize {
+        self.lock().consumers.len()
+    }
+
+    /// Bytes charged to a single consumer.
+    pub fn used_by(&self, consumer: u64) -> usize {
+        self.lock().consumers.get(&consumer).map_or(0, |u| u.used)
+    }
+
+    /// Register a new consumer.
+    pub fn register(&self) -> BudgetAccount {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.consumers.insert(id, Usage::default());
+        BudgetAccount {
+            id,
+            budget: self.clone(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BudgetState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("MemoryBudget")
+            .field("cap", &state.cap)
+            .field("used", &state.used)
+            .field("consumers", &state.consumers.len())
+            .finish()
+    }
+}
+
+/// One consumer's share of a [`MemoryBudget`].
+///
+/// Dropping the account releases everything it charged.
+#[derive(Debug)]
+pub struct BudgetAccount {
+    id: u64,
+    budget: MemoryBudget,
+}
+
+impl BudgetAccount {
+    /// This consumer's ID (matches [`EvictionRequest::consumer`]).
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Charge `bytes` to this consumer, assigning evictions if over the cap.
+    pub fn charge(&self, bytes: usize) {
+        let (requests, callback) = {
+            let mut state = self.budget.lock();
+            state.used += bytes;
+            if let Some(usage) = state.consumers.get_mut(&self.id) {
+                usage.used += bytes;
+                // What it held before may be evictable now
+                usage.stuck = 0;
+            }
+
+            let pending: usize = state.consumers.values().map(|u| u.pending).sum();
+            let overflow = state.used.saturating_sub(state.cap + pending);
+            if overflow == 0 {
+                return;
+            }
+            (state.assign_evictions(overflow), state.on_evict.clone())
+        };
+
+        if let Some(callback) = callback
+            && !requests.is_empty()
+        {
+            callback(&requests);
+        }
+    }
+
+    /// Release `bytes` previously charged.
+    pub fn release(&self, bytes: usize) {
+        let mut state = self.budget.lock();
+        state.used = state.used.saturating_sub(bytes);
+        if let Some(usage) = state.consumers.get_mut(&self.id) {
+            usage.used = usage.used.saturating_sub(bytes);
+            usage.pending = usage.pending.saturating_sub(bytes);
+            usage.stuck = usage.stuck.min(usage.used);
+        }
+    }
+
+    /// This consumer can't free what it was asked to (e.g. all it holds is
+    /// an entry it must keep): drop the request, and leave what it holds
+    /// out of evictions until it charges more.
+    ///
+    /// Pending bytes count toward the cap until released, so a request
+    /// that can never be met would otherwise raise it for good.
+    pub fn cancel_eviction(&self) {
+        if let Some(usage) = self.budget.lock().consumers.get_mut(&self.id) {
+            usage.pending = 0;
+            usage.stuck = usage.used;
+        }
+    }
+
+    /// Bytes currently charged to this consumer.
+    pub fn used(&self) -> usize {
+        self.budget.used_by(self.id)
+    }
+
+    /// Bytes this consumer has been asked to evict.
+    pub fn pending_eviction(&self) -> usize {
+        self.budget
+            .lock()
+            .consumers
+            .get(&self.id)
+            .map_or(0, |u| u.pending)
+    }
+}
+
+impl Drop for BudgetAccount {
+    fn drop(&mut self) {
+        let mut state = self.budget.lock();
+        if let Some(usage) = state.consumers.remove(&self.id) {
+            state.used = state.used.saturating_sub(usage.used);
+        }
+    }
+}
+
+/// A bounded log that evicts its oldest entries first.
+///
+/// Bounded by entry count, and optionally by a shared [`MemoryBudget`].
+/// The newest entry is never evicted by budget pressure.
+#[derive(Debug)]
+pub struct RingLog<T> {
+    entries: VecDeque<(T, usize)>,
+    capacity: usize,
+    bytes: usize,
+    account: Option<BudgetAccount>,
+}
+
+impl<T> RingLog<T> {
+    /// Create a log holding at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            bytes: 0,
+            account: None,
+        }
+    }
+
+    /// Create a log that also charges a shared budget.
+    pub fn with_budget(capacity: usize, budget: &MemoryBudget) -> Self {
+        Self {
+            account: Some(budget.register()),
+            ..Self::new(capacity)
+        }
+    }
+
+    /// Maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the log is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bytes retained by this log.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The budget account, if this log is budgeted.
+    pub fn account(&self) -> Option<&BudgetAccount> {
+        self.account.as_ref()
+    }
+
+    /// Iterate entries, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + ExactSizeIterator {
+        self.entries.iter().map(|(item, _)| item)
+    }
+
+    /// Honor any pending eviction request. Returns the number evicted.
+    pub fn reclaim(&mut self) -> usize {
+        let mut evicted = 0;
+        while self.entries.len() > 1
+            && self
+                .account
+                .as_ref()
+                .is_some_and(|a| a.pending_eviction() > 0)
+        {
+            self.evict_oldest();
+            evicted += 1;
+        }
+        // Down to the newest entry, which is kept: the rest can't be freed
+        if let Some(account) = &self.account
+            && account.pending_eviction() > 0
+        {
+            account.cancel_eviction();
+        }
+        evicted
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, size)) = self.entries.pop_front() {
+            self.bytes -= size;
+            if let Some(account) = &self.account {
+                account.release(size);
+            }
+        }
+    }
+}
+
+impl<T: ByteSize> RingLog<T> {
+    /// Append an entry. Returns the number of entries evicted.
+    pub fn push(&mut self, item: T) -> usize {
+        let size = item.byte_size();
+        self.entries.push_back((item, size));
+        self.bytes += size;
+        if let Some(account) = &self.account {
+            account.charge(size);
+        }
+
+        let mut evicted = 0;
+        while self.entries.len() > self.capacity {
+            self.evict_oldest();
+            evicted += 1;
+        }
+        evicted + self.reclaim()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn entry(n: usize) -> String {
+        "x".repeat(n)
+    }
+
+    #[test]
+    fn ring_log_caps_entries() {
+        let mut log = RingLog::new(3);
+        for i in 0..5 {
+            log.push(i.to_string());
+        }
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.iter().cloned().collect::<Vec<_>>(), ["2", "3", "4"]);
+        assert_eq!(log.bytes(), 3);
+    }
+
+    #[test]
+    fn budget_evicts_largest_consumer_first() {
+        let budget = MemoryBudget::new(100);
+        let mut busy = RingLog::with_budget(usize::MAX, &budget);
+        let mut quiet = RingLog::with_budget(usize::MAX, &budget);
+
+        quiet.push(entry(20));
+        for _ in 0..8 {
+            busy.push(entry(10));
+        }
+        assert_eq!(budget.used(), 100);
+
+        // Over the cap: the busy room pays, the quiet room keeps its history.
+        busy.push(entry(10));
+        assert!(budget.used() <= 100);
+        assert_eq!(quiet.len(), 1);
+        assert_eq!(busy.bytes(), 80);
+    }
+
+    #[test]
+    fn idle_consumer_reclaims_on_request() {
+        let requested = Arc::new(AtomicUsize::new(0));
+        let seen = requested.clone();
+        let budget = MemoryBudget::new(50).with_eviction_callback(move |reqs| {
+            seen.fetch_add(reqs.iter().map(|r| r.bytes).sum(), Ordering::SeqCst);
+        });
+        let mut idle = RingLog::with_budget(usize::MAX, &budget);
+        let mut active = RingLog::with_budget(usize::MAX, &budget);
+
+        for _ in 0..4 {
+            idle.push(entry(10));
+        }
+        active.push(entry(10));
+        active.push(entry(10));
+
+        // The idle log is the largest, so it owes the overflow.
+        assert_eq!(requested.load(Ordering::SeqCst), 10);
+        assert_eq!(idle.account().unwrap().pending_eviction(), 10);
+        assert_eq!(idle.reclaim(), 1);
+        assert_eq!(budget.used(), 50);
+    }
+
+    #[test]
+    fn oversized_entry_does_not_raise_the_cap() {
+        let budget = MemoryBudget::new(50);
+        let mut big = RingLog::with_budget(usize::MAX, &budget);
+        let mut other = RingLog::with_budget(usize::MAX, &budget);
+
+        // Its only entry is the newest, so none of the overflow can be freed
+        big.push(entry(100));
+        assert_eq!(big.len(), 1);
+        assert_eq!(big.account().unwrap().pending_eviction(), 0);
+
+        // The overflow after it falls on the log that can evict
+        for _ in 0..3 {
+            other.push(entry(10));
+        }
+        assert_eq!(other.len(), 1);
+        assert_eq!(big.len(), 1);
+        assert_eq!(budget.used(), 110);
+    }
+
+    #[test]
+    fn dropping_log_releases_budget() {
+        let budget = MemoryBudget::new(1000);
+        {
+            let mut log = RingLog::with_budget(10, &budget);
+            log.push(entry(30));
+            assert_eq!(budget.used(), 30);
+            assert_eq!(budget.consumer_count(), 1);
+        }
+        assert_eq!(budget.used(), 0);
+        assert_eq!(budget.consumer_count(), 0);
+    }
+}
@@ -0,0 +1,207 @@
+//! Several authorities in one process, with transfers between them.
+//!
+//! A transfer between servers goes through the client: the source emits a
+//! passport, the client reconnects to the destination and presents it.
+//! When both authorities live in the same [`RouterAuthority`], the passport
+//! is handed over directly instead and the client stays connected: an
+//! authority hosting a router moves the session in
+//! [`Authority::on_local_transfer`], and the transport tells the client with
+//! `ServerWire::LocalTransferComplete`.
+
+use crate::{Authority, Rejection, Session};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Why a local transfer didn't happen.
+///
+/// On any error the session stays where it was.
+#[derive(Debug, thiserror::Error)]
+pub enum TransferError<E> {
+    #[error("session is not in any authority")]
+    NotConnected,
+    #[error("no such destination")]
+    UnknownDestination,
+    #[error("session is already in the destination")]
+    AlreadyThere,
+    #[error("destination refused the transfer: {0}")]
+    Authority(E),
+}
+
+/// A completed local transfer.
+#[derive(Debug, Clone)]
+pub struct LocalTransferResult {
+    /// What the destination's import policy rejected.
+    pub rejected: Vec<Rejection>,
+    /// The session's ID in the destination.
+    pub new_session_id: u64,
+}
+
+/// Authorities keyed by `K`, each session placed in one of them.
+///
+/// Session IDs come from the transport, moved sessions' included, so they
+/// never collide with the ones it hands out.
+pub struct RouterAuthority<K, A> {
+    authorities: HashMap<K, A>,
+    /// session ID -> authority key
+    placement: HashMap<u64, K>,
+}
+
+impl<K, A> Default for RouterAuthority<K, A> {
+    fn default() -> Self {
+        Self {
+            authorities: HashMap::new(),
+            placement: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, A: Authority> RouterAuthority<K, A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an authority, returning the one it replaces.
+    pub fn insert(&mut self, key: K, authority: A) -> Option<A> {
+        self.authorities.insert(key, authority)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&A> {
+        self.authorities.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut A> {
+        self.authorities.get_mut(key)
+    }
+
+    /// Where a session is.
+    pub fn authority_of(&self, session_id: u64) -> Option<&K> {
+        self.placement.get(&session_id)
+    }
+
+    /// The authority a session is in, to route its intents.
+    pub fn authority_for_mut(&mut self, session_id: u64) -> Option<&mut A> {
+        let key = self.placement.get(&session_id)?;
+        self.authorities.get_mut(key)
+    }
+
+    /// Connect a session to the authority at `key`.
+    pub fn connect(&mut self, key: &K, session: &Session) -> Result<(), TransferError<A::Error>> {
+        let authority = self
+            .authorities
+            .get_mut(key)
+            .ok_or(TransferError::UnknownDestination)?;
+        authority
+            .on_connect(session)
+            .map_err(TransferError::Authority)?;
+        self.placement.insert(session.id, key.clone());
+        Ok(())
+    }
+
+    /// Disconnect a session from wherever it is.
+    pub fn disconnect(&mut self, session: &Session) {
+        if let Some(key) = self.placement.remove(&session.id)
+            && let Some(authority) = self.authorities.get_mut(&key)
+        {
+            authority.on_disconnect(session);
+        }
+    }
+
+    /// Move a session to the authority at `dst`, where it's
+    /// `new_session_id`.
+    ///
+    /// The source's passport goes straight to the destination's
+    /// `on_transfer_in`. If the destination refuses it, nothing changes;
+    /// otherwise the source sees a disconnect. A transport keeping the
+    /// connection can pass the session's own ID.
+    pub fn local_transfer(
+        &mut self,
+        src_session: &Session,
+        dst: &K,
+        new_session_id: u64,
+    ) -> Result<LocalTransferResult, TransferError<A::Error>> {
+        let src = self
+            .placement
+            .get(&src_session.id)
+            .ok_or(TransferError::NotConnected)?
+            .clone();
+        if src == *dst {
+            return Err(TransferError::AlreadyThere);
+        }
+        if !self.authorities.contains_key(dst) {
+            return Err(TransferError::UnknownDestination);
+        }
+        let passport = self.authorities[&src].emit_passport(src_session);
+
+        let moved = Session {
+            id: new_session_id,
+            ..src_session.clone()
+        };
+        let imported = self
+            .authorities
+            .get_mut(dst)
+            .expect("checked above")
+            .on_transfer_in(&moved, passport)
+            .map_err(TransferError::Authority)?;
+
+        self.disconnect(src_session);
+        self.placement.insert(moved.id, dst.clone());
+        Ok(LocalTransferResult {
+            rejected: imported.rejected,
+            new_session_id: moved.id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Identity;
+    use crate::testing::{Refused, TestRoom};
+
+    #[test]
+    fn sessions_move_between_local_authorities() {
+        let mut router = RouterAuthority::new();
+        router.insert("lobby", TestRoom::new());
+        router.insert("games", TestRoom::new());
+        router.insert(
+            "vault",
+            TestRoom {
+                refused: vec!["alice".into()],
+                ..TestRoom::new()
+            },
+        );
+        let alice = Session::new(1, Identity::local("alice"), "alice".into());
+        router.connect(&"lobby", &alice).unwrap();
+
+        // Refused: alice stays put
+        let err = router.local_transfer(&alice, &"vault", 2).unwrap_err();
+        assert!(matches!(err, TransferError::Authority(Refused)));
+        assert_eq!(router.authority_of(alice.id), Some(&"lobby"));
+        assert!(matches!(
+            router.local_transfer(&alice, &"lobby", 2),
+            Err(TransferError::AlreadyThere)
+        ));
+
+        let moved = router.local_transfer(&alice, &"games", 2).unwrap();
+        assert_eq!(moved.new_session_id, 2);
+        assert!(moved.rejected.is_empty());
+        assert_eq!(router.authority_of(2), Some(&"games"));
+        assert_eq!(router.authority_of(alice.id), None);
+        assert!(router.get(&"lobby").unwrap().present.is_empty());
+        assert_eq!(router.get(&"games").unwrap().present, ["alice"]);
+    }
+
+    #[test]
+    fn sessions_can_keep_their_id_across_a_move() {
+        let mut router = RouterAuthority::new();
+        router.insert("lobby", TestRoom::new());
+        router.insert("games", TestRoom::new());
+        let alice = Session::new(7, Identity::local("alice"), "alice".into());
+        router.connect(&"lobby", &alice).unwrap();
+
+        let moved = router.local_transfer(&alice, &"games", alice.id).unwrap();
+        assert_eq!(moved.new_session_id, alice.id);
+        assert_eq!(router.authority_of(alice.id), Some(&"games"));
+        assert!(router.get(&"lobby").unwrap().present.is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+//! Replaying scripted sessions against an authority, for load testing.
+//!
+//! An [`AuthorityScenario`] says which sessions connect, what each sends
+//! and when, and which transfer out. [`run_scenario`] plays it straight
+//! into an authority on simulated time, with no transport and no sleeping,
+//! and times each `handle_intent`. Scenario files are JSON (see
+//! [`load_scenario_from_json`]):
+//!
+//! ```json
+//! {
+//!   "connect_stagger_ms": 10,
+//!   "sessions": [
+//!     {
+//!       "name": "alice",
+//!       "intents": [{ "at_ms": 0, "intent": { "say": "hi" } }],
+//!       "transfer_at": { "at_ms": 500, "destination": "ws://elsewhere" }
+//!     }
+//!   ]
+//! }
+//! ```
+
+use crate::{Authority, DisconnectReason, Identity, Session};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Sessions to replay against an authority.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorityScenario<I> {
+    pub sessions: Vec<TestSessionScript<I>>,
+    pub timing: ScenarioTiming,
+}
+
+/// What one session does, in time since it connected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestSessionScript<I> {
+    pub name: String,
+    pub intents: Vec<(Duration, I)>,
+    /// When the session transfers out, and where to. It sends nothing after.
+    pub transfer_at: Option<(Duration, String)>,
+}
+
+/// When sessions start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScenarioTiming {
+    /// Time between one session connecting and the next.
+    pub connect_stagger: Duration,
+}
+
+/// How a scenario went.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScenarioResult {
+    /// Intents handed to the authority.
+    pub total_intents: u64,
+    /// Connects and intents the authority refused.
+    pub errors: u64,
+    /// Mean time in `handle_intent`, in microseconds.
+    pub avg_latency_us: f64,
+}
+
+impl AuthorityScenario<Value> {
+    /// Decode each intent as an `I`.
+    pub fn decode<I: DeserializeOwned>(self) -> Result<AuthorityScenario<I>, serde_json::Error> {
+        let sessions = self
+            .sessions
+            .into_iter()
+            .map(|script| {
+                let intents = script
+                    .intents
+                    .into_iter()
+                    .map(|(at, intent)| Ok((at, serde_json::from_value(intent)?)))
+                    .collect::<Result<_, serde_json::Error>>()?;
+                Ok(TestSessionScript {
+                    name: script.name,
+                    intents,
+                    transfer_at: script.transfer_at,
+                })
+            })
+            .collect::<Result<_, serde_json::Error>>()?;
+        Ok(AuthorityScenario {
+            sessions,
+            timing: self.timing,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ScenarioFile {
+    #[serde(default)]
+    connect_stagger_ms: u64,
+    sessions: Vec<SessionFile>,
+}
+
+#[derive(Deserialize)]
+struct SessionFile {
+    name: String,
+    #[serde(default)]
+    intents: Vec<IntentFile>,
+    transfer_at: Option<TransferFile>,
+}
+
+#[derive(Deserialize)]
+struct IntentFile {
+    at_ms: u64,
+    intent: Value,
+}
+
+#[derive(Deserialize)]
+struct TransferFile {
+    at_ms: u64,
+    destination: String,
+}
+
+/// Read a scenario file; [`AuthorityScenario::decode`] turns its intents
+/// into the authority's.
+pub fn load_scenario_from_json(path: &str) -> Result<AuthorityScenario<Value>, io::Error> {
+    let file: ScenarioFile = serde_json::from_slice(&std::fs::read(path)?)?;
+    Ok(AuthorityScenario {
+        sessions: file
+            .sessions
+            .into_iter()
+            .map(|session| TestSessionScript {
+                name: session.name,
+                intents: session
+                    .intents
+                    .into_iter()
+                    .map(|intent| (Duration::from_millis(intent.at_ms), intent.intent))
+                    .collect(),
+                transfer_at: session
+                    .transfer_at
+                    .map(|t| (Duration::from_millis(t.at_ms), t.destination)),
+            })
+            .collect(),
+        timing: ScenarioTiming {
+            connect_stagger: Duration::from_millis(file.connect_stagger_ms),
+        },
+    })
+}
+
+enum Step<I> {
+    Intent(I),
+    Transfer(String),
+    Leave,
+}
+
+/// Play `scenario` into `authority` in simulated-time order.
+///
+/// Each session connects, sends its intents, then transfers out or
+/// disconnects after its last step. A session whose connect is refused
+/// sends nothing.
+pub fn run_scenario<A: Authority>(
+    authority: &mut A,
+    scenario: AuthorityScenario<A::Intent>,
+) -> ScenarioResult {
+    let mut sessions = Vec::new();
+    // (when, session index, order within the session, step)
+    let mut steps = Vec::new();
+    for (index, script) in scenario.sessions.into_iter().enumerate() {
+        let start = scenario.timing.connect_stagger * index as u32;
+        let session = Session::new(index as u64 + 1, Identity::local(&script.name), script.name);
+        let mut end = Duration::ZERO;
+        for (at, intent) in script.intents {
+            end = end.max(at);
+            steps.push((start + at, index, steps.len(), Step::Intent(intent)));
+        }
+        match script.transfer_at {
+            Some((at, destination)) => {
+                steps.push((start + at, index, steps.len(), Step::Transfer(destination)))
+            }
+            None => steps.push((start + end, index, steps.len(), Step::Leave)),
+        }
+        sessions.push((start, session));
+    }
+    steps.sort_by_key(|(at, index, order, _)| (*at, *index, *order));
+
+    let mut result = ScenarioResult::default();
+    let mut live = vec![false; sessions.len()];
+    let mut busy = Duration::ZERO;
+    let mut joins = sessions.iter().enumerate().peekable();
+    for (at, index, _, step) in steps {
+        // Everyone due by now connects first
+        while let Some((joining, (_, session))) = joins.next_if(|(_, (start, _))| *start <= at) {
+            match authority.on_connect(session) {
+                Ok(()) => live[joining] = true,
+                Err(_) => result.errors += 1,
+            }
+        }
+        if !live[index] {
+            continue;
+        }
+        let session = &sessions[index].1;
+        match step {
+            Step::Intent(intent) => {
+                result.total_intents += 1;
+                let started = Instant::now();
+                let outcome = authority.handle_intent(session, intent);
+                busy += started.elapsed();
+                if outcome.is_err() {
+                    result.errors += 1;
+                }
+            }
+            Step::Transfer(destination) => {
+                let _ = authority.emit_passport_for_destination(session, &destination);
+                authority.on_disconnect_batch(
+                    std::slice::from_ref(session),
+                    DisconnectReason::Transferred,
+                );
+                live[index] = false;
+            }
+            Step::Leave => {
+                authority.on_disconnect(session);
+                live[index] = false;
+            }
+        }
+    }
+    if result.total_intents > 0 {
+        result.avg_latency_us = busy.as_secs_f64() * 1e6 / result.total_intents as f64;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Add, TestRoom};
+    use crate::{ConnectionEventKind, RecordingAuthority};
+
+    #[test]
+    fn scenario_file_runs_in_simulated_time_order() {
+        let path = std::env::temp_dir().join(format!("scenario-{}.json", std::process::id()));
+        let add =
+            |at_ms: u64, by: u32| serde_json::json!({ "at_ms": at_ms, "intent": { "by": by } });
+        let file = serde_json::json!({
+            "connect_stagger_ms": 100,
+            "sessions": [
+                { "name": "alice", "intents": [add(0, 1), add(250, 3)] },
+                {
+                    "name": "bob",
+                    "intents": [add(50, 2), add(60, 0), add(500, 9)],
+                    "transfer_at": { "at_ms": 200, "destination": "ws://elsewhere" },
+                },
+                { "name": "banned", "intents": [add(0, 5)] },
+            ],
+        });
+        std::fs::write(&path, file.to_string()).unwrap();
+        let scenario = load_scenario_from_json(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(scenario.timing.connect_stagger, Duration::from_millis(100));
+
+        let room = TestRoom {
+            refused: vec!["banned".into()],
+            ..TestRoom::new()
+        };
+        let mut room = RecordingAuthority::new(room, 16);
+        let start = std::time::Instant::now();
+        let result = run_scenario(&mut room, scenario.decode::<Add>().unwrap());
+
+        // Bob's intent after transferring out is never sent
+        assert_eq!(room.inner().applied, [(1, 1), (2, 2), (1, 3)]);
+        let transferred: Vec<u64> = room
+            .list_connection_events(start)
+            .into_iter()
+            .filter(|e| {
+                e.event
+                    == ConnectionEventKind::Disconnected {
+                        reason: DisconnectReason::Transferred,
+                    }
+            })
+            .map(|e| e.session_id)
+            .collect();
+        assert_eq!(transferred, [2]);
+        assert!(room.inner().present.is_empty());
+        assert_eq!(result.total_intents, 4);
+        // Bob's refused intent and the banned connect
+        assert_eq!(result.errors, 2);
+    }
+}
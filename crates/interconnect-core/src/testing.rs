@@ -0,0 +1,440 @@
+//! Helpers for testing authorities.
+
+use crate::{
+    Authority, AuthorityErrorAction, AuthorityEvent, Broadcaster, Identity, ImportResult,
+    RelevanceConfig, ServerWire, Session, SimpleAuthority, SystemEvent,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::Duration;
+
+/// Deterministic hash of `state`'s serialized form.
+///
+/// Map entries are hashed in key order, so two maps with the same contents
+/// fingerprint alike however they iterate. Sequences keep their order; a set
+/// whose iteration order varies should serialize sorted (e.g. a `BTreeSet`).
+/// Returns 0 if `state` doesn't serialize.
+pub fn fingerprint<T: Serialize + ?Sized>(state: &T) -> u64 {
+    let Ok(value) = serde_json::to_value(state) else {
+        return 0;
+    };
+    let mut hasher = DefaultHasher::new();
+    hash_value(&value, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_value(value: &Value, hasher: &mut impl Hasher) {
+    match value {
+        Value::Null => 0u8.hash(hasher),
+        Value::Bool(b) => (1u8, b).hash(hasher),
+        Value::Number(n) => (2u8, n.to_string()).hash(hasher),
+        Value::String(s) => (3u8, s).hash(hasher),
+        Value::Array(items) => {
+            (4u8, items.len()).hash(hasher);
+            for item in items {
+                hash_value(item, hasher);
+            }
+        }
+        Value::Object(map) => {
+            (5u8, map.len()).hash(hasher);
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_unstable_by_key(|(key, _)| *key);
+            for (key, item) in entries {
+                key.hash(hasher);
+                hash_value(item, hasher);
+            }
+        }
+    }
+}
+
+/// Checks that an authority's state did or didn't change across a block.
+///
+/// Take it before the operation under test, then finish with
+/// [`unchanged`](Self::unchanged) or [`changed`](Self::changed). Dropping it
+/// without either fails the test, so a forgotten check can't pass silently.
+///
+/// ```ignore
+/// let guard = FingerprintAssert::new(&room);
+/// room.handle_intent(&session, duplicate)?;
+/// guard.unchanged(&room);
+/// ```
+#[must_use = "finish with `unchanged` or `changed`"]
+#[derive(Debug)]
+pub struct FingerprintAssert {
+    /// The fingerprint when the guard was taken.
+    pub before: u64,
+    checked: bool,
+}
+
+impl FingerprintAssert {
+    /// Record `authority`'s current fingerprint.
+    pub fn new<A>(authority: &A) -> Self
+    where
+        A: Authority,
+        A::Snapshot: Serialize,
+    {
+        Self {
+            before: authority.authority_fingerprint(),
+            checked: false,
+        }
+    }
+
+    /// Panic if `authority`'s state differs from when the guard was taken.
+    #[track_caller]
+    pub fn unchanged<A>(mut self, authority: &A)
+    where
+        A: Authority,
+        A::Snapshot: Serialize,
+    {
+        self.checked = true;
+        let after = authority.authority_fingerprint();
+        assert_eq!(self.before, after, "authority state changed");
+    }
+
+    /// Panic if `authority`'s state is the same as when the guard was taken.
+    #[track_caller]
+    pub fn changed<A>(mut self, authority: &A)
+    where
+        A: Authority,
+        A::Snapshot: Serialize,
+    {
+        self.checked = true;
+        let after = authority.authority_fingerprint();
+        assert_ne!(self.before, after, "authority state did not change");
+    }
+}
+
+impl Drop for FingerprintAssert {
+    fn drop(&mut self) {
+        if !self.checked && !std::thread::panicking() {
+            panic!("FingerprintAssert dropped without checking the authority");
+        }
+    }
+}
+
+/// A [`Broadcaster`] that keeps what it's given, for tests to inspect.
+#[derive(Debug)]
+pub struct PendingBroadcastQueue<S> {
+    pending: Vec<ServerWire<S>>,
+}
+
+impl<S> Default for PendingBroadcastQueue<S> {
+    fn default() -> Self {
+        Self {
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<S> PendingBroadcastQueue<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything broadcast since the last drain, oldest first.
+    pub fn drain(&mut self) -> Vec<ServerWire<S>> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Everything broadcast since the last drain, left queued.
+    pub fn pending(&self) -> &[ServerWire<S>] {
+        &self.pending
+    }
+
+    /// Panic unless exactly `n` snapshots are queued.
+    #[track_caller]
+    pub fn assert_snapshot_broadcast_count(&self, n: usize) {
+        let snapshots = self
+            .pending
+            .iter()
+            .filter(|msg| matches!(msg, ServerWire::Snapshot { .. }))
+            .count();
+        assert_eq!(snapshots, n, "snapshot broadcasts");
+    }
+
+    /// Panic unless `event` is queued.
+    #[track_caller]
+    pub fn assert_system_event_broadcast(&self, event: &SystemEvent) {
+        let events: Vec<_> = self
+            .pending
+            .iter()
+            .filter_map(SystemEvent::from_server_wire)
+            .collect();
+        assert!(
+            events.contains(event),
+            "{event:?} not broadcast; got {events:?}"
+        );
+    }
+}
+
+impl<S> Broadcaster<S> for PendingBroadcastQueue<S> {
+    fn broadcast(&mut self, msg: ServerWire<S>) {
+        self.pending.push(msg);
+    }
+}
+
+/// A small authority for tests: each session adds to its own tally.
+///
+/// The snapshot is every session's tally by session ID. Connected sessions
+/// are listed by name, and passports carry the session's name and the
+/// room's `items`. Adding zero fails with [`Refused`], as does connecting
+/// or transferring in under a name in `refused`. The only valid transfer
+/// destination is `ws://elsewhere`.
+#[derive(Debug, Clone, Default)]
+pub struct TestRoom {
+    /// Every intent applied, as (session ID, amount), oldest first.
+    pub applied: Vec<(u64, u32)>,
+    /// Names of the sessions connected, in the order they joined.
+    pub present: Vec<String>,
+    /// Carried in every passport.
+    pub items: Vec<String>,
+    /// Names refused at connect and at transfer in.
+    pub refused: Vec<String>,
+    /// The snapshot context each transferred session brought, by name.
+    pub arrived_with: Vec<(String, Tallies)>,
+    /// Pause intents this long after an error rather than only reporting it.
+    pub pause_on_error: Option<Duration>,
+    /// Errors recovered from after a pause, oldest first.
+    pub recovered: Vec<String>,
+    /// Each batch of transfers in from a peer, as (peer, accepted, rejected).
+    pub peer_batches: Vec<(Identity, u64, u64)>,
+    /// Scores each tally by how far its session ID is from the viewer's.
+    pub relevance: RelevanceConfig,
+}
+
+/// [`TestRoom`]'s intent: add `by` to the sender's tally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Add {
+    pub by: u32,
+}
+
+/// [`TestRoom`]'s passport.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestPassport {
+    pub name: String,
+    pub items: Vec<String>,
+}
+
+/// [`TestRoom`]'s error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("refused")]
+pub struct Refused;
+
+/// Each session's tally as (session ID, tally), in ID order: [`TestRoom`]'s
+/// snapshot.
+pub type Tallies = Vec<(u64, u32)>;
+
+impl TestRoom {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything added, by everyone.
+    pub fn total(&self) -> u32 {
+        self.applied.iter().map(|(_, by)| by).sum()
+    }
+
+    /// The current snapshot.
+    pub fn tallies(&self) -> Tallies {
+        let mut tallies = BTreeMap::<u64, u32>::new();
+        for (session_id, by) in &self.applied {
+            *tallies.entry(*session_id).or_default() += by;
+        }
+        tallies.into_iter().collect()
+    }
+
+    fn admit(&mut self, name: &str) -> Result<(), Refused> {
+        if self.refused.iter().any(|refused| refused == name) {
+            return Err(Refused);
+        }
+        self.present.push(name.to_string());
+        Ok(())
+    }
+}
+
+impl SimpleAuthority for TestRoom {
+    type Intent = Add;
+    type Snapshot = Tallies;
+    type Passport = TestPassport;
+    type Error = Refused;
+
+    fn on_connect(&mut self, session: &Session) -> Result<(), Refused> {
+        self.admit(&session.name)
+    }
+
+    fn on_transfer_in(
+        &mut self,
+        session: &Session,
+        passport: TestPassport,
+    ) -> Result<ImportResult<TestPassport>, Refused> {
+        self.admit(&session.name)?;
+        Ok(ImportResult::accept(passport))
+    }
+
+    fn on_transfer_context(&mut self, session: &Session, context: Tallies) {
+        self.arrived_with.push((session.name.clone(), context));
+    }
+
+    fn on_disconnect(&mut self, session: &Session) {
+        if let Some(at) = self.present.iter().position(|name| *name == session.name) {
+            self.present.remove(at);
+        }
+    }
+
+    fn handle_intent(&mut self, session: &Session, Add { by }: Add) -> Result<(), Refused> {
+        if by == 0 {
+            return Err(Refused);
+        }
+        self.applied.push((session.id, by));
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Tallies {
+        self.tallies()
+    }
+
+    /// Tallies are entities standing on a line at their session IDs.
+    fn compute_relevance_score(&self, session: &Session, entity_id: u64) -> f32 {
+        self.relevance.score(entity_id.abs_diff(session.id) as f32)
+    }
+
+    /// A spectator sees only the tally of the session it watches.
+    fn clone_session_state(&self, source_session_id: u64, _spectator: u64) -> Option<Tallies> {
+        let mut tallies = self.tallies();
+        tallies.retain(|(session_id, _)| *session_id == source_session_id);
+        Some(tallies)
+    }
+
+    /// The tallies that changed since `base`.
+    fn snapshot_delta_from(
+        &self,
+        _session: &Session,
+        _base_seq: u64,
+        base: &Tallies,
+    ) -> Option<Tallies> {
+        let mut tallies = self.tallies();
+        tallies.retain(|tally| !base.contains(tally));
+        Some(tallies)
+    }
+
+    /// Everything the events added, as big-endian bytes.
+    fn snapshot_delta_from_events(
+        &self,
+        events: &[AuthorityEvent<Add, Tallies, TestPassport>],
+    ) -> Option<Vec<u8>> {
+        let added: u32 = events
+            .iter()
+            .map(|event| match event {
+                AuthorityEvent::Intent { intent, .. } => intent.by,
+                _ => 0,
+            })
+            .sum();
+        Some(added.to_be_bytes().to_vec())
+    }
+
+    fn emit_passport(&self, session: &Session) -> TestPassport {
+        TestPassport {
+            name: session.name.clone(),
+            items: self.items.clone(),
+        }
+    }
+
+    fn validate_destination(&self, destination: &str) -> bool {
+        destination == "ws://elsewhere"
+    }
+
+    fn on_authority_error(&self, _error: &Refused) -> AuthorityErrorAction {
+        match self.pause_on_error {
+            Some(for_duration) => AuthorityErrorAction::PauseAuthority { for_duration },
+            None => AuthorityErrorAction::SendErrorAndContinue,
+        }
+    }
+
+    fn on_authority_recovered(&mut self, previous_error: &Refused) {
+        self.recovered.push(previous_error.to_string());
+    }
+
+    fn on_peer_transfer_complete(
+        &mut self,
+        src_identity: &Identity,
+        accepted: u64,
+        rejected: u64,
+        _elapsed: Duration,
+    ) {
+        self.peer_batches
+            .push((src_identity.clone(), accepted, rejected));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn session() -> Session {
+        Session::new(1, crate::Identity::local("a"), "a".into())
+    }
+
+    /// Counters by name; iteration order depends on insertion history.
+    fn counters(names: &[&str]) -> HashMap<String, u32> {
+        let mut counters = HashMap::new();
+        for name in names {
+            *counters.entry(name.to_string()).or_default() += 1;
+        }
+        counters
+    }
+
+    #[test]
+    fn identical_state_fingerprints_alike_in_any_order() {
+        let names: Vec<String> = (0..64).map(|i| format!("key{i}")).collect();
+        let forward: Vec<&str> = names.iter().map(String::as_str).collect();
+        let backward: Vec<&str> = forward.iter().rev().copied().collect();
+        let a = counters(&forward);
+        let b = counters(&backward);
+        assert_eq!(fingerprint(&a), fingerprint(&b));
+        assert_ne!(fingerprint(&a), fingerprint(&counters(&forward[1..])));
+        // Sequences stay ordered.
+        assert_ne!(fingerprint(&["a", "b"]), fingerprint(&["b", "a"]));
+    }
+
+    #[test]
+    fn guard_checks_for_change() {
+        let mut room = TestRoom::new();
+
+        let guard = FingerprintAssert::new(&room);
+        Authority::handle_intent(&mut room, &session(), Add { by: 1 }).unwrap();
+        guard.changed(&room);
+
+        let guard = FingerprintAssert::new(&room);
+        let _ = SimpleAuthority::snapshot(&room);
+        guard.unchanged(&room);
+    }
+
+    #[test]
+    #[should_panic(expected = "authority state changed")]
+    fn guard_catches_unexpected_mutation() {
+        let mut room = TestRoom::new();
+        let guard = FingerprintAssert::new(&room);
+        Authority::handle_intent(&mut room, &session(), Add { by: 1 }).unwrap();
+        guard.unchanged(&room);
+    }
+
+    #[test]
+    fn queue_collects_until_drained() {
+        let mut queue = PendingBroadcastQueue::new();
+        queue.broadcast(ServerWire::snapshot(1, 10u32));
+        queue.broadcast(ServerWire::RoomClosing {
+            message: "bye".into(),
+        });
+        queue.broadcast(ServerWire::snapshot(2, 20u32));
+
+        queue.assert_snapshot_broadcast_count(2);
+        queue.assert_system_event_broadcast(&SystemEvent::RoomClosing {
+            message: "bye".into(),
+        });
+        assert_eq!(queue.drain().len(), 3);
+        queue.assert_snapshot_broadcast_count(0);
+    }
+}
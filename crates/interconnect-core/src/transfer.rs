@@ -1,7 +1,8 @@
 //! Transfer types for server-to-server handoff.
 
-use crate::Identity;
+use crate::{Identity, IdentityKeyring, canonical_bytes};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// A transfer directive, telling the client to connect to another server.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,6 +11,49 @@ pub struct Transfer {
     pub destination: String,
     /// The passport to present to the destination.
     pub passport: Passport,
+    /// Passports of the rest of the party, when several sessions transfer
+    /// together (see [`Transfer::bundle`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub party: Vec<Passport>,
+}
+
+impl Transfer {
+    /// Transfer a single session.
+    pub fn new(destination: impl Into<String>, passport: Passport) -> Self {
+        Self {
+            destination: destination.into(),
+            passport,
+            party: Vec::new(),
+        }
+    }
+
+    /// Transfer several sessions together, led by the first passport.
+    ///
+    /// Returns `None` for an empty party.
+    pub fn bundle(destination: impl Into<String>, passports: Vec<Passport>) -> Option<Self> {
+        let mut passports = passports.into_iter();
+        let passport = passports.next()?;
+        Some(Self {
+            destination: destination.into(),
+            passport,
+            party: passports.collect(),
+        })
+    }
+
+    /// Whether this moves more than one session.
+    pub fn is_bundle(&self) -> bool {
+        !self.party.is_empty()
+    }
+
+    /// Every passport in the transfer, leader first.
+    pub fn passports(&self) -> impl Iterator<Item = &Passport> {
+        std::iter::once(&self.passport).chain(&self.party)
+    }
+
+    /// Whether every passport is signed by a key `keyring` accepts.
+    pub fn verify(&self, keyring: &IdentityKeyring) -> bool {
+        self.passports().all(|passport| passport.verify(keyring))
+    }
 }
 
 /// A passport carried during transfer.
@@ -43,4 +87,185 @@ impl Passport {
             signature: Some(signature),
         }
     }
+
+    /// Sign with `keyring`'s current key.
+    pub fn sign(self, keyring: &IdentityKeyring) -> Self {
+        let signature = keyring.sign(&self.signed_bytes());
+        Self {
+            signature: Some(signature),
+            ..self
+        }
+    }
+
+    /// Whether the passport is signed by a key `keyring` accepts.
+    pub fn verify(&self, keyring: &IdentityKeyring) -> bool {
+        self.signature
+            .as_deref()
+            .is_some_and(|signature| keyring.verify(&self.signed_bytes(), signature))
+    }
+
+    /// The identity and data, as signed.
+    fn signed_bytes(&self) -> Vec<u8> {
+        canonical_bytes(&(&self.identity, &self.data)).expect("identities and bytes serialize")
+    }
+}
+
+/// Transfer payload carrying the session's view alongside its passport.
+///
+/// The passport goes through the destination's import policy; the snapshot
+/// context lets the destination reconstruct what the session was doing
+/// (current room, quest state, etc.).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferSnapshot<S, P> {
+    /// The session's view at the origin when it transferred.
+    pub snapshot_context: S,
+    /// The passport to run through import policy.
+    pub passport: P,
+}
+
+/// Split a transfer snapshot into its context and passport.
+pub fn split_transfer_snapshot<S, P>(ts: TransferSnapshot<S, P>) -> (S, P) {
+    (ts.snapshot_context, ts.passport)
+}
+
+/// A passport sent either whole or as a patch on an earlier version.
+///
+/// The patch format is app-defined; the receiver applies it to the version
+/// it has cached (see [`PassportCache`]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassportUpdate<P> {
+    /// The full passport, when not patching.
+    pub full: Option<P>,
+    /// Changes since `version - 1`.
+    pub patch: Option<Vec<u8>>,
+    /// Version of the passport this update produces.
+    pub version: u32,
+}
+
+impl<P> PassportUpdate<P> {
+    /// A full passport at `version`.
+    pub fn full(passport: P, version: u32) -> Self {
+        Self {
+            full: Some(passport),
+            patch: None,
+            version,
+        }
+    }
+
+    /// A patch producing `version` from `version - 1`.
+    pub fn patch(patch: Vec<u8>, version: u32) -> Self {
+        Self {
+            full: None,
+            patch: Some(patch),
+            version,
+        }
+    }
+}
+
+/// The last accepted passport per identity, for applying patches.
+///
+/// Kept by the receiving side of an application that sends
+/// [`PassportUpdate`]s; transports that only deal in full passports have no
+/// use for one.
+#[derive(Debug, Clone)]
+pub struct PassportCache<P> {
+    entries: HashMap<Identity, (u32, P)>,
+}
+
+impl<P> Default for PassportCache<P> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<P: Clone> PassportCache<P> {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cached version and passport for an identity.
+    pub fn get(&self, identity: &Identity) -> Option<(u32, &P)> {
+        self.entries.get(identity).map(|(v, p)| (*v, p))
+    }
+
+    /// Cache an accepted passport.
+    pub fn insert(&mut self, identity: Identity, version: u32, passport: P) {
+        self.entries.insert(identity, (version, passport));
+    }
+
+    /// Resolve an update into a full passport and cache it.
+    ///
+    /// Patches go through `apply_patch` against the cached passport. Returns
+    /// `None` if a patch doesn't follow the cached version (or nothing is
+    /// cached, or `apply_patch` fails); ask the origin for a full passport.
+    pub fn apply(
+        &mut self,
+        identity: &Identity,
+        update: PassportUpdate<P>,
+        apply_patch: impl FnOnce(&P, &[u8]) -> Option<P>,
+    ) -> Option<P> {
+        let passport = match (update.full, update.patch) {
+            (Some(full), _) => full,
+            (None, Some(patch)) => {
+                let (version, base) = self.entries.get(identity)?;
+                if version.checked_add(1) != Some(update.version) {
+                    return None;
+                }
+                apply_patch(base, &patch)?
+            }
+            (None, None) => return None,
+        };
+        self.insert(identity.clone(), update.version, passport.clone());
+        Some(passport)
+    }
+
+    /// Forget an identity's passport.
+    pub fn remove(&mut self, identity: &Identity) -> Option<P> {
+        self.entries.remove(identity).map(|(_, p)| p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append(base: &String, patch: &[u8]) -> Option<String> {
+        Some(format!("{base}{}", std::str::from_utf8(patch).ok()?))
+    }
+
+    #[test]
+    fn bundles_keep_party_order_and_single_transfers_stay_compact() {
+        let passport = |name| Passport::new(Identity::local(name), Vec::new());
+        assert!(Transfer::bundle("hub", Vec::new()).is_none());
+
+        let party = Transfer::bundle("hub", vec![passport("a"), passport("b")]).unwrap();
+        assert!(party.is_bundle());
+        let names: Vec<_> = party.passports().map(|p| p.identity.clone()).collect();
+        assert_eq!(names, [Identity::local("a"), Identity::local("b")]);
+
+        let single = serde_json::to_value(Transfer::new("hub", passport("a"))).unwrap();
+        assert!(single.get("party").is_none());
+        let decoded: Transfer = serde_json::from_value(single).unwrap();
+        assert!(!decoded.is_bundle());
+    }
+
+    #[test]
+    fn patches_apply_to_the_previous_version() {
+        let alice = Identity::local("alice");
+        let mut cache = PassportCache::new();
+
+        let full = PassportUpdate::full("ab".to_string(), 1);
+        assert_eq!(cache.apply(&alice, full, append).as_deref(), Some("ab"));
+
+        let patch = PassportUpdate::patch(b"c".to_vec(), 2);
+        assert_eq!(cache.apply(&alice, patch, append).as_deref(), Some("abc"));
+        assert_eq!(cache.get(&alice), Some((2, &"abc".to_string())));
+
+        // Skipped a version: needs a full passport
+        let gap = PassportUpdate::patch(b"e".to_vec(), 4);
+        assert!(cache.apply(&alice, gap, append).is_none());
+    }
 }
@@ -3,8 +3,10 @@
 //! These are the actual messages sent over the wire, generic over
 //! application-defined Intent and Snapshot types.
 
-use crate::{Identity, Manifest};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use crate::{Identity, Manifest, PresenceDelta, QueryPage, SessionToken};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::BTreeMap;
+use std::fmt;
 
 /// Trait for types that can be serialized to/from wire format.
 pub trait Wire: Serialize + DeserializeOwned + Send + Sync + 'static {}
@@ -14,6 +16,7 @@ impl<T> Wire for T where T: Serialize + DeserializeOwned + Send + Sync + 'static
 
 /// Messages sent from client to server.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ClientWire<I> {
     /// Authenticate with the server.
@@ -26,39 +29,226 @@ pub enum ClientWire<I> {
         /// Passport data if transferring from another server.
         #[serde(default)]
         passport: Option<Vec<u8>>,
+        /// Manifest of the server the passport came from, relayed by the client.
+        #[serde(default)]
+        source: Option<Box<Manifest>>,
+        /// Ticket for a passport the origin pushed server-side (instead of `passport`).
+        #[serde(default)]
+        ticket: Option<String>,
+        /// Whether the server may send snapshots unprompted.
+        #[serde(default)]
+        delivery: Delivery,
+        /// Signed session from `ServerWire::ReconnectToken`, possibly issued
+        /// by another instance.
+        #[serde(default)]
+        reconnect_token: Option<SessionToken>,
     },
     /// Send an intent.
     Intent(I),
+    /// Send an intent that must be applied exactly once, even if retried.
+    ///
+    /// The server remembers applied `request_id`s, so a retry after a lost
+    /// ack isn't applied again.
+    TrackedIntent {
+        /// Client-chosen ID; reuse it when retrying.
+        request_id: u64,
+        /// Answer with `IntentAck` once applied.
+        #[serde(default)]
+        require_ack: bool,
+        /// The client already applied this on top of snapshot `base_seq`.
+        /// The server answers `IntentConfirmed` or `IntentCorrected` instead
+        /// of `IntentAck`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        base_seq: Option<u64>,
+        /// Apply only if snapshot `if_seq` is the latest this connection was
+        /// sent and nothing in the room changed since it was taken.
+        /// Otherwise the server answers with a [`ErrorCode::Conflict`] error.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        if_seq: Option<u64>,
+        intent: I,
+    },
     /// Acknowledge a snapshot.
-    Ack { seq: u64 },
+    Ack {
+        seq: u64,
+        /// For client-side prediction: the last snapshot that agreed with
+        /// the client's prediction. Behind `seq` means the prediction went
+        /// wrong, and the server answers with a `Correction`.
+        #[serde(default)]
+        confirmed_seq: u64,
+        /// How many snapshots past `confirmed_seq` the client predicts; 0
+        /// (the default) for clients that don't.
+        #[serde(default)]
+        prediction_horizon: u32,
+    },
     /// Request transfer to another server.
     TransferRequest { destination: String },
     /// Ping (keep-alive).
     Ping,
+    /// Reply to a server `Ping`, echoing its nonce.
+    Pong { nonce: u64 },
+    /// Reattach a held session after reconnecting (instead of `Auth`).
+    ResumeSession {
+        token: String,
+        /// Delivery mode for the resumed connection.
+        #[serde(default)]
+        delivery: Delivery,
+    },
+    /// Ask for the current snapshot. Under [`Delivery::Pull`] this is the
+    /// only way to get one.
+    Resync,
+    /// The client went to the background. The server holds snapshots
+    /// instead of sending them, up to a limit.
+    Pause,
+    /// The client is back; the server delivers what it held.
+    Resume,
+    /// Ask for a page of a read-only query, answered with `QueryPage`.
+    Query {
+        /// Client-chosen ID, echoed in each page.
+        id: u64,
+        /// App-defined query.
+        query: serde_json::Value,
+        /// Cursor from the previous page; `None` for the first.
+        #[serde(default)]
+        cursor: Option<String>,
+    },
+    /// The manifest's type hashes don't match the client's compiled types.
+    TypeMismatch {
+        /// The manifest's snapshot type hash.
+        expected_snapshot: String,
+        /// The client's snapshot type hash.
+        got: String,
+        /// The manifest's intent type hash.
+        #[serde(default)]
+        expected_intent: String,
+        /// The client's intent type hash.
+        #[serde(default)]
+        got_intent: String,
+    },
+    /// Start receiving a server-side stream. The only topic is
+    /// [`ADMIN_TOPIC`], for sessions with the `admin` capability.
+    Subscribe { topics: Vec<String> },
+}
+
+/// How a client receives snapshots, chosen at handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    /// The server sends snapshots as state changes.
+    #[default]
+    Push,
+    /// The server sends snapshots only in reply to `Resync`, e.g. for a
+    /// proxy that polls on its own schedule. Other messages still arrive
+    /// unprompted.
+    Pull,
 }
 
 /// Messages sent from server to client.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ServerWire<S> {
     /// Server manifest.
-    Manifest(Manifest),
+    Manifest(Box<Manifest>),
     /// State snapshot.
-    Snapshot { seq: u64, data: S },
+    ///
+    /// `stale` marks one sent while the authority isn't taking intents;
+    /// show it read-only (see [`ConnectionState::Ghost`](crate::ConnectionState::Ghost)).
+    Snapshot {
+        seq: u64,
+        data: S,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        stale: bool,
+    },
+    /// State snapshot for a client whose prediction diverged; reconcile the
+    /// predicted state to it.
+    Correction { seq: u64, data: S },
+    /// Changes since snapshot `base_seq`, which the client acked.
+    Delta { seq: u64, base_seq: u64, data: S },
     /// Transfer directive.
     Transfer {
         destination: String,
         passport: Vec<u8>,
     },
+    /// Transfer directive for a passport already pushed to the destination.
+    /// Present `token` as the `ticket` in `Auth`.
+    TransferTicket { destination: String, token: String },
+    /// The session moved to `destination` on this server without
+    /// reconnecting; it continues as `session_id`.
+    LocalTransferComplete {
+        destination: String,
+        session_id: u64,
+    },
     /// Error message.
     Error { code: String, message: String },
     /// System message (informational).
-    System { message: String },
+    System {
+        message: String,
+        /// What the message is about, if clients should treat it specially.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        category: Option<SystemCategory>,
+    },
+    /// The server is closing the room (e.g. shutting down) and this
+    /// connection with it. Sent in place of the per-session leaves and
+    /// snapshots a one-by-one teardown would cost.
+    RoomClosing { message: String },
     /// Pong (keep-alive response).
     Pong,
+    /// Latency probe; answer with a `Pong` carrying the same nonce.
+    Ping { nonce: u64 },
+    /// Token for resuming this session after a dropped connection.
+    ResumeToken { token: String },
+    /// Signed session to present as `reconnect_token` in a later `Auth`.
+    ReconnectToken { token: SessionToken },
+    /// A `TrackedIntent` was applied; `seq` is the first snapshot
+    /// reflecting it.
+    IntentAck { request_id: u64, seq: u64 },
+    /// An optimistic `TrackedIntent` came out as the client predicted; keep
+    /// the local state. `seq` is the first snapshot reflecting it.
+    IntentConfirmed { request_id: u64, seq: u64 },
+    /// An optimistic `TrackedIntent` didn't come out as the client
+    /// predicted. Roll back to `corrective_snapshot`, which is snapshot
+    /// `seq`.
+    IntentCorrected {
+        request_id: u64,
+        seq: u64,
+        corrective_snapshot: S,
+    },
+    /// One page of the answer to `ClientWire::Query`.
+    QueryPage {
+        id: u64,
+        /// Zero-based page number.
+        page: u32,
+        total_pages: u32,
+        data: Vec<serde_json::Value>,
+        /// Send this back in the next `Query` for the following page.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
+    },
+    /// Something happened on the server, for a session subscribed to
+    /// [`ADMIN_TOPIC`].
+    Admin { event: LifecycleEvent },
+    /// Who joined and who left since the last `Presence`, for an authority
+    /// that keeps its roster out of snapshots (see [`Roster`](crate::Roster)).
+    /// `joined` is keyed by the key `left` will name them by later.
+    Presence {
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        joined: BTreeMap<String, Identity>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        left: Vec<String>,
+    },
 }
 
 impl<S> ServerWire<S> {
+    /// Create an (authoritative) snapshot message.
+    pub fn snapshot(seq: u64, data: S) -> Self {
+        Self::Snapshot {
+            seq,
+            data,
+            stale: false,
+        }
+    }
+
     /// Create an error message.
     pub fn error(code: impl Into<String>, message: impl Into<String>) -> Self {
         Self::Error {
@@ -71,8 +261,426 @@ impl<S> ServerWire<S> {
     pub fn system(message: impl Into<String>) -> Self {
         Self::System {
             message: message.into(),
+            category: None,
+        }
+    }
+
+    /// Send a page of query results in reply to query `id`.
+    pub fn query_page(id: u64, page: QueryPage) -> Self {
+        Self::QueryPage {
+            id,
+            page: page.page,
+            total_pages: page.total_pages,
+            data: page.items,
+            cursor: page.next_cursor,
+        }
+    }
+
+    /// Send a change in presence.
+    pub fn presence(delta: PresenceDelta) -> Self {
+        Self::Presence {
+            joined: delta.joined,
+            left: delta.left,
+        }
+    }
+
+    /// Create a maintenance notice.
+    pub fn maintenance(message: impl Into<String>) -> Self {
+        Self::System {
+            message: message.into(),
+            category: Some(SystemCategory::Maintenance),
+        }
+    }
+}
+
+/// Sends a message to every session, as a transport's fan-out does.
+///
+/// Code that broadcasts through this can be tested against a
+/// [`PendingBroadcastQueue`](crate::PendingBroadcastQueue) instead of a
+/// running server.
+pub trait Broadcaster<S> {
+    fn broadcast(&mut self, msg: ServerWire<S>);
+}
+
+/// Topic of the lifecycle event stream (see `ClientWire::Subscribe`).
+pub const ADMIN_TOPIC: &str = "admin";
+
+/// A session lifecycle event, streamed to admins as `ServerWire::Admin`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    /// A session joined, fresh or by transfer.
+    Connected { session_id: u64, name: String },
+    /// Several sessions joined in quick succession, as `(session_id, name)`
+    /// in join order. Sent instead of a `Connected` each during join storms.
+    MultipleConnected { sessions: Vec<(u64, String)> },
+    /// A session came back after a dropped connection.
+    Resumed { session_id: u64, name: String },
+    /// A session left for good.
+    Disconnected { session_id: u64, name: String },
+    /// A session left for another server.
+    TransferredOut {
+        session_id: u64,
+        name: String,
+        destination: String,
+    },
+    /// An intent failed in the authority.
+    IntentFailed {
+        session_id: u64,
+        name: String,
+        error: String,
+    },
+}
+
+/// Kinds of `ServerWire::System` message clients may react to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SystemCategory {
+    /// The server is (or stops) holding intents for maintenance.
+    Maintenance,
+}
+
+/// Well-known codes for `ServerWire::Error`.
+///
+/// Sent as snake_case strings, so apps can still use codes of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// An intent failed in the authority.
+    IntentError,
+    /// The session is sending intents faster than allowed.
+    RateLimited,
+    /// The transfer destination is unknown.
+    InvalidDestination,
+    /// The session may not transfer.
+    TransferForbidden,
+    /// Pushing the passport to the destination failed.
+    TransferFailed,
+    /// The session already has as many pending transfers as allowed.
+    TooManyTransfers,
+    /// The server can't take on more work right now.
+    Overloaded,
+    /// Transfers from the source server are refused.
+    SourceBlocked,
+    /// The transfer ticket is unknown, used, or expired.
+    InvalidTicket,
+    /// The resume token is unknown or its grace window has passed.
+    ResumeExpired,
+    /// The authority refused the identity.
+    InvalidIdentity,
+    /// A client message couldn't be decoded.
+    MalformedMessage,
+    /// The client broke the protocol (e.g. sent a corrupt passport).
+    ProtocolError,
+    /// A query couldn't be answered.
+    InvalidQuery,
+    /// The server failed while handling the request (e.g. the authority
+    /// panicked).
+    InternalError,
+    /// The server ended the session.
+    Kicked,
+    /// The session may not subscribe to that topic.
+    SubscriptionForbidden,
+    /// The transfer destination is this server.
+    TransferLoopback,
+    /// An intent failed the authority's schema.
+    IntentRejected,
+    /// The identity already holds as many sessions as allowed.
+    TooManyConnections,
+    /// The transferring session's passport failed validation.
+    InvalidPassport,
+    /// The identity is banned.
+    Banned,
+    /// The transferring session's passport is larger than allowed.
+    PassportTooLarge,
+    /// What the request refers to doesn't exist.
+    NotFound,
+    /// The request conflicts with the current state (e.g. a name already
+    /// taken).
+    Conflict,
+    /// The session may not do that.
+    Forbidden,
+    /// The session is making requests faster than allowed.
+    TooManyRequests,
+    /// The request is well-formed but can't be carried out.
+    UnprocessableEntity,
+}
+
+impl ErrorCode {
+    /// The code as sent on the wire.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::IntentError => "intent_error",
+            Self::RateLimited => "rate_limited",
+            Self::InvalidDestination => "invalid_destination",
+            Self::TransferForbidden => "transfer_forbidden",
+            Self::TransferFailed => "transfer_failed",
+            Self::TooManyTransfers => "too_many_transfers",
+            Self::Overloaded => "overloaded",
+            Self::SourceBlocked => "source_blocked",
+            Self::InvalidTicket => "invalid_ticket",
+            Self::ResumeExpired => "resume_expired",
+            Self::InvalidIdentity => "invalid_identity",
+            Self::MalformedMessage => "malformed_message",
+            Self::ProtocolError => "protocol_error",
+            Self::InvalidQuery => "invalid_query",
+            Self::InternalError => "internal_error",
+            Self::Kicked => "kicked",
+            Self::SubscriptionForbidden => "subscription_forbidden",
+            Self::TransferLoopback => "transfer_loopback",
+            Self::IntentRejected => "intent_rejected",
+            Self::TooManyConnections => "too_many_connections",
+            Self::InvalidPassport => "invalid_passport",
+            Self::Banned => "banned",
+            Self::PassportTooLarge => "passport_too_large",
+            Self::NotFound => "not_found",
+            Self::Conflict => "conflict",
+            Self::Forbidden => "forbidden",
+            Self::TooManyRequests => "too_many_requests",
+            Self::UnprocessableEntity => "unprocessable_entity",
+        }
+    }
+
+    /// The HTTP status an HTTP transport answers with.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Self::MalformedMessage | Self::ProtocolError | Self::InvalidQuery => 400,
+            Self::InvalidTicket | Self::ResumeExpired => 401,
+            Self::Forbidden
+            | Self::TransferForbidden
+            | Self::SourceBlocked
+            | Self::InvalidIdentity
+            | Self::Kicked
+            | Self::SubscriptionForbidden
+            | Self::Banned => 403,
+            Self::NotFound | Self::InvalidDestination => 404,
+            Self::Conflict | Self::TransferLoopback => 409,
+            Self::PassportTooLarge => 413,
+            Self::UnprocessableEntity
+            | Self::IntentError
+            | Self::IntentRejected
+            | Self::InvalidPassport => 422,
+            Self::TooManyRequests
+            | Self::RateLimited
+            | Self::TooManyTransfers
+            | Self::TooManyConnections => 429,
+            Self::InternalError => 500,
+            Self::TransferFailed => 502,
+            Self::Overloaded => 503,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<ErrorCode> for String {
+    fn from(code: ErrorCode) -> Self {
+        code.as_str().to_string()
+    }
+}
+
+/// Version of the wire format, bumped on incompatible changes.
+///
+/// Included in [`wire_schema`] so generated clients can tell which
+/// protocol they were built against.
+pub const WIRE_SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema for the wire messages, specialized to the app's types.
+///
+/// Returns `{ "version", "client", "server" }`, where `client` describes
+/// `ClientWire<I>` and `server` describes `ServerWire<S>`. Feed it to a
+/// code generator to build clients in other languages.
+#[cfg(feature = "schema")]
+pub fn wire_schema<I: schemars::JsonSchema, S: schemars::JsonSchema>() -> serde_json::Value {
+    serde_json::json!({
+        "version": WIRE_SCHEMA_VERSION,
+        "client": schemars::schema_for!(ClientWire<I>),
+        "server": schemars::schema_for!(ServerWire<S>),
+    })
+}
+
+/// How wire messages are encoded on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WireEncoding {
+    /// JSON, sent as text frames.
+    #[default]
+    Json,
+}
+
+impl WireEncoding {
+    /// Whether messages in this encoding travel as binary frames.
+    pub fn is_binary(&self) -> bool {
+        match self {
+            Self::Json => false,
+        }
+    }
+
+    /// Encode a message.
+    pub fn encode<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, WireError> {
+        match self {
+            Self::Json => Ok(to_json(msg)?),
+        }
+    }
+
+    /// Decode a message.
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, WireError> {
+        match self {
+            Self::Json => Ok(from_json(data)?),
+        }
+    }
+}
+
+/// The encoding a MIME type names, if this build supports it.
+///
+/// Case and parameters (`; charset=utf-8`) are ignored. Binary types such
+/// as `application/cbor` and `application/msgpack` give `None` until they
+/// have a [`WireEncoding`], so clients asking for them fall back to JSON.
+pub fn wire_format_from_content_type(content_type: &str) -> Option<WireEncoding> {
+    let mime = content_type.split(';').next()?.trim();
+    match mime.to_ascii_lowercase().as_str() {
+        "application/json" | "text/json" => Some(WireEncoding::Json),
+        _ => None,
+    }
+}
+
+/// The encoding a connection negotiated at the WebSocket upgrade, kept on
+/// its [`Session`](crate::Session) for the connection's lifetime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SessionEncoding {
+    pub encoding: WireEncoding,
+}
+
+/// Error encoding or decoding a wire message.
+#[derive(Debug, thiserror::Error)]
+pub enum WireError {
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    /// Encoded, but over a size limit.
+    #[error("{bytes} bytes is over the {limit}-byte limit")]
+    TooLarge { bytes: usize, limit: usize },
+}
+
+/// Case convention for message tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TagCase {
+    /// `resume_token` (native).
+    #[default]
+    SnakeCase,
+    /// `resumeToken`.
+    CamelCase,
+}
+
+impl TagCase {
+    /// Convert a native (snake_case) name to this case.
+    fn apply(self, name: &str) -> String {
+        match self {
+            Self::SnakeCase => name.to_string(),
+            Self::CamelCase => {
+                let mut out = String::with_capacity(name.len());
+                let mut upper = false;
+                for c in name.chars() {
+                    if c == '_' {
+                        upper = true;
+                    } else if upper {
+                        out.push(c.to_ascii_uppercase());
+                        upper = false;
+                    } else {
+                        out.push(c);
+                    }
+                }
+                out
+            }
         }
     }
+
+    /// Convert a name in this case back to snake_case.
+    fn revert(self, name: &str) -> String {
+        match self {
+            Self::SnakeCase => name.to_string(),
+            Self::CamelCase => {
+                let mut out = String::with_capacity(name.len() + 4);
+                for c in name.chars() {
+                    if c.is_ascii_uppercase() {
+                        out.push('_');
+                        out.push(c.to_ascii_lowercase());
+                    } else {
+                        out.push(c);
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+/// JSON shape of message tags, for clients that can't use the native one.
+///
+/// Natively, messages are tagged `"type"` with snake_case names:
+/// `{"type":"resume_token","token":".."}`. A config with tag `"kind"` and
+/// [`TagCase::CamelCase`] sends `{"kind":"resumeToken","token":".."}`
+/// instead, and accepts the same shape back. Only the message tag changes;
+/// field names and app payloads (intents, snapshots) are sent as-is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WireConfig {
+    /// Key holding the message tag.
+    pub tag: &'static str,
+    /// Case of the tag's value.
+    pub case: TagCase,
+}
+
+impl Default for WireConfig {
+    fn default() -> Self {
+        Self::native()
+    }
+}
+
+impl WireConfig {
+    /// The native shape: `"type"`, snake_case.
+    pub const fn native() -> Self {
+        Self {
+            tag: "type",
+            case: TagCase::SnakeCase,
+        }
+    }
+
+    /// Whether this is the native shape (no rewriting needed).
+    pub fn is_native(&self) -> bool {
+        *self == Self::native()
+    }
+
+    /// Serialize a wire message to a JSON string in this shape.
+    pub fn to_json_string<T: Serialize>(&self, msg: &T) -> Result<String, serde_json::Error> {
+        if self.is_native() {
+            return to_json_string(msg);
+        }
+        let mut value = serde_json::to_value(msg)?;
+        if let Some(object) = value.as_object_mut()
+            && let Some(serde_json::Value::String(name)) = object.remove("type")
+        {
+            object.insert(self.tag.into(), self.case.apply(&name).into());
+        }
+        serde_json::to_string(&value)
+    }
+
+    /// Deserialize a wire message sent in this shape.
+    pub fn from_json_str<T: DeserializeOwned>(&self, data: &str) -> Result<T, serde_json::Error> {
+        if self.is_native() {
+            return from_json_str(data);
+        }
+        let mut value: serde_json::Value = serde_json::from_str(data)?;
+        if let Some(object) = value.as_object_mut()
+            && let Some(serde_json::Value::String(name)) = object.remove(self.tag)
+        {
+            object.insert("type".into(), self.case.revert(&name).into());
+        }
+        serde_json::from_value(value)
+    }
 }
 
 /// Serialize a wire message to JSON bytes.
@@ -95,17 +703,96 @@ pub fn from_json_str<T: DeserializeOwned>(data: &str) -> Result<T, serde_json::E
     serde_json::from_str(data)
 }
 
+/// Serialize several wire messages into one payload, as a JSON array.
+///
+/// Sending a batch in one frame saves per-frame overhead when many messages
+/// go out at once. Messages are objects, so receivers can tell a batch from
+/// a single message by the leading `[`.
+pub fn encode_batch<T: Serialize>(msgs: &[T]) -> Result<Vec<u8>, serde_json::Error> {
+    serde_json::to_vec(msgs)
+}
+
+/// Deserialize a payload from [`encode_batch`].
+pub fn decode_batch<T: DeserializeOwned>(data: &[u8]) -> Result<Vec<T>, serde_json::Error> {
+    serde_json::from_slice(data)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn content_types_map_to_encodings() {
+        assert_eq!(
+            wire_format_from_content_type("application/json"),
+            Some(WireEncoding::Json)
+        );
+        assert_eq!(
+            wire_format_from_content_type(" Application/JSON; charset=utf-8"),
+            Some(WireEncoding::Json)
+        );
+        assert_eq!(wire_format_from_content_type("application/cbor"), None);
+        assert_eq!(wire_format_from_content_type("application/msgpack"), None);
+        assert_eq!(wire_format_from_content_type(""), None);
+    }
+
+    #[test]
+    fn delivery_defaults_to_push() {
+        let auth: ClientWire<TestIntent> =
+            from_json_str(r#"{"type":"auth","identity":"local:alice"}"#).unwrap();
+        assert!(matches!(
+            auth,
+            ClientWire::Auth {
+                delivery: Delivery::Push,
+                ..
+            }
+        ));
+
+        let auth: ClientWire<TestIntent> =
+            from_json_str(r#"{"type":"auth","identity":"local:proxy","delivery":"pull"}"#).unwrap();
+        assert!(matches!(
+            auth,
+            ClientWire::Auth {
+                delivery: Delivery::Pull,
+                ..
+            }
+        ));
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn wire_schema_includes_app_types() {
+        let schema = wire_schema::<TestIntent, TestSnapshot>();
+        assert_eq!(schema["version"], WIRE_SCHEMA_VERSION);
+        let client = schema["client"].to_string();
+        assert!(client.contains("TestIntent"));
+        assert!(client.contains("resume_session"));
+        assert!(schema["server"].to_string().contains("TestSnapshot"));
+    }
+
+    #[test]
+    fn alternate_tag_shape_roundtrip() {
+        let config = WireConfig {
+            tag: "kind",
+            case: TagCase::CamelCase,
+        };
+        let msg: ServerWire<()> = ServerWire::ResumeToken { token: "t".into() };
+        let json = config.to_json_string(&msg).unwrap();
+        assert_eq!(json, r#"{"kind":"resumeToken","token":"t"}"#);
+
+        let parsed: ServerWire<()> = config.from_json_str(&json).unwrap();
+        assert!(matches!(parsed, ServerWire::ResumeToken { token } if token == "t"));
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
     enum TestIntent {
         Move { x: i32, y: i32 },
         Chat { msg: String },
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
     struct TestSnapshot {
         tick: u64,
         players: Vec<String>,
@@ -126,24 +813,122 @@ mod tests {
         }
     }
 
+    #[test]
+    fn tracked_intents_default_to_no_ack() {
+        let json = r#"{"type":"tracked_intent","request_id":3,"intent":{"Chat":{"msg":"hi"}}}"#;
+        let parsed: ClientWire<TestIntent> = from_json_str(json).unwrap();
+        assert!(matches!(
+            parsed,
+            ClientWire::TrackedIntent {
+                request_id: 3,
+                require_ack: false,
+                base_seq: None,
+                if_seq: None,
+                intent: TestIntent::Chat { .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn plain_acks_carry_no_prediction() {
+        let json = r#"{"type":"ack","seq":7}"#;
+        let parsed: ClientWire<TestIntent> = from_json_str(json).unwrap();
+        assert!(matches!(
+            parsed,
+            ClientWire::Ack {
+                seq: 7,
+                confirmed_seq: 0,
+                prediction_horizon: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn admin_events_are_tagged_inside_the_frame() {
+        let json = r#"{"type":"subscribe","topics":["admin"]}"#;
+        let parsed: ClientWire<TestIntent> = from_json_str(json).unwrap();
+        assert!(matches!(parsed, ClientWire::Subscribe { topics } if topics == [ADMIN_TOPIC]));
+
+        let msg: ServerWire<TestSnapshot> = ServerWire::Admin {
+            event: LifecycleEvent::Connected {
+                session_id: 7,
+                name: "alice".into(),
+            },
+        };
+        let value: serde_json::Value =
+            serde_json::from_str(&to_json_string(&msg).unwrap()).unwrap();
+        assert_eq!(value["type"], "admin");
+        assert_eq!(value["event"]["kind"], "connected");
+    }
+
     #[test]
     fn server_wire_roundtrip() {
-        let msg: ServerWire<TestSnapshot> = ServerWire::Snapshot {
-            seq: 42,
-            data: TestSnapshot {
+        let msg: ServerWire<TestSnapshot> = ServerWire::snapshot(
+            42,
+            TestSnapshot {
                 tick: 100,
                 players: vec!["alice".into()],
             },
-        };
+        );
         let json = to_json_string(&msg).unwrap();
         let parsed: ServerWire<TestSnapshot> = from_json_str(&json).unwrap();
 
         match parsed {
-            ServerWire::Snapshot { seq, data } => {
+            ServerWire::Snapshot { seq, data, stale } => {
                 assert_eq!(seq, 42);
                 assert_eq!(data.tick, 100);
+                assert!(!stale);
             }
             _ => panic!("wrong variant"),
         }
     }
+
+    #[test]
+    fn batch_roundtrip() {
+        let msgs: Vec<ServerWire<TestSnapshot>> = vec![
+            ServerWire::system("one"),
+            ServerWire::snapshot(
+                2,
+                TestSnapshot {
+                    tick: 2,
+                    players: vec!["alice".into()],
+                },
+            ),
+        ];
+        let data = encode_batch(&msgs).unwrap();
+        assert_eq!(data[0], b'[');
+
+        let decoded: Vec<ServerWire<TestSnapshot>> = decode_batch(&data).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert!(matches!(
+            &decoded[1],
+            ServerWire::Snapshot { seq: 2, data, .. } if data.players == ["alice"]
+        ));
+    }
+
+    #[test]
+    fn error_code_matches_serde_name() {
+        let code = ErrorCode::TooManyTransfers;
+        let json = to_json_string(&code).unwrap();
+        assert_eq!(json, format!("\"{}\"", code.as_str()));
+
+        let msg: ServerWire<TestSnapshot> = ServerWire::error(code, "slow down");
+        assert!(matches!(msg, ServerWire::Error { code, .. } if code == "too_many_transfers"));
+    }
+
+    #[test]
+    fn error_codes_map_to_http_statuses() {
+        assert_eq!(ErrorCode::NotFound.status_code(), 404);
+        assert_eq!(ErrorCode::Conflict.status_code(), 409);
+        assert_eq!(ErrorCode::Forbidden.status_code(), 403);
+        assert_eq!(ErrorCode::TooManyRequests.status_code(), 429);
+        assert_eq!(ErrorCode::UnprocessableEntity.status_code(), 422);
+        // The older codes land in the same families
+        assert_eq!(ErrorCode::RateLimited.status_code(), 429);
+        assert_eq!(ErrorCode::IntentError.status_code(), 422);
+        assert_eq!(
+            to_json_string(&ErrorCode::NotFound).unwrap(),
+            "\"not_found\""
+        );
+    }
 }
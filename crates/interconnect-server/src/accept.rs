@@ -0,0 +1,171 @@
+//! Connection admission before the WebSocket upgrade.
+//!
+//! A flood of connections can exhaust the server before any of them
+//! authenticates. [`AcceptLimiter`] caps how fast each address may connect
+//! and how many of its connections may be waiting on auth at once.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Limits on incoming connections, per remote address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcceptPolicy {
+    /// Connections from one address that may be open but not yet
+    /// authenticated.
+    pub max_pending: usize,
+    /// New connections per second from one address (also the burst size).
+    pub per_ip_rate: u32,
+}
+
+impl Default for AcceptPolicy {
+    fn default() -> Self {
+        Self {
+            max_pending: 8,
+            per_ip_rate: 10,
+        }
+    }
+}
+
+/// Why a connection was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum AcceptRejection {
+    #[error("too many unauthenticated connections from this address")]
+    TooManyPending,
+    #[error("connecting too often")]
+    RateLimited,
+}
+
+#[derive(Debug)]
+struct AddrState {
+    pending: usize,
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Applies an [`AcceptPolicy`] across the accept loop.
+#[derive(Debug)]
+pub struct AcceptLimiter {
+    policy: AcceptPolicy,
+    addrs: Mutex<HashMap<IpAddr, AddrState>>,
+}
+
+impl AcceptLimiter {
+    pub fn new(policy: AcceptPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            policy,
+            addrs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Admit a connection from `ip`, or say why not.
+    ///
+    /// Call before the WebSocket upgrade and drop the connection on
+    /// rejection. Hold the returned guard until the session authenticates.
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<PendingConnection, AcceptRejection> {
+        let rate = f64::from(self.policy.per_ip_rate);
+        let now = Instant::now();
+        let mut addrs = self.addrs.lock().unwrap();
+        let addr = addrs.entry(ip).or_insert(AddrState {
+            pending: 0,
+            tokens: rate,
+            refilled: now,
+        });
+
+        let elapsed = now.duration_since(addr.refilled).as_secs_f64();
+        addr.tokens = (addr.tokens + elapsed * rate).min(rate);
+        addr.refilled = now;
+
+        if addr.pending >= self.policy.max_pending {
+            return Err(AcceptRejection::TooManyPending);
+        }
+        if addr.tokens < 1.0 {
+            return Err(AcceptRejection::RateLimited);
+        }
+        addr.tokens -= 1.0;
+        addr.pending += 1;
+        Ok(PendingConnection {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    /// Unauthenticated connections currently open from `ip`.
+    pub fn pending(&self, ip: IpAddr) -> usize {
+        let addrs = self.addrs.lock().unwrap();
+        addrs.get(&ip).map_or(0, |a| a.pending)
+    }
+
+    /// Forget addresses with nothing pending and a full allowance.
+    ///
+    /// Call periodically so the table doesn't grow with every address seen.
+    pub fn prune(&self) {
+        let rate = f64::from(self.policy.per_ip_rate);
+        let now = Instant::now();
+        self.addrs.lock().unwrap().retain(|_, a| {
+            let elapsed = now.duration_since(a.refilled).as_secs_f64();
+            a.pending > 0 || a.tokens + elapsed * rate < rate
+        });
+    }
+}
+
+/// An admitted connection that hasn't authenticated yet.
+///
+/// Dropping it frees the address's pending slot.
+#[derive(Debug)]
+pub struct PendingConnection {
+    limiter: Arc<AcceptLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for PendingConnection {
+    fn drop(&mut self) {
+        let mut addrs = self.limiter.addrs.lock().unwrap();
+        if let Some(addr) = addrs.get_mut(&self.ip) {
+            addr.pending = addr.pending.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn pending_connections_are_capped_per_address() {
+        let limiter = AcceptLimiter::new(AcceptPolicy {
+            max_pending: 2,
+            per_ip_rate: 100,
+        });
+        let first = limiter.admit(IP).unwrap();
+        let _second = limiter.admit(IP).unwrap();
+        assert_eq!(
+            limiter.admit(IP).unwrap_err(),
+            AcceptRejection::TooManyPending
+        );
+
+        // Other addresses are unaffected
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(limiter.admit(other).is_ok());
+
+        drop(first);
+        assert_eq!(limiter.pending(IP), 1);
+        assert!(limiter.admit(IP).is_ok());
+    }
+
+    #[test]
+    fn bursts_beyond_the_rate_are_refused() {
+        let limiter = AcceptLimiter::new(AcceptPolicy {
+            max_pending: 100,
+            per_ip_rate: 3,
+        });
+        for _ in 0..3 {
+            drop(limiter.admit(IP).unwrap());
+        }
+        assert_eq!(limiter.admit(IP).unwrap_err(), AcceptRejection::RateLimited);
+    }
+}
@@ -0,0 +1,250 @@
+//! Identities refused at connect.
+//!
+//! A [`BanList`] holds one [`BanEntry`] per banned identity, permanent or
+//! until a given time. Expired bans stop applying at once but stay listed
+//! until [`BanList::expire_bans`] sweeps them, which the transport does
+//! every [`AuthorityConfig::ban_sweep`](crate::AuthorityConfig::ban_sweep).
+//! The list serializes as its entries, oldest first, for persistence.
+
+use interconnect_core::{Identity, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// One banned identity.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanEntry {
+    pub identity: Identity,
+    pub reason: String,
+    pub banned_at: Timestamp,
+    /// When the ban lifts; `None` for a permanent ban.
+    pub expires_at: Option<Timestamp>,
+}
+
+impl BanEntry {
+    /// Ban `identity` from now on, permanently.
+    pub fn new(identity: Identity, reason: impl Into<String>) -> Self {
+        Self {
+            identity,
+            reason: reason.into(),
+            banned_at: Timestamp::now(),
+            expires_at: None,
+        }
+    }
+
+    /// Lift the ban at `expires_at`.
+    pub fn until(self, expires_at: Timestamp) -> Self {
+        Self {
+            expires_at: Some(expires_at),
+            ..self
+        }
+    }
+
+    /// Whether the ban still applies at `now`.
+    pub fn is_active_at(&self, now: Timestamp) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+/// Banned identities.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<BanEntry>", into = "Vec<BanEntry>")]
+pub struct BanList {
+    bans: HashMap<Identity, BanEntry>,
+    /// How many bans expire at each time, so counting the active ones only
+    /// looks at those already past.
+    expiring: BTreeMap<Timestamp, usize>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a ban, replacing any earlier one for the same identity.
+    pub fn ban(&mut self, entry: BanEntry) -> Option<BanEntry> {
+        if let Some(expires_at) = entry.expires_at {
+            *self.expiring.entry(expires_at).or_default() += 1;
+        }
+        let replaced = self.bans.insert(entry.identity.clone(), entry);
+        if let Some(replaced) = &replaced {
+            self.forget_expiry(replaced);
+        }
+        replaced
+    }
+
+    /// Lift a ban.
+    pub fn unban(&mut self, identity: &Identity) -> Option<BanEntry> {
+        let entry = self.bans.remove(identity)?;
+        self.forget_expiry(&entry);
+        Some(entry)
+    }
+
+    /// The identity's ban, if it still applies.
+    pub fn find_ban(&self, identity: &Identity) -> Option<&BanEntry> {
+        self.find_ban_at(identity, Timestamp::now())
+    }
+
+    fn find_ban_at(&self, identity: &Identity, now: Timestamp) -> Option<&BanEntry> {
+        self.bans
+            .get(identity)
+            .filter(|entry| entry.is_active_at(now))
+    }
+
+    /// Whether the identity is banned.
+    pub fn is_banned(&self, identity: &Identity) -> bool {
+        self.find_ban(identity).is_some()
+    }
+
+    /// Every ban, expired ones included, oldest first.
+    pub fn list_bans(&self) -> Vec<&BanEntry> {
+        let mut bans: Vec<&BanEntry> = self.bans.values().collect();
+        bans.sort_by_key(|entry| entry.banned_at);
+        bans
+    }
+
+    /// The bans that still apply, oldest first.
+    pub fn list_active_bans(&self) -> Vec<&BanEntry> {
+        self.list_active_bans_at(Timestamp::now())
+    }
+
+    fn list_active_bans_at(&self, now: Timestamp) -> Vec<&BanEntry> {
+        let mut bans = self.list_bans();
+        bans.retain(|entry| entry.is_active_at(now));
+        bans
+    }
+
+    /// Drop the bans that no longer apply.
+    pub fn expire_bans(&mut self) {
+        self.expire_bans_at(Timestamp::now());
+    }
+
+    fn expire_bans_at(&mut self, now: Timestamp) {
+        if self.expired_count(now) == 0 {
+            return;
+        }
+        self.bans.retain(|_, entry| entry.is_active_at(now));
+        self.expiring.retain(|&expires_at, _| now < expires_at);
+    }
+
+    /// Bans held, expired ones included.
+    pub fn ban_count(&self) -> usize {
+        self.bans.len()
+    }
+
+    /// Bans that still apply.
+    ///
+    /// Not quite O(1): the bans expired since the last
+    /// [`expire_bans`](Self::expire_bans) are counted on each call, in
+    /// O(log n + k) for k of them. A running count would go stale as time
+    /// passes without a `&mut self` to update it; the transport's periodic
+    /// sweep keeps k small instead.
+    pub fn active_ban_count(&self) -> usize {
+        self.active_ban_count_at(Timestamp::now())
+    }
+
+    fn active_ban_count_at(&self, now: Timestamp) -> usize {
+        self.bans.len() - self.expired_count(now)
+    }
+
+    /// Bans held that expired by `now`.
+    fn expired_count(&self, now: Timestamp) -> usize {
+        self.expiring.range(..=now).map(|(_, count)| count).sum()
+    }
+
+    fn forget_expiry(&mut self, entry: &BanEntry) {
+        let Some(expires_at) = entry.expires_at else {
+            return;
+        };
+        if let Some(count) = self.expiring.get_mut(&expires_at) {
+            *count -= 1;
+            if *count == 0 {
+                self.expiring.remove(&expires_at);
+            }
+        }
+    }
+}
+
+impl From<Vec<BanEntry>> for BanList {
+    fn from(entries: Vec<BanEntry>) -> Self {
+        let mut bans = Self::new();
+        for entry in entries {
+            bans.ban(entry);
+        }
+        bans
+    }
+}
+
+impl From<BanList> for Vec<BanEntry> {
+    fn from(bans: BanList) -> Self {
+        bans.list_bans().into_iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ban(name: &str, banned_at: u64, expires_at: Option<u64>) -> BanEntry {
+        BanEntry {
+            identity: Identity::local(name),
+            reason: "spam".into(),
+            banned_at: Timestamp::from_secs(banned_at),
+            expires_at: expires_at.map(Timestamp::from_secs),
+        }
+    }
+
+    fn bans() -> BanList {
+        let mut bans = BanList::new();
+        bans.ban(ban("mallory", 1, None));
+        bans.ban(ban("eve", 2, Some(10)));
+        bans.ban(ban("trudy", 3, Some(20)));
+        bans
+    }
+
+    #[test]
+    fn expired_bans_stop_applying_then_are_swept() {
+        let mut bans = bans();
+        let now = Timestamp::from_secs(15);
+        let eve = Identity::local("eve");
+
+        assert!(bans.find_ban_at(&eve, Timestamp::from_secs(5)).is_some());
+        assert!(bans.find_ban_at(&eve, now).is_none());
+        assert_eq!(bans.ban_count(), 3);
+        assert_eq!(bans.active_ban_count_at(now), 2);
+        let active: Vec<_> = bans
+            .list_active_bans_at(now)
+            .iter()
+            .map(|entry| entry.identity.payload().to_string())
+            .collect();
+        assert_eq!(active, ["mallory", "trudy"]);
+
+        bans.expire_bans_at(now);
+        assert_eq!(bans.ban_count(), 2);
+        assert_eq!(bans.active_ban_count_at(now), 2);
+        assert_eq!(bans.active_ban_count_at(Timestamp::from_secs(20)), 1);
+    }
+
+    #[test]
+    fn rebanning_replaces_the_earlier_ban() {
+        let mut bans = bans();
+        let replaced = bans.ban(ban("eve", 4, None)).unwrap();
+        assert_eq!(replaced.expires_at, Some(Timestamp::from_secs(10)));
+        assert_eq!(bans.active_ban_count_at(Timestamp::from_secs(15)), 3);
+
+        assert!(bans.unban(&Identity::local("mallory")).is_some());
+        assert!(bans.unban(&Identity::local("mallory")).is_none());
+        assert_eq!(bans.ban_count(), 2);
+    }
+
+    #[test]
+    fn serializes_as_entries() {
+        let bans = bans();
+        let json = serde_json::to_string(&bans).unwrap();
+        let restored: BanList = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.list_bans(), bans.list_bans());
+        assert_eq!(
+            restored.active_ban_count_at(Timestamp::from_secs(15)),
+            bans.active_ban_count_at(Timestamp::from_secs(15))
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Batching broadcast frames.
+//!
+//! When many sessions update at once, each connection receives a burst of
+//! small frames. [`FrameBatcher`] collects the messages already waiting and
+//! sends them as one JSON array, the format of
+//! [`encode_batch`](interconnect_core::encode_batch).
+
+/// What one batch saved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    /// Messages in the batch.
+    pub count: usize,
+    /// Bytes saved versus one frame per message: frame headers avoided,
+    /// less the array's brackets and commas.
+    pub bytes_saved: usize,
+}
+
+/// Size of a server-to-client WebSocket frame header (unmasked).
+fn frame_header_len(payload_len: usize) -> usize {
+    match payload_len {
+        0..=125 => 2,
+        126..=65535 => 4,
+        _ => 10,
+    }
+}
+
+/// Collects serialized messages into one frame.
+#[derive(Debug)]
+pub struct FrameBatcher {
+    max_batch: usize,
+    texts: Vec<String>,
+}
+
+impl FrameBatcher {
+    /// Batches hold at most `max_batch` messages (at least one).
+    pub fn new(max_batch: usize) -> Self {
+        Self {
+            max_batch: max_batch.max(1),
+            texts: Vec::new(),
+        }
+    }
+
+    /// Add a serialized message.
+    pub fn push(&mut self, text: String) {
+        self.texts.push(text);
+    }
+
+    /// Whether the batch should be sent before adding more.
+    pub fn is_full(&self) -> bool {
+        self.texts.len() >= self.max_batch
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+
+    /// Bytes queued so far.
+    pub fn queued_bytes(&self) -> usize {
+        self.texts.iter().map(String::len).sum()
+    }
+
+    /// The frame to send, emptying the batcher.
+    ///
+    /// A lone message goes out as-is, so quiet connections see the same
+    /// frames as before batching.
+    pub fn take(&mut self) -> Option<(String, BatchStats)> {
+        match self.texts.len() {
+            0 => None,
+            1 => {
+                let text = self.texts.pop()?;
+                Some((
+                    text,
+                    BatchStats {
+                        count: 1,
+                        bytes_saved: 0,
+                    },
+                ))
+            }
+            count => {
+                let headers: usize = self.texts.iter().map(|t| frame_header_len(t.len())).sum();
+                let frame = format!("[{}]", self.texts.join(","));
+                let overhead = frame.len() - self.queued_bytes() + frame_header_len(frame.len());
+                self.texts.clear();
+                Some((
+                    frame,
+                    BatchStats {
+                        count,
+                        bytes_saved: headers.saturating_sub(overhead),
+                    },
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::{ServerWire, decode_batch, to_json_string};
+
+    #[test]
+    fn batches_decode_as_arrays() {
+        let mut batcher = FrameBatcher::new(2);
+        for text in ["one", "two"] {
+            let msg: ServerWire<()> = ServerWire::system(text);
+            batcher.push(to_json_string(&msg).unwrap());
+        }
+        assert!(batcher.is_full());
+
+        let (frame, stats) = batcher.take().unwrap();
+        assert!(batcher.is_empty());
+        assert_eq!(stats.count, 2);
+        let decoded: Vec<ServerWire<()>> = decode_batch(frame.as_bytes()).unwrap();
+        assert!(matches!(&decoded[1], ServerWire::System { message, .. } if message == "two"));
+    }
+
+    #[test]
+    fn lone_messages_are_sent_unwrapped() {
+        let mut batcher = FrameBatcher::new(8);
+        assert_eq!(batcher.take(), None);
+        batcher.push("{}".into());
+        assert_eq!(
+            batcher.take(),
+            Some((
+                "{}".to_string(),
+                BatchStats {
+                    count: 1,
+                    bytes_saved: 0
+                }
+            ))
+        );
+    }
+}
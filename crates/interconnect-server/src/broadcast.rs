@@ -0,0 +1,190 @@
+//! Spreading snapshot delivery over time.
+//!
+//! Snapshotting every session the moment the authority changes spikes CPU
+//! and the network once there are thousands of them. A
+//! [`SnapshotScheduler`] spreads the deliveries evenly across a window
+//! instead, fastest clients first, and a [`DeliveryQueue`] hands them out as
+//! they come due.
+
+use interconnect_core::Session;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::time::{Duration, Instant};
+
+/// How a [`SnapshotScheduler`] spreads deliveries.
+#[derive(Debug, Clone, Copy)]
+pub struct StaggerConfig {
+    /// The deliveries for one change are spread across this long.
+    pub window: Duration,
+    /// Orders sessions within the window; the highest goes first.
+    pub priority_fn: fn(&Session) -> f32,
+}
+
+impl StaggerConfig {
+    /// Spread across `window`, lowest round-trip time first.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            priority_fn: by_rtt,
+        }
+    }
+}
+
+/// Faster clients first. Unmeasured ones count as fast, as elsewhere.
+fn by_rtt(session: &Session) -> f32 {
+    -session
+        .quality
+        .rtt
+        .map_or(0.0, |rtt| rtt.as_secs_f32() * 1000.0)
+}
+
+/// Assigns each session a delivery time within the window.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotScheduler {
+    config: StaggerConfig,
+}
+
+impl SnapshotScheduler {
+    pub fn new(config: StaggerConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &StaggerConfig {
+        &self.config
+    }
+
+    /// How long each session waits for its snapshot, spaced evenly across
+    /// the configured window in priority order. The first goes at once;
+    /// equal priorities keep their order in `sessions`.
+    pub fn distribute_snapshot_load<'a>(
+        &self,
+        sessions: &[&'a Session],
+    ) -> Vec<(&'a Session, Duration)> {
+        self.offsets(sessions, self.config.window)
+    }
+
+    /// [`distribute_snapshot_load`](Self::distribute_snapshot_load) across
+    /// `window`, as delivery times from now.
+    pub fn stagger_snapshot_delivery<'a>(
+        &self,
+        sessions: &[&'a Session],
+        window: Duration,
+    ) -> Vec<(&'a Session, Instant)> {
+        let now = Instant::now();
+        self.offsets(sessions, window)
+            .into_iter()
+            .map(|(session, offset)| (session, now + offset))
+            .collect()
+    }
+
+    fn offsets<'a>(
+        &self,
+        sessions: &[&'a Session],
+        window: Duration,
+    ) -> Vec<(&'a Session, Duration)> {
+        let priority = self.config.priority_fn;
+        let mut ordered = sessions.to_vec();
+        ordered.sort_by(|a, b| priority(b).total_cmp(&priority(a)));
+        let slot = window.div_f64(ordered.len().max(1) as f64);
+        ordered
+            .into_iter()
+            .enumerate()
+            .map(|(i, session)| (session, slot.mul_f64(i as f64)))
+            .collect()
+    }
+}
+
+/// Sessions waiting for their snapshot, soonest first.
+#[derive(Debug, Default)]
+pub struct DeliveryQueue {
+    due: BinaryHeap<Reverse<(Instant, u64)>>,
+    queued: HashSet<u64>,
+}
+
+impl DeliveryQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver to `session_id` at `at`.
+    ///
+    /// A session already waiting keeps its place: the snapshot it gets then
+    /// carries the newer change too. Returns whether it was added.
+    pub fn schedule(&mut self, session_id: u64, at: Instant) -> bool {
+        if !self.queued.insert(session_id) {
+            return false;
+        }
+        self.due.push(Reverse((at, session_id)));
+        true
+    }
+
+    /// When the next delivery is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due.peek().map(|Reverse((at, _))| *at)
+    }
+
+    /// Take the sessions due by `now`, soonest first.
+    pub fn pop_due(&mut self, now: Instant) -> Vec<u64> {
+        let mut ready = Vec::new();
+        while let Some(Reverse((at, session_id))) = self.due.peek().copied() {
+            if at > now {
+                break;
+            }
+            self.due.pop();
+            self.queued.remove(&session_id);
+            ready.push(session_id);
+        }
+        ready
+    }
+
+    pub fn len(&self) -> usize {
+        self.due.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.due.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::Identity;
+
+    fn session(id: u64, rtt_ms: Option<u64>) -> Session {
+        let mut session = Session::new(id, Identity::local("u"), format!("u{id}"));
+        session.quality.rtt = rtt_ms.map(Duration::from_millis);
+        session
+    }
+
+    #[test]
+    fn fast_clients_first_evenly_spaced() {
+        let slow = session(1, Some(300));
+        let fast = session(2, Some(20));
+        let new = session(3, None);
+        let middling = session(4, Some(80));
+        let scheduler = SnapshotScheduler::new(StaggerConfig::new(Duration::from_millis(100)));
+
+        let plan = scheduler.distribute_snapshot_load(&[&slow, &fast, &new, &middling]);
+        let order: Vec<u64> = plan.iter().map(|(s, _)| s.id).collect();
+        assert_eq!(order, [3, 2, 4, 1]);
+        let offsets: Vec<Duration> = plan.iter().map(|(_, at)| *at).collect();
+        assert_eq!(offsets, [0, 25, 50, 75].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn queue_delivers_in_time_order_once() {
+        let now = Instant::now();
+        let mut queue = DeliveryQueue::new();
+        assert!(queue.schedule(1, now + Duration::from_millis(20)));
+        assert!(queue.schedule(2, now));
+        // Already waiting; its delivery covers this change too
+        assert!(!queue.schedule(1, now + Duration::from_millis(50)));
+
+        assert_eq!(queue.next_due(), Some(now));
+        assert_eq!(queue.pop_due(now), [2]);
+        assert_eq!(queue.pop_due(now + Duration::from_millis(20)), [1]);
+        assert!(queue.is_empty());
+        assert!(queue.schedule(1, now));
+    }
+}
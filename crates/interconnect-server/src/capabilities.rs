@@ -0,0 +1,40 @@
+//! Transport policy for what sessions may do.
+
+use interconnect_core::{Authority, Capabilities, Session};
+
+/// Default capabilities by kind of session.
+///
+/// The authority gets the final say through
+/// [`Authority::capabilities_for`](interconnect_core::Authority::capabilities_for).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilityPolicy {
+    /// For sessions with a real identity.
+    pub identified_capabilities: Capabilities,
+    /// For anonymous (guest) sessions.
+    pub anonymous_capabilities: Capabilities,
+}
+
+impl CapabilityPolicy {
+    /// The policy default for a session.
+    pub fn for_session(&self, session: &Session) -> Capabilities {
+        if session.is_anonymous() {
+            self.anonymous_capabilities
+        } else {
+            self.identified_capabilities
+        }
+    }
+
+    /// The policy default, adjusted by the authority.
+    pub fn resolve<A: Authority>(&self, authority: &A, session: &Session) -> Capabilities {
+        authority.capabilities_for(session, self.for_session(session))
+    }
+}
+
+impl Default for CapabilityPolicy {
+    fn default() -> Self {
+        Self {
+            identified_capabilities: Capabilities::full(),
+            anonymous_capabilities: Capabilities::guest(),
+        }
+    }
+}
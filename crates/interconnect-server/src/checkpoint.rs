@@ -0,0 +1,105 @@
+//! Keeping authority checkpoints on disk.
+
+use interconnect_core::Persistable;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why saving or loading a checkpoint failed.
+#[derive(Debug, thiserror::Error)]
+pub enum CheckpointError {
+    #[error("checkpoint I/O failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("checkpoint rejected by the authority: {0}")]
+    Authority(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Stores one checkpoint in a file.
+///
+/// Writes go to a temporary file that is then renamed over the old one, so
+/// a crash mid-write leaves the previous checkpoint intact.
+#[derive(Debug, Clone)]
+pub struct FileCheckpointStore {
+    path: PathBuf,
+}
+
+impl FileCheckpointStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `authority`'s checkpoint.
+    pub fn save<A: Persistable>(&self, authority: &A) -> Result<(), CheckpointError> {
+        let bytes = authority
+            .checkpoint()
+            .map_err(|e| CheckpointError::Authority(Box::new(e)))?;
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Restore `authority` from the stored checkpoint.
+    ///
+    /// Returns `false`, leaving `authority` untouched, if there is none yet.
+    pub fn load<A: Persistable>(&self, authority: &mut A) -> Result<bool, CheckpointError> {
+        let bytes = match std::fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        authority
+            .restore(&bytes)
+            .map_err(|e| CheckpointError::Authority(Box::new(e)))?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Tally {
+        count: u32,
+    }
+
+    impl Persistable for Tally {
+        type Error = serde_json::Error;
+
+        fn checkpoint(&self) -> Result<Vec<u8>, Self::Error> {
+            serde_json::to_vec(&self.count)
+        }
+
+        fn restore(&mut self, checkpoint: &[u8]) -> Result<(), Self::Error> {
+            self.count = serde_json::from_slice(checkpoint)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn checkpoints_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "interconnect-checkpoint-{}.json",
+            std::process::id()
+        ));
+        let store = FileCheckpointStore::new(&path);
+        let mut restarted = Tally::default();
+        assert!(!store.load(&mut restarted).unwrap());
+
+        store.save(&Tally { count: 7 }).unwrap();
+        assert!(store.load(&mut restarted).unwrap());
+        assert_eq!(restarted.count, 7);
+
+        std::fs::write(&path, b"not json").unwrap();
+        assert!(matches!(
+            store.load(&mut restarted),
+            Err(CheckpointError::Authority(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+//! Server configuration, from code and the environment.
+
+use crate::{
+    AcceptPolicy, BroadcastThrottle, CapabilityPolicy, CoalesceConfig, DEFAULT_YIELD_EVERY,
+    IdentityOverflow, LoggingObserver, Observer, PanicPolicy, ReconnectGrace,
+    SerializationFailurePolicy, SpectatorRegistry, SpikeGuard, StaggerConfig, TopicThrottles,
+};
+use interconnect_core::{ExportedSession, IdentityKeyring, Manifest, SnapshotBudget};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How [`spawn_authority`](crate::spawn_authority) runs connections.
+///
+/// Every field but the manifest's identity and the observer can be
+/// overridden from the environment (see [`AuthorityConfig::with_env_override`]); the variable
+/// is named on each field.
+#[derive(Debug, Clone)]
+pub struct AuthorityConfig {
+    /// Sent to every client after `Auth`. `INTERCONNECT_NAME` sets its name.
+    pub manifest: Manifest,
+    /// `INTERCONNECT_ACCEPT_MAX_PENDING` and `INTERCONNECT_ACCEPT_PER_IP_RATE`.
+    pub accept: AcceptPolicy,
+    /// `INTERCONNECT_INTENT_INTERVAL_MS` and
+    /// `INTERCONNECT_GUEST_INTENT_INTERVAL_MS` (0 for no limit), and
+    /// `INTERCONNECT_GUEST_TRANSFER`.
+    pub capabilities: CapabilityPolicy,
+    /// `INTERCONNECT_RECONNECT_GRACE_MS`.
+    pub reconnect: ReconnectGrace,
+    /// `INTERCONNECT_SNAPSHOT_BYTES_PER_SEC` and
+    /// `INTERCONNECT_SNAPSHOT_BURST_BYTES`.
+    pub snapshot_budget: SnapshotBudget,
+    /// Spread each change's snapshots over a window rather than sending
+    /// them all at once; `None` sends at once.
+    /// `INTERCONNECT_SNAPSHOT_STAGGER_MS` sets the window (0 for none).
+    pub snapshot_stagger: Option<StaggerConfig>,
+    /// Holds back snapshots over a hard size limit.
+    /// `INTERCONNECT_SNAPSHOT_HARD_LIMIT_BYTES` (0 for no limit).
+    pub snapshot_spike_guard: SpikeGuard,
+    /// `INTERCONNECT_PANIC_POLICY`: `kill_session`, `kill_server` or
+    /// `log_and_continue`.
+    pub panic_policy: PanicPolicy,
+    /// `INTERCONNECT_SERIALIZATION_FAILURE`: `notify_session`, `skip` or
+    /// `disconnect`.
+    pub serialization_failure: SerializationFailurePolicy,
+    /// Request headers passed to the authority at connect (see
+    /// [`connect_info`](crate::connect_info)).
+    /// `INTERCONNECT_HINT_HEADERS`, comma-separated.
+    pub hint_headers: Vec<String>,
+    /// Sessions connected or held for reconnect at once; `None` for no
+    /// limit. `INTERCONNECT_MAX_SESSIONS` (0 for no limit).
+    pub max_sessions: Option<usize>,
+    /// Sessions one identity may hold at once, held ones included; `None`
+    /// for no limit. A reconnect always replaces a held session first.
+    /// `INTERCONNECT_MAX_SESSIONS_PER_IDENTITY` (0 for no limit).
+    pub max_sessions_per_identity: Option<usize>,
+    /// Whether a connect past `max_sessions_per_identity` is refused or
+    /// ends the oldest session. `INTERCONNECT_IDENTITY_OVERFLOW`: `reject`
+    /// or `evict_oldest`.
+    pub identity_overflow: IdentityOverflow,
+    /// How often expired bans are dropped from the ban list (see
+    /// [`BanList::expire_bans`](crate::BanList::expire_bans)); `None`
+    /// never. `INTERCONNECT_BAN_SWEEP_MS` (0 for never).
+    pub ban_sweep: Option<Duration>,
+    /// Transfers in from one peer server within this long of the first are
+    /// summarized together in
+    /// [`Authority::on_peer_transfer_complete`](interconnect_core::Authority::on_peer_transfer_complete);
+    /// `None` never summarizes. `INTERCONNECT_PEER_TRANSFER_WINDOW_MS` (0
+    /// for never).
+    pub peer_transfer_window: Option<Duration>,
+    /// The key the federation shares. Signs this server's manifest and the
+    /// reconnect tokens sessions are issued, and checks those presented in
+    /// `Auth`: reconnect tokens, and the `source` manifest a transfer in
+    /// must carry. `None` issues no tokens and refuses transfers in. Set
+    /// in code only.
+    pub keyring: Option<IdentityKeyring>,
+    /// Inbound messages a connection handles before letting other tasks
+    /// run (see [`YieldBudget`](crate::YieldBudget)), so a client sending a
+    /// burst can't hold up everyone else. `INTERCONNECT_YIELD_EVERY` (0
+    /// never yields).
+    pub yield_every: usize,
+    /// How long a connection may take to authenticate and receive its
+    /// initial state before it is dropped; `None` for no limit. Without
+    /// one, a client that connects and never sends `Auth` holds its slot
+    /// forever. `INTERCONNECT_MAX_SYNC_MS` (0 for no limit).
+    pub max_sync_duration: Option<Duration>,
+    /// Passports larger than this are refused before the authority sees
+    /// them; smaller ones over
+    /// [`LARGE_PASSPORT_BYTES`](interconnect_core::LARGE_PASSPORT_BYTES)
+    /// go to [`Authority::on_large_passport`] first. Outgoing transfers are
+    /// held to the same limit by [`Authority::passport_size_estimate`], and
+    /// logged over `LARGE_PASSPORT_BYTES`.
+    /// `INTERCONNECT_MAX_PASSPORT_BYTES`.
+    ///
+    /// [`Authority::on_large_passport`]: interconnect_core::Authority::on_large_passport
+    /// [`Authority::passport_size_estimate`]: interconnect_core::Authority::passport_size_estimate
+    pub max_passport_bytes: usize,
+    /// How often snapshots of each [topic](interconnect_core::Authority::snapshot_topic)
+    /// may go out; by default every snapshot goes at once.
+    /// `INTERCONNECT_SNAPSHOT_THROTTLE_MS`, as comma-separated
+    /// `topic=ms`, e.g. `presence=1000,game-state=0`.
+    pub snapshot_throttles: TopicThrottles,
+    /// Snapshots held for a paused session (see [`PauseBuffer`](crate::PauseBuffer)),
+    /// and how long a run of them must be to be coalesced on resume.
+    /// `INTERCONNECT_PAUSE_MAX_QUEUE` and `INTERCONNECT_PAUSE_COALESCE_FROM`
+    /// (0 never coalesces).
+    pub pause_buffer: CoalesceConfig,
+    /// Told about each intent as it arrives and once it's handled; logs
+    /// through `tracing` by default.
+    pub observer: Arc<dyn Observer>,
+    /// Which sessions spectate which. A spectator's snapshots show the
+    /// session it follows (see
+    /// [`Authority::clone_session_state`](interconnect_core::Authority::clone_session_state)),
+    /// and a session's links go when it leaves. Follow and unfollow through
+    /// [`AuthorityHandle::spectators`](crate::AuthorityHandle::spectators).
+    /// Set in code only.
+    pub spectators: Arc<std::sync::Mutex<SpectatorRegistry>>,
+    /// Sessions the server this one replaces exported at shutdown (see
+    /// [`AuthorityHandle::shutdown`](crate::AuthorityHandle::shutdown)),
+    /// imported before the first connection. A client claims its session
+    /// with its reconnect token (so this needs a `keyring`) within the
+    /// reconnect grace; the rest are disconnected. Set in code only.
+    pub handoff: Vec<ExportedSession>,
+}
+
+/// A setting had an invalid value: an environment variable that doesn't
+/// parse, or a field set in code that [`AuthorityConfig::validate`] refuses.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{var}: invalid value {value:?} (expected {expected})")]
+pub struct ConfigError {
+    /// The variable, or field, at fault.
+    pub var: &'static str,
+    pub value: String,
+    pub expected: &'static str,
+}
+
+impl AuthorityConfig {
+    /// Defaults for everything but the manifest.
+    pub fn new(manifest: Manifest) -> Self {
+        Self {
+            manifest,
+            accept: AcceptPolicy::default(),
+            capabilities: CapabilityPolicy::default(),
+            reconnect: ReconnectGrace::new(Duration::from_secs(10)),
+            snapshot_budget: SnapshotBudget::unlimited(),
+            snapshot_stagger: None,
+            snapshot_spike_guard: SpikeGuard::unlimited(),
+            panic_policy: PanicPolicy::default(),
+            serialization_failure: SerializationFailurePolicy::default(),
+            hint_headers: Vec::new(),
+            max_sessions: None,
+            max_sessions_per_identity: None,
+            identity_overflow: IdentityOverflow::default(),
+            ban_sweep: Some(Duration::from_secs(60)),
+            peer_transfer_window: Some(Duration::from_secs(5)),
+            keyring: None,
+            yield_every: DEFAULT_YIELD_EVERY,
+            max_sync_duration: Some(Duration::from_secs(30)),
+            max_passport_bytes: 1024 * 1024,
+            snapshot_throttles: TopicThrottles::new(),
+            pause_buffer: CoalesceConfig::new(16).coalesce_from(2),
+            observer: Arc::new(LoggingObserver),
+            spectators: Arc::default(),
+            handoff: Vec::new(),
+        }
+    }
+
+    /// Defaults, overridden from the environment.
+    pub fn from_env(manifest: Manifest) -> Result<Self, ConfigError> {
+        Self::new(manifest).with_env_override()
+    }
+
+    /// Apply `INTERCONNECT_*` variables on top of this configuration.
+    ///
+    /// Unset variables leave their field alone.
+    pub fn with_env_override(self) -> Result<Self, ConfigError> {
+        self.with_overrides(|var| std::env::var(var).ok())
+    }
+
+    fn with_overrides(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let env = Env(var);
+        if let Some(name) = env.0("INTERCONNECT_NAME") {
+            self.manifest.name = name.into();
+        }
+        env.set(
+            "INTERCONNECT_ACCEPT_MAX_PENDING",
+            &mut self.accept.max_pending,
+        )?;
+        env.set(
+            "INTERCONNECT_ACCEPT_PER_IP_RATE",
+            &mut self.accept.per_ip_rate,
+        )?;
+        if let Some(ms) = env.parse::<u64>("INTERCONNECT_INTENT_INTERVAL_MS")? {
+            self.capabilities
+                .identified_capabilities
+                .min_intent_interval = interval(ms);
+        }
+        if let Some(ms) = env.parse::<u64>("INTERCONNECT_GUEST_INTENT_INTERVAL_MS")? {
+            self.capabilities.anonymous_capabilities.min_intent_interval = interval(ms);
+        }
+        env.set(
+            "INTERCONNECT_GUEST_TRANSFER",
+            &mut self.capabilities.anonymous_capabilities.transfer,
+        )?;
+        if let Some(ms) = env.parse("INTERCONNECT_RECONNECT_GRACE_MS")? {
+            self.reconnect = ReconnectGrace::new(Duration::from_millis(ms));
+        }
+        env.set(
+            "INTERCONNECT_SNAPSHOT_BYTES_PER_SEC",
+            &mut self.snapshot_budget.bytes_per_second,
+        )?;
+        env.set(
+            "INTERCONNECT_SNAPSHOT_BURST_BYTES",
+            &mut self.snapshot_budget.burst_bytes,
+        )?;
+        if let Some(ms) = env.parse::<u64>("INTERCONNECT_SNAPSHOT_STAGGER_MS")? {
+            self.snapshot_stagger = interval(ms).map(|window| match self.snapshot_stagger {
+                Some(stagger) => StaggerConfig { window, ..stagger },
+                None => StaggerConfig::new(window),
+            });
+        }
+        if let Some(limit) = env.parse::<usize>("INTERCONNECT_SNAPSHOT_HARD_LIMIT_BYTES")? {
+            self.snapshot_spike_guard = match limit {
+                0 => SpikeGuard::unlimited(),
+                limit => SpikeGuard::new(limit),
+            };
+        }
+        if let Some(policy) = env.0("INTERCONNECT_PANIC_POLICY") {
+            self.panic_policy = match policy.as_str() {
+                "kill_session" => PanicPolicy::KillSession,
+                "kill_server" => PanicPolicy::KillServer,
+                "log_and_continue" => PanicPolicy::LogAndContinue,
+                _ => {
+                    return Err(ConfigError {
+                        var: "INTERCONNECT_PANIC_POLICY",
+                        value: policy,
+                        expected: "kill_session, kill_server or log_and_continue",
+                    });
+                }
+            };
+        }
+        if let Some(policy) = env.0("INTERCONNECT_SERIALIZATION_FAILURE") {
+            self.serialization_failure = match policy.as_str() {
+                "notify_session" => SerializationFailurePolicy::NotifySession,
+                "skip" => SerializationFailurePolicy::Skip,
+                "disconnect" => SerializationFailurePolicy::Disconnect,
+                _ => {
+                    return Err(ConfigError {
+                        var: "INTERCONNECT_SERIALIZATION_FAILURE",
+                        value: policy,
+                        expected: "notify_session, skip or disconnect",
+                    });
+                }
+            };
+        }
+        if let Some(headers) = env.0("INTERCONNECT_HINT_HEADERS") {
+            self.hint_headers = headers
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(max) = env.parse::<usize>("INTERCONNECT_MAX_SESSIONS")? {
+            self.max_sessions = (max > 0).then_some(max);
+        }
+        if let Some(max) = env.parse::<usize>("INTERCONNECT_MAX_SESSIONS_PER_IDENTITY")? {
+            self.max_sessions_per_identity = (max > 0).then_some(max);
+        }
+        if let Some(overflow) = env.0("INTERCONNECT_IDENTITY_OVERFLOW") {
+            self.identity_overflow = match overflow.as_str() {
+                "reject" => IdentityOverflow::Reject,
+                "evict_oldest" => IdentityOverflow::EvictOldest,
+                _ => {
+                    return Err(ConfigError {
+                        var: "INTERCONNECT_IDENTITY_OVERFLOW",
+                        value: overflow,
+                        expected: "reject or evict_oldest",
+                    });
+                }
+            };
+        }
+        env.set("INTERCONNECT_YIELD_EVERY", &mut self.yield_every)?;
+        if let Some(ms) = env.parse::<u64>("INTERCONNECT_BAN_SWEEP_MS")? {
+            self.ban_sweep = interval(ms);
+        }
+        if let Some(ms) = env.parse::<u64>("INTERCONNECT_PEER_TRANSFER_WINDOW_MS")? {
+            self.peer_transfer_window = interval(ms);
+        }
+        env.set(
+            "INTERCONNECT_MAX_PASSPORT_BYTES",
+            &mut self.max_passport_bytes,
+        )?;
+        if let Some(throttles) = env.0("INTERCONNECT_SNAPSHOT_THROTTLE_MS") {
+            self.snapshot_throttles = parse_throttles(&throttles).ok_or(ConfigError {
+                var: "INTERCONNECT_SNAPSHOT_THROTTLE_MS",
+                value: throttles,
+                expected: "comma-separated topic=ms",
+            })?;
+        }
+        env.set(
+            "INTERCONNECT_PAUSE_MAX_QUEUE",
+            &mut self.pause_buffer.max_queue_depth,
+        )?;
+        if let Some(threshold) = env.parse::<usize>("INTERCONNECT_PAUSE_COALESCE_FROM")? {
+            self.pause_buffer.coalesce_threshold = match threshold {
+                0 => usize::MAX,
+                threshold => threshold,
+            };
+        }
+        if let Some(ms) = env.parse::<u64>("INTERCONNECT_MAX_SYNC_MS")? {
+            self.max_sync_duration = interval(ms);
+        }
+        Ok(self)
+    }
+
+    /// Check values only code can set wrong: a zero period for
+    /// [`ban_sweep`](Self::ban_sweep) or
+    /// [`peer_transfer_window`](Self::peer_transfer_window), where `None`
+    /// means never. [`spawn_authority`](crate::spawn_authority) refuses a
+    /// config that fails this.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let periods = [
+            ("ban_sweep", self.ban_sweep),
+            ("peer_transfer_window", self.peer_transfer_window),
+        ];
+        for (var, period) in periods {
+            if period == Some(Duration::ZERO) {
+                return Err(ConfigError {
+                    var,
+                    value: "0ms".into(),
+                    expected: "a nonzero duration, or None for never",
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `presence=1000,game-state=0` as throttles.
+fn parse_throttles(value: &str) -> Option<TopicThrottles> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .try_fold(TopicThrottles::new(), |throttles, entry| {
+            let (topic, ms) = entry.split_once('=')?;
+            let ms = ms.trim().parse().ok()?;
+            Some(throttles.with_topic(
+                topic.trim(),
+                BroadcastThrottle::new(Duration::from_millis(ms)),
+            ))
+        })
+}
+
+/// A zero interval means no limit (or no window).
+fn interval(ms: u64) -> Option<Duration> {
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+struct Env<F>(F);
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    fn parse<T: FromStr>(&self, var: &'static str) -> Result<Option<T>, ConfigError> {
+        let Some(value) = (self.0)(var) else {
+            return Ok(None);
+        };
+        match value.trim().parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => Err(ConfigError {
+                var,
+                value,
+                expected: std::any::type_name::<T>(),
+            }),
+        }
+    }
+
+    fn set<T: FromStr>(&self, var: &'static str, field: &mut T) -> Result<(), ConfigError> {
+        if let Some(value) = self.parse(var)? {
+            *field = value;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::Identity;
+    use std::collections::HashMap;
+
+    fn config() -> AuthorityConfig {
+        AuthorityConfig::new(Manifest {
+            identity: Identity::local("test"),
+            name: "Test".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        })
+    }
+
+    #[test]
+    fn environment_overrides_code_defaults() {
+        let env: HashMap<&str, &str> = [
+            ("INTERCONNECT_NAME", "Docker"),
+            ("INTERCONNECT_MAX_SESSIONS", "100"),
+            ("INTERCONNECT_RECONNECT_GRACE_MS", "2500"),
+            ("INTERCONNECT_GUEST_INTENT_INTERVAL_MS", "0"),
+            ("INTERCONNECT_PANIC_POLICY", "log_and_continue"),
+            ("INTERCONNECT_SERIALIZATION_FAILURE", "disconnect"),
+            (
+                "INTERCONNECT_HINT_HEADERS",
+                "X-Client-Version, CF-IPCountry",
+            ),
+            ("INTERCONNECT_SNAPSHOT_STAGGER_MS", "200"),
+            ("INTERCONNECT_SNAPSHOT_HARD_LIMIT_BYTES", "65536"),
+            ("INTERCONNECT_YIELD_EVERY", "8"),
+            ("INTERCONNECT_MAX_SESSIONS_PER_IDENTITY", "2"),
+            ("INTERCONNECT_IDENTITY_OVERFLOW", "evict_oldest"),
+            ("INTERCONNECT_BAN_SWEEP_MS", "0"),
+            ("INTERCONNECT_PEER_TRANSFER_WINDOW_MS", "250"),
+            ("INTERCONNECT_MAX_SYNC_MS", "5000"),
+            ("INTERCONNECT_MAX_PASSPORT_BYTES", "4096"),
+            ("INTERCONNECT_PAUSE_MAX_QUEUE", "32"),
+            ("INTERCONNECT_PAUSE_COALESCE_FROM", "0"),
+            (
+                "INTERCONNECT_SNAPSHOT_THROTTLE_MS",
+                "presence=1000, game-state=0",
+            ),
+        ]
+        .into();
+        let config = config()
+            .with_overrides(|var| env.get(var).map(|v| v.to_string()))
+            .unwrap();
+
+        assert_eq!(config.manifest.name.as_str(), "Docker");
+        assert_eq!(config.max_sessions, Some(100));
+        assert_eq!(config.reconnect.window, Duration::from_millis(2500));
+        assert_eq!(
+            config
+                .capabilities
+                .anonymous_capabilities
+                .min_intent_interval,
+            None
+        );
+        assert_eq!(config.panic_policy, PanicPolicy::LogAndContinue);
+        assert_eq!(
+            config.serialization_failure,
+            SerializationFailurePolicy::Disconnect
+        );
+        assert_eq!(config.hint_headers, ["X-Client-Version", "CF-IPCountry"]);
+        assert_eq!(
+            config.snapshot_stagger.map(|stagger| stagger.window),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(config.snapshot_spike_guard, SpikeGuard::new(65536));
+        assert_eq!(config.yield_every, 8);
+        assert_eq!(config.max_sessions_per_identity, Some(2));
+        assert_eq!(config.identity_overflow, IdentityOverflow::EvictOldest);
+        assert_eq!(config.ban_sweep, None);
+        assert_eq!(
+            config.peer_transfer_window,
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(config.max_sync_duration, Some(Duration::from_secs(5)));
+        assert_eq!(config.max_passport_bytes, 4096);
+        assert_eq!(config.pause_buffer, CoalesceConfig::new(32));
+        assert_eq!(
+            config.snapshot_throttles,
+            TopicThrottles::new()
+                .with_topic("presence", BroadcastThrottle::new(Duration::from_secs(1)))
+                .with_topic("game-state", BroadcastThrottle::IMMEDIATE)
+        );
+        // Untouched
+        assert_eq!(config.accept, AcceptPolicy::default());
+    }
+
+    #[test]
+    fn invalid_values_name_the_variable() {
+        let err = config()
+            .with_overrides(|var| (var == "INTERCONNECT_ACCEPT_PER_IP_RATE").then(|| "lots".into()))
+            .unwrap_err();
+        assert_eq!(err.var, "INTERCONNECT_ACCEPT_PER_IP_RATE");
+        assert_eq!(err.value, "lots");
+    }
+
+    #[test]
+    fn zero_periods_are_refused() {
+        assert!(config().validate().is_ok());
+        let err = AuthorityConfig {
+            ban_sweep: Some(Duration::ZERO),
+            ..config()
+        }
+        .validate()
+        .unwrap_err();
+        assert_eq!(err.var, "ban_sweep");
+        // The environment's 0 already means never
+        let never = config()
+            .with_overrides(|var| (var == "INTERCONNECT_BAN_SWEEP_MS").then(|| "0".into()))
+            .unwrap();
+        assert!(never.validate().is_ok());
+    }
+}
@@ -0,0 +1,71 @@
+//! Read-your-writes delivery for the session that changed state.
+
+/// How a session sees the effects of its own intents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConsistencyPolicy {
+    /// Send the submitter its post-intent snapshot directly, ahead of
+    /// batching, throttling and pull-mode hold-back.
+    ///
+    /// Everyone else still gets the broadcast as usual.
+    pub read_your_writes: bool,
+}
+
+/// Tracks snapshots sent directly to one session.
+///
+/// A direct snapshot supersedes the broadcast snapshots still queued for
+/// the session, including its own copy of the one it was sent directly.
+/// Those are skipped until that copy comes through; later snapshots are
+/// delivered as usual.
+#[derive(Debug, Default)]
+pub struct OwnWrites {
+    /// Own broadcast snapshots not yet seen.
+    pending: u32,
+}
+
+impl OwnWrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a snapshot was sent directly and is also broadcast.
+    pub fn sent_directly(&mut self) {
+        self.pending += 1;
+    }
+
+    /// Forget pending writes, e.g. after missing broadcasts.
+    pub fn reset(&mut self) {
+        self.pending = 0;
+    }
+
+    /// Whether a broadcast snapshot should still be delivered.
+    ///
+    /// `own` is true for the broadcast copy of this session's write.
+    pub fn deliver(&mut self, own: bool) -> bool {
+        if self.pending == 0 {
+            return true;
+        }
+        if own {
+            self.pending -= 1;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_snapshots_are_skipped_until_own_echo() {
+        let mut writes = OwnWrites::new();
+        assert!(writes.deliver(false));
+
+        writes.sent_directly();
+        // Queued before our write: already superseded
+        assert!(!writes.deliver(false));
+        // Our own copy
+        assert!(!writes.deliver(true));
+        // Newer than our write
+        assert!(writes.deliver(false));
+    }
+}
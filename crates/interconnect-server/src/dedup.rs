@@ -0,0 +1,76 @@
+//! Applying tracked intents once.
+//!
+//! A client retries a `ClientWire::TrackedIntent` until it sees the
+//! `IntentAck`, so the same request can arrive more than once, possibly on
+//! a new connection. [`DedupCache`] remembers recently applied requests and
+//! the snapshot seq to ack them with.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Recently applied requests, oldest evicted first.
+///
+/// Key by something that survives a reconnect, such as identity and
+/// request ID.
+#[derive(Debug)]
+pub struct DedupCache<K> {
+    capacity: usize,
+    applied: HashMap<K, u64>,
+    order: VecDeque<K>,
+}
+
+impl<K: Eq + Hash + Clone> DedupCache<K> {
+    /// Remember up to `capacity` requests.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            applied: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// The seq a request was acked with, if it was already applied.
+    pub fn get(&self, key: &K) -> Option<u64> {
+        self.applied.get(key).copied()
+    }
+
+    /// Record that a request was applied, first reflected in snapshot `seq`.
+    pub fn insert(&mut self, key: K, seq: u64) {
+        if self.applied.insert(key.clone(), seq).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.applied.remove(&oldest);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.applied.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_are_recognized_until_evicted() {
+        let mut cache = DedupCache::new(2);
+        cache.insert(("alice", 1), 10);
+        assert_eq!(cache.get(&("alice", 1)), Some(10));
+        assert_eq!(cache.get(&("bob", 1)), None);
+
+        cache.insert(("alice", 2), 11);
+        cache.insert(("alice", 3), 12);
+        assert_eq!(cache.get(&("alice", 1)), None);
+        assert_eq!(cache.get(&("alice", 3)), Some(12));
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,191 @@
+//! Delta encoding against each client's last acked snapshot.
+//!
+//! A [`DeltaEncoder`] belongs to one connection. It keeps the last few
+//! snapshots sent and the newest one the client acked (`ClientWire::Ack`).
+//! When the acked snapshot is still in its history, it asks the authority
+//! for a delta from it via [`Authority::snapshot_delta_from`]; otherwise, or
+//! if the authority declines, it sends the full snapshot.
+//!
+//! A new connection always starts with one full snapshot: until it has
+//! sent one, the encoder sends nothing else and ignores acks, so a stale
+//! ack from an earlier connection can't make a delta the client has no
+//! base for.
+
+use interconnect_core::{Authority, ServerWire, Session};
+use std::collections::VecDeque;
+
+/// Per-connection snapshot history and ack state.
+#[derive(Debug, Clone)]
+pub struct DeltaEncoder<S> {
+    capacity: usize,
+    history: VecDeque<(u64, S)>,
+    acked: Option<u64>,
+    /// Sequence number of the first full snapshot sent.
+    synced_at: Option<u64>,
+}
+
+impl<S: Clone> DeltaEncoder<S> {
+    /// Keep the last `capacity` snapshots as delta bases.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            history: VecDeque::with_capacity(capacity),
+            acked: None,
+            synced_at: None,
+        }
+    }
+
+    /// Record a client ack. Acks older than the newest one, or than the
+    /// first full snapshot, are ignored.
+    pub fn ack(&mut self, seq: u64) {
+        if self.synced_at.is_none_or(|synced| seq < synced) {
+            return;
+        }
+        if self.acked.is_none_or(|acked| seq > acked) {
+            self.acked = Some(seq);
+        }
+    }
+
+    /// The newest acked sequence number.
+    pub fn acked(&self) -> Option<u64> {
+        self.acked
+    }
+
+    /// Encode `snapshot` as sequence `seq`, as a delta when possible. The
+    /// first is always the full snapshot.
+    ///
+    /// The full snapshot is kept as a base for later deltas either way.
+    pub fn encode<A>(
+        &mut self,
+        authority: &A,
+        session: &Session,
+        seq: u64,
+        snapshot: S,
+    ) -> ServerWire<S>
+    where
+        A: Authority<Snapshot = S>,
+    {
+        let delta = self.acked.and_then(|base_seq| {
+            let base = self.base(base_seq)?;
+            let data = authority.snapshot_delta_from(session, base_seq, base)?;
+            Some(ServerWire::Delta {
+                seq,
+                base_seq,
+                data,
+            })
+        });
+
+        if delta.is_none() {
+            self.synced_at.get_or_insert(seq);
+        }
+        if self.capacity > 0 {
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back((seq, snapshot.clone()));
+        }
+
+        delta.unwrap_or(ServerWire::snapshot(seq, snapshot))
+    }
+
+    fn base(&self, seq: u64) -> Option<&S> {
+        self.history
+            .iter()
+            .find(|(s, _)| *s == seq)
+            .map(|(_, snapshot)| snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::testing::{Add, Tallies, TestRoom};
+    use interconnect_core::{Authority, Identity};
+    use std::collections::BTreeMap;
+
+    fn add(room: &mut TestRoom, session_id: u64, by: u32) {
+        let session = Session::new(session_id, Identity::local("a"), "a".into());
+        Authority::handle_intent(room, &session, Add { by }).unwrap();
+    }
+
+    #[test]
+    fn deltas_follow_acks() {
+        let session = Session::new(1, Identity::local("alice"), "alice".into());
+        let mut room = TestRoom::new();
+        add(&mut room, 1, 1);
+        let mut encoder = DeltaEncoder::new(4);
+
+        // Nothing acked yet: full snapshot
+        let wire = encoder.encode(&room, &session, 1, room.tallies());
+        assert!(matches!(wire, ServerWire::Snapshot { seq: 1, .. }));
+
+        encoder.ack(1);
+        add(&mut room, 2, 2);
+        add(&mut room, 3, 3);
+        match encoder.encode(&room, &session, 2, room.tallies()) {
+            ServerWire::Delta {
+                seq,
+                base_seq,
+                data,
+            } => {
+                assert_eq!((seq, base_seq), (2, 1));
+                assert_eq!(data, vec![(2, 2), (3, 3)]);
+            }
+            other => panic!("expected delta, got {other:?}"),
+        }
+
+        // A stale ack doesn't move the base back
+        encoder.ack(0);
+        assert_eq!(encoder.acked(), Some(1));
+    }
+
+    #[test]
+    fn connection_starts_with_one_full_snapshot() {
+        let session = Session::new(1, Identity::local("alice"), "alice".into());
+        let mut room = TestRoom::new();
+        add(&mut room, 1, 1);
+        add(&mut room, 2, 2);
+        let mut encoder = DeltaEncoder::new(4);
+        // What the client builds from what it's sent
+        let mut client = BTreeMap::new();
+        let mut apply = |wire: ServerWire<Tallies>| match wire {
+            ServerWire::Snapshot { data, .. } => client = data.into_iter().collect(),
+            ServerWire::Delta { data, .. } => client.extend(data),
+            other => panic!("unexpected {other:?}"),
+        };
+
+        // An ack left over from an earlier connection
+        encoder.ack(0);
+        assert_eq!(encoder.acked(), None);
+        let first = encoder.encode(&room, &session, 0, room.tallies());
+        assert!(matches!(first, ServerWire::Snapshot { seq: 0, .. }));
+        apply(first);
+
+        encoder.ack(0);
+        add(&mut room, 3, 3);
+        let next = encoder.encode(&room, &session, 1, room.tallies());
+        assert!(matches!(
+            next,
+            ServerWire::Delta {
+                seq: 1,
+                base_seq: 0,
+                ..
+            }
+        ));
+        apply(next);
+        assert_eq!(client.into_iter().collect::<Tallies>(), room.tallies());
+    }
+
+    #[test]
+    fn evicted_base_sends_full_snapshot() {
+        let session = Session::new(1, Identity::local("alice"), "alice".into());
+        let room = TestRoom::new();
+        let mut encoder = DeltaEncoder::new(1);
+
+        encoder.encode(&room, &session, 1, room.tallies());
+        encoder.encode(&room, &session, 2, room.tallies());
+        encoder.ack(1);
+        let wire = encoder.encode(&room, &session, 3, room.tallies());
+        assert!(matches!(wire, ServerWire::Snapshot { seq: 3, .. }));
+    }
+}
@@ -0,0 +1,62 @@
+//! Keeping one chatty connection from starving the others.
+//!
+//! A connection's task only gives up its worker thread when it waits on
+//! something that isn't ready. A client sending a tight burst keeps its
+//! socket ready, so on a single-threaded runtime its task could handle
+//! message after message while every other connection waits. A
+//! [`YieldBudget`] bounds that: a connection handles at most its budget of
+//! inbound messages before other tasks get a turn.
+
+/// Inbound messages a connection handles between yields by default.
+pub const DEFAULT_YIELD_EVERY: usize = 32;
+
+/// Counts a connection's inbound messages and yields to other tasks every
+/// so many.
+#[derive(Debug, Clone)]
+pub struct YieldBudget {
+    every: usize,
+    handled: usize,
+}
+
+impl YieldBudget {
+    /// Yield after every `every` messages; 0 never yields.
+    pub fn new(every: usize) -> Self {
+        Self { every, handled: 0 }
+    }
+
+    /// Count an inbound message, yielding first if the connection has used
+    /// up its budget.
+    pub async fn message_received(&mut self) {
+        if self.spend() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// Count a message; true when it's time to yield.
+    fn spend(&mut self) -> bool {
+        if self.every == 0 {
+            return false;
+        }
+        self.handled += 1;
+        if self.handled > self.every {
+            self.handled = 1;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yields_once_per_budget() {
+        let mut budget = YieldBudget::new(3);
+        let yields: Vec<bool> = (0..7).map(|_| budget.spend()).collect();
+        assert_eq!(yields, [false, false, false, true, false, false, true]);
+
+        let mut never = YieldBudget::new(0);
+        assert!((0..100).all(|_| !never.spend()));
+    }
+}
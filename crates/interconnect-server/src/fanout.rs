@@ -0,0 +1,324 @@
+//! Broadcasting state to many connections without losing messages.
+//!
+//! `tokio::sync::broadcast` drops whatever a lagging receiver hasn't read
+//! and reports only how much. For state sync that's backwards: a stale
+//! snapshot is worth dropping, an error or system message isn't. A
+//! [`StateBroadcast`] keeps, per subscriber, only the latest snapshot and
+//! every other message up to a bound. A subscriber that overruns the bound
+//! is told to resync rather than left to guess what it missed.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What a [`StateSubscriber`] receives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received<S, M> {
+    /// The latest snapshot, standing in for `replaced` older ones the
+    /// subscriber never read.
+    Snapshot { snapshot: S, replaced: usize },
+    /// A message, in the order sent.
+    Message(M),
+    /// The subscriber fell more than the bound behind and `missed` messages
+    /// were dropped. Fetch the full state before relying on it again.
+    Resync { missed: usize },
+}
+
+/// How far one subscriber is behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Lag {
+    /// Messages waiting to be read.
+    pub messages: usize,
+    /// Whether a snapshot is waiting.
+    pub snapshot: bool,
+    /// Snapshots replaced unread since the subscriber last took one.
+    pub replaced: usize,
+}
+
+/// A broadcast that coalesces snapshots and never silently drops messages.
+///
+/// Sending never waits on subscribers: a slow one only holds up itself.
+/// Clone it to send from several places; subscribers see the end once every
+/// clone is dropped.
+pub struct StateBroadcast<S, M> {
+    shared: Arc<Shared<S, M>>,
+}
+
+/// The receiving end for one subscriber.
+pub struct StateSubscriber<S, M> {
+    id: u64,
+    wake: Arc<Notify>,
+    shared: Arc<Shared<S, M>>,
+}
+
+struct Shared<S, M> {
+    capacity: usize,
+    state: Mutex<State<S, M>>,
+}
+
+struct State<S, M> {
+    next_id: u64,
+    /// Orders snapshots against messages.
+    next_seq: u64,
+    senders: usize,
+    subscribers: HashMap<u64, Slot<S, M>>,
+}
+
+struct Slot<S, M> {
+    wake: Arc<Notify>,
+    snapshot: Option<(u64, S)>,
+    replaced: usize,
+    messages: VecDeque<(u64, M)>,
+    /// Messages dropped since the subscriber was last told to resync.
+    missed: usize,
+}
+
+impl<S: Clone, M: Clone> StateBroadcast<S, M> {
+    /// Keep up to `capacity` unread messages per subscriber.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                capacity: capacity.max(1),
+                state: Mutex::new(State {
+                    next_id: 0,
+                    next_seq: 0,
+                    senders: 1,
+                    subscribers: HashMap::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Replace each subscriber's unread snapshot with `snapshot`. Returns
+    /// the number of subscribers.
+    pub fn send_snapshot(&self, snapshot: S) -> usize {
+        let mut state = self.shared.state.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        for slot in state.subscribers.values_mut() {
+            if slot.snapshot.replace((seq, snapshot.clone())).is_some() {
+                slot.replaced += 1;
+            }
+            slot.wake.notify_one();
+        }
+        state.subscribers.len()
+    }
+
+    /// Queue `message` for every subscriber. Returns the number of
+    /// subscribers.
+    ///
+    /// A subscriber with a full queue loses its unread messages and gets a
+    /// [`Received::Resync`] in their place.
+    pub fn send(&self, message: M) -> usize {
+        let capacity = self.shared.capacity;
+        let mut state = self.shared.state.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        for slot in state.subscribers.values_mut() {
+            if slot.messages.len() == capacity {
+                slot.missed += slot.messages.len() + 1;
+                slot.messages.clear();
+            } else {
+                slot.messages.push_back((seq, message.clone()));
+            }
+            slot.wake.notify_one();
+        }
+        state.subscribers.len()
+    }
+
+    /// Receive everything sent from now on.
+    pub fn subscribe(&self) -> StateSubscriber<S, M> {
+        let wake = Arc::new(Notify::new());
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.subscribers.insert(
+            id,
+            Slot {
+                wake: wake.clone(),
+                snapshot: None,
+                replaced: 0,
+                messages: VecDeque::new(),
+                missed: 0,
+            },
+        );
+        StateSubscriber {
+            id,
+            wake,
+            shared: self.shared.clone(),
+        }
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.shared.state.lock().unwrap().subscribers.len()
+    }
+}
+
+impl<S, M> Clone for StateBroadcast<S, M> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<S, M> Drop for StateBroadcast<S, M> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            for slot in state.subscribers.values() {
+                slot.wake.notify_one();
+            }
+        }
+    }
+}
+
+impl<S, M> StateSubscriber<S, M> {
+    /// The next thing to handle, waiting if there's nothing yet. `None`
+    /// once every sender is gone and everything has been read.
+    ///
+    /// A resync comes first; otherwise messages and the snapshot come in the
+    /// order they were sent, the snapshot at the place of its latest send.
+    pub async fn recv(&mut self) -> Option<Received<S, M>> {
+        loop {
+            match self.take() {
+                Ok(received) => return Some(received),
+                Err(true) => return None,
+                Err(false) => self.wake.notified().await,
+            }
+        }
+    }
+
+    /// The next thing to handle, if there is one now.
+    pub fn try_recv(&mut self) -> Option<Received<S, M>> {
+        self.take().ok()
+    }
+
+    /// How far behind this subscriber is.
+    pub fn lag(&self) -> Lag {
+        let state = self.shared.state.lock().unwrap();
+        let slot = &state.subscribers[&self.id];
+        Lag {
+            messages: slot.messages.len(),
+            snapshot: slot.snapshot.is_some(),
+            replaced: slot.replaced,
+        }
+    }
+
+    /// Take the next item, or say whether the broadcast has ended.
+    fn take(&mut self) -> Result<Received<S, M>, bool> {
+        let mut state = self.shared.state.lock().unwrap();
+        let closed = state.senders == 0;
+        let slot = state.subscribers.get_mut(&self.id).expect("subscribed");
+        if slot.missed > 0 {
+            let missed = std::mem::take(&mut slot.missed);
+            return Ok(Received::Resync { missed });
+        }
+        let snapshot_first = match (&slot.snapshot, slot.messages.front()) {
+            (Some((snapshot_seq, _)), Some((message_seq, _))) => snapshot_seq < message_seq,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if snapshot_first && let Some((_, snapshot)) = slot.snapshot.take() {
+            let replaced = std::mem::take(&mut slot.replaced);
+            return Ok(Received::Snapshot { snapshot, replaced });
+        }
+        match slot.messages.pop_front() {
+            Some((_, message)) => Ok(Received::Message(message)),
+            None => Err(closed),
+        }
+    }
+}
+
+impl<S, M> Drop for StateSubscriber<S, M> {
+    fn drop(&mut self) {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .subscribers
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_subscriber_gets_latest_snapshot_and_every_message() {
+        let tx: StateBroadcast<u32, &str> = StateBroadcast::new(8);
+        let mut slow = tx.subscribe();
+
+        tx.send("alice joined");
+        tx.send_snapshot(1);
+        tx.send_snapshot(2);
+        tx.send("bob joined");
+        tx.send_snapshot(3);
+        assert_eq!(
+            slow.lag(),
+            Lag {
+                messages: 2,
+                snapshot: true,
+                replaced: 2
+            }
+        );
+
+        assert_eq!(slow.try_recv(), Some(Received::Message("alice joined")));
+        assert_eq!(slow.try_recv(), Some(Received::Message("bob joined")));
+        assert_eq!(
+            slow.try_recv(),
+            Some(Received::Snapshot {
+                snapshot: 3,
+                replaced: 2
+            })
+        );
+        assert_eq!(slow.try_recv(), None);
+    }
+
+    #[test]
+    fn snapshot_keeps_its_place_among_messages() {
+        let tx: StateBroadcast<u32, &str> = StateBroadcast::new(8);
+        let mut rx = tx.subscribe();
+        tx.send_snapshot(1);
+        tx.send("error");
+        assert_eq!(
+            rx.try_recv(),
+            Some(Received::Snapshot {
+                snapshot: 1,
+                replaced: 0
+            })
+        );
+        assert_eq!(rx.try_recv(), Some(Received::Message("error")));
+    }
+
+    #[test]
+    fn overrun_asks_for_resync_without_holding_up_others() {
+        let tx: StateBroadcast<u32, u32> = StateBroadcast::new(2);
+        let mut slow = tx.subscribe();
+        let mut fast = tx.subscribe();
+        for i in 0..3 {
+            tx.send(i);
+            assert_eq!(fast.try_recv(), Some(Received::Message(i)));
+        }
+        tx.send(3);
+
+        assert_eq!(slow.try_recv(), Some(Received::Resync { missed: 3 }));
+        assert_eq!(slow.try_recv(), Some(Received::Message(3)));
+        assert_eq!(fast.try_recv(), Some(Received::Message(3)));
+    }
+
+    #[tokio::test]
+    async fn ends_when_every_sender_is_gone() {
+        let tx: StateBroadcast<u32, u32> = StateBroadcast::new(4);
+        let mut rx = tx.subscribe();
+        let tx2 = tx.clone();
+        drop(tx);
+        tx2.send(7);
+        drop(tx2);
+        assert_eq!(rx.recv().await, Some(Received::Message(7)));
+        assert_eq!(rx.recv().await, None);
+    }
+}
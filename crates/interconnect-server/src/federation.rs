@@ -0,0 +1,471 @@
+//! Server-to-server transfer channel.
+//!
+//! By default a transfer goes through the client: the origin hands it the
+//! passport and it presents that to the destination. With federation the
+//! origin connects to the destination itself and pushes the passport; the
+//! destination answers with a one-time ticket, and the client only ever sees
+//! the ticket (`ServerWire::TransferTicket`, redeemed via `ClientWire::Auth`).
+//!
+//! If the session leaves the origin before its ticket reaches it, the origin
+//! cancels the ticket at the destination so the transfer can't complete for
+//! someone who is gone. [`PendingTransfers`] tracks tickets until delivery.
+
+use futures_util::{SinkExt, StreamExt};
+use interconnect_core::{Authority, ErrorCode, Manifest, Passport, from_json_str, to_json_string};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// Messages sent from the origin to the destination.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FederationRequest {
+    /// Hand over a user's passport.
+    PushTransfer {
+        /// The origin server's manifest.
+        source: Box<Manifest>,
+        /// The passport to import.
+        passport: Passport,
+    },
+    /// Withdraw a ticket issued for an earlier push.
+    CancelTransfer { token: String },
+}
+
+/// Messages sent from the destination back to the origin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FederationResponse {
+    /// The passport is held; the client redeems it with this token.
+    Ticket { token: String },
+    /// The ticket was withdrawn (or had already been redeemed or expired).
+    Cancelled,
+    /// The push was refused.
+    Error { code: String, message: String },
+}
+
+/// Error pushing a transfer to a peer.
+#[derive(Debug, thiserror::Error)]
+pub enum FederationError {
+    #[error("websocket: {0}")]
+    WebSocket(#[from] Box<tungstenite::Error>),
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("rejected ({code}): {message}")]
+    Rejected { code: String, message: String },
+    #[error("connection closed before a response")]
+    Closed,
+}
+
+/// Pushes passports to peer servers.
+#[derive(Debug, Clone)]
+pub struct FederationClient {
+    source: Manifest,
+}
+
+impl FederationClient {
+    /// Push on behalf of the server described by `source`.
+    pub fn new(source: Manifest) -> Self {
+        Self { source }
+    }
+
+    /// Push a passport to `destination` and return the client's ticket.
+    pub async fn push_transfer(
+        &self,
+        destination: &str,
+        passport: Passport,
+    ) -> Result<String, FederationError> {
+        let request = FederationRequest::PushTransfer {
+            source: Box::new(self.source.clone()),
+            passport,
+        };
+        match self.request(destination, request).await? {
+            FederationResponse::Ticket { token } => Ok(token),
+            FederationResponse::Error { code, message } => {
+                Err(FederationError::Rejected { code, message })
+            }
+            FederationResponse::Cancelled => Err(FederationError::Closed),
+        }
+    }
+
+    /// Withdraw a ticket from `destination`.
+    pub async fn cancel_transfer(
+        &self,
+        destination: &str,
+        token: String,
+    ) -> Result<(), FederationError> {
+        match self
+            .request(destination, FederationRequest::CancelTransfer { token })
+            .await?
+        {
+            FederationResponse::Error { code, message } => {
+                Err(FederationError::Rejected { code, message })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn request(
+        &self,
+        destination: &str,
+        request: FederationRequest,
+    ) -> Result<FederationResponse, FederationError> {
+        let (mut ws, _) = tokio_tungstenite::connect_async(destination)
+            .await
+            .map_err(Box::new)?;
+
+        ws.send(Message::Text(to_json_string(&request)?.into()))
+            .await
+            .map_err(Box::new)?;
+
+        while let Some(msg) = ws.next().await {
+            if let Message::Text(text) = msg.map_err(Box::new)? {
+                return Ok(from_json_str(&text)?);
+            }
+        }
+        Err(FederationError::Closed)
+    }
+}
+
+/// A ticket the origin has received but not yet handed to its session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingTransfer {
+    /// The destination holding the ticket.
+    pub destination: String,
+    /// The ticket.
+    pub token: String,
+}
+
+/// Origin-side tickets awaiting delivery, by session.
+#[derive(Debug, Default)]
+pub struct PendingTransfers {
+    by_session: HashMap<u64, Vec<PendingTransfer>>,
+}
+
+impl PendingTransfers {
+    /// Create an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a ticket pushed on behalf of a session.
+    pub fn insert(&mut self, session_id: u64, destination: String, token: String) {
+        self.by_session
+            .entry(session_id)
+            .or_default()
+            .push(PendingTransfer { destination, token });
+    }
+
+    /// The session's tickets not yet delivered.
+    pub fn pending_transfers(&self, session_id: u64) -> &[PendingTransfer] {
+        self.by_session
+            .get(&session_id)
+            .map_or(&[], |pending| pending.as_slice())
+    }
+
+    /// The session received its ticket; stop tracking it.
+    pub fn delivered(&mut self, session_id: u64, token: &str) {
+        if let Some(pending) = self.by_session.get_mut(&session_id) {
+            pending.retain(|p| p.token != token);
+            if pending.is_empty() {
+                self.by_session.remove(&session_id);
+            }
+        }
+    }
+
+    /// The session left: take its undelivered tickets so the transport can
+    /// cancel them with [`FederationClient::cancel_transfer`].
+    pub fn cancel_for_session(&mut self, session_id: u64) -> Vec<PendingTransfer> {
+        self.by_session.remove(&session_id).unwrap_or_default()
+    }
+}
+
+/// What to do when the ticket store is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TicketOverflow {
+    /// Refuse new tickets until old ones are redeemed or expire.
+    #[default]
+    Reject,
+    /// Drop the oldest ticket to make room.
+    EvictOldest,
+}
+
+/// Bounds on the ticket store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TicketLimits {
+    /// Tickets held across all users.
+    pub max_tickets: usize,
+    /// Tickets held for any one user.
+    pub max_per_identity: usize,
+    /// Behavior at `max_tickets`.
+    pub overflow: TicketOverflow,
+}
+
+impl Default for TicketLimits {
+    fn default() -> Self {
+        Self {
+            max_tickets: 1024,
+            max_per_identity: 2,
+            overflow: TicketOverflow::Reject,
+        }
+    }
+}
+
+struct Ticket {
+    passport: Passport,
+    issued: Instant,
+    deadline: Instant,
+}
+
+/// Passports pushed by peers, waiting for their clients to arrive.
+pub struct TicketStore {
+    ttl: Duration,
+    limits: TicketLimits,
+    tickets: HashMap<String, Ticket>,
+}
+
+impl TicketStore {
+    /// Hold pushed passports for `ttl` before dropping them.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            limits: TicketLimits::default(),
+            tickets: HashMap::new(),
+        }
+    }
+
+    /// Set capacity limits.
+    pub fn with_limits(mut self, limits: TicketLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Hold a passport and return its ticket.
+    ///
+    /// Fails with [`ErrorCode::TooManyTransfers`] if the user already has
+    /// `max_per_identity` tickets, or [`ErrorCode::Overloaded`] if the store
+    /// is full and set to reject.
+    pub fn issue(&mut self, passport: Passport) -> Result<String, ErrorCode> {
+        self.issue_at(passport, Instant::now())
+    }
+
+    fn issue_at(&mut self, passport: Passport, now: Instant) -> Result<String, ErrorCode> {
+        // Reclaim abandoned tickets before counting
+        self.expire_at(now);
+
+        let held = self
+            .tickets
+            .values()
+            .filter(|t| t.passport.identity == passport.identity)
+            .count();
+        if held >= self.limits.max_per_identity {
+            return Err(ErrorCode::TooManyTransfers);
+        }
+
+        if self.tickets.len() >= self.limits.max_tickets {
+            match self.limits.overflow {
+                TicketOverflow::Reject => return Err(ErrorCode::Overloaded),
+                TicketOverflow::EvictOldest => {
+                    let oldest = self
+                        .tickets
+                        .iter()
+                        .min_by_key(|(_, t)| t.issued)
+                        .map(|(token, _)| token.clone());
+                    if let Some(token) = oldest {
+                        self.tickets.remove(&token);
+                    }
+                }
+            }
+        }
+
+        let token = crate::resume::new_token();
+        self.tickets.insert(
+            token.clone(),
+            Ticket {
+                passport,
+                issued: now,
+                deadline: now + self.ttl,
+            },
+        );
+        Ok(token)
+    }
+
+    /// Withdraw a ticket. Returns whether it was held.
+    pub fn cancel(&mut self, token: &str) -> bool {
+        self.tickets.remove(token).is_some()
+    }
+
+    /// Take the passport for a ticket. Tickets are single-use.
+    pub fn redeem(&mut self, token: &str) -> Option<Passport> {
+        self.redeem_at(token, Instant::now())
+    }
+
+    fn redeem_at(&mut self, token: &str, now: Instant) -> Option<Passport> {
+        let ticket = self.tickets.remove(token)?;
+        (now < ticket.deadline).then_some(ticket.passport)
+    }
+
+    /// Drop tickets past their deadline.
+    pub fn expire(&mut self) {
+        self.expire_at(Instant::now());
+    }
+
+    fn expire_at(&mut self, now: Instant) {
+        self.tickets.retain(|_, t| now < t.deadline);
+    }
+
+    /// Number of tickets held.
+    pub fn len(&self) -> usize {
+        self.tickets.len()
+    }
+
+    /// Whether no tickets are held.
+    pub fn is_empty(&self) -> bool {
+        self.tickets.is_empty()
+    }
+}
+
+/// Handle a peer's push (or cancellation) on the destination side.
+///
+/// Runs [`Authority::can_accept_transfer_from`] on the source, then holds
+/// the passport. The passport itself is imported later, through
+/// `on_transfer_in`, when the client redeems its ticket.
+pub fn accept_push<A: Authority>(
+    authority: &A,
+    tickets: &mut TicketStore,
+    request: FederationRequest,
+) -> FederationResponse {
+    match request {
+        FederationRequest::PushTransfer { source, passport } => {
+            if !authority.can_accept_transfer_from(&source) {
+                return FederationResponse::Error {
+                    code: ErrorCode::SourceBlocked.into(),
+                    message: format!("Transfers from {} are not accepted", source.name),
+                };
+            }
+            match tickets.issue(passport) {
+                Ok(token) => FederationResponse::Ticket { token },
+                Err(code) => FederationResponse::Error {
+                    code: code.into(),
+                    message: "Transfer ticket refused".into(),
+                },
+            }
+        }
+        FederationRequest::CancelTransfer { token } => {
+            tickets.cancel(&token);
+            FederationResponse::Cancelled
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::Identity;
+
+    fn passport() -> Passport {
+        Passport::new(Identity::local("alice"), b"{}".to_vec())
+    }
+
+    #[test]
+    fn tickets_are_single_use() {
+        let mut tickets = TicketStore::new(Duration::from_secs(30));
+        let token = tickets.issue(passport()).unwrap();
+        assert_eq!(tickets.len(), 1);
+        assert!(tickets.redeem(&token).is_some());
+        assert!(tickets.redeem(&token).is_none());
+    }
+
+    #[test]
+    fn expired_tickets_do_not_redeem() {
+        let mut tickets = TicketStore::new(Duration::from_secs(30));
+        let now = Instant::now();
+        let token = tickets.issue_at(passport(), now).unwrap();
+        assert!(
+            tickets
+                .redeem_at(&token, now + Duration::from_secs(30))
+                .is_none()
+        );
+    }
+
+    fn passport_for(name: &str) -> Passport {
+        Passport::new(Identity::local(name), Vec::new())
+    }
+
+    #[test]
+    fn one_identity_cannot_monopolize() {
+        let mut tickets = TicketStore::new(Duration::from_secs(30));
+        tickets.issue(passport()).unwrap();
+        tickets.issue(passport()).unwrap();
+        assert_eq!(tickets.issue(passport()), Err(ErrorCode::TooManyTransfers));
+        assert!(tickets.issue(passport_for("bob")).is_ok());
+    }
+
+    #[test]
+    fn overflow_policy_at_capacity() {
+        let limits = TicketLimits {
+            max_tickets: 2,
+            max_per_identity: 2,
+            overflow: TicketOverflow::Reject,
+        };
+        let now = Instant::now();
+        let later = |secs| now + Duration::from_secs(secs);
+
+        let mut tickets = TicketStore::new(Duration::from_secs(30)).with_limits(limits);
+        tickets.issue_at(passport_for("a"), now).unwrap();
+        tickets.issue_at(passport_for("b"), later(1)).unwrap();
+        assert_eq!(
+            tickets.issue_at(passport_for("c"), later(2)),
+            Err(ErrorCode::Overloaded)
+        );
+
+        let mut tickets = TicketStore::new(Duration::from_secs(30)).with_limits(TicketLimits {
+            overflow: TicketOverflow::EvictOldest,
+            ..limits
+        });
+        let oldest = tickets.issue_at(passport_for("a"), now).unwrap();
+        tickets.issue_at(passport_for("b"), later(1)).unwrap();
+        tickets.issue_at(passport_for("c"), later(2)).unwrap();
+        assert_eq!(tickets.len(), 2);
+        assert!(tickets.redeem_at(&oldest, later(3)).is_none());
+    }
+
+    #[test]
+    fn disconnect_cancels_pending_transfer() {
+        let mut tickets = TicketStore::new(Duration::from_secs(30));
+        let mut pending = PendingTransfers::new();
+
+        // Origin pushed for session 7; the session drops before delivery
+        let token = tickets.issue(passport()).unwrap();
+        pending.insert(7, "ws://b".into(), token.clone());
+        assert_eq!(pending.pending_transfers(7).len(), 1);
+
+        // What the destination does with each CancelTransfer
+        for transfer in pending.cancel_for_session(7) {
+            assert!(tickets.cancel(&transfer.token));
+        }
+        assert!(pending.pending_transfers(7).is_empty());
+        assert!(tickets.redeem(&token).is_none());
+    }
+
+    #[test]
+    fn delivered_tickets_are_not_cancelled() {
+        let mut pending = PendingTransfers::new();
+        pending.insert(7, "ws://b".into(), "t".into());
+        pending.delivered(7, "t");
+        assert!(pending.cancel_for_session(7).is_empty());
+    }
+
+    #[test]
+    fn expired_tickets_free_capacity() {
+        let limits = TicketLimits {
+            max_tickets: 1,
+            ..TicketLimits::default()
+        };
+        let mut tickets = TicketStore::new(Duration::from_secs(30)).with_limits(limits);
+        let now = Instant::now();
+        tickets.issue_at(passport_for("a"), now).unwrap();
+        let after_ttl = now + Duration::from_secs(30);
+        assert!(tickets.issue_at(passport_for("b"), after_ttl).is_ok());
+    }
+}
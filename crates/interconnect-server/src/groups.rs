@@ -0,0 +1,97 @@
+//! Delivering an authority's [`Outbox`] to connections.
+
+use interconnect_core::{Groups, Outbox, Recipients};
+use std::collections::BTreeMap;
+use tokio::sync::mpsc;
+
+/// Routes queued messages to the connections of their recipients.
+///
+/// Each connection registers for a receiver and forwards what arrives to
+/// its socket. Connections that went away are dropped on the next route.
+#[derive(Debug)]
+pub struct GroupRouter<M> {
+    sessions: BTreeMap<u64, mpsc::UnboundedSender<M>>,
+}
+
+impl<M> Default for GroupRouter<M> {
+    fn default() -> Self {
+        Self {
+            sessions: BTreeMap::new(),
+        }
+    }
+}
+
+impl<M: Clone> GroupRouter<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start receiving messages for `session_id`.
+    pub fn register(&mut self, session_id: u64) -> mpsc::UnboundedReceiver<M> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.sessions.insert(session_id, tx);
+        rx
+    }
+
+    pub fn unregister(&mut self, session_id: u64) {
+        self.sessions.remove(&session_id);
+    }
+
+    /// Deliver everything in `outbox`, resolving groups against `groups`.
+    ///
+    /// Returns the number of deliveries made.
+    pub fn route(&mut self, groups: &Groups, outbox: &mut Outbox<M>) -> usize {
+        let mut delivered = 0;
+        for (recipients, msg) in outbox.drain() {
+            let targets: Vec<u64> = match recipients {
+                Recipients::All => self.sessions.keys().copied().collect(),
+                Recipients::Group(group) => groups.members(&group).collect(),
+                Recipients::Session(id) => vec![id],
+            };
+            for id in targets {
+                let Some(tx) = self.sessions.get(&id) else {
+                    continue;
+                };
+                if tx.send(msg.clone()).is_ok() {
+                    delivered += 1;
+                } else {
+                    self.sessions.remove(&id);
+                }
+            }
+        }
+        delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::{Identity, Session};
+
+    #[test]
+    fn group_broadcasts_reach_members_only() {
+        let alice = Session::new(1, Identity::local("alice"), "alice".into());
+        let mut groups = Groups::new();
+        groups.context(&alice).join_group("lobby");
+
+        let mut router = GroupRouter::new();
+        let mut alice_rx = router.register(1);
+        let mut bob_rx = router.register(2);
+
+        let mut outbox = Outbox::new();
+        outbox.broadcast_group("lobby", "hi lobby");
+        outbox.broadcast("hi all");
+        assert_eq!(router.route(&groups, &mut outbox), 3);
+        assert!(outbox.is_empty());
+
+        assert_eq!(alice_rx.try_recv().unwrap(), "hi lobby");
+        assert_eq!(alice_rx.try_recv().unwrap(), "hi all");
+        assert_eq!(bob_rx.try_recv().unwrap(), "hi all");
+        assert!(bob_rx.try_recv().is_err());
+
+        // A closed connection is forgotten
+        drop(bob_rx);
+        outbox.send_to(2, "gone");
+        assert_eq!(router.route(&groups, &mut outbox), 0);
+    }
+}
@@ -0,0 +1,149 @@
+//! Limiting how many sessions one identity holds at once.
+//!
+//! Nothing stops one user opening a hundred connections. An
+//! [`IdentitySessions`] index counts each identity's sessions, held ones
+//! included, so the transport can refuse (or make room for) a connect past
+//! the limit. A session held for reconnect is always the first to give way:
+//! a user whose connection dropped can sign in again without waiting out
+//! the grace window.
+
+use interconnect_core::Identity;
+use std::collections::{HashMap, VecDeque};
+
+/// What to do with a connect past the per-identity limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdentityOverflow {
+    /// Refuse the new connect.
+    #[default]
+    Reject,
+    /// End the identity's oldest session to make room.
+    EvictOldest,
+}
+
+/// The outcome of [`IdentitySessions::admit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityAdmission {
+    /// Under the limit.
+    Admit,
+    /// At the limit; refuse.
+    Reject,
+    /// At the limit; end this session first.
+    Evict(u64),
+}
+
+/// Each identity's sessions, oldest first.
+#[derive(Debug, Default)]
+pub struct IdentitySessions {
+    by_identity: HashMap<Identity, VecDeque<u64>>,
+}
+
+impl IdentitySessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `identity` may open another session, given at most `max`.
+    ///
+    /// At the limit, a session for which `held` is true (waiting out its
+    /// reconnect grace) is evicted whatever `overflow` says; otherwise
+    /// `overflow` decides.
+    pub fn admit(
+        &self,
+        identity: &Identity,
+        max: usize,
+        overflow: IdentityOverflow,
+        held: impl Fn(u64) -> bool,
+    ) -> IdentityAdmission {
+        let Some(sessions) = self.by_identity.get(identity) else {
+            return IdentityAdmission::Admit;
+        };
+        if sessions.len() < max {
+            return IdentityAdmission::Admit;
+        }
+        if let Some(&id) = sessions.iter().find(|&&id| held(id)) {
+            return IdentityAdmission::Evict(id);
+        }
+        match (overflow, sessions.front()) {
+            (IdentityOverflow::EvictOldest, Some(&oldest)) => IdentityAdmission::Evict(oldest),
+            _ => IdentityAdmission::Reject,
+        }
+    }
+
+    /// Count a new session.
+    pub fn insert(&mut self, identity: &Identity, session_id: u64) {
+        self.by_identity
+            .entry(identity.clone())
+            .or_default()
+            .push_back(session_id);
+    }
+
+    /// Stop counting a session. Unknown sessions are ignored.
+    pub fn remove(&mut self, identity: &Identity, session_id: u64) {
+        let Some(sessions) = self.by_identity.get_mut(identity) else {
+            return;
+        };
+        sessions.retain(|&id| id != session_id);
+        if sessions.is_empty() {
+            self.by_identity.remove(identity);
+        }
+    }
+
+    /// Sessions `identity` holds.
+    pub fn count(&self, identity: &Identity) -> usize {
+        self.by_identity.get(identity).map_or(0, VecDeque::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_rejects_or_evicts_the_oldest() {
+        let alice = Identity::local("alice");
+        let mut index = IdentitySessions::new();
+        index.insert(&alice, 1);
+        index.insert(&alice, 2);
+        let none_held = |_| false;
+
+        assert_eq!(
+            index.admit(&alice, 3, IdentityOverflow::Reject, none_held),
+            IdentityAdmission::Admit
+        );
+        assert_eq!(
+            index.admit(&alice, 2, IdentityOverflow::Reject, none_held),
+            IdentityAdmission::Reject
+        );
+        assert_eq!(
+            index.admit(&alice, 2, IdentityOverflow::EvictOldest, none_held),
+            IdentityAdmission::Evict(1)
+        );
+        // Others are unaffected
+        assert_eq!(
+            index.admit(
+                &Identity::local("bob"),
+                2,
+                IdentityOverflow::Reject,
+                none_held
+            ),
+            IdentityAdmission::Admit
+        );
+
+        index.remove(&alice, 1);
+        assert_eq!(index.count(&alice), 1);
+        index.remove(&alice, 2);
+        assert_eq!(index.count(&alice), 0);
+    }
+
+    #[test]
+    fn reconnects_replace_held_sessions() {
+        let alice = Identity::local("alice");
+        let mut index = IdentitySessions::new();
+        index.insert(&alice, 1);
+        index.insert(&alice, 2);
+        assert_eq!(
+            index.admit(&alice, 2, IdentityOverflow::Reject, |id| id == 2),
+            IdentityAdmission::Evict(2)
+        );
+    }
+}
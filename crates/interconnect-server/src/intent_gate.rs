@@ -0,0 +1,182 @@
+//! Pausing intent processing for maintenance.
+//!
+//! While paused, connections stay open and intents are buffered instead of
+//! applied; resuming hands them back in arrival order for the transport to
+//! replay. This gives a short read-only freeze without disconnecting anyone.
+
+use interconnect_core::{ServerWire, Session};
+use std::collections::VecDeque;
+
+/// What to do with an intent when the buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntentOverflow {
+    /// Refuse the new intent.
+    #[default]
+    Reject,
+    /// Drop the oldest buffered intent to make room.
+    DropOldest,
+}
+
+/// Pause behavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntentPauseConfig {
+    /// Intents buffered across all sessions while paused.
+    pub max_buffered: usize,
+    /// Behavior at `max_buffered`.
+    pub overflow: IntentOverflow,
+    /// Tell clients when intents are paused and resumed.
+    pub notify_clients: bool,
+}
+
+impl Default for IntentPauseConfig {
+    fn default() -> Self {
+        Self {
+            max_buffered: 256,
+            overflow: IntentOverflow::Reject,
+            notify_clients: true,
+        }
+    }
+}
+
+/// The outcome of [`IntentGate::admit`].
+#[derive(Debug)]
+pub enum Admission<I> {
+    /// Not paused: apply the intent now.
+    Apply(I),
+    /// Held until [`IntentGate::resume_intents`].
+    Buffered,
+    /// Paused and the buffer is full.
+    Rejected(I),
+}
+
+/// Holds intents while processing is paused.
+#[derive(Debug)]
+pub struct IntentGate<I> {
+    config: IntentPauseConfig,
+    paused: bool,
+    buffer: VecDeque<(Session, I)>,
+}
+
+impl<I> IntentGate<I> {
+    /// Create an unpaused gate.
+    pub fn new(config: IntentPauseConfig) -> Self {
+        Self {
+            config,
+            paused: false,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Start buffering intents.
+    ///
+    /// Returns a notice to broadcast, if clients should be told.
+    pub fn pause_intents<S>(&mut self) -> Option<ServerWire<S>> {
+        self.paused = true;
+        self.config
+            .notify_clients
+            .then(|| ServerWire::maintenance("Paused for maintenance; actions will apply shortly"))
+    }
+
+    /// Stop buffering and take the held intents, oldest first, for replay.
+    ///
+    /// The notice, if any, should be broadcast after the replay.
+    pub fn resume_intents<S>(&mut self) -> (Vec<(Session, I)>, Option<ServerWire<S>>) {
+        self.paused = false;
+        let notice = self
+            .config
+            .notify_clients
+            .then(|| ServerWire::maintenance("Maintenance over"));
+        (self.buffer.drain(..).collect(), notice)
+    }
+
+    /// Whether intents are being buffered.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Number of buffered intents.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Pass an intent through, or buffer it while paused.
+    pub fn admit(&mut self, session: &Session, intent: I) -> Admission<I> {
+        if !self.paused {
+            return Admission::Apply(intent);
+        }
+        if self.buffer.len() >= self.config.max_buffered {
+            match self.config.overflow {
+                IntentOverflow::Reject => return Admission::Rejected(intent),
+                IntentOverflow::DropOldest => {
+                    if self.buffer.pop_front().is_none() {
+                        // Zero-capacity buffer
+                        return Admission::Rejected(intent);
+                    }
+                }
+            }
+        }
+        self.buffer.push_back((session.clone(), intent));
+        Admission::Buffered
+    }
+
+    /// Drop buffered intents from a session that left.
+    pub fn forget_session(&mut self, session_id: u64) {
+        self.buffer.retain(|(s, _)| s.id != session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::Identity;
+
+    fn session(id: u64) -> Session {
+        Session::new(id, Identity::local("alice"), "alice".into())
+    }
+
+    #[test]
+    fn paused_intents_replay_in_order() {
+        let mut gate = IntentGate::new(IntentPauseConfig::default());
+        assert!(matches!(
+            gate.admit(&session(1), "a"),
+            Admission::Apply("a")
+        ));
+
+        let notice: Option<ServerWire<()>> = gate.pause_intents();
+        assert!(notice.is_some());
+        assert!(matches!(gate.admit(&session(1), "b"), Admission::Buffered));
+        assert!(matches!(gate.admit(&session(2), "c"), Admission::Buffered));
+
+        let (held, _): (_, Option<ServerWire<()>>) = gate.resume_intents();
+        let held: Vec<_> = held.into_iter().map(|(s, i)| (s.id, i)).collect();
+        assert_eq!(held, [(1, "b"), (2, "c")]);
+        assert!(!gate.is_paused());
+    }
+
+    #[test]
+    fn overflow_policy() {
+        let config = IntentPauseConfig {
+            max_buffered: 1,
+            overflow: IntentOverflow::Reject,
+            notify_clients: false,
+        };
+        let mut gate = IntentGate::new(config);
+        let _: Option<ServerWire<()>> = gate.pause_intents();
+        gate.admit(&session(1), "a");
+        assert!(matches!(
+            gate.admit(&session(1), "b"),
+            Admission::Rejected("b")
+        ));
+
+        let mut gate = IntentGate::new(IntentPauseConfig {
+            overflow: IntentOverflow::DropOldest,
+            ..config
+        });
+        let _: Option<ServerWire<()>> = gate.pause_intents();
+        gate.admit(&session(1), "a");
+        gate.admit(&session(1), "b");
+        let (held, notice): (_, Option<ServerWire<()>>) = gate.resume_intents();
+        assert_eq!(held.into_iter().map(|(_, i)| i).collect::<Vec<_>>(), ["b"]);
+        assert!(notice.is_none());
+    }
+}
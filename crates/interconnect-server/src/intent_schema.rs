@@ -0,0 +1,133 @@
+//! Checking intents against the authority's JSON Schema.
+//!
+//! Deserializing an intent only checks its shape. An [`IntentValidator`]
+//! built from [`Authority::intent_schema`] also checks its values, so
+//! negative quantities or coordinates off the map are refused before any
+//! handler runs. Enabled by the `intent-schema` feature.
+//!
+//! [`Authority::intent_schema`]: interconnect_core::Authority::intent_schema
+
+use serde_json::Value;
+
+/// The schema itself is invalid.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid intent schema: {0}")]
+pub struct InvalidSchema(String);
+
+/// Why an intent failed the schema, one entry per violation.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{}", .violations.join("; "))]
+pub struct IntentRejection {
+    pub violations: Vec<String>,
+}
+
+/// A compiled intent schema.
+pub struct IntentValidator {
+    validator: jsonschema::Validator,
+}
+
+impl IntentValidator {
+    /// Compile `schema`.
+    pub fn new(schema: &Value) -> Result<Self, InvalidSchema> {
+        let validator =
+            jsonschema::validator_for(schema).map_err(|e| InvalidSchema(e.to_string()))?;
+        Ok(Self { validator })
+    }
+
+    /// Check one intent, as JSON.
+    pub fn validate(&self, intent: &Value) -> Result<(), IntentRejection> {
+        let violations: Vec<String> = self
+            .validator
+            .iter_errors(intent)
+            .map(|e| e.to_string())
+            .collect();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(IntentRejection { violations })
+        }
+    }
+
+    /// Check the intent in a raw client message.
+    ///
+    /// Covers `intent` and `tracked_intent` messages; anything else passes,
+    /// as does text that isn't JSON (decoding reports that).
+    pub fn validate_message(&self, text: &str) -> Result<(), IntentRejection> {
+        let Ok(Value::Object(mut message)) = serde_json::from_str::<Value>(text) else {
+            return Ok(());
+        };
+        let intent = match message.get("type").and_then(Value::as_str) {
+            // The intent's own fields sit beside the tag
+            Some("intent") => {
+                message.remove("type");
+                Value::Object(message)
+            }
+            Some("tracked_intent") => match message.remove("intent") {
+                Some(intent) => intent,
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        self.validate(&intent)
+    }
+}
+
+impl std::fmt::Debug for IntentValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IntentValidator").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn trade() -> IntentValidator {
+        IntentValidator::new(&json!({
+            "type": "object",
+            "properties": {
+                "item": { "type": "string" },
+                "quantity": { "type": "integer", "minimum": 1, "maximum": 99 }
+            },
+            "required": ["item", "quantity"]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let validator = trade();
+        assert!(
+            validator
+                .validate(&json!({"item": "ore", "quantity": 3}))
+                .is_ok()
+        );
+        let rejection = validator
+            .validate(&json!({"item": "ore", "quantity": -5}))
+            .unwrap_err();
+        assert_eq!(rejection.violations.len(), 1);
+    }
+
+    #[test]
+    fn finds_the_intent_in_client_messages() {
+        let validator = trade();
+        let plain = r#"{"type":"intent","item":"ore","quantity":0}"#;
+        let tracked =
+            r#"{"type":"tracked_intent","request_id":1,"intent":{"item":"ore","quantity":100}}"#;
+        assert!(validator.validate_message(plain).is_err());
+        assert!(validator.validate_message(tracked).is_err());
+        assert!(
+            validator
+                .validate_message(r#"{"type":"intent","item":"ore","quantity":1}"#)
+                .is_ok()
+        );
+        // Not an intent
+        assert!(validator.validate_message(r#"{"type":"ping"}"#).is_ok());
+    }
+
+    #[test]
+    fn invalid_schema_is_refused() {
+        assert!(IntentValidator::new(&json!({"type": 12})).is_err());
+    }
+}
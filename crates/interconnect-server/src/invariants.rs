@@ -0,0 +1,68 @@
+//! Running an authority's self-checks.
+//!
+//! [`Authority::assert_invariants`] is cheap to call but easy to forget.
+//! [`debug_assert_invariants`] belongs after every `handle_intent`;
+//! [`PeriodicInvariantChecker`] catches drift in release builds too.
+
+use interconnect_core::{Authority, InvariantViolation};
+use std::future::Future;
+use std::time::Duration;
+
+/// Panic if the authority's invariants don't hold, in debug builds only.
+#[track_caller]
+pub fn debug_assert_invariants<A: Authority>(authority: &A) {
+    if cfg!(debug_assertions)
+        && let Err(violation) = authority.assert_invariants()
+    {
+        panic!("{} ({})", violation, violation.context);
+    }
+}
+
+/// Checks an authority's invariants on a fixed interval.
+#[derive(Debug, Clone, Copy)]
+pub struct PeriodicInvariantChecker {
+    interval: Duration,
+}
+
+impl PeriodicInvariantChecker {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval }
+    }
+
+    /// Run `check` every interval, forever, passing violations to `on_error`.
+    ///
+    /// `check` typically takes the transport's lock and calls
+    /// [`Authority::assert_invariants`]; spawn this as a background task.
+    pub async fn run<F, Fut>(self, mut check: F, mut on_error: impl FnMut(InvariantViolation))
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(), InvariantViolation>>,
+    {
+        let mut tick = tokio::time::interval(self.interval);
+        loop {
+            tick.tick().await;
+            if let Err(violation) = check().await {
+                on_error(violation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn violations_reach_on_error() {
+        let checker = PeriodicInvariantChecker::new(Duration::from_millis(1));
+        let mut seen = Vec::new();
+        let run = checker.run(
+            || async { Err(InvariantViolation::new("count below zero", (-1).into())) },
+            |v| seen.push(v.description),
+        );
+        // The first check runs immediately
+        let _ = tokio::time::timeout(Duration::from_millis(20), run).await;
+        assert!(!seen.is_empty());
+        assert_eq!(seen[0], "count below zero");
+    }
+}
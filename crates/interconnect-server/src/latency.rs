@@ -0,0 +1,249 @@
+//! Round-trip measurement across all sessions.
+//!
+//! Each connection [`register`](LatencyProber::register)s and forwards the
+//! nonces it receives as `ServerWire::Ping`; when the client answers with
+//! `ClientWire::Pong`, the connection reports it through
+//! [`on_pong`](LatencyProber::on_pong).
+//!
+//! The same pings and pongs, plus snapshot acks, feed each connection's
+//! [`QualityEstimator`], which keeps `Session::quality` current.
+
+use futures_util::future::join_all;
+use interconnect_core::ConnectionQuality;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+/// Pings every registered session and collects round-trip times.
+pub struct LatencyProber {
+    timeout: Duration,
+    next_nonce: AtomicU64,
+    /// session ID -> channel to the connection
+    sessions: Mutex<HashMap<u64, mpsc::UnboundedSender<u64>>>,
+    /// nonce -> waiting probe
+    pending: Mutex<HashMap<u64, oneshot::Sender<Instant>>>,
+}
+
+impl LatencyProber {
+    /// Sessions that don't answer within `timeout` report no RTT.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            next_nonce: AtomicU64::new(1),
+            sessions: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a connection. Send a `Ping` for each nonce received.
+    pub fn register(&self, session_id: u64) -> mpsc::UnboundedReceiver<u64> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.sessions.lock().unwrap().insert(session_id, tx);
+        rx
+    }
+
+    /// Stop probing a connection.
+    pub fn unregister(&self, session_id: u64) {
+        self.sessions.lock().unwrap().remove(&session_id);
+    }
+
+    /// Report a `Pong` from a client.
+    pub fn on_pong(&self, nonce: u64) {
+        if let Some(waiter) = self.pending.lock().unwrap().remove(&nonce) {
+            let _ = waiter.send(Instant::now());
+        }
+    }
+
+    /// Ping all sessions at once and wait for their answers.
+    ///
+    /// Maps each session ID to its RTT, or `None` if it didn't answer in
+    /// time.
+    pub async fn ping_all(&self) -> HashMap<u64, Option<Duration>> {
+        let mut probes = Vec::new();
+        {
+            let sessions = self.sessions.lock().unwrap();
+            let mut pending = self.pending.lock().unwrap();
+            for (&session_id, tx) in sessions.iter() {
+                let nonce = self.next_nonce.fetch_add(1, Ordering::Relaxed);
+                let (done_tx, done_rx) = oneshot::channel();
+                pending.insert(nonce, done_tx);
+                let sent = Instant::now();
+                if tx.send(nonce).is_ok() {
+                    probes.push((session_id, nonce, sent, done_rx));
+                } else {
+                    pending.remove(&nonce);
+                }
+            }
+        }
+
+        let results = join_all(probes.into_iter().map(
+            |(session_id, nonce, sent, done)| async move {
+                let rtt = match tokio::time::timeout(self.timeout, done).await {
+                    Ok(Ok(received)) => Some(received.duration_since(sent)),
+                    _ => None,
+                };
+                (session_id, nonce, rtt)
+            },
+        ))
+        .await;
+
+        let mut pending = self.pending.lock().unwrap();
+        results
+            .into_iter()
+            .map(|(session_id, nonce, rtt)| {
+                pending.remove(&nonce);
+                (session_id, rtt)
+            })
+            .collect()
+    }
+
+    /// Number of registered sessions.
+    pub fn session_count(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+}
+
+/// Weight of each new sample in the smoothed estimates.
+const SMOOTHING: f64 = 0.125;
+
+fn smooth(old: Option<f64>, sample: f64) -> f64 {
+    match old {
+        Some(old) => old + (sample - old) * SMOOTHING,
+        None => sample,
+    }
+}
+
+/// Estimates one connection's [`ConnectionQuality`].
+///
+/// RTT and loss come from pings: a ping unanswered within `timeout` counts
+/// as lost. Bandwidth comes from how long snapshots take to be acked, less
+/// the round trip.
+#[derive(Debug)]
+pub struct QualityEstimator {
+    timeout: Duration,
+    rtt: Option<f64>,
+    loss: f64,
+    bandwidth: Option<f64>,
+    /// nonce -> sent at
+    pings: HashMap<u64, Instant>,
+    /// seq -> (sent at, bytes)
+    snapshots: HashMap<u64, (Instant, usize)>,
+}
+
+impl QualityEstimator {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            rtt: None,
+            loss: 0.0,
+            bandwidth: None,
+            pings: HashMap::new(),
+            snapshots: HashMap::new(),
+        }
+    }
+
+    /// A `Ping` with this nonce went out.
+    pub fn ping_sent(&mut self, nonce: u64) {
+        self.ping_sent_at(nonce, Instant::now());
+    }
+
+    fn ping_sent_at(&mut self, nonce: u64, now: Instant) {
+        let timeout = self.timeout;
+        let before = self.pings.len();
+        self.pings
+            .retain(|_, sent| now.duration_since(*sent) < timeout);
+        for _ in self.pings.len()..before {
+            self.loss = smooth(Some(self.loss), 1.0);
+        }
+        self.pings.insert(nonce, now);
+    }
+
+    /// The client answered a `Ping`.
+    pub fn pong(&mut self, nonce: u64) {
+        if let Some(sent) = self.pings.remove(&nonce) {
+            let rtt = sent.elapsed().as_secs_f64();
+            self.rtt = Some(smooth(self.rtt, rtt));
+            self.loss = smooth(Some(self.loss), 0.0);
+        }
+    }
+
+    /// A snapshot of `bytes` went out as `seq`.
+    pub fn snapshot_sent(&mut self, seq: u64, bytes: usize) {
+        let now = Instant::now();
+        let timeout = self.timeout;
+        self.snapshots
+            .retain(|_, (sent, _)| now.duration_since(*sent) < timeout);
+        self.snapshots.insert(seq, (now, bytes));
+    }
+
+    /// The client acked snapshot `seq`.
+    pub fn acked(&mut self, seq: u64) {
+        let Some((sent, bytes)) = self.snapshots.remove(&seq) else {
+            return;
+        };
+        self.snapshots.retain(|&s, _| s > seq);
+        let rtt = self.rtt.unwrap_or(0.0);
+        // Never less than a millisecond, so one fast ack can't read as infinite
+        let transfer = (sent.elapsed().as_secs_f64() - rtt).max(0.001);
+        self.bandwidth = Some(smooth(self.bandwidth, bytes as f64 / transfer));
+    }
+
+    /// The current estimate.
+    pub fn quality(&self) -> ConnectionQuality {
+        ConnectionQuality {
+            rtt: self.rtt.map(Duration::from_secs_f64),
+            est_bandwidth: self.bandwidth.map(|bw| bw as u64),
+            loss: self.loss as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn answered_and_silent_sessions() {
+        let prober = Arc::new(LatencyProber::new(Duration::from_millis(50)));
+        let mut fast = prober.register(1);
+        let _silent = prober.register(2);
+
+        let responder = {
+            let prober = prober.clone();
+            tokio::spawn(async move {
+                if let Some(nonce) = fast.recv().await {
+                    prober.on_pong(nonce);
+                }
+            })
+        };
+
+        let rtts = prober.ping_all().await;
+        responder.await.unwrap();
+        assert!(rtts[&1].is_some());
+        assert_eq!(rtts[&2], None);
+    }
+
+    #[test]
+    fn quality_tracks_pongs_losses_and_acks() {
+        let mut estimator = QualityEstimator::new(Duration::from_millis(50));
+        assert_eq!(estimator.quality(), ConnectionQuality::default());
+
+        estimator.ping_sent(1);
+        estimator.pong(1);
+        assert!(estimator.quality().rtt.is_some());
+        assert_eq!(estimator.quality().loss, 0.0);
+
+        // Ping 2 is never answered; it's counted once the next goes out
+        let now = Instant::now();
+        estimator.ping_sent_at(2, now);
+        estimator.ping_sent_at(3, now + Duration::from_millis(100));
+        assert!(estimator.quality().loss > 0.0);
+
+        estimator.snapshot_sent(7, 10_000);
+        estimator.acked(7);
+        assert!(estimator.quality().est_bandwidth.is_some());
+    }
+}
@@ -0,0 +1,88 @@
+//! Server-side transport for Interconnect.
+//!
+//! `interconnect-core` defines the protocol and the [`Authority`] trait; this
+//! crate provides the pieces a WebSocket server needs to run one.
+//!
+//! [`Authority`]: interconnect_core::Authority
+
+mod accept;
+mod bans;
+mod batch;
+mod broadcast;
+mod capabilities;
+mod checkpoint;
+mod config;
+mod consistency;
+mod dedup;
+mod delta;
+mod fairness;
+mod fanout;
+mod federation;
+mod groups;
+mod identity_limit;
+mod intent_gate;
+#[cfg(feature = "intent-schema")]
+mod intent_schema;
+mod invariants;
+mod latency;
+mod lifecycle_batch;
+mod load;
+#[cfg(feature = "long-poll")]
+mod long_poll;
+mod manifest;
+mod observer;
+mod panic_guard;
+mod pause;
+mod peer_transfer;
+mod priority;
+mod resume;
+mod server;
+mod snapshot_budget;
+mod snapshot_size;
+mod spectator;
+mod throttle;
+mod ws;
+
+pub use accept::{AcceptLimiter, AcceptPolicy, AcceptRejection, PendingConnection};
+pub use bans::{BanEntry, BanList};
+pub use batch::{BatchStats, FrameBatcher};
+pub use broadcast::{DeliveryQueue, SnapshotScheduler, StaggerConfig};
+pub use capabilities::CapabilityPolicy;
+pub use checkpoint::{CheckpointError, FileCheckpointStore};
+pub use config::{AuthorityConfig, ConfigError};
+pub use consistency::{ConsistencyPolicy, OwnWrites};
+pub use dedup::DedupCache;
+pub use delta::DeltaEncoder;
+pub use fairness::{DEFAULT_YIELD_EVERY, YieldBudget};
+pub use fanout::{Lag, Received, StateBroadcast, StateSubscriber};
+pub use federation::{
+    FederationClient, FederationError, FederationRequest, FederationResponse, PendingTransfer,
+    PendingTransfers, TicketLimits, TicketOverflow, TicketStore, accept_push,
+};
+pub use groups::GroupRouter;
+pub use identity_limit::{IdentityAdmission, IdentityOverflow, IdentitySessions};
+pub use intent_gate::{Admission, IntentGate, IntentOverflow, IntentPauseConfig};
+#[cfg(feature = "intent-schema")]
+pub use intent_schema::{IntentRejection, IntentValidator, InvalidSchema};
+pub use invariants::{PeriodicInvariantChecker, debug_assert_invariants};
+pub use latency::{LatencyProber, QualityEstimator};
+pub use lifecycle_batch::{LifecycleEventBatcher, LifecycleEventMerge, merge_lifecycle_events};
+pub use load::LoadCounters;
+#[cfg(feature = "long-poll")]
+pub use long_poll::{LongPoll, LongPollConfig};
+pub use manifest::ManifestCache;
+pub use observer::{LoggingObserver, Observer};
+pub use panic_guard::{AuthorityPanic, PanicGuard, PanicPolicy};
+pub use pause::{CoalesceConfig, PauseBuffer, Resumed};
+pub use peer_transfer::{PeerTransferBatcher, PeerTransferStats};
+pub use priority::{PrioritizedIntent, PriorityIntentQueue};
+pub use resume::{ReconnectGrace, ResumeStore};
+pub use server::{AuthorityHandle, GracefulShutdownHandle, spawn_authority};
+pub use snapshot_budget::SnapshotMeter;
+pub use snapshot_size::{DEFAULT_SIZE_WINDOW, DEFAULT_SPIKE_THRESHOLD, SizeTracker, SpikeGuard};
+pub use spectator::SpectatorRegistry;
+pub use throttle::{BroadcastThrottle, TopicCoalescer, TopicThrottles};
+pub use ws::{
+    CLIENT_VERSION_HEADER, SerializationFailurePolicy, ToWsMessage, client_version, connect_info,
+    negotiate_encoding,
+};
@@ -0,0 +1,207 @@
+//! Merging lifecycle events during join storms.
+//!
+//! When many sessions join within a tick, admins would get a
+//! `LifecycleEvent::Connected` each. [`LifecycleEventBatcher`] holds events
+//! for a short window and collapses runs of joins into one
+//! `LifecycleEvent::MultipleConnected`.
+
+use interconnect_core::LifecycleEvent;
+use std::time::{Duration, Instant};
+
+/// The outcome of [`merge_lifecycle_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifecycleEventMerge {
+    /// The first event, with the second folded in if they combine.
+    pub merged: Option<LifecycleEvent>,
+    /// The second event, if it couldn't be folded in.
+    pub remainder: Option<LifecycleEvent>,
+}
+
+/// Fold `b` into `a` if both are joins; otherwise keep them apart.
+pub fn merge_lifecycle_events(a: LifecycleEvent, b: LifecycleEvent) -> LifecycleEventMerge {
+    let (mut sessions, joined) = match (joined(a), joined(b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (a, b) => {
+            return LifecycleEventMerge {
+                merged: Some(a.map_or_else(|event| event, multiple)),
+                remainder: Some(b.map_or_else(|event| event, multiple)),
+            };
+        }
+    };
+    sessions.extend(joined);
+    LifecycleEventMerge {
+        merged: Some(multiple(sessions)),
+        remainder: None,
+    }
+}
+
+/// The sessions a join event covers, or the event if it isn't one.
+fn joined(event: LifecycleEvent) -> Result<Vec<(u64, String)>, LifecycleEvent> {
+    match event {
+        LifecycleEvent::Connected { session_id, name } => Ok(vec![(session_id, name)]),
+        LifecycleEvent::MultipleConnected { sessions } => Ok(sessions),
+        other => Err(other),
+    }
+}
+
+/// A join event for `sessions`, plain `Connected` for just one.
+fn multiple(mut sessions: Vec<(u64, String)>) -> LifecycleEvent {
+    match sessions.len() {
+        1 => {
+            let (session_id, name) = sessions.remove(0);
+            LifecycleEvent::Connected { session_id, name }
+        }
+        _ => LifecycleEvent::MultipleConnected { sessions },
+    }
+}
+
+/// Holds lifecycle events for a window, then releases them merged.
+pub struct LifecycleEventBatcher {
+    window: Duration,
+    opened: Option<Instant>,
+    events: Vec<LifecycleEvent>,
+}
+
+impl LifecycleEventBatcher {
+    /// Hold events for `window` after the first one arrives.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            opened: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// The batch window.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Hold `event` until the window closes.
+    pub fn push(&mut self, event: LifecycleEvent) {
+        self.push_at(event, Instant::now());
+    }
+
+    fn push_at(&mut self, event: LifecycleEvent, now: Instant) {
+        self.opened.get_or_insert(now);
+        self.events.push(event);
+    }
+
+    /// When the held events are due, if any are held.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.opened.map(|opened| opened + self.window)
+    }
+
+    /// The held events, merged, once the window has closed; nothing before.
+    pub fn flush_due(&mut self) -> Vec<LifecycleEvent> {
+        self.flush_due_at(Instant::now())
+    }
+
+    fn flush_due_at(&mut self, now: Instant) -> Vec<LifecycleEvent> {
+        if self.deadline().is_none_or(|deadline| now < deadline) {
+            return Vec::new();
+        }
+        self.flush()
+    }
+
+    /// The held events, merged, whether or not the window has closed.
+    ///
+    /// Order is kept: only joins next to each other merge, so a session
+    /// that joins and leaves within the window is still seen to join first.
+    pub fn flush(&mut self) -> Vec<LifecycleEvent> {
+        self.opened = None;
+        let mut flushed = Vec::new();
+        let mut events = std::mem::take(&mut self.events).into_iter();
+        let Some(mut current) = events.next() else {
+            return flushed;
+        };
+        for next in events {
+            let merge = merge_lifecycle_events(current, next);
+            let merged = merge.merged.expect("a merge keeps the first event");
+            match merge.remainder {
+                Some(remainder) => {
+                    flushed.push(merged);
+                    current = remainder;
+                }
+                None => current = merged,
+            }
+        }
+        flushed.push(current);
+        flushed
+    }
+
+    /// Number of events held.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected(session_id: u64, name: &str) -> LifecycleEvent {
+        LifecycleEvent::Connected {
+            session_id,
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn joins_merge_and_other_events_stay_apart() {
+        let merge = merge_lifecycle_events(connected(1, "a"), connected(2, "b"));
+        assert_eq!(
+            merge,
+            LifecycleEventMerge {
+                merged: Some(LifecycleEvent::MultipleConnected {
+                    sessions: vec![(1, "a".into()), (2, "b".into())],
+                }),
+                remainder: None,
+            }
+        );
+
+        let left = LifecycleEvent::Disconnected {
+            session_id: 1,
+            name: "a".into(),
+        };
+        let merge = merge_lifecycle_events(connected(1, "a"), left.clone());
+        assert_eq!(merge.merged, Some(connected(1, "a")));
+        assert_eq!(merge.remainder, Some(left));
+    }
+
+    #[test]
+    fn batcher_releases_merged_runs_when_the_window_closes() {
+        let mut batcher = LifecycleEventBatcher::new(Duration::from_millis(100));
+        let now = Instant::now();
+        let left = LifecycleEvent::Disconnected {
+            session_id: 2,
+            name: "b".into(),
+        };
+        batcher.push_at(connected(1, "a"), now);
+        batcher.push_at(connected(2, "b"), now);
+        batcher.push_at(left.clone(), now);
+        batcher.push_at(connected(3, "c"), now);
+
+        assert!(
+            batcher
+                .flush_due_at(now + Duration::from_millis(50))
+                .is_empty()
+        );
+        assert_eq!(
+            batcher.flush_due_at(now + Duration::from_millis(100)),
+            [
+                LifecycleEvent::MultipleConnected {
+                    sessions: vec![(1, "a".into()), (2, "b".into())],
+                },
+                left,
+                connected(3, "c"),
+            ]
+        );
+        assert!(batcher.is_empty());
+        assert_eq!(batcher.deadline(), None);
+    }
+}
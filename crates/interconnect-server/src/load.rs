@@ -0,0 +1,82 @@
+//! Live load counters for monitoring.
+//!
+//! A scraper polling every few seconds shouldn't have to take the
+//! authority's lock to learn how busy the server is. [`LoadCounters`] are
+//! plain atomics the transport keeps current as sessions subscribe to
+//! snapshots and intents queue for the authority, so reading them costs
+//! nothing however often it happens.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How busy a server is right now.
+#[derive(Debug, Default)]
+pub struct LoadCounters {
+    snapshot_subscribers: AtomicUsize,
+    intents_in_flight: AtomicUsize,
+}
+
+impl LoadCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sessions currently receiving pushed snapshots: connected, with
+    /// [`Delivery::Push`](interconnect_core::Delivery::Push), and not paused.
+    pub fn snapshot_subscriber_count(&self) -> usize {
+        self.snapshot_subscribers.load(Ordering::Relaxed)
+    }
+
+    /// Intents accepted from clients but not yet handled by the authority.
+    pub fn intents_in_flight(&self) -> usize {
+        self.intents_in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Count a snapshot subscriber until the guard drops.
+    pub(crate) fn subscribe(&self) -> LoadGuard<'_> {
+        LoadGuard::new(&self.snapshot_subscribers)
+    }
+
+    /// Count an intent in flight until the guard drops.
+    pub(crate) fn intent_queued(&self) -> LoadGuard<'_> {
+        LoadGuard::new(&self.intents_in_flight)
+    }
+}
+
+/// One unit of a counter, given back on drop.
+#[derive(Debug)]
+pub(crate) struct LoadGuard<'a>(&'a AtomicUsize);
+
+impl<'a> LoadGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for LoadGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guards_count_while_held() {
+        let load = LoadCounters::new();
+        let alice = load.subscribe();
+        let bob = load.subscribe();
+        let intent = load.intent_queued();
+        assert_eq!(load.snapshot_subscriber_count(), 2);
+        assert_eq!(load.intents_in_flight(), 1);
+
+        drop(alice);
+        drop(intent);
+        assert_eq!(load.snapshot_subscriber_count(), 1);
+        assert_eq!(load.intents_in_flight(), 0);
+        drop(bob);
+        assert_eq!(load.snapshot_subscriber_count(), 0);
+    }
+}
@@ -0,0 +1,756 @@
+//! HTTP long-polling, for clients that can't keep a WebSocket open.
+//!
+//! Some networks (restrictive proxies, locked-down firewalls) only let
+//! plain HTTP through. [`LongPoll`] serves a
+//! [`spawn_authority`](crate::spawn_authority) server's room over three
+//! routes, with the same wire types:
+//!
+//! - `POST /auth` takes a `ClientWire::Auth` and answers with the manifest
+//!   and a `ServerWire::ResumeToken`, as a JSON array. Send the token as
+//!   `Authorization: Bearer <token>` on the other routes.
+//! - `POST /intent` takes a `ClientWire::Intent` or `ClientWire::TrackedIntent`
+//!   and answers `204 No Content` once it's applied, an `IntentAck` if the
+//!   tracked intent asked for one, or a `ServerWire::Error`. Intents pass
+//!   the same checks as on a WebSocket. Optimistic intents (with a
+//!   `base_seq`) need a WebSocket.
+//! - `GET /snapshot?since=<seq>` answers with the first
+//!   `ServerWire::Snapshot` after `seq`, waiting up to
+//!   [`LongPollConfig::poll_timeout`] for one, and `204 No Content` if none
+//!   came. Leave `since` out for the current state.
+//!
+//! Snapshots are full state, so a session's queue of pending snapshots is
+//! only ever the latest one it hasn't seen: a poll answers at once if the
+//! room changed since `since`. A snapshot's `seq` is the room's version,
+//! which is also what a tracked intent's `if_seq` is checked against.
+//! Sessions that stop polling leave after [`LongPollConfig::idle_timeout`],
+//! and the rest leave with the WebSocket server's on shutdown.
+//!
+//! Sessions here are the WebSocket server's too: they count against the
+//! same bans and session limits, and a change through either transport
+//! wakes waiting polls. `POST /auth` is held to the server's
+//! [`AcceptPolicy`](crate::AcceptPolicy) per address, so serve the router
+//! with `into_make_service_with_connect_info::<SocketAddr>()`.
+
+use crate::AuthorityHandle;
+use crate::resume::new_token;
+use crate::server::{IntentCall, IntentOutcome, Shared, run_intent, session_snapshot, stopped};
+use crate::ws::header_hints;
+use axum::Router;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use futures_util::FutureExt;
+use interconnect_core::{
+    Authority, Capabilities, ClientWire, ConnectionState, ErrorCode, LifecycleEvent, ServerWire,
+    Session, from_json_str, to_json_string,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// How long polls wait and idle sessions last.
+#[derive(Debug, Clone)]
+pub struct LongPollConfig {
+    /// How long `GET /snapshot` waits for a change before answering `204`.
+    /// Keep it under the proxies' request timeout.
+    pub poll_timeout: Duration,
+    /// How long a session may go without a request before it leaves.
+    /// Must be longer than `poll_timeout`.
+    pub idle_timeout: Duration,
+    /// Request headers passed to the authority at `POST /auth` (see
+    /// [`connect_info`](crate::connect_info)).
+    pub hint_headers: Vec<String>,
+}
+
+impl Default for LongPollConfig {
+    fn default() -> Self {
+        Self {
+            poll_timeout: Duration::from_secs(25),
+            idle_timeout: Duration::from_secs(60),
+            hint_headers: Vec::new(),
+        }
+    }
+}
+
+/// Serves an authority over HTTP long-polling (see the module docs).
+pub struct LongPoll<A: Authority> {
+    shared: Arc<Shared<A>>,
+    config: LongPollConfig,
+    /// Sessions by their bearer token.
+    sessions: Mutex<HashMap<String, PolledSession>>,
+}
+
+struct PolledSession {
+    session: Session,
+    capabilities: Capabilities,
+    /// When its last intent was accepted. Held while one is handled, so a
+    /// session's intents run one at a time, as on a WebSocket.
+    last_intent: Arc<tokio::sync::Mutex<Option<Instant>>>,
+    last_seen: Instant,
+    /// Signalled when a newer connection of its identity evicts it.
+    evicted: Arc<Notify>,
+}
+
+#[derive(Deserialize)]
+struct Since {
+    since: Option<u64>,
+}
+
+impl<A> LongPoll<A>
+where
+    A: Authority + Send + Sync + 'static,
+    A::Intent: DeserializeOwned + Send,
+    A::Snapshot: Serialize + Send,
+{
+    /// Serve the room behind `handle` alongside its WebSocket server.
+    pub fn new(handle: &AuthorityHandle<A>, config: LongPollConfig) -> Self {
+        Self {
+            shared: handle.shared().clone(),
+            config,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The routes, to serve with `axum::serve` or nest in a larger app.
+    ///
+    /// Starts the task that expires idle sessions and closes the rest with
+    /// the room on shutdown.
+    pub fn router(self) -> Router {
+        let poll = Arc::new(self);
+        let transports = &poll.shared.transports;
+        transports.lock().unwrap().spawn(sweep_idle(poll.clone()));
+        Router::new()
+            .route("/auth", post(auth::<A>))
+            .route("/intent", post(intent::<A>))
+            .route("/snapshot", get(snapshot::<A>))
+            .with_state(poll)
+    }
+
+    /// The session `headers` carry a token for, marked as seen.
+    fn session(&self, headers: &HeaderMap) -> Option<Session> {
+        self.polled(headers, |polled| polled.session.clone())
+    }
+
+    /// Look at the session `headers` carry a token for, marking it as seen.
+    fn polled<T>(&self, headers: &HeaderMap, f: impl FnOnce(&PolledSession) -> T) -> Option<T> {
+        let token = bearer(headers)?;
+        let mut sessions = self.sessions.lock().unwrap();
+        let polled = sessions.get_mut(token)?;
+        polled.last_seen = Instant::now();
+        Some(f(polled))
+    }
+
+    /// Disconnect the session `headers` carry a token for.
+    async fn end(&self, headers: &HeaderMap) {
+        let Some(token) = bearer(headers) else { return };
+        let ended = self.sessions.lock().unwrap().remove(token);
+        if let Some(polled) = ended {
+            self.disconnect(vec![polled.session]).await;
+        }
+    }
+
+    /// Disconnect sessions that stopped polling or were evicted.
+    async fn expire_idle(&self) {
+        let expired: Vec<Session> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let gone: Vec<String> = sessions
+                .iter()
+                .filter(|(_, polled)| {
+                    polled.last_seen.elapsed() > self.config.idle_timeout
+                        || polled.evicted.notified().now_or_never().is_some()
+                })
+                .map(|(token, _)| token.clone())
+                .collect();
+            gone.iter()
+                .filter_map(|token| sessions.remove(token))
+                .map(|polled| polled.session)
+                .collect()
+        };
+        self.disconnect(expired).await;
+    }
+
+    /// Hand every session to the WebSocket server, which disconnects them
+    /// with its own once they've closed.
+    async fn close(&self) {
+        let closing: Vec<Session> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.drain().map(|(_, polled)| polled.session).collect()
+        };
+        let mut sessions = self.shared.sessions.lock().await;
+        let mut spectators = self.shared.config.spectators.lock().unwrap();
+        for session in closing {
+            sessions.active -= 1;
+            sessions.identities.remove(&session.identity, session.id);
+            sessions.evictions.remove(&session.id);
+            spectators.forget_session(session.id);
+            sessions.closing.push(session);
+        }
+    }
+
+    /// Take sessions out of the room.
+    async fn disconnect(&self, expired: Vec<Session>) {
+        if expired.is_empty() {
+            return;
+        }
+        let mut sessions = self.shared.sessions.lock().await;
+        for session in &expired {
+            sessions.active -= 1;
+            sessions.identities.remove(&session.identity, session.id);
+            sessions.evictions.remove(&session.id);
+        }
+        drop(sessions);
+        {
+            let mut spectators = self.shared.config.spectators.lock().unwrap();
+            for session in &expired {
+                spectators.forget_session(session.id);
+            }
+        }
+        let mut authority = self.shared.authority.write().await;
+        for session in &expired {
+            tracing::debug!("{} stopped polling", session.name);
+            authority.on_disconnect(session);
+        }
+        self.shared.version.fetch_add(1, Ordering::Relaxed);
+        drop(authority);
+        for session in &expired {
+            self.shared.emit(LifecycleEvent::Disconnected {
+                session_id: session.id,
+                name: session.name.clone(),
+            });
+        }
+        let _ = self.shared.changes.send(());
+    }
+}
+
+/// Expire idle sessions every `idle_timeout` until shutdown, then close
+/// the rest.
+async fn sweep_idle<A>(poll: Arc<LongPoll<A>>)
+where
+    A: Authority + Send + Sync + 'static,
+    A::Intent: DeserializeOwned + Send,
+    A::Snapshot: Serialize + Send,
+{
+    let mut shutdown = poll.shared.shutdown.subscribe();
+    let mut ticks = tokio::time::interval(poll.config.idle_timeout);
+    loop {
+        tokio::select! {
+            _ = stopped(&mut shutdown) => break,
+            _ = ticks.tick() => poll.expire_idle().await,
+        }
+    }
+    poll.close().await;
+}
+
+async fn auth<A>(
+    State(poll): State<Arc<LongPoll<A>>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: String,
+) -> Response
+where
+    A: Authority + Send + Sync + 'static,
+    A::Intent: DeserializeOwned + Send,
+    A::Snapshot: Serialize + Send,
+{
+    // Held to the same limits as a WebSocket connection from the address
+    let _pending = match poll.shared.limiter.admit(addr.ip()) {
+        Ok(pending) => pending,
+        Err(e) => {
+            tracing::debug!("Refused long-poll auth from {}: {}", addr, e);
+            return error(ErrorCode::RateLimited, e.to_string());
+        }
+    };
+    poll.expire_idle().await;
+    let (identity, name) = match from_json_str::<ClientWire<A::Intent>>(&body) {
+        Ok(ClientWire::Auth {
+            passport: Some(_), ..
+        }) => {
+            return error(
+                ErrorCode::ProtocolError,
+                "Transfers need a WebSocket connection",
+            );
+        }
+        Ok(ClientWire::Auth { identity, name, .. }) => (identity, name),
+        Ok(_) => {
+            return error(ErrorCode::ProtocolError, "Expected auth");
+        }
+        Err(e) => {
+            return error(ErrorCode::MalformedMessage, e.to_string());
+        }
+    };
+    let info = header_hints(&headers, &poll.config.hint_headers);
+    let mut authority = poll.shared.authority.write().await;
+    if let Err(e) = authority.validate_connect(&identity, &info) {
+        return error(ErrorCode::InvalidIdentity, e.to_string());
+    }
+    let id = match poll.shared.admit(&mut authority, &identity).await {
+        Ok(id) => id,
+        Err((code, message)) => return error(code, message),
+    };
+    let name = name.unwrap_or_else(|| identity.display_name());
+    let mut session = Session::new(id, identity, name);
+    // Nothing to sync: the first poll brings the state
+    session.enter_state(ConnectionState::Live);
+    if let Err(e) = authority.on_connect_with_info(&session, &info) {
+        return error(ErrorCode::Forbidden, e.to_string());
+    }
+    let capabilities = poll
+        .shared
+        .config
+        .capabilities
+        .resolve(&*authority, &session);
+    // Counted under the authority's lock, so concurrent joins can't overshoot
+    let evicted = {
+        let mut sessions = poll.shared.sessions.lock().await;
+        sessions.active += 1;
+        sessions.identities.insert(&session.identity, session.id);
+        sessions.evictions.entry(session.id).or_default().clone()
+    };
+    poll.shared.version.fetch_add(1, Ordering::Relaxed);
+    drop(authority);
+    poll.shared.emit(LifecycleEvent::Connected {
+        session_id: session.id,
+        name: session.name.clone(),
+    });
+
+    let token = new_token();
+    tracing::debug!("{} connected by long-poll", session.name);
+    poll.sessions.lock().unwrap().insert(
+        token.clone(),
+        PolledSession {
+            session,
+            capabilities,
+            last_intent: Arc::default(),
+            last_seen: Instant::now(),
+            evicted,
+        },
+    );
+    let _ = poll.shared.changes.send(());
+    let greeting: [ServerWire<A::Snapshot>; 2] = [
+        ServerWire::Manifest(Box::new(poll.shared.config.manifest.clone())),
+        ServerWire::ResumeToken { token },
+    ];
+    json(StatusCode::OK, &greeting)
+}
+
+async fn intent<A>(
+    State(poll): State<Arc<LongPoll<A>>>,
+    headers: HeaderMap,
+    body: String,
+) -> Response
+where
+    A: Authority + Send + Sync + 'static,
+    A::Intent: DeserializeOwned + Send,
+    A::Snapshot: Serialize + Send,
+{
+    poll.expire_idle().await;
+    let Some((session, capabilities, last_intent)) = poll.polled(&headers, |polled| {
+        (
+            polled.session.clone(),
+            polled.capabilities,
+            polled.last_intent.clone(),
+        )
+    }) else {
+        return unknown_session();
+    };
+    // (request ID, require ack, if_seq) for a tracked intent
+    let (intent, tracked) = match from_json_str::<ClientWire<A::Intent>>(&body) {
+        Ok(ClientWire::Intent(intent)) => (intent, None),
+        Ok(ClientWire::TrackedIntent {
+            base_seq: Some(_), ..
+        }) => {
+            return error(
+                ErrorCode::ProtocolError,
+                "Optimistic intents need a WebSocket connection",
+            );
+        }
+        Ok(ClientWire::TrackedIntent {
+            request_id,
+            require_ack,
+            if_seq,
+            intent,
+            ..
+        }) => (intent, Some((request_id, require_ack, if_seq))),
+        Ok(_) => {
+            return error(ErrorCode::ProtocolError, "Expected an intent");
+        }
+        Err(e) => {
+            return error(ErrorCode::MalformedMessage, e.to_string());
+        }
+    };
+
+    let mut last_intent = last_intent.lock().await;
+    // A tracked intent is applied once; a retry only gets its ack again
+    if let Some((request_id, require_ack, _)) = tracked {
+        let key = (session.identity.clone(), request_id);
+        let applied = poll.shared.sessions.lock().await.applied.get(&key);
+        if let Some(seq) = applied {
+            return acked::<A>(request_id, require_ack, seq);
+        }
+    }
+    // `if_seq` is checked against the room's version, which this transport
+    // numbers its snapshots by
+    let call = IntentCall {
+        intent,
+        text: &body,
+        base_seq: None,
+        latest_seq: None,
+        if_seq: tracked.and_then(|(request_id, _, if_seq)| {
+            if_seq.map(|if_seq| (request_id, if_seq, Some(if_seq)))
+        }),
+    };
+    // The first snapshot to reflect it
+    let seq = match run_intent(
+        &poll.shared,
+        &session,
+        &capabilities,
+        &mut last_intent,
+        call,
+    )
+    .await
+    {
+        IntentOutcome::Applied { version, .. } => version,
+        IntentOutcome::Refused(code, message) => return error(code, message),
+        IntentOutcome::Panicked { ends_session } => {
+            if ends_session {
+                poll.end(&headers).await;
+            }
+            return error(ErrorCode::InternalError, "The server failed handling that");
+        }
+        IntentOutcome::Failed {
+            kick: Some(reason), ..
+        } => {
+            poll.end(&headers).await;
+            return error(ErrorCode::Kicked, reason);
+        }
+        IntentOutcome::Failed { code, message, .. } => return error(code, message),
+    };
+    drop(last_intent);
+    let Some((request_id, require_ack, _)) = tracked else {
+        return StatusCode::NO_CONTENT.into_response();
+    };
+    poll.shared
+        .sessions
+        .lock()
+        .await
+        .applied
+        .insert((session.identity.clone(), request_id), seq);
+    if require_ack {
+        poll.shared
+            .authority
+            .write()
+            .await
+            .on_intent_ack(&session, request_id);
+    }
+    acked::<A>(request_id, require_ack, seq)
+}
+
+async fn snapshot<A>(
+    State(poll): State<Arc<LongPoll<A>>>,
+    headers: HeaderMap,
+    Query(Since { since }): Query<Since>,
+) -> Response
+where
+    A: Authority + Send + Sync + 'static,
+    A::Intent: DeserializeOwned + Send,
+    A::Snapshot: Serialize + Send,
+{
+    poll.expire_idle().await;
+    let Some(session) = poll.session(&headers) else {
+        return unknown_session();
+    };
+    // Subscribed before looking, so a change in between still wakes us. A
+    // `since` from before a restart isn't a version to wait past.
+    let mut changes = poll.shared.changes.subscribe();
+    let changed = async {
+        while since.is_some_and(|since| poll.shared.version.load(Ordering::Relaxed) == since) {
+            // Lagging only means several changes coalesced; the sender lives
+            // as long as `poll`
+            let _ = changes.recv().await;
+        }
+    };
+    if tokio::time::timeout(poll.config.poll_timeout, changed)
+        .await
+        .is_err()
+    {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    let (data, seq) = session_snapshot(&poll.shared, &session).await;
+    json(StatusCode::OK, &ServerWire::snapshot(seq, data))
+}
+
+/// The answer to tracked intent `request_id`, applied as of snapshot `seq`.
+fn acked<A: Authority>(request_id: u64, require_ack: bool, seq: u64) -> Response
+where
+    A::Snapshot: Serialize,
+{
+    if !require_ack {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    let msg: ServerWire<A::Snapshot> = ServerWire::IntentAck { request_id, seq };
+    json(StatusCode::OK, &msg)
+}
+
+fn json<T: Serialize>(status: StatusCode, body: &T) -> Response {
+    match to_json_string(body) {
+        Ok(body) => (status, [(header::CONTENT_TYPE, "application/json")], body).into_response(),
+        Err(e) => {
+            tracing::error!("Long-poll response couldn't be encoded: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// An error response, with the status `code` maps to.
+fn error(code: ErrorCode, message: impl Into<String>) -> Response {
+    let status =
+        StatusCode::from_u16(code.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let msg: ServerWire<()> = ServerWire::error(code, message);
+    json(status, &msg)
+}
+
+/// The token in an `Authorization: Bearer` header.
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn unknown_session() -> Response {
+    error(
+        ErrorCode::ResumeExpired,
+        "Session expired; authenticate again",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthorityConfig, BanEntry, spawn_authority};
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use futures_util::SinkExt;
+    use interconnect_core::testing::{Tallies, TestRoom};
+    use interconnect_core::{Identity, Manifest};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+    use tower::ServiceExt;
+
+    async fn serve(poll_timeout: Duration) -> (AuthorityHandle<TestRoom>, Router) {
+        let config = LongPollConfig {
+            poll_timeout,
+            ..LongPollConfig::default()
+        };
+        serve_room(TestRoom::new(), config).await
+    }
+
+    async fn serve_room(
+        room: TestRoom,
+        config: LongPollConfig,
+    ) -> (AuthorityHandle<TestRoom>, Router) {
+        let manifest = Manifest {
+            identity: Identity::local("counter"),
+            name: "Counter".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle = spawn_authority(room, AuthorityConfig::new(manifest), listener).unwrap();
+        let router = LongPoll::new(&handle, config).router();
+        (handle, router)
+    }
+
+    async fn send(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> (StatusCode, String) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let request = request.body(Body::from(body.to_string())).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn sign_in(router: &Router, identity: &str) -> String {
+        let auth = format!(r#"{{"type":"auth","identity":"{identity}"}}"#);
+        let (status, body) = send(router, "POST", "/auth", None, &auth).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let greeting: Vec<ServerWire<Tallies>> = from_json_str(&body).unwrap();
+        let [ServerWire::Manifest(_), ServerWire::ResumeToken { token }] = &greeting[..] else {
+            panic!("{greeting:?}");
+        };
+        token.clone()
+    }
+
+    /// The next snapshot's seq and total.
+    async fn poll(router: &Router, token: &str, since: Option<u64>) -> (u64, u32) {
+        let uri = match since {
+            Some(since) => format!("/snapshot?since={since}"),
+            None => "/snapshot".into(),
+        };
+        let (status, body) = send(router, "GET", &uri, Some(token), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let ServerWire::<Tallies>::Snapshot { seq, data, .. } = from_json_str(&body).unwrap()
+        else {
+            panic!("{body}");
+        };
+        (seq, data.iter().map(|(_, tally)| tally).sum())
+    }
+
+    #[tokio::test]
+    async fn intents_show_in_the_next_snapshot() {
+        let (handle, router) = serve(Duration::from_millis(20)).await;
+        let token = sign_in(&router, "local:alice").await;
+
+        let intent = r#"{"type":"intent","by":2}"#;
+        let (status, _) = send(&router, "POST", "/intent", Some(&token), intent).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, body) = send(&router, "GET", "/snapshot", Some(&token), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let snapshot: ServerWire<Tallies> = from_json_str(&body).unwrap();
+        let ServerWire::Snapshot { seq, data, .. } = snapshot else {
+            panic!("{body}");
+        };
+        assert_eq!(data.iter().map(|(_, tally)| tally).sum::<u32>(), 2);
+
+        // Nothing changed since: the poll times out empty
+        let uri = format!("/snapshot?since={seq}");
+        let (status, _) = send(&router, "GET", &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn intents_pass_the_websocket_checks() {
+        let room = TestRoom {
+            pause_on_error: Some(Duration::from_secs(60)),
+            ..TestRoom::new()
+        };
+        let config = LongPollConfig {
+            poll_timeout: Duration::from_millis(20),
+            ..LongPollConfig::default()
+        };
+        let (handle, router) = serve_room(room, config).await;
+        let add = |by: u32| format!(r#"{{"type":"intent","by":{by}}}"#);
+
+        // Guests are rate-limited
+        let guest = sign_in(&router, "anon:guest").await;
+        let (status, _) = send(&router, "POST", "/intent", Some(&guest), &add(1)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&router, "POST", "/intent", Some(&guest), &add(1)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        // A failure pauses the room for everyone, as the authority asked
+        let alice = sign_in(&router, "local:alice").await;
+        let (status, _) = send(&router, "POST", "/intent", Some(&alice), &add(0)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = send(&router, "POST", "/intent", Some(&alice), &add(2)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(handle.authority().read().await.total(), 1);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_leave_and_the_rest_close_with_the_room() {
+        let config = LongPollConfig {
+            poll_timeout: Duration::from_millis(10),
+            idle_timeout: Duration::from_millis(50),
+            ..LongPollConfig::default()
+        };
+        let (handle, router) = serve_room(TestRoom::new(), config).await;
+        sign_in(&router, "local:alice").await;
+        // Nothing else is asked of the server in the meantime
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(handle.authority().read().await.present.is_empty());
+
+        sign_in(&router, "local:bob").await;
+        let room = handle.authority().clone();
+        handle.shutdown().await.unwrap();
+        assert!(room.read().await.present.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_are_refused() {
+        let (handle, router) = serve(Duration::from_millis(20)).await;
+        let (status, _) = send(&router, "GET", "/snapshot", Some("nope"), "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn banned_identities_are_refused() {
+        let (handle, router) = serve(Duration::from_millis(20)).await;
+        let mallory = BanEntry::new(Identity::local("mallory"), "spam");
+        handle.bans().lock().unwrap().ban(mallory);
+        let auth = r#"{"type":"auth","identity":"local:mallory"}"#;
+        let (status, body) = send(&router, "POST", "/auth", None, auth).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn websocket_changes_wake_polls() {
+        let (handle, router) = serve(Duration::from_secs(5)).await;
+        let token = sign_in(&router, "local:alice").await;
+        let (seen, _) = poll(&router, &token, None).await;
+
+        let waiting = tokio::spawn({
+            let (router, token) = (router.clone(), token.clone());
+            async move { poll(&router, &token, Some(seen)).await }
+        });
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let auth = r#"{"type":"auth","identity":"local:bob"}"#;
+        ws.send(Message::text(auth)).await.unwrap();
+        ws.send(Message::text(r#"{"type":"intent","by":3}"#))
+            .await
+            .unwrap();
+        let mut latest = waiting.await.unwrap();
+        while latest.1 != 3 {
+            latest = poll(&router, &token, Some(latest.0)).await;
+        }
+
+        // Made against a snapshot the room has moved past
+        let intent = |if_seq: u64| {
+            format!(
+                r#"{{"type":"tracked_intent","request_id":1,"require_ack":true,"if_seq":{if_seq},"intent":{{"by":1}}}}"#
+            )
+        };
+        let (status, _) = send(&router, "POST", "/intent", Some(&token), &intent(seen)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, body) =
+            send(&router, "POST", "/intent", Some(&token), &intent(latest.0)).await;
+        assert_eq!(status, StatusCode::OK);
+        let ack: ServerWire<Tallies> = from_json_str(&body).unwrap();
+        assert!(
+            matches!(ack, ServerWire::IntentAck { request_id: 1, .. }),
+            "{body}"
+        );
+        assert_eq!(handle.authority().read().await.total(), 4);
+        handle.shutdown().await.unwrap();
+    }
+}
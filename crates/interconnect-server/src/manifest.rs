@@ -0,0 +1,88 @@
+//! The manifest, encoded once for every connection.
+//!
+//! Every client gets the server's [`Manifest`] first thing, and it rarely
+//! changes. A [`ManifestCache`] keeps the encoded `ServerWire::Manifest`
+//! message, so greeting a connection copies a buffer instead of cloning and
+//! serializing the manifest again. Replace it with
+//! [`set`](ManifestCache::set) when the manifest does change.
+
+use crate::ToWsMessage;
+use interconnect_core::{Manifest, ServerWire, WireEncoding, WireError, to_json_string};
+use std::sync::Arc;
+use tokio_tungstenite::tungstenite::Message;
+
+/// A manifest and its encoded `ServerWire::Manifest` message.
+#[derive(Debug, Clone)]
+pub struct ManifestCache {
+    manifest: Arc<Manifest>,
+    json: Arc<str>,
+}
+
+impl ManifestCache {
+    /// Encode `manifest` for sending.
+    pub fn new(manifest: Manifest) -> Result<Self, WireError> {
+        // The message carries no snapshot, so any snapshot type encodes it
+        let msg: ServerWire<()> = ServerWire::Manifest(Box::new(manifest.clone()));
+        Ok(Self {
+            manifest: Arc::new(manifest),
+            json: to_json_string(&msg)?.into(),
+        })
+    }
+
+    /// Replace the manifest, encoding the new one.
+    pub fn set(&mut self, manifest: Manifest) -> Result<(), WireError> {
+        *self = Self::new(manifest)?;
+        Ok(())
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The encoded message, as JSON.
+    pub fn json(&self) -> &Arc<str> {
+        &self.json
+    }
+}
+
+impl ToWsMessage for ManifestCache {
+    fn to_ws_message(&self, encoding: WireEncoding) -> Result<Message, WireError> {
+        match encoding {
+            WireEncoding::Json => Ok(Message::Text(self.json.as_ref().into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::{Identity, ServerName};
+
+    fn manifest(name: &str) -> Manifest {
+        Manifest {
+            identity: Identity::local(name),
+            name: ServerName::new(name),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn cached_message_matches_a_fresh_encode() {
+        let mut cache = ManifestCache::new(manifest("alpha")).unwrap();
+        let fresh: ServerWire<u32> = ServerWire::Manifest(manifest("alpha").into());
+        assert_eq!(
+            cache.to_ws_message(WireEncoding::Json).unwrap(),
+            fresh.to_ws_message(WireEncoding::Json).unwrap()
+        );
+
+        cache.set(manifest("beta")).unwrap();
+        assert_eq!(cache.manifest().name, ServerName::new("beta"));
+        assert!(cache.json().contains("beta"));
+    }
+}
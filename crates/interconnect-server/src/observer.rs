@@ -0,0 +1,122 @@
+//! Observation hooks for logging and metrics.
+
+use crate::BatchStats;
+use interconnect_core::{RecoveryAttempt, Session};
+use serde_json::Value;
+use std::time::Duration;
+
+/// Observes transport activity.
+///
+/// All methods default to no-ops; implement the ones you need.
+pub trait Observer: Send + Sync {
+    /// Called after an intent has been handled.
+    ///
+    /// `intent_type` comes from [`Authority::intent_type_name`], so it is
+    /// suitable as a metrics label.
+    ///
+    /// [`Authority::intent_type_name`]: interconnect_core::Authority::intent_type_name
+    fn on_intent_handled(
+        &self,
+        _session: &Session,
+        _intent_type: &'static str,
+        _elapsed: Duration,
+        _succeeded: bool,
+    ) {
+    }
+
+    /// Called when an intent arrives, before it is handled.
+    ///
+    /// `payload` is the authority's [`Redactor`] projection of the intent
+    /// (see [`Authority::redacted_intent_to_json`]), never the intent
+    /// itself, so it is safe to audit or log.
+    ///
+    /// [`Redactor`]: interconnect_core::Redactor
+    /// [`Authority::redacted_intent_to_json`]: interconnect_core::Authority::redacted_intent_to_json
+    fn on_intent_received(&self, _session: &Session, _intent_type: &'static str, _payload: &Value) {
+    }
+
+    /// Called after several broadcast messages went to a session as one
+    /// frame (see [`FrameBatcher`](crate::FrameBatcher)).
+    fn on_batch_sent(&self, _session: &Session, _stats: BatchStats) {}
+
+    /// Called when a snapshot is much larger than the session's recent ones
+    /// (see [`SizeTracker`](crate::SizeTracker)), which usually means a bug
+    /// such as recursive state or an unbounded list.
+    fn on_snapshot_size_spike(
+        &self,
+        _session: &Session,
+        _current_bytes: usize,
+        _rolling_avg_bytes: usize,
+    ) {
+    }
+
+    /// Called when the authority takes intents again after pausing itself
+    /// (see [`Authority::on_authority_recovered`]). The error is given as
+    /// text.
+    ///
+    /// [`Authority::on_authority_recovered`]: interconnect_core::Authority::on_authority_recovered
+    fn on_authority_recovered(&self, _attempt: &RecoveryAttempt<String>) {}
+}
+
+impl std::fmt::Debug for dyn Observer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+/// An observer that logs through `tracing`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingObserver;
+
+impl Observer for LoggingObserver {
+    fn on_intent_handled(
+        &self,
+        session: &Session,
+        intent_type: &'static str,
+        elapsed: Duration,
+        succeeded: bool,
+    ) {
+        tracing::debug!(
+            session = session.id,
+            intent_type,
+            elapsed_us = elapsed.as_micros() as u64,
+            succeeded,
+            "intent handled"
+        );
+    }
+
+    fn on_intent_received(&self, session: &Session, intent_type: &'static str, payload: &Value) {
+        tracing::trace!(session = session.id, intent_type, %payload, "intent received");
+    }
+
+    fn on_batch_sent(&self, session: &Session, stats: BatchStats) {
+        tracing::trace!(
+            session = session.id,
+            count = stats.count,
+            bytes_saved = stats.bytes_saved,
+            "batch sent"
+        );
+    }
+
+    fn on_snapshot_size_spike(
+        &self,
+        session: &Session,
+        current_bytes: usize,
+        rolling_avg_bytes: usize,
+    ) {
+        tracing::warn!(
+            session = session.id,
+            current_bytes,
+            rolling_avg_bytes,
+            "snapshot size spike"
+        );
+    }
+
+    fn on_authority_recovered(&self, attempt: &RecoveryAttempt<String>) {
+        tracing::info!(
+            paused_ms = attempt.paused_for.as_millis() as u64,
+            error = %attempt.error,
+            "authority recovered"
+        );
+    }
+}
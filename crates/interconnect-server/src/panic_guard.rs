@@ -0,0 +1,122 @@
+//! Containing authority panics.
+//!
+//! A panic in `handle_intent` unwinds through the connection task holding
+//! the room's lock. [`PanicGuard`] catches it at the call, so the lock is
+//! released normally (never poisoned) and the [`PanicPolicy`] decides what
+//! happens next.
+
+use std::any::Any;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+/// What to do when an authority call panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// End the session that sent the request; the room carries on.
+    #[default]
+    KillSession,
+    /// Abort the process. For authorities whose state can't be trusted
+    /// after a panic.
+    KillServer,
+    /// Report the failure to the session and carry on.
+    LogAndContinue,
+}
+
+/// An authority call panicked and the policy let the server carry on.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("authority panicked: {message}")]
+pub struct AuthorityPanic {
+    /// The panic message, if it had one.
+    pub message: String,
+    /// The policy in force.
+    pub policy: PanicPolicy,
+}
+
+impl AuthorityPanic {
+    /// Whether the transport should close the session.
+    pub fn ends_session(&self) -> bool {
+        self.policy == PanicPolicy::KillSession
+    }
+}
+
+/// Runs authority calls under a [`PanicPolicy`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PanicGuard {
+    policy: PanicPolicy,
+}
+
+impl PanicGuard {
+    pub fn new(policy: PanicPolicy) -> Self {
+        Self { policy }
+    }
+
+    pub fn policy(&self) -> PanicPolicy {
+        self.policy
+    }
+
+    /// Run `f`, catching a panic.
+    ///
+    /// Call it while holding the lock, so the guard drops normally. The
+    /// authority may be mid-update after a panic; `KillServer` exists for
+    /// authorities where that matters.
+    pub fn call<R>(&self, f: impl FnOnce() -> R) -> Result<R, AuthorityPanic> {
+        let payload = match catch_unwind(AssertUnwindSafe(f)) {
+            Ok(result) => return Ok(result),
+            Err(payload) => payload,
+        };
+        let message = panic_message(payload.as_ref());
+        tracing::error!(policy = ?self.policy, "authority panicked: {}", message);
+        if self.policy == PanicPolicy::KillServer {
+            std::process::abort();
+        }
+        Err(AuthorityPanic {
+            message,
+            policy: self.policy,
+        })
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::testing::{Add, TestRoom};
+    use interconnect_core::{Authority, Identity, Session};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn panicking_intents_are_contained() {
+        let room = Arc::new(Mutex::new(TestRoom::new()));
+        let guard = PanicGuard::new(PanicPolicy::KillSession);
+        let session = Session::new(1, Identity::local("alice"), "alice".into());
+
+        let panicked = {
+            let mut room = room.lock().unwrap();
+            guard.call(|| {
+                let add = Add { by: 500 };
+                assert!(add.by < 100, "increment too large");
+                Authority::handle_intent(&mut *room, &session, add)
+            })
+        };
+        let panic = panicked.unwrap_err();
+        assert_eq!(panic.message, "increment too large");
+        assert!(panic.ends_session());
+
+        // The lock isn't poisoned and the room carries on
+        let mut room = room.lock().unwrap();
+        assert!(
+            guard
+                .call(|| Authority::handle_intent(&mut *room, &session, Add { by: 2 }))
+                .is_ok()
+        );
+        assert_eq!(room.total(), 2);
+    }
+}
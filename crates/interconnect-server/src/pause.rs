@@ -0,0 +1,177 @@
+//! Holding snapshots for backgrounded clients.
+//!
+//! A mobile client going to the background sends `ClientWire::Pause` rather
+//! than dropping the connection. [`PauseBuffer`] holds what would have been
+//! sent until `ClientWire::Resume`; if too much piles up, the held
+//! snapshots are dropped and one full snapshot is sent on resume instead.
+//! A [`CoalesceConfig`] can also have a long queue reduced to one snapshot
+//! with [`Authority::coalesce_snapshots`] on resume, so a client that was
+//! away doesn't replay every intermediate state.
+//!
+//! [`Authority::coalesce_snapshots`]: interconnect_core::Authority::coalesce_snapshots
+
+/// What to deliver when a paused session resumes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resumed<T> {
+    /// Send these, in order.
+    Queued(Vec<T>),
+    /// The queue overflowed: send one full snapshot.
+    FullSnapshot,
+}
+
+/// How much a [`PauseBuffer`] holds, and when it coalesces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoalesceConfig {
+    /// Snapshots held before falling back to a full one.
+    pub max_queue_depth: usize,
+    /// Queues at least this long are coalesced into one snapshot on resume.
+    pub coalesce_threshold: usize,
+}
+
+impl CoalesceConfig {
+    /// Hold up to `max_queue_depth` snapshots and never coalesce them.
+    pub fn new(max_queue_depth: usize) -> Self {
+        Self {
+            max_queue_depth,
+            coalesce_threshold: usize::MAX,
+        }
+    }
+
+    /// Coalesce queues of `threshold` snapshots or more.
+    pub fn coalesce_from(mut self, threshold: usize) -> Self {
+        self.coalesce_threshold = threshold;
+        self
+    }
+}
+
+/// Snapshots held for one paused session.
+#[derive(Debug)]
+pub struct PauseBuffer<T> {
+    config: CoalesceConfig,
+    paused: bool,
+    queued: Vec<T>,
+    overflowed: bool,
+}
+
+impl<T> PauseBuffer<T> {
+    /// Hold at most `max_queued` snapshots before falling back to a full one.
+    pub fn new(max_queued: usize) -> Self {
+        Self::with_config(CoalesceConfig::new(max_queued))
+    }
+
+    /// Hold and coalesce as `config` says.
+    pub fn with_config(config: CoalesceConfig) -> Self {
+        Self {
+            config,
+            paused: false,
+            queued: Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Hold a snapshot. Once the queue overflows, further ones are dropped:
+    /// the full snapshot on resume supersedes them.
+    pub fn push(&mut self, snapshot: T) {
+        if self.overflowed {
+            return;
+        }
+        if self.queued.len() >= self.config.max_queue_depth {
+            self.queued.clear();
+            self.overflowed = true;
+            return;
+        }
+        self.queued.push(snapshot);
+    }
+
+    /// Snapshots held so far.
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Stop holding, returning what to deliver.
+    pub fn resume(&mut self) -> Resumed<T> {
+        self.paused = false;
+        if std::mem::take(&mut self.overflowed) {
+            Resumed::FullSnapshot
+        } else {
+            Resumed::Queued(std::mem::take(&mut self.queued))
+        }
+    }
+
+    /// [`resume`](Self::resume), with a queue over the coalescing threshold
+    /// reduced to one snapshot by `coalesce` (usually
+    /// [`Authority::coalesce_snapshots`]). `coalesce` is only called with a
+    /// non-empty queue.
+    ///
+    /// [`Authority::coalesce_snapshots`]: interconnect_core::Authority::coalesce_snapshots
+    pub fn resume_with(&mut self, coalesce: impl FnOnce(Vec<T>) -> Option<T>) -> Resumed<T> {
+        match self.resume() {
+            Resumed::Queued(queued)
+                if !queued.is_empty() && queued.len() >= self.config.coalesce_threshold =>
+            {
+                Resumed::Queued(coalesce(queued).into_iter().collect())
+            }
+            resumed => resumed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_snapshots_are_delivered_on_resume() {
+        let mut buffer = PauseBuffer::new(3);
+        buffer.pause();
+        buffer.push(1);
+        buffer.push(2);
+        assert_eq!(buffer.resume(), Resumed::Queued(vec![1, 2]));
+        assert!(!buffer.is_paused());
+    }
+
+    #[test]
+    fn overflow_falls_back_to_a_full_snapshot() {
+        let mut buffer = PauseBuffer::new(2);
+        buffer.pause();
+        for seq in 0..5 {
+            buffer.push(seq);
+        }
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.resume(), Resumed::FullSnapshot);
+
+        // The next pause starts clean
+        buffer.pause();
+        buffer.push(9);
+        assert_eq!(buffer.resume(), Resumed::Queued(vec![9]));
+    }
+
+    #[test]
+    fn long_queues_are_coalesced_on_resume() {
+        let mut buffer = PauseBuffer::with_config(CoalesceConfig::new(10).coalesce_from(3));
+        let max = |queued: Vec<u32>| queued.into_iter().max();
+
+        buffer.pause();
+        buffer.push(1);
+        buffer.push(2);
+        assert_eq!(buffer.resume_with(max), Resumed::Queued(vec![1, 2]));
+
+        buffer.pause();
+        for seq in [4, 7, 5] {
+            buffer.push(seq);
+        }
+        assert_eq!(buffer.resume_with(max), Resumed::Queued(vec![7]));
+    }
+}
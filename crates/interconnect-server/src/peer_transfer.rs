@@ -0,0 +1,138 @@
+//! Batching of inbound transfers per peer server.
+//!
+//! A peer migrating many users at once produces a burst of `on_transfer_in`
+//! calls. [`PeerTransferBatcher`] groups them by source so the authority gets
+//! one [`on_peer_transfer_complete`] summary per burst.
+//!
+//! [`on_peer_transfer_complete`]: interconnect_core::Authority::on_peer_transfer_complete
+
+use interconnect_core::Identity;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Totals for one batch of transfers from a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerTransferStats {
+    /// The peer server the users came from.
+    pub src_identity: Identity,
+    /// Transfers the authority accepted.
+    pub accepted: u64,
+    /// Transfers the authority refused.
+    pub rejected: u64,
+    /// Time from the first to the last transfer in the batch.
+    pub elapsed: Duration,
+}
+
+struct Batch {
+    opened: Instant,
+    last: Instant,
+    accepted: u64,
+    rejected: u64,
+}
+
+/// Groups inbound transfers by source within a fixed window.
+pub struct PeerTransferBatcher {
+    window: Duration,
+    open: HashMap<Identity, Batch>,
+}
+
+impl PeerTransferBatcher {
+    /// Batch transfers from the same source for `window` after the first.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            open: HashMap::new(),
+        }
+    }
+
+    /// The batch window.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record the outcome of one `on_transfer_in` call.
+    pub fn record(&mut self, src: &Identity, accepted: bool) {
+        self.record_at(src, accepted, Instant::now());
+    }
+
+    fn record_at(&mut self, src: &Identity, accepted: bool, now: Instant) {
+        let batch = self.open.entry(src.clone()).or_insert(Batch {
+            opened: now,
+            last: now,
+            accepted: 0,
+            rejected: 0,
+        });
+        batch.last = now;
+        if accepted {
+            batch.accepted += 1;
+        } else {
+            batch.rejected += 1;
+        }
+    }
+
+    /// Close every batch whose window has elapsed and return its totals.
+    pub fn flush_due(&mut self) -> Vec<PeerTransferStats> {
+        self.flush_due_at(Instant::now())
+    }
+
+    fn flush_due_at(&mut self, now: Instant) -> Vec<PeerTransferStats> {
+        let due: Vec<Identity> = self
+            .open
+            .iter()
+            .filter(|(_, batch)| now.duration_since(batch.opened) >= self.window)
+            .map(|(src, _)| src.clone())
+            .collect();
+        due.into_iter()
+            .filter_map(|src| {
+                let batch = self.open.remove(&src)?;
+                Some(PeerTransferStats {
+                    src_identity: src,
+                    accepted: batch.accepted,
+                    rejected: batch.rejected,
+                    elapsed: batch.last.duration_since(batch.opened),
+                })
+            })
+            .collect()
+    }
+
+    /// Number of batches still open.
+    pub fn open_count(&self) -> usize {
+        self.open.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_by_source_until_window_closes() {
+        let mut batcher = PeerTransferBatcher::new(Duration::from_secs(5));
+        let a = Identity::local("server-a");
+        let b = Identity::local("server-b");
+        let now = Instant::now();
+
+        batcher.record_at(&a, true, now);
+        batcher.record_at(&a, false, now + Duration::from_secs(1));
+        batcher.record_at(&a, true, now + Duration::from_secs(2));
+        batcher.record_at(&b, true, now + Duration::from_secs(3));
+
+        assert!(
+            batcher
+                .flush_due_at(now + Duration::from_secs(4))
+                .is_empty()
+        );
+
+        let stats = batcher.flush_due_at(now + Duration::from_secs(5));
+        assert_eq!(
+            stats,
+            [PeerTransferStats {
+                src_identity: a,
+                accepted: 2,
+                rejected: 1,
+                elapsed: Duration::from_secs(2),
+            }]
+        );
+        assert_eq!(batcher.open_count(), 1);
+    }
+}
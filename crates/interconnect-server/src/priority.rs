@@ -0,0 +1,222 @@
+//! Applying intents by priority.
+//!
+//! When several intents wait for the authority at once, the one with the
+//! highest [`IntentPriority`] goes first, and equal priorities go in arrival
+//! order. A flood of low-priority intents then can't hold up an admin's.
+
+use interconnect_core::IntentPriority;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+/// An intent waiting in a [`PriorityIntentQueue`].
+///
+/// Ordered by priority, then earliest `received_at`; the greatest is the
+/// next to apply.
+#[derive(Debug, Clone)]
+pub struct PrioritizedIntent<I> {
+    pub priority: IntentPriority,
+    pub received_at: Instant,
+    pub session_id: u64,
+    pub intent: I,
+}
+
+impl<I> PartialEq for PrioritizedIntent<I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<I> Eq for PrioritizedIntent<I> {}
+
+impl<I> PartialOrd for PrioritizedIntent<I> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<I> Ord for PrioritizedIntent<I> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            // Earlier arrivals first
+            .then_with(|| other.received_at.cmp(&self.received_at))
+    }
+}
+
+/// Intents waiting to be applied, highest priority first.
+#[derive(Debug)]
+pub struct PriorityIntentQueue<I> {
+    heap: BinaryHeap<PrioritizedIntent<I>>,
+}
+
+impl<I> Default for PriorityIntentQueue<I> {
+    fn default() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+        }
+    }
+}
+
+impl<I> PriorityIntentQueue<I> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue an intent that arrived now.
+    pub fn push(&mut self, session_id: u64, priority: IntentPriority, intent: I) {
+        self.heap.push(PrioritizedIntent {
+            priority,
+            received_at: Instant::now(),
+            session_id,
+            intent,
+        });
+    }
+
+    /// The next intent to apply.
+    pub fn pop(&mut self) -> Option<PrioritizedIntent<I>> {
+        self.heap.pop()
+    }
+
+    /// The next intent to apply, left in the queue.
+    pub fn peek(&self) -> Option<&PrioritizedIntent<I>> {
+        self.heap.peek()
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+/// Hands out turns at the authority, one at a time, by priority.
+///
+/// Connections apply intents themselves; a connection holds a [`Turn`]
+/// while it does, and the others queue for the next one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IntentTurns {
+    inner: Arc<Mutex<Turns>>,
+}
+
+#[derive(Debug, Default)]
+struct Turns {
+    busy: bool,
+    waiting: PriorityIntentQueue<oneshot::Sender<()>>,
+}
+
+impl IntentTurns {
+    /// Wait for this session's turn.
+    pub(crate) async fn take(&self, session_id: u64, priority: IntentPriority) -> Turn {
+        let rx = {
+            let mut turns = self.inner.lock().unwrap();
+            if !turns.busy {
+                turns.busy = true;
+                return Turn {
+                    turns: self.inner.clone(),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            turns.waiting.push(session_id, priority, tx);
+            rx
+        };
+        let mut waiting = Waiting {
+            rx,
+            turns: self.inner.clone(),
+        };
+        // The sender lives in the queue until it's our turn
+        let _ = (&mut waiting.rx).await;
+        Turn {
+            turns: self.inner.clone(),
+        }
+    }
+}
+
+/// Hand the turn to the next live waiter, or free it.
+fn pass_on(turns: &Mutex<Turns>) {
+    let mut turns = turns.lock().unwrap();
+    // Skip waiters whose connection has gone
+    while let Some(next) = turns.waiting.pop() {
+        if next.intent.send(()).is_ok() {
+            return;
+        }
+    }
+    turns.busy = false;
+}
+
+/// A queued [`IntentTurns::take`].
+struct Waiting {
+    rx: oneshot::Receiver<()>,
+    turns: Arc<Mutex<Turns>>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        // Cancelled after being handed the turn, but before taking it
+        if self.rx.try_recv().is_ok() {
+            pass_on(&self.turns);
+        }
+    }
+}
+
+/// A session's turn at the authority; the next starts when it drops.
+#[derive(Debug)]
+pub(crate) struct Turn {
+    turns: Arc<Mutex<Turns>>,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        pass_on(&self.turns);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn higher_priorities_first_then_arrival_order() {
+        let mut queue = PriorityIntentQueue::new();
+        queue.push(1, IntentPriority::Low, "flood 1");
+        queue.push(2, IntentPriority::Normal, "chat");
+        queue.push(1, IntentPriority::Low, "flood 2");
+        queue.push(3, IntentPriority::Critical, "kick");
+        queue.push(4, IntentPriority::Normal, "move");
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|p| p.intent)
+            .collect();
+        assert_eq!(order, ["kick", "chat", "move", "flood 1", "flood 2"]);
+    }
+
+    #[tokio::test]
+    async fn turns_go_to_the_highest_priority_waiter() {
+        let turns = IntentTurns::default();
+        let first = turns.take(1, IntentPriority::Normal).await;
+
+        let (order_tx, mut order) = tokio::sync::mpsc::unbounded_channel();
+        let mut waiters = Vec::new();
+        for (session, priority) in [(2, IntentPriority::Low), (3, IntentPriority::Critical)] {
+            let turns = turns.clone();
+            let order_tx = order_tx.clone();
+            waiters.push(tokio::spawn(async move {
+                let _turn = turns.take(session, priority).await;
+                order_tx.send(session).unwrap();
+            }));
+            // Let it queue before the next one
+            tokio::task::yield_now().await;
+        }
+
+        drop(first);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(order.recv().await, Some(3));
+        assert_eq!(order.recv().await, Some(2));
+    }
+}
@@ -0,0 +1,245 @@
+//! Reconnect grace: hold disconnected sessions so flaky clients can resume.
+//!
+//! On connect the transport issues a resume token. When the connection drops,
+//! it [`hold`](ResumeStore::hold)s the session instead of calling
+//! `on_disconnect`. If the client reconnects with `ClientWire::ResumeSession`
+//! inside the window, the session is reattached as if nothing happened (no
+//! leave/join). Otherwise the transport [`finalize`](ResumeStore::finalize)s
+//! the disconnect once the window elapses.
+
+use interconnect_core::Session;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a disconnected session is held for resumption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectGrace {
+    /// The grace window.
+    pub window: Duration,
+}
+
+impl ReconnectGrace {
+    /// Hold sessions for `window` after disconnect.
+    pub const fn new(window: Duration) -> Self {
+        Self { window }
+    }
+}
+
+struct Held {
+    session: Session,
+    deadline: Instant,
+}
+
+/// Resume tokens and sessions held in their grace window.
+pub struct ResumeStore {
+    grace: ReconnectGrace,
+    /// token -> session ID
+    tokens: HashMap<String, u64>,
+    /// session ID -> token
+    by_session: HashMap<u64, String>,
+    held: HashMap<u64, Held>,
+}
+
+impl ResumeStore {
+    /// Create an empty store.
+    pub fn new(grace: ReconnectGrace) -> Self {
+        Self {
+            grace,
+            tokens: HashMap::new(),
+            by_session: HashMap::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// The grace window.
+    pub fn window(&self) -> Duration {
+        self.grace.window
+    }
+
+    /// Issue a resume token for a live session, replacing any previous one.
+    pub fn issue(&mut self, session_id: u64) -> String {
+        self.revoke(session_id);
+        let token = new_token();
+        self.tokens.insert(token.clone(), session_id);
+        self.by_session.insert(session_id, token.clone());
+        token
+    }
+
+    /// Hold a disconnected session for the grace window.
+    ///
+    /// Returns the deadline, or `None` if the session has no token (e.g. it
+    /// was revoked after transferring out) and should disconnect now.
+    pub fn hold(&mut self, session: Session) -> Option<Instant> {
+        self.hold_at(session, Instant::now())
+    }
+
+    fn hold_at(&mut self, session: Session, now: Instant) -> Option<Instant> {
+        if !self.by_session.contains_key(&session.id) {
+            return None;
+        }
+        let deadline = now + self.grace.window;
+        self.held.insert(session.id, Held { session, deadline });
+        Some(deadline)
+    }
+
+    /// Reattach a held session by token.
+    ///
+    /// Returns `None` if the token is unknown, the session isn't held, or
+    /// its window has elapsed.
+    pub fn resume(&mut self, token: &str) -> Option<Session> {
+        self.resume_at(token, Instant::now())
+    }
+
+    fn resume_at(&mut self, token: &str, now: Instant) -> Option<Session> {
+        let id = *self.tokens.get(token)?;
+        if self.held.get(&id)?.deadline <= now {
+            return None;
+        }
+        self.held.remove(&id).map(|held| held.session)
+    }
+
+    /// Finalize a held session whose window has elapsed.
+    ///
+    /// Returns the session if the transport should now call `on_disconnect`.
+    /// Returns `None` if it was resumed, or re-held with a later deadline.
+    pub fn finalize(&mut self, session_id: u64) -> Option<Session> {
+        self.finalize_at(session_id, Instant::now())
+    }
+
+    fn finalize_at(&mut self, session_id: u64, now: Instant) -> Option<Session> {
+        if self.held.get(&session_id)?.deadline > now {
+            return None;
+        }
+        self.revoke(session_id);
+        self.held.remove(&session_id).map(|held| held.session)
+    }
+
+    /// Finalize a held session now, ahead of its window (e.g. to make room
+    /// for its identity's new connection).
+    ///
+    /// Returns the session if it was held; the transport should call
+    /// `on_disconnect`.
+    pub fn release(&mut self, session_id: u64) -> Option<Session> {
+        let held = self.held.remove(&session_id)?;
+        self.revoke(session_id);
+        Some(held.session)
+    }
+
+    /// Whether a session is held for reconnect.
+    pub fn is_held(&self, session_id: u64) -> bool {
+        self.held.contains_key(&session_id)
+    }
+
+    /// Finalize every held session whose window has elapsed.
+    pub fn expire(&mut self) -> Vec<Session> {
+        let now = Instant::now();
+        let due: Vec<u64> = self
+            .held
+            .iter()
+            .filter(|(_, held)| held.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        due.into_iter()
+            .filter_map(|id| self.finalize_at(id, now))
+            .collect()
+    }
+
+    /// Invalidate a session's token (e.g. after it transfers out).
+    pub fn revoke(&mut self, session_id: u64) {
+        if let Some(token) = self.by_session.remove(&session_id) {
+            self.tokens.remove(&token);
+        }
+    }
+
+    /// Number of sessions currently held.
+    pub fn held_count(&self) -> usize {
+        self.held.len()
+    }
+}
+
+/// A random 128-bit token, hex-encoded.
+pub(crate) fn new_token() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("OS random source unavailable");
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::Identity;
+
+    fn session(id: u64) -> Session {
+        Session::new(id, Identity::local("alice"), "alice".into())
+    }
+
+    fn store() -> ResumeStore {
+        ResumeStore::new(ReconnectGrace::new(Duration::from_secs(10)))
+    }
+
+    #[test]
+    fn resume_within_window() {
+        let mut store = store();
+        let token = store.issue(1);
+        let now = Instant::now();
+        store.hold_at(session(1), now).unwrap();
+
+        let resumed = store.resume_at(&token, now + Duration::from_secs(5));
+        assert_eq!(resumed.map(|s| s.id), Some(1));
+        // Resumed sessions aren't finalized when the old timer fires.
+        assert!(
+            store
+                .finalize_at(1, now + Duration::from_secs(10))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn finalize_after_window() {
+        let mut store = store();
+        let token = store.issue(1);
+        let now = Instant::now();
+        store.hold_at(session(1), now).unwrap();
+
+        assert!(store.finalize_at(1, now + Duration::from_secs(5)).is_none());
+        let finalized = store.finalize_at(1, now + Duration::from_secs(10));
+        assert_eq!(finalized.map(|s| s.id), Some(1));
+        assert!(store.resume_at(&token, now).is_none());
+    }
+
+    #[test]
+    fn release_ends_the_hold_early() {
+        let mut store = store();
+        let token = store.issue(1);
+        let now = Instant::now();
+        store.hold_at(session(1), now).unwrap();
+        assert!(store.is_held(1));
+
+        assert_eq!(store.release(1).map(|s| s.id), Some(1));
+        assert!(!store.is_held(1));
+        assert!(store.resume_at(&token, now).is_none());
+        // The old timer finds nothing to finalize
+        assert!(
+            store
+                .finalize_at(1, now + Duration::from_secs(10))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn revoked_sessions_are_not_held() {
+        let mut store = store();
+        store.issue(1);
+        store.revoke(1);
+        assert!(store.hold(session(1)).is_none());
+        assert_eq!(store.held_count(), 0);
+    }
+
+    #[test]
+    fn unknown_token_does_not_resume() {
+        let mut store = store();
+        store.issue(1);
+        store.hold(session(1)).unwrap();
+        assert!(store.resume("not-a-token").is_none());
+    }
+}
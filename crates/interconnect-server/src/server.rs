@@ -0,0 +1,3148 @@
+//! A ready-made WebSocket server for an authority.
+//!
+//! [`spawn_authority`] runs the whole connection lifecycle: accept limiting,
+//! the WebSocket upgrade, `Auth` (including transfers in and resumes),
+//! snapshots after every change, transfers out, and the reconnect grace
+//! window on disconnect. Applications that need more (federation tickets,
+//! custom broadcasts) build their own loop from the pieces in this crate, as
+//! the chat example does.
+//!
+//! Transfers in need [`AuthorityConfig::keyring`]: the `source` manifest a
+//! client relays with its passport is only trusted signed by that key. A
+//! passport may also be a [`Transfer::bundle`], which every member of the
+//! party presents: the first to arrive brings the party in through
+//! [`Authority::on_party_transfer_in`], and the rest take their sessions
+//! when they connect.
+
+use crate::{
+    AcceptLimiter, AuthorityConfig, BanList, DedupCache, DeliveryQueue, IdentityAdmission,
+    IdentitySessions, LoadCounters, ManifestCache, PanicGuard, PauseBuffer, PeerTransferBatcher,
+    ResumeStore, Resumed, SnapshotMeter, SnapshotScheduler, SpectatorRegistry, StaggerConfig,
+    ToWsMessage, TopicCoalescer, YieldBudget, client_version, connect_info, negotiate_encoding,
+    priority::IntentTurns,
+};
+use futures_util::{Sink, SinkExt, StreamExt};
+use interconnect_core::{
+    ADMIN_TOPIC, Authority, AuthorityErrorAction, Capabilities, ClientPrediction, ClientWire,
+    ConnectInfo, ConnectionState, Delivery, DisconnectReason, ErrorCode, ExportedSession, Identity,
+    LARGE_PASSPORT_BYTES, LargePassportAction, LifecycleEvent, LoopbackAction, OptimisticOutcome,
+    PartyImportResult, PassportDecodeAction, RecoveryAttempt, Roster, ServerWire, Session,
+    SessionEncoding, Transfer, TransferSnapshot, WireError, WireErrorAction, from_json_str,
+    split_transfer_snapshot,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, Notify, RwLock, broadcast, watch};
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// How often idle per-address accept state is pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tracked intents remembered for deduplication.
+const APPLIED_INTENTS: usize = 4096;
+
+/// Lifecycle events buffered for slow admin subscribers.
+const ADMIN_EVENTS: usize = 256;
+
+/// Stops a server started with [`spawn_authority`].
+///
+/// Connections are closed and their sessions disconnected at once, without
+/// the reconnect grace window.
+#[derive(Debug, Clone)]
+pub struct GracefulShutdownHandle {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl GracefulShutdownHandle {
+    fn new() -> Self {
+        Self {
+            tx: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Ask the server to shut down.
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<bool> {
+        self.tx.subscribe()
+    }
+}
+
+/// A running server from [`spawn_authority`].
+pub struct AuthorityHandle<A: Authority> {
+    shared: Arc<Shared<A>>,
+    local_addr: SocketAddr,
+    shutdown: GracefulShutdownHandle,
+    task: JoinHandle<Vec<ExportedSession>>,
+}
+
+impl<A: Authority> AuthorityHandle<A> {
+    /// The authority, shared with the connections.
+    pub fn authority(&self) -> &Arc<RwLock<A>> {
+        &self.shared.authority
+    }
+
+    /// Identities refused at connect. Ban, unban, or save it from here;
+    /// sessions already connected stay connected.
+    pub fn bans(&self) -> &Arc<std::sync::Mutex<BanList>> {
+        &self.shared.bans
+    }
+
+    /// Who spectates whom (see [`AuthorityConfig::spectators`]). A change
+    /// shows in each spectator's next snapshot.
+    pub fn spectators(&self) -> &Arc<std::sync::Mutex<SpectatorRegistry>> {
+        &self.shared.config.spectators
+    }
+
+    /// Live counts of snapshot subscribers and intents in flight, cheap
+    /// enough to read on every metrics scrape.
+    pub fn load(&self) -> &Arc<LoadCounters> {
+        &self.shared.load
+    }
+
+    /// The state another transport shares with this server.
+    #[cfg(feature = "long-poll")]
+    pub(crate) fn shared(&self) -> &Arc<Shared<A>> {
+        &self.shared
+    }
+
+    /// The address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn shutdown_handle(&self) -> GracefulShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Shut down and wait until every session has been disconnected.
+    ///
+    /// Returns the sessions the authority exported just before (see
+    /// [`Authority::export_sessions`]), for the next server's
+    /// [`AuthorityConfig::handoff`].
+    pub async fn shutdown(self) -> Result<Vec<ExportedSession>, JoinError> {
+        self.shutdown.shutdown();
+        self.task.await
+    }
+
+    /// Wait for the server to stop (after a shutdown from elsewhere), with
+    /// its exported sessions as from [`shutdown`](Self::shutdown).
+    pub async fn join(self) -> Result<Vec<ExportedSession>, JoinError> {
+        self.task.await
+    }
+}
+
+/// Serve `authority` on `listener` until shut down.
+///
+/// Shutdown comes from the handle, or from the authority itself through
+/// [`AuthorityErrorAction::ShutdownAuthority`]; either way
+/// [`AuthorityHandle::join`] returns once every session has left, and the
+/// process can exit.
+///
+/// Sessions in [`AuthorityConfig::handoff`] are imported before the first
+/// connection is accepted.
+///
+/// Fails if `config` doesn't [validate](AuthorityConfig::validate), if the
+/// authority refuses the handoff, or, with the `intent-schema` feature, if
+/// the authority's [`intent_schema`](Authority::intent_schema) doesn't
+/// compile.
+pub fn spawn_authority<A>(
+    mut authority: A,
+    mut config: AuthorityConfig,
+    listener: TcpListener,
+) -> std::io::Result<AuthorityHandle<A>>
+where
+    A: Authority + Send + Sync + 'static,
+    A::Intent: DeserializeOwned + Send,
+    A::Snapshot: Serialize + DeserializeOwned + Send,
+    A::Passport: Serialize + DeserializeOwned + Send,
+{
+    let local_addr = listener.local_addr()?;
+    config
+        .validate()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    #[cfg(feature = "intent-schema")]
+    let intent_validator = authority
+        .intent_schema()
+        .map(|schema| crate::IntentValidator::new(&schema))
+        .transpose()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    #[cfg(not(feature = "intent-schema"))]
+    if authority.intent_schema().is_some() {
+        tracing::warn!("Intent schema ignored; enable the `intent-schema` feature to check it");
+    }
+    // Known to the authority before anyone connects; their clients claim
+    // them with reconnect tokens
+    let handoff = std::mem::take(&mut config.handoff);
+    let handed_off: HashMap<u64, Session> = handoff
+        .iter()
+        .map(|exported| (exported.session.id, exported.session.clone()))
+        .collect();
+    authority
+        .import_sessions(handoff)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    // Clients check these against their compiled types
+    config.manifest = config.manifest.with_types(&authority);
+    // Signed, so peers can trust it when clients relay it with a transfer
+    if let Some(keyring) = &config.keyring {
+        config.manifest = config.manifest.with_keyring(keyring).sign(keyring);
+    }
+    let manifest = ManifestCache::new(config.manifest.clone())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let limiter = AcceptLimiter::new(config.accept);
+    let peer_transfers = PeerTransferBatcher::new(config.peer_transfer_window.unwrap_or_default());
+    let shutdown = GracefulShutdownHandle::new();
+    let (changes, _) = broadcast::channel(16);
+    let (lifecycle, _) = broadcast::channel(ADMIN_EVENTS);
+    let shared = Arc::new(Shared {
+        authority: Arc::new(RwLock::new(authority)),
+        sessions: Mutex::new(Sessions {
+            next_id: handed_off.keys().max().map_or(1, |id| id + 1),
+            active: 0,
+            resume: ResumeStore::new(config.reconnect),
+            identities: IdentitySessions::new(),
+            evictions: HashMap::new(),
+            applied: DedupCache::new(APPLIED_INTENTS),
+            paused_until: None,
+            closing: Vec::new(),
+            handed_off,
+            arriving: HashMap::new(),
+        }),
+        config,
+        manifest,
+        changes,
+        version: AtomicU64::new(0),
+        limiter,
+        lifecycle,
+        intent_turns: IntentTurns::default(),
+        bans: Arc::new(std::sync::Mutex::new(BanList::new())),
+        load: Arc::new(LoadCounters::new()),
+        recipients: std::sync::Mutex::new(HashMap::new()),
+        #[cfg(feature = "intent-schema")]
+        intent_validator,
+        shutdown: shutdown.clone(),
+        pause: std::sync::Mutex::new(None),
+        paused: Notify::new(),
+        peer_transfers: std::sync::Mutex::new(peer_transfers),
+        transports: std::sync::Mutex::new(JoinSet::new()),
+    });
+    if let Some(stagger) = shared.config.snapshot_stagger {
+        tokio::spawn(stagger_snapshots(shared.clone(), stagger));
+    }
+    if let Some(every) = shared.config.ban_sweep {
+        tokio::spawn(sweep_bans(shared.clone(), every));
+    }
+    if let Some(window) = shared.config.peer_transfer_window {
+        tokio::spawn(summarize_peer_transfers(shared.clone(), window));
+    }
+    tokio::spawn(recover_when_due(shared.clone()));
+    tokio::spawn(expire_handoff(shared.clone()));
+    let task = tokio::spawn(serve(shared.clone(), listener));
+    Ok(AuthorityHandle {
+        shared,
+        local_addr,
+        shutdown,
+        task,
+    })
+}
+
+/// State both transports serve a room from.
+pub(crate) struct Shared<A: Authority> {
+    pub(crate) authority: Arc<RwLock<A>>,
+    pub(crate) sessions: Mutex<Sessions>,
+    pub(crate) config: AuthorityConfig,
+    /// `config.manifest`, encoded once for every connection.
+    manifest: ManifestCache,
+    /// Signals that the authority changed; each connection snapshots for
+    /// itself.
+    pub(crate) changes: broadcast::Sender<()>,
+    /// Counts changes to the authority, bumped under its write lock; what a
+    /// tracked intent's `if_seq` is checked against.
+    pub(crate) version: AtomicU64,
+    /// Refuses floods of new connections from one address.
+    pub(crate) limiter: Arc<AcceptLimiter>,
+    /// Lifecycle events for admin subscribers.
+    lifecycle: broadcast::Sender<LifecycleEvent>,
+    /// Orders intents waiting for the authority by priority.
+    intent_turns: IntentTurns,
+    /// Identities refused at connect (see [`AuthorityHandle::bans`]).
+    bans: Arc<std::sync::Mutex<BanList>>,
+    /// See [`AuthorityHandle::load`].
+    load: Arc<LoadCounters>,
+    /// Connected sessions, woken in turn when snapshots are staggered.
+    recipients: std::sync::Mutex<HashMap<u64, Recipient>>,
+    /// Checks intents against the authority's schema before they're applied.
+    #[cfg(feature = "intent-schema")]
+    intent_validator: Option<crate::IntentValidator>,
+    pub(crate) shutdown: GracefulShutdownHandle,
+    /// The error behind the current pause, for `on_authority_recovered`.
+    pause: std::sync::Mutex<Option<Pause<A::Error>>>,
+    /// Wakes the recovery timer when a pause starts or is extended.
+    paused: Notify,
+    /// Transfers in by source peer, for `on_peer_transfer_complete`.
+    peer_transfers: std::sync::Mutex<PeerTransferBatcher>,
+    /// Tasks of other transports serving the room. On shutdown they move
+    /// their sessions to `Sessions::closing` and end.
+    pub(crate) transports: std::sync::Mutex<JoinSet<()>>,
+}
+
+/// A pause the authority asked for (`AuthorityErrorAction::PauseAuthority`).
+struct Pause<E> {
+    error: E,
+    since: Instant,
+    until: Instant,
+}
+
+/// A connection waiting on the snapshot scheduler.
+struct Recipient {
+    session: Session,
+    due: Arc<Notify>,
+}
+
+impl<A: Authority> Shared<A> {
+    pub(crate) fn emit(&self, event: LifecycleEvent) {
+        // No subscribers is fine
+        let _ = self.lifecycle.send(event);
+    }
+
+    /// Check `identity` against the bans and session limits, making room
+    /// for it if the overflow policy says to, and allot its session an ID.
+    /// Call with the authority's write lock held.
+    pub(crate) async fn admit(
+        &self,
+        authority: &mut A,
+        identity: &Identity,
+    ) -> Result<u64, (ErrorCode, String)> {
+        let banned = self
+            .bans
+            .lock()
+            .unwrap()
+            .find_ban(identity)
+            .map(|ban| ban.reason.clone());
+        if let Some(reason) = banned {
+            tracing::info!("Refused banned {}: {}", identity, reason);
+            return Err((ErrorCode::Banned, format!("Banned: {}", reason)));
+        }
+
+        let mut sessions = self.sessions.lock().await;
+        if self
+            .config
+            .max_sessions
+            .is_some_and(|max| sessions.active >= max)
+        {
+            return Err((ErrorCode::Overloaded, "The server is full".into()));
+        }
+        if let Some(max) = self.config.max_sessions_per_identity {
+            let admission =
+                sessions
+                    .identities
+                    .admit(identity, max, self.config.identity_overflow, |id| {
+                        sessions.resume.is_held(id)
+                    });
+            match admission {
+                IdentityAdmission::Admit => {}
+                IdentityAdmission::Reject => {
+                    return Err((
+                        ErrorCode::TooManyConnections,
+                        format!("{} is already connected too many times", identity),
+                    ));
+                }
+                IdentityAdmission::Evict(old) => {
+                    sessions.identities.remove(identity, old);
+                    match sessions.resume.release(old) {
+                        // Held for reconnect: disconnect it here, its task
+                        // finds it gone
+                        Some(held) => {
+                            sessions.active -= 1;
+                            sessions.evictions.remove(&old);
+                            self.config.spectators.lock().unwrap().forget_session(old);
+                            authority.on_disconnect(&held);
+                            self.emit(LifecycleEvent::Disconnected {
+                                session_id: held.id,
+                                name: held.name.clone(),
+                            });
+                        }
+                        // Connected: its transport disconnects it
+                        None => sessions.evictions.entry(old).or_default().notify_one(),
+                    }
+                }
+            }
+        }
+        sessions.next_id += 1;
+        Ok(sessions.next_id - 1)
+    }
+}
+
+pub(crate) struct Sessions {
+    next_id: u64,
+    /// Sessions connected or held for reconnect.
+    pub(crate) active: usize,
+    resume: ResumeStore,
+    /// Each identity's sessions, for `max_sessions_per_identity`.
+    pub(crate) identities: IdentitySessions,
+    /// Tells a connected session it was evicted to make room for its
+    /// identity's new connection.
+    pub(crate) evictions: HashMap<u64, Arc<Notify>>,
+    pub(crate) applied: DedupCache<(Identity, u64)>,
+    /// Intents are refused until then (see `AuthorityErrorAction::PauseAuthority`).
+    paused_until: Option<Instant>,
+    /// Sessions ended by shutdown, disconnected together once every
+    /// connection has closed.
+    pub(crate) closing: Vec<Session>,
+    /// Sessions imported from the server this one replaced, by ID, until
+    /// their clients reconnect or the reconnect grace runs out.
+    handed_off: HashMap<u64, Session>,
+    /// Members of a party brought in by the first of them to connect, by
+    /// identity, until their own connections arrive or the reconnect grace
+    /// runs out; refused members keep why.
+    arriving: HashMap<Identity, Result<Session, String>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ConnectionError {
+    #[error(transparent)]
+    Ws(#[from] tungstenite::Error),
+    #[error(transparent)]
+    Wire(#[from] WireError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("authority: {0}")]
+    Authority(Box<dyn std::error::Error + Send + Sync>),
+}
+
+async fn serve<A>(shared: Arc<Shared<A>>, listener: TcpListener) -> Vec<ExportedSession>
+where
+    A: Authority + Send + Sync + 'static,
+    A::Intent: DeserializeOwned + Send,
+    A::Snapshot: Serialize + DeserializeOwned + Send,
+    A::Passport: Serialize + DeserializeOwned + Send,
+{
+    let limiter = &shared.limiter;
+    let mut shutdown = shared.shutdown.subscribe();
+    let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+    let mut connections = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            _ = stopped(&mut shutdown) => break,
+            _ = prune.tick() => limiter.prune(),
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            accepted = listener.accept() => {
+                let (stream, addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("Accept failed: {}", e);
+                        continue;
+                    }
+                };
+                // Refuse floods before spending anything on the upgrade
+                let pending = match limiter.admit(addr.ip()) {
+                    Ok(pending) => pending,
+                    Err(e) => {
+                        tracing::debug!("Refused connection from {}: {}", addr, e);
+                        continue;
+                    }
+                };
+                let shared = shared.clone();
+                connections.spawn(async move {
+                    let result = handle_connection(&shared, stream, addr, pending).await;
+                    if let Err(e) = result {
+                        tracing::warn!("Connection error from {}: {}", addr, e);
+                    }
+                });
+            }
+        }
+    }
+
+    // Every connection sees the shutdown and closes, then their sessions
+    // leave together
+    while connections.join_next().await.is_some() {}
+    let mut transports = std::mem::take(&mut *shared.transports.lock().unwrap());
+    while transports.join_next().await.is_some() {}
+    let closing = {
+        let mut sessions = shared.sessions.lock().await;
+        let unclaimed: Vec<Session> = sessions.handed_off.drain().map(|(_, s)| s).collect();
+        let arriving: Vec<Session> = sessions
+            .arriving
+            .drain()
+            .filter_map(|(_, s)| s.ok())
+            .collect();
+        let mut closing = std::mem::take(&mut sessions.closing);
+        closing.extend(unclaimed);
+        closing.extend(arriving);
+        closing
+    };
+    // Taken while the authority still knows everyone, for the next server
+    let mut authority = shared.authority.write().await;
+    let exported = authority.export_sessions();
+    if closing.is_empty() {
+        return exported;
+    }
+    authority.on_disconnect_batch(&closing, DisconnectReason::RoomClosed);
+    drop(authority);
+    for session in &closing {
+        shared.emit(LifecycleEvent::Disconnected {
+            session_id: session.id,
+            name: session.name.clone(),
+        });
+    }
+    tracing::debug!("Closed the room on {} sessions", closing.len());
+    exported
+}
+
+async fn handle_connection<A>(
+    shared: &Arc<Shared<A>>,
+    stream: TcpStream,
+    addr: SocketAddr,
+    pending: crate::PendingConnection,
+) -> Result<(), ConnectionError>
+where
+    A: Authority + Send + Sync + 'static,
+    A::Intent: DeserializeOwned + Send,
+    A::Snapshot: Serialize + DeserializeOwned + Send,
+    A::Passport: Serialize + DeserializeOwned + Send,
+{
+    let mut info = ConnectInfo::new();
+    let mut encoding = SessionEncoding::default();
+    let mut version = None;
+    // The callback's signature is fixed by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let ws = tokio_tungstenite::accept_hdr_async(
+        stream,
+        |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+            info = connect_info(request, &shared.config.hint_headers);
+            version = client_version(request);
+            let (negotiated, protocol) = negotiate_encoding(request);
+            encoding = negotiated;
+            if let Some(value) = protocol.and_then(|p| HeaderValue::from_str(&p).ok()) {
+                response
+                    .headers_mut()
+                    .insert("sec-websocket-protocol", value);
+            }
+            Ok(response)
+        },
+    )
+    .await?;
+    let (mut sink, mut stream) = ws.split();
+    let mut shutdown = shared.shutdown.subscribe();
+    tracing::debug!("New connection from {}", addr);
+    let sync_deadline = shared
+        .config
+        .max_sync_duration
+        .map(|limit| tokio::time::Instant::now() + limit);
+
+    // Wait for auth (or a resume of a held session)
+    let (mut session, delivery) = loop {
+        let msg = tokio::select! {
+            _ = stopped(&mut shutdown) => return Ok(()),
+            _ = deadline(sync_deadline) => {
+                tracing::debug!("Dropped {}: never authenticated", addr);
+                return Ok(());
+            }
+            msg = stream.next() => match msg {
+                Some(msg) => msg?,
+                None => return Ok(()),
+            },
+        };
+        let Message::Text(text) = msg else { continue };
+        let wire: ClientWire<A::Intent> = match from_json_str(&text) {
+            Ok(wire) => wire,
+            Err(e) => {
+                let action = shared
+                    .authority
+                    .write()
+                    .await
+                    .on_wire_error(None, &text, &e);
+                match action {
+                    WireErrorAction::Ignore => tracing::warn!("Invalid message: {}", e),
+                    WireErrorAction::WarnClient { message } => {
+                        let msg: ServerWire<A::Snapshot> =
+                            ServerWire::error(ErrorCode::MalformedMessage, message);
+                        sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                    }
+                    WireErrorAction::Disconnect => return Ok(()),
+                }
+                continue;
+            }
+        };
+
+        match wire {
+            ClientWire::ResumeSession { token, delivery } => {
+                let resumed = shared.sessions.lock().await.resume.resume(&token);
+                if let Some(session) = resumed {
+                    tracing::info!("{} resumed", session.name);
+                    shared.emit(LifecycleEvent::Resumed {
+                        session_id: session.id,
+                        name: session.name.clone(),
+                    });
+                    break (session, delivery);
+                }
+                let msg: ServerWire<A::Snapshot> = ServerWire::error(
+                    ErrorCode::ResumeExpired,
+                    "Session expired; authenticate again",
+                );
+                sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+            }
+            ClientWire::Auth {
+                identity,
+                name,
+                mut passport,
+                source,
+                delivery,
+                reconnect_token,
+                ..
+            } => {
+                let mut authority = shared.authority.write().await;
+                // The client only relays the source's manifest: trust it
+                // only signed by a key the federation shares
+                if passport.is_some() {
+                    let trusted = source.as_deref().filter(|src| {
+                        shared
+                            .config
+                            .keyring
+                            .as_ref()
+                            .is_some_and(|keyring| src.verify(keyring))
+                    });
+                    let refused = match trusted {
+                        None => {
+                            Some("Transfers need a source manifest signed by a trusted key".into())
+                        }
+                        Some(src) if !authority.can_accept_transfer_from(src) => {
+                            Some(format!("Transfers from {} are not accepted", src.name))
+                        }
+                        Some(_) => None,
+                    };
+                    if let Some(message) = refused {
+                        let msg: ServerWire<A::Snapshot> =
+                            ServerWire::error(ErrorCode::SourceBlocked, message);
+                        sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                        return Ok(());
+                    }
+                }
+                if let Err(e) = authority.validate_connect(&identity, &info) {
+                    let msg: ServerWire<A::Snapshot> =
+                        ServerWire::error(ErrorCode::InvalidIdentity, e.to_string());
+                    sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                    continue;
+                }
+                // A signed reconnect token restores the session's name, even
+                // one issued by another instance sharing the key
+                let restored = match (&reconnect_token, &shared.config.keyring) {
+                    (Some(token), Some(keyring)) => {
+                        match keyring.verify_session_token(&*authority, token) {
+                            Some(restored) if restored.identity == identity => Some(restored),
+                            _ => {
+                                let msg: ServerWire<A::Snapshot> = ServerWire::error(
+                                    ErrorCode::ResumeExpired,
+                                    "Invalid or expired reconnect token",
+                                );
+                                sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                                continue;
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                let id = match shared.admit(&mut authority, &identity).await {
+                    Ok(id) => id,
+                    Err((code, message)) => {
+                        let msg: ServerWire<A::Snapshot> = ServerWire::error(code, message);
+                        sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                        return Ok(());
+                    }
+                };
+                // Handed over by the server this one replaced: the authority
+                // already has it, under its old ID
+                let handed_off = match &restored {
+                    Some(restored) if passport.is_none() => shared
+                        .sessions
+                        .lock()
+                        .await
+                        .handed_off
+                        .remove(&restored.id)
+                        .is_some(),
+                    _ => false,
+                };
+                let id = match &restored {
+                    Some(restored) if handed_off => restored.id,
+                    _ => id,
+                };
+                let name = restored
+                    .map(|r| r.name)
+                    .or(name)
+                    .unwrap_or_else(|| identity.display_name());
+                let mut session = Session::new(id, identity, name);
+
+                // Sized up before decoding, so a huge one costs nothing more
+                if let Some(raw) = &mut passport {
+                    let refused = if raw.len() > shared.config.max_passport_bytes {
+                        Some(format!(
+                            "Passport of {} bytes is over the limit of {}",
+                            raw.len(),
+                            shared.config.max_passport_bytes
+                        ))
+                    } else if raw.len() > LARGE_PASSPORT_BYTES {
+                        match authority.on_large_passport(&session, raw.len()) {
+                            LargePassportAction::Accept => None,
+                            LargePassportAction::Reject { reason } => Some(reason),
+                            LargePassportAction::Truncate { max_bytes } => {
+                                raw.truncate(max_bytes);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    if let Some(reason) = refused {
+                        let msg: ServerWire<A::Snapshot> =
+                            ServerWire::error(ErrorCode::PassportTooLarge, reason);
+                        sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                        continue;
+                    }
+                }
+
+                // A party that transferred together: the first member to
+                // arrive brings everyone in, the rest take what was kept
+                let bundle = passport
+                    .as_deref()
+                    .and_then(|raw| serde_json::from_slice::<Transfer>(raw).ok())
+                    .filter(Transfer::is_bundle);
+                let party = match bundle {
+                    Some(bundle) => {
+                        let arrived = shared
+                            .sessions
+                            .lock()
+                            .await
+                            .arriving
+                            .remove(&session.identity);
+                        let joined = match arrived {
+                            Some(Ok(held)) => {
+                                session = held;
+                                Ok(None)
+                            }
+                            Some(Err(reason)) => Ok(Some(reason)),
+                            None => import_party(shared, &mut authority, &session, &bundle).await,
+                        };
+                        if let Ok(Some(reason)) = joined {
+                            let msg: ServerWire<A::Snapshot> =
+                                ServerWire::error(ErrorCode::InvalidPassport, reason);
+                            sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                            continue;
+                        }
+                        Some(joined.map(|_| ()))
+                    }
+                    None => None,
+                };
+
+                let joined = match (party, passport) {
+                    (Some(joined), _) => joined,
+                    (None, Some(raw)) => match decode_passport::<A>(&raw) {
+                        Ok((context, passport)) => {
+                            if let Err(errors) = authority.validate_passport(&session, &passport) {
+                                let errors: Vec<String> =
+                                    errors.iter().map(ToString::to_string).collect();
+                                let msg: ServerWire<A::Snapshot> = ServerWire::error(
+                                    ErrorCode::InvalidPassport,
+                                    format!("Invalid passport: {}", errors.join("; ")),
+                                );
+                                sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                                continue;
+                            }
+                            let joined = authority.on_transfer_in(&session, passport).map(|_| {
+                                if let Some(context) = context {
+                                    authority.on_transfer_context(&session, context);
+                                }
+                            });
+                            if let Some(src) = &source
+                                && shared.config.peer_transfer_window.is_some()
+                            {
+                                shared
+                                    .peer_transfers
+                                    .lock()
+                                    .unwrap()
+                                    .record(&src.identity, joined.is_ok());
+                            }
+                            joined
+                        }
+                        Err(e) => match authority.on_passport_decode_error(&session, &raw, &e) {
+                            PassportDecodeAction::ConnectFresh => {
+                                authority.on_connect_with_info(&session, &info)
+                            }
+                            PassportDecodeAction::Reject => {
+                                let msg: ServerWire<A::Snapshot> = ServerWire::error(
+                                    ErrorCode::ProtocolError,
+                                    format!("Corrupt passport: {}", e),
+                                );
+                                sink.send(msg.to_ws_message(encoding.encoding)?).await?;
+                                continue;
+                            }
+                        },
+                    },
+                    (None, None) if handed_off => Ok(()),
+                    (None, None) => authority.on_connect_with_info(&session, &info),
+                };
+                joined.map_err(|e| ConnectionError::Authority(Box::new(e)))?;
+                shared.version.fetch_add(1, Ordering::Relaxed);
+                // Counted under the authority's lock, so concurrent joins can't overshoot
+                let mut sessions = shared.sessions.lock().await;
+                sessions.active += 1;
+                sessions.identities.insert(&session.identity, session.id);
+                drop(sessions);
+                shared.emit(LifecycleEvent::Connected {
+                    session_id: session.id,
+                    name: session.name.clone(),
+                });
+                break (session, delivery);
+            }
+            _ => {}
+        }
+    };
+    // Both describe this connection, not one a resumed session had before
+    session.encoding = encoding;
+    session.client_version = version;
+    session.enter_state(ConnectionState::Syncing);
+    // Authenticated: no longer counts against the address
+    drop(pending);
+    let _ = shared.changes.send(());
+
+    let capabilities = {
+        let authority = shared.authority.read().await;
+        shared.config.capabilities.resolve(&*authority, &session)
+    };
+    let mut meter = SnapshotMeter::new(shared.config.snapshot_budget);
+    let mut fairness = YieldBudget::new(shared.config.yield_every);
+    let mut coalescer = TopicCoalescer::new(shared.config.snapshot_throttles.clone());
+    // Who this client was last told is present
+    let mut roster = Roster::new();
+    let evicted = shared
+        .sessions
+        .lock()
+        .await
+        .evictions
+        .entry(session.id)
+        .or_default()
+        .clone();
+    let mut changes = shared.changes.subscribe();
+    // With staggering on, the scheduler says when to snapshot instead
+    let due = shared.config.snapshot_stagger.map(|_| {
+        let due = Arc::new(Notify::new());
+        let recipient = Recipient {
+            session: session.clone(),
+            due: due.clone(),
+        };
+        shared
+            .recipients
+            .lock()
+            .unwrap()
+            .insert(session.id, recipient);
+        due
+    });
+    let mut last_intent: Option<Instant> = None;
+    // Snapshots held while the client is paused, and the room's version
+    // when the latest was taken
+    let mut held = PauseBuffer::with_config(shared.config.pause_buffer);
+    let mut held_version = 0u64;
+    let mut subscribed = (delivery == Delivery::Push).then(|| shared.load.subscribe());
+    let mut seq = 0u64;
+    // The room's version when the latest snapshot sent was taken
+    let mut seen = 0u64;
+    // The client's prediction diverged; its next snapshot is a correction
+    let mut correcting = false;
+    let mut admin_events: Option<broadcast::Receiver<LifecycleEvent>> = None;
+    // Leaving by transfer or shutdown skips the grace window
+    let mut hold = true;
+    // Why the session left, when the authority is told more than a close
+    let mut leaving: Option<DisconnectReason> = None;
+
+    // An error ends the connection like a close; the session still leaves below
+    let result: Result<(), ConnectionError> = async {
+        let sync = async {
+            sink.send(shared.manifest.to_ws_message(session.encoding.encoding)?)
+                .await?;
+            let token = shared.sessions.lock().await.resume.issue(session.id);
+            let msg: ServerWire<A::Snapshot> = ServerWire::ResumeToken { token };
+            sink.send(msg.to_ws_message(session.encoding.encoding)?)
+                .await?;
+            // For reconnecting here or to any instance with the key
+            if let Some(keyring) = &shared.config.keyring {
+                let token = keyring.sign_session_token(&*shared.authority.read().await, &session);
+                let msg: ServerWire<A::Snapshot> = ServerWire::ReconnectToken { token };
+                sink.send(msg.to_ws_message(session.encoding.encoding)?)
+                    .await?;
+            }
+            // A live session starts from one full snapshot, seq 0, past any
+            // throttling; everything after builds on it
+            if delivery == Delivery::Push {
+                match snapshot_message(shared, &session, seq, false).await {
+                    Ok((msg, version)) => {
+                        sink.send(msg).await?;
+                        seq += 1;
+                        seen = version;
+                    }
+                    Err(e) => serialization_failed(shared, &session, &mut sink, e).await?,
+                }
+                if let Some(msg) = presence_update(shared, &mut roster).await {
+                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                }
+            }
+            Ok::<_, ConnectionError>(())
+        };
+        let synced = match sync_deadline {
+            Some(at) => tokio::time::timeout_at(at, sync).await.ok(),
+            None => Some(sync.await),
+        };
+        match synced {
+            Some(synced) => synced?,
+            None => {
+                tracing::info!(
+                    "Dropped {}: still syncing after {:?}",
+                    session.name,
+                    session.time_in_state()
+                );
+                leaving = Some(DisconnectReason::SyncTimeout);
+                hold = false;
+                return Ok(());
+            }
+        }
+        session.enter_state(ConnectionState::Live);
+
+        loop {
+            tokio::select! {
+                _ = stopped(&mut shutdown) => {
+                    let msg: ServerWire<A::Snapshot> = ServerWire::RoomClosing { message: "Server shutting down".into() };
+                    if let Ok(msg) = msg.to_ws_message(session.encoding.encoding) {
+                        let _ = sink.send(msg).await;
+                    }
+                    let _ = sink.send(Message::Close(None)).await;
+                    hold = false;
+                    break;
+                }
+
+                _ = evicted.notified() => {
+                    tracing::info!("Evicted {}: signed in elsewhere", session.name);
+                    let msg: ServerWire<A::Snapshot> = ServerWire::error(ErrorCode::Kicked, "Signed in from another connection");
+                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                    let _ = sink.send(Message::Close(None)).await;
+                    hold = false;
+                    break;
+                }
+
+                event = admin_event(&mut admin_events) => {
+                    let msg: ServerWire<A::Snapshot> = ServerWire::Admin { event };
+                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                }
+
+                change = next_change(&mut changes, due.as_deref()) => {
+                    // Lagging only means several changes coalesced
+                    if matches!(change, Err(broadcast::error::RecvError::Closed)) {
+                        break;
+                    }
+                    if delivery == Delivery::Pull {
+                        continue;
+                    }
+                    if held.is_paused() {
+                        let (data, version) = session_snapshot(shared, &session).await;
+                        held.push(data);
+                        held_version = version;
+                        continue;
+                    }
+                    if let Some(msg) = presence_update(shared, &mut roster).await {
+                        sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                    }
+                    let (data, version) = session_snapshot(shared, &session).await;
+                    let topic = A::snapshot_topic(&data);
+                    if !coalescer.admit(topic) {
+                        continue;
+                    }
+                    let msg = match encode_snapshot(shared, &session, data, seq, std::mem::take(&mut correcting)).await {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            serialization_failed(shared, &session, &mut sink, e).await?;
+                            continue;
+                        }
+                    };
+                    coalescer.sent(topic);
+                    seq += 1;
+                    seen = version;
+                    let wait = meter.reserve(msg.len());
+                    if !wait.is_zero() {
+                        shared.authority.write().await.on_budget_exceeded(&session, msg.len(), meter.budget());
+                        tokio::time::sleep(wait).await;
+                    }
+                    sink.send(msg).await?;
+                }
+
+                _ = deadline(coalescer.due().map(tokio::time::Instant::from_std)) => {
+                    // A held-back topic's interval is up; send the latest state
+                    let topic = coalescer.take_held().flatten();
+                    if held.is_paused() {
+                        continue;
+                    }
+                    match snapshot_message(shared, &session, seq, std::mem::take(&mut correcting)).await {
+                        Ok((msg, version)) => {
+                            seq += 1;
+                            seen = version;
+                            sink.send(msg).await?;
+                            coalescer.sent(topic);
+                        }
+                        Err(e) => serialization_failed(shared, &session, &mut sink, e).await?,
+                    }
+                }
+
+                msg = stream.next() => {
+                    let msg = match msg {
+                        Some(Ok(msg)) => msg,
+                        Some(Err(e)) => {
+                            tracing::debug!("WebSocket error: {}", e);
+                            break;
+                        }
+                        None => break,
+                    };
+                    // A burst from this client mustn't hold up other connections
+                    fairness.message_received().await;
+                    let Message::Text(text) = msg else { continue };
+                    let wire: ClientWire<A::Intent> = match from_json_str(&text) {
+                        Ok(wire) => wire,
+                        Err(e) => {
+                            let action = shared.authority.write().await.on_wire_error(Some(&session), &text, &e);
+                            match action {
+                                WireErrorAction::Ignore => tracing::warn!("Invalid message: {}", e),
+                                WireErrorAction::WarnClient { message } => {
+                                    let msg: ServerWire<A::Snapshot> = ServerWire::error(ErrorCode::MalformedMessage, message);
+                                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                }
+                                WireErrorAction::Disconnect => break,
+                            }
+                            continue;
+                        }
+                    };
+
+                    // A tracked intent is applied once; a retry only gets its ack again.
+                    // The outcome of an optimistic one isn't kept, so its retry is
+                    // answered with the current state, which is right either way.
+                    let (wire, tracked, if_seq) = match wire {
+                        ClientWire::TrackedIntent { request_id, require_ack, base_seq, if_seq, intent } => {
+                            let applied = shared.sessions.lock().await.applied.get(&(session.identity.clone(), request_id));
+                            if let Some(acked) = applied {
+                                if base_seq.is_some() {
+                                    match correction_message(shared, &session, request_id, seq).await {
+                                        Ok((msg, version)) => {
+                                            seq += 1;
+                                            seen = version;
+                                            sink.send(msg).await?;
+                                        }
+                                        Err(e) => serialization_failed(shared, &session, &mut sink, e).await?,
+                                    }
+                                } else if require_ack {
+                                    let msg: ServerWire<A::Snapshot> = ServerWire::IntentAck { request_id, seq: acked };
+                                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                }
+                                continue;
+                            }
+                            (ClientWire::Intent(intent), Some((request_id, require_ack, base_seq)), if_seq)
+                        }
+                        wire => (wire, None, None),
+                    };
+
+                    match wire {
+                        ClientWire::Intent(intent) => {
+                            let latest_seq = seq.checked_sub(1);
+                            let call = IntentCall {
+                                intent,
+                                text: &text,
+                                base_seq: tracked.and_then(|(_, _, base_seq)| base_seq),
+                                latest_seq,
+                                if_seq: if_seq.zip(tracked).map(|(if_seq, (request_id, ..))| {
+                                    (request_id, if_seq, (Some(if_seq) == latest_seq).then_some(seen))
+                                }),
+                            };
+                            let outcome = match run_intent(shared, &session, &capabilities, &mut last_intent, call).await {
+                                IntentOutcome::Applied { outcome, .. } => outcome,
+                                IntentOutcome::Refused(code, message) => {
+                                    let msg: ServerWire<A::Snapshot> = ServerWire::error(code, message);
+                                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                    continue;
+                                }
+                                IntentOutcome::Panicked { ends_session } => {
+                                    let msg: ServerWire<A::Snapshot> = ServerWire::error(ErrorCode::InternalError, "The server failed handling that");
+                                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                    if ends_session {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                                IntentOutcome::Failed { code, message, kick } => {
+                                    let msg: ServerWire<A::Snapshot> = ServerWire::error(code, message);
+                                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                    if let Some(reason) = kick {
+                                        let msg: ServerWire<A::Snapshot> = ServerWire::error(ErrorCode::Kicked, reason);
+                                        sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                        let _ = sink.send(Message::Close(None)).await;
+                                        hold = false;
+                                        break;
+                                    }
+                                    // The client applied something that didn't happen
+                                    if let Some((request_id, _, Some(_))) = tracked {
+                                        match correction_message(shared, &session, request_id, seq).await {
+                                            Ok((msg, version)) => {
+                                                seq += 1;
+                                                seen = version;
+                                                sink.send(msg).await?;
+                                            }
+                                            Err(e) => serialization_failed(shared, &session, &mut sink, e).await?,
+                                        }
+                                    }
+                                    continue;
+                                }
+                            };
+                            if let Some((request_id, require_ack, base_seq)) = tracked {
+                                // Our next snapshot is the first to reflect it
+                                shared.sessions.lock().await.applied.insert((session.identity.clone(), request_id), seq);
+                                let msg = match (base_seq, outcome) {
+                                    (Some(_), OptimisticOutcome::Corrected) => {
+                                        match correction_message(shared, &session, request_id, seq).await {
+                                            Ok((msg, version)) => {
+                                                seq += 1;
+                                                seen = version;
+                                                Some(msg)
+                                            }
+                                            Err(e) => {
+                                                serialization_failed(shared, &session, &mut sink, e).await?;
+                                                None
+                                            }
+                                        }
+                                    }
+                                    (Some(_), OptimisticOutcome::Confirmed) => {
+                                        let msg: ServerWire<A::Snapshot> = ServerWire::IntentConfirmed { request_id, seq };
+                                        Some(msg.to_ws_message(session.encoding.encoding)?)
+                                    }
+                                    (None, _) if require_ack => {
+                                        let msg: ServerWire<A::Snapshot> = ServerWire::IntentAck { request_id, seq };
+                                        Some(msg.to_ws_message(session.encoding.encoding)?)
+                                    }
+                                    (None, _) => None,
+                                };
+                                if let Some(msg) = msg {
+                                    sink.send(msg).await?;
+                                    shared.authority.write().await.on_intent_ack(&session, request_id);
+                                }
+                            }
+                        }
+
+                        ClientWire::TransferRequest { mut destination } => {
+                            if !session.state.can_transfer() {
+                                let msg: ServerWire<A::Snapshot> = ServerWire::error(
+                                    ErrorCode::ProtocolError,
+                                    format!("Transfers need a live session, not a {:?} one", session.state),
+                                );
+                                sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                continue;
+                            }
+                            if !capabilities.transfer {
+                                let msg: ServerWire<A::Snapshot> = ServerWire::error(ErrorCode::TransferForbidden, "This session can't transfer");
+                                sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                continue;
+                            }
+                            // Carried out, it would only reconnect the client here
+                            if shared.config.manifest.is_endpoint(&destination) {
+                                let action = shared.authority.write().await.on_transfer_loopback(&session, &destination);
+                                match action {
+                                    LoopbackAction::Allow => {}
+                                    LoopbackAction::Reject => {
+                                        let msg: ServerWire<A::Snapshot> = ServerWire::error(ErrorCode::TransferLoopback, format!("Already connected to {}", destination));
+                                        sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                        continue;
+                                    }
+                                    LoopbackAction::Redirect { url } => destination = url,
+                                }
+                            }
+                            // A room the authority hosts itself: the session moves without reconnecting
+                            let moved = {
+                                let mut authority = shared.authority.write().await;
+                                let moved = authority.on_local_transfer(&session, &destination);
+                                if matches!(moved, Some(Ok(_))) {
+                                    shared.version.fetch_add(1, Ordering::Relaxed);
+                                }
+                                moved
+                            };
+                            if let Some(moved) = moved {
+                                let msg: ServerWire<A::Snapshot> = match moved {
+                                    Ok(moved) => {
+                                        tracing::info!("{} moved to {}", session.name, destination);
+                                        let _ = shared.changes.send(());
+                                        ServerWire::LocalTransferComplete { destination, session_id: moved.new_session_id }
+                                    }
+                                    Err(e) => ServerWire::error(ErrorCode::TransferFailed, format!("Transfer to {} failed: {}", destination, e)),
+                                };
+                                sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                continue;
+                            }
+                            // Sized up before it's emitted, so an oversized one never goes out
+                            let transfer = {
+                                let authority = shared.authority.read().await;
+                                authority.validate_destination(&destination).then(|| {
+                                    let estimate = authority.passport_size_estimate(&session);
+                                    if estimate > shared.config.max_passport_bytes {
+                                        return Err(estimate);
+                                    }
+                                    if estimate > LARGE_PASSPORT_BYTES {
+                                        tracing::warn!("Passport for {} is ~{} bytes", session.name, estimate);
+                                    }
+                                    Ok(authority.emit_transfer_snapshot(&session, &destination))
+                                })
+                            };
+                            let transfer = match transfer {
+                                Some(Ok(transfer)) => transfer,
+                                Some(Err(estimate)) => {
+                                    let msg: ServerWire<A::Snapshot> = ServerWire::error(
+                                        ErrorCode::PassportTooLarge,
+                                        format!("Passport of ~{} bytes is over the limit of {}", estimate, shared.config.max_passport_bytes),
+                                    );
+                                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                    continue;
+                                }
+                                None => {
+                                    let msg: ServerWire<A::Snapshot> = ServerWire::error(ErrorCode::InvalidDestination, format!("Unknown destination: {}", destination));
+                                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                    continue;
+                                }
+                            };
+                            let passport = serde_json::to_vec(&transfer)?;
+                            let msg: ServerWire<A::Snapshot> = ServerWire::Transfer { destination: destination.clone(), passport };
+                            // Nothing may follow the directive: the loop ends
+                            // here, so no snapshot can trail it
+                            leaving = Some(DisconnectReason::Transferred);
+                            sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                            let _ = sink.send(Message::Close(None)).await;
+                            tracing::info!("{} transferred out", session.name);
+                            shared.emit(LifecycleEvent::TransferredOut {
+                                session_id: session.id,
+                                name: session.name.clone(),
+                                destination,
+                            });
+                            hold = false;
+                            break;
+                        }
+
+                        ClientWire::Resync => {
+                            match snapshot_message(shared, &session, seq, std::mem::take(&mut correcting)).await {
+                                Ok((msg, version)) => {
+                                    seq += 1;
+                                    seen = version;
+                                    sink.send(msg).await?;
+                                }
+                                Err(e) => serialization_failed(shared, &session, &mut sink, e).await?,
+                            }
+                        }
+
+                        ClientWire::Pause => {
+                            held.pause();
+                            session.enter_state(ConnectionState::Paused);
+                            subscribed = None;
+                            shared.authority.write().await.on_session_paused(&session);
+                        }
+
+                        ClientWire::Resume => {
+                            session.enter_state(ConnectionState::Live);
+                            if delivery == Delivery::Push {
+                                subscribed.get_or_insert_with(|| shared.load.subscribe());
+                            }
+                            shared.authority.write().await.on_session_resumed(&session);
+                            let resumed = {
+                                let authority = shared.authority.read().await;
+                                held.resume_with(|queued| authority.coalesce_snapshots(queued))
+                            };
+                            match resumed {
+                                Resumed::Queued(queued) if !queued.is_empty() => {
+                                    let last = queued.len() - 1;
+                                    for (i, data) in queued.into_iter().enumerate() {
+                                        match encode_snapshot(shared, &session, data, seq, std::mem::take(&mut correcting)).await {
+                                            Ok(msg) => {
+                                                seq += 1;
+                                                if i == last {
+                                                    seen = held_version;
+                                                }
+                                                sink.send(msg).await?;
+                                            }
+                                            Err(e) => serialization_failed(shared, &session, &mut sink, e).await?,
+                                        }
+                                    }
+                                }
+                                // Overflowed, or nothing changed meanwhile: the current state
+                                _ => match snapshot_message(shared, &session, seq, std::mem::take(&mut correcting)).await {
+                                    Ok((msg, version)) => {
+                                        seq += 1;
+                                        seen = version;
+                                        sink.send(msg).await?;
+                                    }
+                                    Err(e) => serialization_failed(shared, &session, &mut sink, e).await?,
+                                },
+                            }
+                        }
+
+                        ClientWire::Query { id, query, cursor } => {
+                            let page = shared.authority.read().await.query_page(&session, &query, cursor.as_deref());
+                            let msg: ServerWire<A::Snapshot> = match page {
+                                Ok(page) => ServerWire::query_page(id, page),
+                                Err(e) => ServerWire::error(ErrorCode::InvalidQuery, format!("Query {}: {}", id, e)),
+                            };
+                            sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                        }
+
+                        ClientWire::Ack { seq: acked, confirmed_seq, prediction_horizon } => {
+                            session.prediction = ClientPrediction { confirmed_seq, horizon: prediction_horizon };
+                            correcting |= session.prediction.diverged(acked);
+                        }
+
+                        ClientWire::Ping => {
+                            let msg: ServerWire<A::Snapshot> = ServerWire::Pong;
+                            sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                        }
+
+                        ClientWire::Subscribe { topics } => {
+                            for topic in topics {
+                                let refused = if topic != ADMIN_TOPIC {
+                                    Some(ServerWire::error(ErrorCode::ProtocolError, format!("Unknown topic: {}", topic)))
+                                } else if !capabilities.admin {
+                                    Some(ServerWire::error(ErrorCode::SubscriptionForbidden, "Only admins may subscribe to admin events"))
+                                } else {
+                                    None
+                                };
+                                if let Some(msg) = refused {
+                                    let msg: ServerWire<A::Snapshot> = msg;
+                                    sink.send(msg.to_ws_message(session.encoding.encoding)?).await?;
+                                    continue;
+                                }
+                                admin_events.get_or_insert_with(|| shared.lifecycle.subscribe());
+                            }
+                        }
+
+                        ClientWire::TypeMismatch { expected_snapshot, got, expected_intent, got_intent } => {
+                            // Another app's client reached this server: a deployment problem, not a client bug
+                            tracing::error!(
+                                "Configuration error: {} expects snapshot type {} and intent type {}, server has {} and {}",
+                                addr, got, got_intent, expected_snapshot, expected_intent
+                            );
+                        }
+
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+    // Held or gone, the session gets no more snapshots
+    drop(subscribed);
+    if due.is_some() {
+        shared.recipients.lock().unwrap().remove(&session.id);
+    }
+
+    // Hold the session for the grace window, then finalize the disconnect
+    let mut sessions = shared.sessions.lock().await;
+    if !hold {
+        sessions.resume.revoke(session.id);
+        drop(sessions);
+    } else if sessions.resume.hold(session.clone()).is_some() {
+        drop(sessions);
+        tokio::select! {
+            _ = stopped(&mut shutdown) => {}
+            _ = tokio::time::sleep(shared.config.reconnect.window) => {}
+        }
+        // Resumed on another connection meanwhile
+        if shared
+            .sessions
+            .lock()
+            .await
+            .resume
+            .finalize(session.id)
+            .is_none()
+        {
+            return result;
+        }
+    } else {
+        drop(sessions);
+    }
+    let mut sessions = shared.sessions.lock().await;
+    sessions.active -= 1;
+    sessions.identities.remove(&session.identity, session.id);
+    sessions.evictions.remove(&session.id);
+    shared
+        .config
+        .spectators
+        .lock()
+        .unwrap()
+        .forget_session(session.id);
+    if shared.shutdown.is_shutdown() && leaving.is_none() {
+        // Left with the rest in `serve`, sparing the others a snapshot each
+        sessions.closing.push(session);
+        tracing::debug!("Connection closed: {}", addr);
+        return result;
+    }
+    drop(sessions);
+    let mut authority = shared.authority.write().await;
+    match leaving {
+        Some(reason) => authority.on_disconnect_batch(std::slice::from_ref(&session), reason),
+        None => authority.on_disconnect(&session),
+    }
+    shared.version.fetch_add(1, Ordering::Relaxed);
+    drop(authority);
+    shared.emit(LifecycleEvent::Disconnected {
+        session_id: session.id,
+        name: session.name.clone(),
+    });
+    let _ = shared.changes.send(());
+    tracing::debug!("Connection closed: {}", addr);
+    result
+}
+
+/// The next change to snapshot for: as the authority signals it, or when
+/// the scheduler says so.
+async fn next_change(
+    changes: &mut broadcast::Receiver<()>,
+    due: Option<&Notify>,
+) -> Result<(), broadcast::error::RecvError> {
+    match due {
+        Some(due) => {
+            due.notified().await;
+            Ok(())
+        }
+        None => changes.recv().await,
+    }
+}
+
+/// Spread each change's snapshots over the stagger window, waking each
+/// connection when its turn comes. Runs until shutdown.
+async fn stagger_snapshots<A: Authority>(shared: Arc<Shared<A>>, stagger: StaggerConfig) {
+    let scheduler = SnapshotScheduler::new(stagger);
+    let mut queue = DeliveryQueue::new();
+    let mut changes = shared.changes.subscribe();
+    let mut shutdown = shared.shutdown.subscribe();
+    loop {
+        let next_due = queue.next_due();
+        tokio::select! {
+            _ = stopped(&mut shutdown) => break,
+
+            change = changes.recv() => {
+                if matches!(change, Err(broadcast::error::RecvError::Closed)) {
+                    break;
+                }
+                let recipients = shared.recipients.lock().unwrap();
+                let sessions: Vec<&Session> = recipients.values().map(|r| &r.session).collect();
+                for (session, at) in scheduler.stagger_snapshot_delivery(&sessions, stagger.window) {
+                    queue.schedule(session.id, at);
+                }
+            }
+
+            _ = sleep_until(next_due) => {
+                let recipients = shared.recipients.lock().unwrap();
+                for session_id in queue.pop_due(Instant::now()) {
+                    // Gone since it was scheduled
+                    if let Some(recipient) = recipients.get(&session_id) {
+                        recipient.due.notify_one();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Drop expired bans every `every` until shutdown.
+async fn sweep_bans<A: Authority>(shared: Arc<Shared<A>>, every: Duration) {
+    let mut shutdown = shared.shutdown.subscribe();
+    let mut ticks = tokio::time::interval(every);
+    loop {
+        tokio::select! {
+            _ = stopped(&mut shutdown) => break,
+            _ = ticks.tick() => shared.bans.lock().unwrap().expire_bans(),
+        }
+    }
+}
+
+/// Tell the authority about each peer's transfers in once their window has
+/// closed, until shutdown.
+async fn summarize_peer_transfers<A: Authority>(shared: Arc<Shared<A>>, window: Duration) {
+    let mut shutdown = shared.shutdown.subscribe();
+    let mut ticks = tokio::time::interval(window);
+    loop {
+        tokio::select! {
+            _ = stopped(&mut shutdown) => break,
+            _ = ticks.tick() => {
+                let due = shared.peer_transfers.lock().unwrap().flush_due();
+                if due.is_empty() {
+                    continue;
+                }
+                let mut authority = shared.authority.write().await;
+                for stats in due {
+                    authority.on_peer_transfer_complete(
+                        &stats.src_identity,
+                        stats.accepted,
+                        stats.rejected,
+                        stats.elapsed,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Tell the authority each pause it asked for is over as it ends, whether
+/// or not an intent comes along.
+async fn recover_when_due<A: Authority>(shared: Arc<Shared<A>>) {
+    let mut shutdown = shared.shutdown.subscribe();
+    loop {
+        let until = shared
+            .pause
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|pause| pause.until);
+        tokio::select! {
+            _ = stopped(&mut shutdown) => break,
+            // A new or longer pause starts the wait over
+            _ = shared.paused.notified() => {}
+            _ = sleep_until(until) => recover(&shared, Instant::now()).await,
+        }
+    }
+}
+
+/// Sleep until `at`, or forever without one.
+async fn sleep_until(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The next event for an admin subscription. Never resolves for a session
+/// that hasn't subscribed.
+async fn admin_event(events: &mut Option<broadcast::Receiver<LifecycleEvent>>) -> LifecycleEvent {
+    if let Some(events) = events {
+        loop {
+            match events.recv().await {
+                Ok(event) => return event,
+                // A slow admin misses events rather than holding up the server
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    std::future::pending().await
+}
+
+/// Disconnect handed-over sessions no client claimed within the reconnect
+/// grace. Those left at shutdown close with the room instead.
+async fn expire_handoff<A: Authority>(shared: Arc<Shared<A>>) {
+    let mut shutdown = shared.shutdown.subscribe();
+    tokio::select! {
+        _ = stopped(&mut shutdown) => return,
+        _ = tokio::time::sleep(shared.config.reconnect.window) => {}
+    }
+    let unclaimed: Vec<Session> = {
+        let mut sessions = shared.sessions.lock().await;
+        sessions.handed_off.drain().map(|(_, s)| s).collect()
+    };
+    if unclaimed.is_empty() {
+        return;
+    }
+    shared
+        .authority
+        .write()
+        .await
+        .on_disconnect_batch(&unclaimed, DisconnectReason::GraceExpired);
+    shared.version.fetch_add(1, Ordering::Relaxed);
+    for session in &unclaimed {
+        shared.emit(LifecycleEvent::Disconnected {
+            session_id: session.id,
+            name: session.name.clone(),
+        });
+    }
+    let _ = shared.changes.send(());
+}
+
+/// Bring in a party that transferred together (see [`Transfer::bundle`])
+/// as `session`, the first member to connect, arrives. The others are kept
+/// for their own connections until the reconnect grace runs out.
+///
+/// Returns why `session` itself was refused, if it was.
+async fn import_party<A>(
+    shared: &Arc<Shared<A>>,
+    authority: &mut A,
+    session: &Session,
+    bundle: &Transfer,
+) -> Result<Option<String>, A::Error>
+where
+    A: Authority + Send + Sync + 'static,
+    A::Snapshot: DeserializeOwned,
+    A::Passport: DeserializeOwned,
+{
+    if !bundle.passports().any(|p| p.identity == session.identity) {
+        return Ok(Some(format!("{} is not in this party", session.identity)));
+    }
+    let mut sessions = shared.sessions.lock().await;
+    let mut members = Vec::new();
+    let mut contexts = Vec::new();
+    let mut passports = Vec::new();
+    let mut refused: Vec<(Session, String)> = Vec::new();
+    for raw in bundle.passports() {
+        let member = if raw.identity == session.identity {
+            session.clone()
+        } else {
+            sessions.next_id += 1;
+            let id = sessions.next_id - 1;
+            Session::new(id, raw.identity.clone(), raw.identity.display_name())
+        };
+        let decoded = decode_passport::<A>(&raw.data)
+            .map_err(|e| format!("Corrupt passport: {}", e))
+            .and_then(|(context, passport)| {
+                match authority.validate_passport(&member, &passport) {
+                    Ok(()) => Ok((context, passport)),
+                    Err(errors) => {
+                        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                        Err(format!("Invalid passport: {}", errors.join("; ")))
+                    }
+                }
+            });
+        match decoded {
+            Ok((context, passport)) => {
+                members.push(member);
+                contexts.push(context);
+                passports.push(passport);
+            }
+            Err(reason) => refused.push((member, reason)),
+        }
+    }
+
+    let (imported, failed) = match authority.on_party_transfer_in(&members, passports) {
+        Ok(imported) => (imported, None),
+        // Refused as a whole: everyone is told so when they arrive
+        Err(e) => {
+            refused.extend(members.drain(..).map(|member| (member, e.to_string())));
+            contexts.clear();
+            (PartyImportResult::default(), Some(e))
+        }
+    };
+    for (id, rejection) in imported.refused {
+        if let Some(at) = members.iter().position(|m| m.id == id) {
+            refused.push((members.remove(at), rejection.reason));
+            contexts.remove(at);
+        }
+    }
+    for (member, context) in members.iter().zip(contexts) {
+        if let Some(context) = context {
+            authority.on_transfer_context(member, context);
+        }
+    }
+
+    let mut outcome = None;
+    let mut kept = Vec::new();
+    for member in members {
+        if member.id != session.id {
+            kept.push((member.identity.clone(), Some(member.id)));
+            sessions
+                .arriving
+                .insert(member.identity.clone(), Ok(member));
+        }
+    }
+    for (member, reason) in refused {
+        if member.id == session.id {
+            outcome = Some(reason);
+        } else {
+            kept.push((member.identity.clone(), None));
+            sessions.arriving.insert(member.identity, Err(reason));
+        }
+    }
+    if !kept.is_empty() {
+        tokio::spawn(expire_party(shared.clone(), kept));
+    }
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(outcome),
+    }
+}
+
+/// Disconnect party members whose own connections didn't arrive within
+/// the reconnect grace, and forget the refused ones (`None`). Those left at
+/// shutdown close with the room instead.
+async fn expire_party<A: Authority>(shared: Arc<Shared<A>>, members: Vec<(Identity, Option<u64>)>) {
+    let mut shutdown = shared.shutdown.subscribe();
+    tokio::select! {
+        _ = stopped(&mut shutdown) => return,
+        _ = tokio::time::sleep(shared.config.reconnect.window) => {}
+    }
+    let unclaimed: Vec<Session> = {
+        let mut sessions = shared.sessions.lock().await;
+        members
+            .into_iter()
+            .filter_map(|(identity, id)| {
+                // Still this party's, not a later one's
+                let ours = match sessions.arriving.get(&identity)? {
+                    Ok(held) => Some(held.id) == id,
+                    Err(_) => id.is_none(),
+                };
+                if !ours {
+                    return None;
+                }
+                sessions.arriving.remove(&identity)?.ok()
+            })
+            .collect()
+    };
+    if unclaimed.is_empty() {
+        return;
+    }
+    shared
+        .authority
+        .write()
+        .await
+        .on_disconnect_batch(&unclaimed, DisconnectReason::GraceExpired);
+    shared.version.fetch_add(1, Ordering::Relaxed);
+    let _ = shared.changes.send(());
+}
+
+/// Wait until `at`, or forever without a deadline.
+async fn deadline(at: Option<tokio::time::Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for a shutdown.
+pub(crate) async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    // A dropped sender means the handle is gone; nobody can stop us then
+    if shutdown.wait_for(|stop| *stop).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// An intent on its way through [`run_intent`].
+pub(crate) struct IntentCall<'a, I> {
+    pub(crate) intent: I,
+    /// The message it came in, for schema validation.
+    #[cfg_attr(not(feature = "intent-schema"), allow(dead_code))]
+    pub(crate) text: &'a str,
+    /// An optimistic intent's `base_seq`.
+    pub(crate) base_seq: Option<u64>,
+    /// The seq of the latest snapshot the session was sent.
+    pub(crate) latest_seq: Option<u64>,
+    /// A tracked intent's request ID and `if_seq`, and the room's version
+    /// that snapshot was taken at (`None` if the session knows it's stale).
+    pub(crate) if_seq: Option<(u64, u64, Option<u64>)>,
+}
+
+/// What became of an intent in [`run_intent`].
+pub(crate) enum IntentOutcome {
+    /// Applied to the room, which is now at `version`.
+    Applied {
+        outcome: OptimisticOutcome,
+        #[cfg_attr(not(feature = "long-poll"), allow(dead_code))]
+        version: u64,
+    },
+    /// Refused before it reached the authority.
+    Refused(ErrorCode, String),
+    /// The authority panicked; the session ends if `ends_session`.
+    Panicked { ends_session: bool },
+    /// The authority failed it, and any pause or shutdown it asked for is
+    /// under way. A `kick` ends the session with that reason.
+    Failed {
+        code: ErrorCode,
+        message: String,
+        kick: Option<String>,
+    },
+}
+
+/// Take an intent from `session` through the checks every transport runs,
+/// then to the authority: the session's state, capabilities and rate limit,
+/// schema, the pause gate, priority turns, `if_seq`, the panic guard and
+/// [`Authority::on_authority_error`]. Replies are the transport's.
+pub(crate) async fn run_intent<A: Authority>(
+    shared: &Shared<A>,
+    session: &Session,
+    capabilities: &Capabilities,
+    last_intent: &mut Option<Instant>,
+    call: IntentCall<'_, A::Intent>,
+) -> IntentOutcome {
+    let IntentCall {
+        intent,
+        base_seq,
+        latest_seq,
+        if_seq,
+        ..
+    } = call;
+    if !session.state.is_writable() {
+        return IntentOutcome::Refused(
+            ErrorCode::ProtocolError,
+            format!("Intents need a live session, not a {:?} one", session.state),
+        );
+    }
+    let now = Instant::now();
+    if !capabilities.intent_allowed(*last_intent, now) {
+        return IntentOutcome::Refused(
+            ErrorCode::RateLimited,
+            "Too many intents; slow down".into(),
+        );
+    }
+    *last_intent = Some(now);
+    #[cfg(feature = "intent-schema")]
+    if let Some(validator) = &shared.intent_validator
+        && let Err(rejection) = validator.validate_message(call.text)
+    {
+        return IntentOutcome::Refused(
+            ErrorCode::IntentRejected,
+            format!("Intent rejected: {}", rejection),
+        );
+    }
+    if shared
+        .sessions
+        .lock()
+        .await
+        .paused_until
+        .is_some_and(|until| now < until)
+    {
+        return IntentOutcome::Refused(
+            ErrorCode::Overloaded,
+            "The server is paused; try again shortly".into(),
+        );
+    }
+    // Ahead of the timer, the authority still hears of it first
+    recover(shared, now).await;
+
+    let in_flight = shared.load.intent_queued();
+    // Wait behind higher-priority intents, not just for the lock
+    let intent_type = A::intent_type_name(&intent);
+    let (priority, payload) = {
+        let authority = shared.authority.read().await;
+        (
+            authority.intent_priority(session, &intent),
+            authority.redacted_intent_to_json(&intent),
+        )
+    };
+    shared
+        .config
+        .observer
+        .on_intent_received(session, intent_type, &payload);
+    let turn = shared.intent_turns.take(session.id, priority).await;
+    let mut authority = shared.authority.write().await;
+    // `if_seq` is compare-and-swap: refuse an edit made against a snapshot
+    // the room has since moved past, whether or not it was sent a newer one
+    if let Some((request_id, if_seq, version)) = if_seq
+        && version != Some(shared.version.load(Ordering::Relaxed))
+    {
+        return IntentOutcome::Refused(
+            ErrorCode::Conflict,
+            format!(
+                "Intent {} was made against snapshot {}, which is out of date",
+                request_id, if_seq
+            ),
+        );
+    }
+    let started = Instant::now();
+    let guard = PanicGuard::new(shared.config.panic_policy);
+    let result = match guard.call(|| match base_seq {
+        Some(base_seq) => authority.handle_optimistic_intent(
+            session,
+            intent,
+            base_seq,
+            latest_seq.unwrap_or(base_seq),
+        ),
+        None => authority
+            .handle_intent(session, intent)
+            .map(|()| OptimisticOutcome::Confirmed),
+    }) {
+        Ok(result) => result,
+        Err(panic) => {
+            return IntentOutcome::Panicked {
+                ends_session: panic.ends_session(),
+            };
+        }
+    };
+    shared.config.observer.on_intent_handled(
+        session,
+        intent_type,
+        started.elapsed(),
+        result.is_ok(),
+    );
+    let version = match result {
+        Ok(_) => shared.version.fetch_add(1, Ordering::Relaxed) + 1,
+        Err(_) => shared.version.load(Ordering::Relaxed),
+    };
+    drop(authority);
+    drop(turn);
+    drop(in_flight);
+    let e = match result {
+        Ok(outcome) => {
+            let _ = shared.changes.send(());
+            return IntentOutcome::Applied { outcome, version };
+        }
+        Err(e) => e,
+    };
+    shared.emit(LifecycleEvent::IntentFailed {
+        session_id: session.id,
+        name: session.name.clone(),
+        error: e.to_string(),
+    });
+    let (action, code) = {
+        let authority = shared.authority.read().await;
+        (authority.on_authority_error(&e), authority.error_code(&e))
+    };
+    let message = e.to_string();
+    let mut kick = None;
+    match action {
+        AuthorityErrorAction::SendErrorAndContinue => {}
+        AuthorityErrorAction::KickSession { reason } => {
+            tracing::info!("Kicked {}: {}", session.name, reason);
+            kick = Some(reason);
+        }
+        AuthorityErrorAction::PauseAuthority { for_duration } => {
+            tracing::warn!("Pausing intents for {:?} after: {}", for_duration, e);
+            let now = Instant::now();
+            shared.sessions.lock().await.paused_until = Some(now + for_duration);
+            // A pause during a pause extends it; recovery reports the latest error
+            let mut pause = shared.pause.lock().unwrap();
+            let since = pause
+                .as_ref()
+                .filter(|pause| now < pause.until)
+                .map_or(now, |pause| pause.since);
+            *pause = Some(Pause {
+                error: e,
+                since,
+                until: now + for_duration,
+            });
+            shared.paused.notify_one();
+        }
+        AuthorityErrorAction::ShutdownAuthority => {
+            tracing::error!("Shutting down after: {}", e);
+            shared.shutdown.shutdown();
+        }
+    }
+    IntentOutcome::Failed {
+        code,
+        message,
+        kick,
+    }
+}
+
+/// The session's current snapshot, redacted, and the room's version it was
+/// taken at. A spectator's shows the session it follows.
+pub(crate) async fn session_snapshot<A: Authority>(
+    shared: &Shared<A>,
+    session: &Session,
+) -> (A::Snapshot, u64) {
+    let authority = shared.authority.read().await;
+    let version = shared.version.load(Ordering::Relaxed);
+    let spectators = shared.config.spectators.lock().unwrap();
+    let mut data = spectators.snapshot_for(&*authority, session);
+    drop(spectators);
+    authority.redact_snapshot(session, &mut data);
+    let data = match &session.client_version {
+        Some(version) => authority.translate_snapshot_for_version(data, version),
+        None => data,
+    };
+    (data, version)
+}
+
+/// The session's current snapshot as a frame, sent as a `Correction` if the
+/// client's prediction diverged, and the room's version it was taken at.
+/// Marked stale while intents are paused.
+async fn snapshot_message<A>(
+    shared: &Shared<A>,
+    session: &Session,
+    seq: u64,
+    correction: bool,
+) -> Result<(Message, u64), WireError>
+where
+    A: Authority,
+    A::Snapshot: Serialize,
+{
+    let (data, version) = session_snapshot(shared, session).await;
+    let msg = encode_snapshot(shared, session, data, seq, correction).await?;
+    Ok((msg, version))
+}
+
+/// Tell the authority a pause it asked for is over, once, if it is by `now`.
+async fn recover<A: Authority>(shared: &Shared<A>, now: Instant) {
+    let ended = {
+        let mut pause = shared.pause.lock().unwrap();
+        match pause.take() {
+            Some(ended) if ended.until <= now => Some(ended),
+            still => {
+                *pause = still;
+                None
+            }
+        }
+    };
+    let Some(ended) = ended else { return };
+    let attempt = RecoveryAttempt {
+        paused_for: now.duration_since(ended.since),
+        recovered_at: now,
+        error: ended.error,
+    };
+    shared
+        .authority
+        .write()
+        .await
+        .on_authority_recovered(&attempt.error);
+    shared
+        .config
+        .observer
+        .on_authority_recovered(&attempt.described());
+}
+
+/// Who joined and left since `roster`, if the authority reports presence
+/// and anyone did.
+async fn presence_update<A: Authority>(
+    shared: &Shared<A>,
+    roster: &mut Roster,
+) -> Option<ServerWire<A::Snapshot>> {
+    let present = shared.authority.read().await.presence()?;
+    roster.update(present).map(ServerWire::presence)
+}
+
+/// Encode `data` as the session's next snapshot message.
+async fn encode_snapshot<A>(
+    shared: &Shared<A>,
+    session: &Session,
+    data: A::Snapshot,
+    seq: u64,
+    correction: bool,
+) -> Result<Message, WireError>
+where
+    A: Authority,
+    A::Snapshot: Serialize,
+{
+    let stale = shared
+        .sessions
+        .lock()
+        .await
+        .paused_until
+        .is_some_and(|until| Instant::now() < until);
+    let msg: ServerWire<A::Snapshot> = if correction {
+        ServerWire::Correction { seq, data }
+    } else {
+        ServerWire::Snapshot { seq, data, stale }
+    };
+    within_limit(shared, msg.to_ws_message(session.encoding.encoding)?)
+}
+
+/// Apply the [`SerializationFailurePolicy`](crate::SerializationFailurePolicy)
+/// to a snapshot that didn't serialize or was over the hard limit. Only
+/// `Disconnect` returns an error.
+async fn serialization_failed<A, S>(
+    shared: &Shared<A>,
+    session: &Session,
+    sink: &mut S,
+    error: WireError,
+) -> Result<(), ConnectionError>
+where
+    A: Authority,
+    A::Snapshot: Serialize,
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    tracing::error!("Snapshot for {} couldn't be sent: {}", session.name, error);
+    let policy = shared.config.serialization_failure;
+    if let Some(msg) = policy.recover::<A::Snapshot>(error)? {
+        sink.send(msg.to_ws_message(session.encoding.encoding)?)
+            .await?;
+    }
+    Ok(())
+}
+
+/// The session's current snapshot as a correction to optimistic intent
+/// `request_id`, and the room's version it was taken at.
+async fn correction_message<A>(
+    shared: &Shared<A>,
+    session: &Session,
+    request_id: u64,
+    seq: u64,
+) -> Result<(Message, u64), WireError>
+where
+    A: Authority,
+    A::Snapshot: Serialize,
+{
+    let (corrective_snapshot, version) = session_snapshot(shared, session).await;
+    let msg: ServerWire<A::Snapshot> = ServerWire::IntentCorrected {
+        request_id,
+        seq,
+        corrective_snapshot,
+    };
+    Ok((
+        within_limit(shared, msg.to_ws_message(session.encoding.encoding)?)?,
+        version,
+    ))
+}
+
+/// Hold back `msg` if it's over the configured hard limit.
+fn within_limit<A: Authority>(shared: &Shared<A>, msg: Message) -> Result<Message, WireError> {
+    shared.config.snapshot_spike_guard.check(msg.len())?;
+    Ok(msg)
+}
+
+/// Decode a transfer: a full `TransferSnapshot`, or a bare passport, which
+/// comes without the session's view at the origin.
+fn decode_passport<A>(raw: &[u8]) -> Result<(Option<A::Snapshot>, A::Passport), serde_json::Error>
+where
+    A: Authority,
+    A::Snapshot: DeserializeOwned,
+    A::Passport: DeserializeOwned,
+{
+    let error = match serde_json::from_slice::<TransferSnapshot<A::Snapshot, A::Passport>>(raw) {
+        Ok(transfer) => {
+            let (context, passport) = split_transfer_snapshot(transfer);
+            return Ok((Some(context), passport));
+        }
+        Err(e) => e,
+    };
+    let passport = serde_json::from_slice(raw).map_err(|_| error)?;
+    Ok((None, passport))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::testing::{Add, Refused, Tallies, TestPassport, TestRoom};
+    use interconnect_core::{
+        ConnectionEventKind, IdentityKeyring, ImportResult, ImportSessionError,
+        LocalTransferResult, Manifest, Passport, RecordingAuthority, RouterAuthority, SigningKey,
+        SimpleAuthority, TransferError,
+    };
+
+    fn manifest() -> Manifest {
+        Manifest {
+            identity: Identity::local("counter"),
+            name: "Counter".into(),
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            endpoint: None,
+            signing_keys: Vec::new(),
+            signature: None,
+        }
+    }
+
+    /// The key the test federation shares.
+    fn federation_key() -> IdentityKeyring {
+        IdentityKeyring::new(SigningKey::new("k1", "federation"), Duration::from_secs(60))
+    }
+
+    /// Defaults, trusting transfers from the test federation.
+    fn federated() -> AuthorityConfig {
+        AuthorityConfig {
+            keyring: Some(federation_key()),
+            ..AuthorityConfig::new(manifest())
+        }
+    }
+
+    /// A peer's manifest, signed as its server would.
+    fn peer(name: &str) -> Manifest {
+        Manifest {
+            identity: Identity::local(name),
+            ..manifest()
+        }
+        .sign(&federation_key())
+    }
+
+    #[tokio::test]
+    async fn spawned_server_shuts_down() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle =
+            spawn_authority(TestRoom::new(), AuthorityConfig::new(manifest()), listener).unwrap();
+        assert_ne!(handle.local_addr().port(), 0);
+
+        let stop = handle.shutdown_handle();
+        assert!(!stop.is_shutdown());
+        handle.authority().write().await.applied.push((1, 3));
+        handle.shutdown().await.unwrap();
+        assert!(stop.is_shutdown());
+    }
+
+    /// Each intent's session and payload, as the observer was told.
+    #[derive(Default)]
+    struct Received(std::sync::Mutex<Vec<(u64, serde_json::Value)>>);
+
+    impl crate::Observer for Received {
+        fn on_intent_received(
+            &self,
+            session: &Session,
+            _intent_type: &'static str,
+            payload: &serde_json::Value,
+        ) {
+            self.0.lock().unwrap().push((session.id, payload.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn observers_get_the_redacted_intent() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let received = Arc::new(Received::default());
+        let config = AuthorityConfig {
+            observer: received.clone(),
+            ..AuthorityConfig::new(manifest())
+        };
+        let handle = spawn_authority(TestRoom::new(), config, listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let auth = r#"{"type":"auth","identity":"local:alice"}"#;
+        ws.send(Message::text(auth)).await.unwrap();
+        ws.send(Message::text(r#"{"type":"intent","by":4}"#))
+            .await
+            .unwrap();
+        while handle.authority().read().await.total() != 4 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // The room's projection, which keeps only the type name
+        let name = std::any::type_name::<interconnect_core::testing::Add>();
+        assert_eq!(*received.0.lock().unwrap(), [(1, serde_json::json!(name))]);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconnect_tokens_restore_the_session_name() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle = spawn_authority(TestRoom::new(), federated(), listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut first, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let auth = r#"{"type":"auth","identity":"local:alice","name":"Alice"}"#;
+        first.send(Message::text(auth)).await.unwrap();
+        let token = loop {
+            let Some(Ok(Message::Text(text))) = first.next().await else {
+                panic!("connection ended");
+            };
+            if let ServerWire::<Tallies>::ReconnectToken { token } = from_json_str(&text).unwrap() {
+                break token;
+            }
+        };
+
+        // Presented by the same identity, it brings the name back
+        let (mut second, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let auth = serde_json::json!({
+            "type": "auth",
+            "identity": "local:alice",
+            "reconnect_token": token,
+        });
+        second.send(Message::text(auth.to_string())).await.unwrap();
+        while handle.authority().read().await.present.len() != 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(handle.authority().read().await.present, ["Alice", "Alice"]);
+
+        // Anyone else's is refused
+        let (mut third, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let auth = serde_json::json!({
+            "type": "auth",
+            "identity": "local:mallory",
+            "reconnect_token": token,
+        });
+        third.send(Message::text(auth.to_string())).await.unwrap();
+        let Some(Ok(Message::Text(text))) = third.next().await else {
+            panic!("connection ended");
+        };
+        let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+        assert!(
+            matches!(&wire, ServerWire::Error { code, .. } if code == ErrorCode::ResumeExpired.as_str()),
+            "{wire:?}"
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn spectators_are_sent_the_view_they_follow() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AuthorityConfig {
+            reconnect: crate::ReconnectGrace::new(Duration::from_millis(20)),
+            ..AuthorityConfig::new(manifest())
+        };
+        let handle = spawn_authority(TestRoom::new(), config, listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let join = async |identity: &str, by: u32| {
+            let (mut ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+            let auth = format!(r#"{{"type":"auth","identity":"{identity}"}}"#);
+            ws.send(Message::text(auth)).await.unwrap();
+            let total = handle.authority().read().await.total() + by;
+            let intent = format!(r#"{{"type":"intent","by":{by}}}"#);
+            ws.send(Message::text(intent)).await.unwrap();
+            while handle.authority().read().await.total() != total {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            ws
+        };
+        let mut alice = join("local:alice", 3).await;
+        let mut bob = join("local:bob", 4).await;
+
+        handle.spectators().lock().unwrap().follow(2, 1);
+        alice
+            .send(Message::text(r#"{"type":"intent","by":1}"#))
+            .await
+            .unwrap();
+        // Bob sees alice's tally, not the room
+        let followed = async {
+            while let Some(Ok(msg)) = bob.next().await {
+                let Message::Text(text) = msg else { continue };
+                if let ServerWire::<Tallies>::Snapshot { data, .. } = from_json_str(&text).unwrap()
+                    && data == [(1, 4)]
+                {
+                    return;
+                }
+            }
+            panic!("connection ended");
+        };
+        tokio::time::timeout(Duration::from_secs(2), followed)
+            .await
+            .unwrap();
+
+        // Alice leaving ends the link
+        drop(alice);
+        while handle.authority().read().await.present.len() != 1 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(handle.spectators().lock().unwrap().source_of(2), None);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn paused_sessions_can_neither_send_intents_nor_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle =
+            spawn_authority(TestRoom::new(), AuthorityConfig::new(manifest()), listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let auth = r#"{"type":"auth","identity":"local:alice"}"#;
+        ws.send(Message::text(auth)).await.unwrap();
+        // Live once the first snapshot is out
+        while let Some(Ok(msg)) = ws.next().await {
+            let Message::Text(text) = msg else { continue };
+            let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+            if matches!(wire, ServerWire::Snapshot { .. }) {
+                break;
+            }
+        }
+        ws.send(Message::text(r#"{"type":"pause"}"#)).await.unwrap();
+
+        let requests = [
+            r#"{"type":"intent","by":1}"#,
+            r#"{"type":"transfer_request","destination":"ws://elsewhere"}"#,
+        ];
+        for request in requests {
+            ws.send(Message::text(request)).await.unwrap();
+            let code = loop {
+                let Some(Ok(Message::Text(text))) = ws.next().await else {
+                    panic!("connection ended");
+                };
+                if let ServerWire::<Tallies>::Error { code, .. } = from_json_str(&text).unwrap() {
+                    break code;
+                }
+            };
+            assert_eq!(code, ErrorCode::ProtocolError.as_str(), "{request}");
+        }
+        assert_eq!(handle.authority().read().await.total(), 0);
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Who's here, by session ID. Hands its sessions to the next server.
+    #[derive(Default)]
+    struct Guestbook(std::collections::BTreeMap<u64, Session>);
+
+    impl SimpleAuthority for Guestbook {
+        type Intent = Add;
+        type Snapshot = Vec<String>;
+        type Passport = TestPassport;
+        type Error = Refused;
+
+        fn on_connect(&mut self, session: &Session) -> Result<(), Refused> {
+            self.0.insert(session.id, session.clone());
+            Ok(())
+        }
+
+        fn on_transfer_in(
+            &mut self,
+            _session: &Session,
+            _passport: TestPassport,
+        ) -> Result<ImportResult<TestPassport>, Refused> {
+            Err(Refused)
+        }
+
+        fn on_disconnect(&mut self, session: &Session) {
+            self.0.remove(&session.id);
+        }
+
+        fn handle_intent(&mut self, _session: &Session, _intent: Add) -> Result<(), Refused> {
+            Ok(())
+        }
+
+        fn snapshot(&self) -> Vec<String> {
+            self.0
+                .values()
+                .map(|session| session.name.clone())
+                .collect()
+        }
+
+        fn emit_passport(&self, session: &Session) -> TestPassport {
+            TestPassport {
+                name: session.name.clone(),
+                items: Vec::new(),
+            }
+        }
+
+        fn validate_destination(&self, _destination: &str) -> bool {
+            false
+        }
+
+        fn export_sessions(&self) -> Vec<ExportedSession> {
+            let exported = self.0.values().map(|session| ExportedSession {
+                session: session.clone(),
+                authority_state: Vec::new(),
+            });
+            exported.collect()
+        }
+
+        fn import_sessions(
+            &mut self,
+            sessions: Vec<ExportedSession>,
+        ) -> Result<(), ImportSessionError> {
+            let imported = sessions.into_iter().map(|e| (e.session.id, e.session));
+            self.0.extend(imported);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_survive_a_restart() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let first = spawn_authority(Guestbook::default(), federated(), listener).unwrap();
+        let url = format!("ws://{}", first.local_addr());
+        let mut tokens = Vec::new();
+        for identity in ["local:alice", "local:bob"] {
+            let (mut ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+            let auth = format!(r#"{{"type":"auth","identity":"{identity}"}}"#);
+            ws.send(Message::text(auth)).await.unwrap();
+            let token = loop {
+                let Some(Ok(Message::Text(text))) = ws.next().await else {
+                    panic!("connection ended");
+                };
+                let wire: ServerWire<Vec<String>> = from_json_str(&text).unwrap();
+                if let ServerWire::ReconnectToken { token } = wire {
+                    break token;
+                }
+            };
+            tokens.push((ws, token));
+        }
+        let exported = first.shutdown().await.unwrap();
+        assert_eq!(exported.len(), 2);
+
+        // The next server knows them before anyone connects
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AuthorityConfig {
+            handoff: exported,
+            reconnect: crate::ReconnectGrace::new(Duration::from_millis(200)),
+            ..federated()
+        };
+        let second = spawn_authority(Guestbook::default(), config, listener).unwrap();
+        let present = |ids: &[u64]| {
+            let room = second.authority().clone();
+            let ids = ids.to_vec();
+            async move { room.read().await.0.keys().copied().collect::<Vec<_>>() == ids }
+        };
+        assert!(present(&[1, 2]).await);
+
+        // Alice claims hers; bob never comes back
+        let url = format!("ws://{}", second.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let auth = serde_json::json!({
+            "type": "auth",
+            "identity": "local:alice",
+            "reconnect_token": tokens[0].1,
+        });
+        ws.send(Message::text(auth.to_string())).await.unwrap();
+        let snapshot = loop {
+            let Some(Ok(Message::Text(text))) = ws.next().await else {
+                panic!("connection ended");
+            };
+            let wire: ServerWire<Vec<String>> = from_json_str(&text).unwrap();
+            if let ServerWire::Snapshot { data, .. } = wire {
+                break data;
+            }
+        };
+        assert_eq!(snapshot, ["alice", "bob"]);
+        let expired = async {
+            while !present(&[1]).await {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(2), expired)
+            .await
+            .unwrap();
+        second.shutdown().await.unwrap();
+    }
+
+    /// Send `intents` from `other` while `ws` is paused, then resume and
+    /// collect the snapshots that follow.
+    async fn resume_after<S>(
+        ws: &mut S,
+        other: &mut S,
+        intents: &[u32],
+        handle: &AuthorityHandle<TestRoom>,
+    ) -> Vec<Tallies>
+    where
+        S: Sink<Message, Error = tokio_tungstenite::tungstenite::Error>
+            + StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+            + Unpin,
+    {
+        ws.send(Message::text(r#"{"type":"pause"}"#)).await.unwrap();
+        for by in intents {
+            let total = handle.authority().read().await.total() + by;
+            let intent = format!(r#"{{"type":"intent","by":{by}}}"#);
+            other.send(Message::text(intent)).await.unwrap();
+            while handle.authority().read().await.total() != total {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            // Let the connection hold this change before the next
+            tokio::time::sleep(Duration::from_millis(30)).await;
+        }
+        ws.send(Message::text(r#"{"type":"resume"}"#))
+            .await
+            .unwrap();
+
+        let mut snapshots = Vec::new();
+        while let Ok(Some(Ok(Message::Text(text)))) =
+            tokio::time::timeout(Duration::from_millis(200), ws.next()).await
+        {
+            if let ServerWire::Snapshot { data, .. } = from_json_str(&text).unwrap() {
+                snapshots.push(data);
+            }
+        }
+        snapshots
+    }
+
+    #[tokio::test]
+    async fn paused_sessions_get_held_snapshots_coalesced_on_resume() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AuthorityConfig {
+            pause_buffer: crate::CoalesceConfig::new(8).coalesce_from(3),
+            ..AuthorityConfig::new(manifest())
+        };
+        let handle = spawn_authority(TestRoom::new(), config, listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let mut sockets = Vec::new();
+        for name in ["bob", "alice"] {
+            let (mut ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+            let auth = format!(r#"{{"type":"auth","identity":"local:{name}"}}"#);
+            ws.send(Message::text(auth)).await.unwrap();
+            while let Some(Ok(msg)) = ws.next().await {
+                let Message::Text(text) = msg else { continue };
+                let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+                if matches!(wire, ServerWire::Snapshot { .. }) {
+                    break;
+                }
+            }
+            sockets.push(ws);
+        }
+        let [mut other, mut ws] = <[_; 2]>::try_from(sockets).unwrap();
+
+        // A paused session can't write, so bob's intents make the changes.
+        // Short of the threshold, each held snapshot is delivered in turn
+        let snapshots = resume_after(&mut ws, &mut other, &[1, 2], &handle).await;
+        assert_eq!(snapshots, [vec![(1, 1)], vec![(1, 3)]]);
+
+        // At it, the authority reduces them to one
+        let snapshots = resume_after(&mut ws, &mut other, &[1, 1, 1], &handle).await;
+        assert_eq!(snapshots, [vec![(1, 6)]]);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn silent_connection_is_dropped_after_max_sync_duration() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AuthorityConfig {
+            max_sync_duration: Some(Duration::from_millis(50)),
+            ..AuthorityConfig::new(manifest())
+        };
+        let handle = spawn_authority(TestRoom::new(), config, listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        // Never authenticates; the server hangs up rather than waiting
+        let ended = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(msg)) = ws.next().await {
+                if msg.is_close() {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(ended.is_ok());
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn nothing_follows_the_transfer_directive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let start = Instant::now();
+        let room = RecordingAuthority::new(TestRoom::new(), 16);
+        let handle = spawn_authority(room, AuthorityConfig::new(manifest()), listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let auth = r#"{"type":"auth","identity":"local:alice"}"#;
+        ws.send(Message::text(auth)).await.unwrap();
+        let transfer = r#"{"type":"transfer_request","destination":"ws://elsewhere"}"#;
+        ws.send(Message::text(transfer)).await.unwrap();
+        // Changes while the transfer is underway would otherwise snapshot
+        handle
+            .authority()
+            .write()
+            .await
+            .inner_mut()
+            .applied
+            .push((1, 7));
+
+        let mut after_transfer = Vec::new();
+        let mut transferred = false;
+        while let Some(Ok(msg)) = ws.next().await {
+            let Message::Text(text) = msg else { continue };
+            let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+            if transferred {
+                after_transfer.push(wire);
+            } else {
+                transferred = matches!(wire, ServerWire::Transfer { .. });
+            }
+        }
+        assert!(transferred);
+        assert!(after_transfer.is_empty(), "{after_transfer:?}");
+
+        // The session leaves once the connection task winds down
+        let left = ConnectionEventKind::Disconnected {
+            reason: DisconnectReason::Transferred,
+        };
+        let recorded = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let events = handle
+                    .authority()
+                    .read()
+                    .await
+                    .list_connection_events(start);
+                if events.iter().any(|event| event.event == left) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(recorded.is_ok());
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_passport_is_refused_before_decoding() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AuthorityConfig {
+            max_passport_bytes: 16,
+            ..federated()
+        };
+        let handle = spawn_authority(TestRoom::new(), config, listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let auth = serde_json::json!({
+            "type": "auth",
+            "identity": "local:alice",
+            "passport": vec![b'x'; 17],
+            "source": peer("peer"),
+        });
+        ws.send(Message::text(auth.to_string())).await.unwrap();
+
+        let Some(Ok(Message::Text(text))) = ws.next().await else {
+            panic!("connection ended");
+        };
+        let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+        assert!(
+            matches!(&wire, ServerWire::Error { code, .. } if code == ErrorCode::PassportTooLarge.as_str()),
+            "{wire:?}"
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_passport_is_refused_before_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AuthorityConfig {
+            max_passport_bytes: 16,
+            ..AuthorityConfig::new(manifest())
+        };
+        let handle = spawn_authority(TestRoom::new(), config, listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let auth = r#"{"type":"auth","identity":"local:alice"}"#;
+        ws.send(Message::text(auth)).await.unwrap();
+        let transfer = r#"{"type":"transfer_request","destination":"ws://elsewhere"}"#;
+        ws.send(Message::text(transfer)).await.unwrap();
+
+        // Past the manifest, token and snapshot
+        let refused = loop {
+            let Some(Ok(Message::Text(text))) = ws.next().await else {
+                panic!("connection ended");
+            };
+            let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+            if matches!(wire, ServerWire::Error { .. } | ServerWire::Transfer { .. }) {
+                break wire;
+            }
+        };
+        assert!(
+            matches!(&refused, ServerWire::Error { code, .. } if code == ErrorCode::PassportTooLarge.as_str()),
+            "{refused:?}"
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn passports_without_a_trusted_source_are_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle = spawn_authority(TestRoom::new(), federated(), listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let passport = serde_json::to_vec(&TestPassport::default()).unwrap();
+        let unsigned = Manifest {
+            identity: Identity::local("peer"),
+            ..manifest()
+        };
+        let forged = Manifest {
+            identity: Identity::local("elsewhere"),
+            ..peer("peer")
+        };
+        let sources = [None, Some(unsigned), Some(forged)];
+        for source in sources {
+            let (mut ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+            let auth = serde_json::json!({
+                "type": "auth",
+                "identity": "local:alice",
+                "passport": passport,
+                "source": source,
+            });
+            ws.send(Message::text(auth.to_string())).await.unwrap();
+            let Some(Ok(Message::Text(text))) = ws.next().await else {
+                panic!("connection ended");
+            };
+            let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+            assert!(
+                matches!(&wire, ServerWire::Error { code, .. } if code == ErrorCode::SourceBlocked.as_str()),
+                "{wire:?}"
+            );
+            // Refused outright
+            assert!(!matches!(ws.next().await, Some(Ok(Message::Text(_)))));
+        }
+        assert!(handle.authority().read().await.present.is_empty());
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn transfers_in_bring_their_snapshot_context() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle = spawn_authority(TestRoom::new(), federated(), listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let transfer = TransferSnapshot {
+            snapshot_context: vec![(7, 3)],
+            passport: TestPassport::default(),
+        };
+        let auth = serde_json::json!({
+            "type": "auth",
+            "identity": "local:alice",
+            "passport": serde_json::to_vec(&transfer).unwrap(),
+            "source": peer("peer"),
+        });
+        ws.send(Message::text(auth.to_string())).await.unwrap();
+        while handle.authority().read().await.present.is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let room = handle.authority().read().await;
+        let name = room.present[0].clone();
+        assert_eq!(room.arrived_with, [(name, vec![(7, 3)])]);
+        drop(room);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn parties_arrive_together() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AuthorityConfig {
+            reconnect: crate::ReconnectGrace::new(Duration::from_millis(200)),
+            ..federated()
+        };
+        let room = TestRoom {
+            refused: vec!["carol".into()],
+            ..TestRoom::new()
+        };
+        let handle = spawn_authority(room, config, listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let party = ["alice", "bob", "carol", "dave"].map(|name| {
+            let passport = TestPassport {
+                name: name.into(),
+                items: Vec::new(),
+            };
+            Passport::new(
+                Identity::local(name),
+                serde_json::to_vec(&passport).unwrap(),
+            )
+        });
+        let bundle = Transfer::bundle("ws://here", party.to_vec()).unwrap();
+        let bundle = serde_json::to_vec(&bundle).unwrap();
+        let arrive = async |name: &str| {
+            let (mut ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+            let auth = serde_json::json!({
+                "type": "auth",
+                "identity": format!("local:{name}"),
+                "passport": bundle,
+                "source": peer("peer"),
+            });
+            ws.send(Message::text(auth.to_string())).await.unwrap();
+            loop {
+                let Some(Ok(Message::Text(text))) = ws.next().await else {
+                    panic!("connection ended");
+                };
+                let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+                if matches!(wire, ServerWire::Error { .. } | ServerWire::Snapshot { .. }) {
+                    break (ws, wire);
+                }
+            }
+        };
+        let present = async || handle.authority().read().await.present.clone();
+
+        // The first to arrive brings in everyone the room accepts
+        let (_alice, wire) = arrive("alice").await;
+        assert!(matches!(wire, ServerWire::Snapshot { .. }), "{wire:?}");
+        assert_eq!(present().await, ["alice", "bob", "dave"]);
+
+        // The rest take the sessions kept for them, or are told why not
+        let (_bob, wire) = arrive("bob").await;
+        assert!(matches!(wire, ServerWire::Snapshot { .. }), "{wire:?}");
+        assert_eq!(present().await, ["alice", "bob", "dave"]);
+        let (_carol, wire) = arrive("carol").await;
+        assert!(
+            matches!(&wire, ServerWire::Error { code, .. } if code == ErrorCode::InvalidPassport.as_str()),
+            "{wire:?}"
+        );
+
+        // Dave never comes
+        let expired = async {
+            while present().await != ["alice", "bob"] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(2), expired)
+            .await
+            .unwrap();
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn transfers_in_are_summarized_per_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AuthorityConfig {
+            peer_transfer_window: Some(Duration::from_millis(200)),
+            ..federated()
+        };
+        let room = TestRoom {
+            refused: vec!["bob".into()],
+            ..TestRoom::new()
+        };
+        let handle = spawn_authority(room, config, listener).unwrap();
+        let passport = serde_json::to_vec(&TestPassport::default()).unwrap();
+        let mut connections = Vec::new();
+        for name in ["alice", "bob"] {
+            let url = format!("ws://{}", handle.local_addr());
+            let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+            let auth = serde_json::json!({
+                "type": "auth",
+                "identity": format!("local:{name}"),
+                "name": name,
+                "passport": passport,
+                "source": peer("peer"),
+            });
+            ws.send(Message::text(auth.to_string())).await.unwrap();
+            connections.push(ws);
+        }
+
+        let summarized = tokio::time::timeout(Duration::from_secs(5), async {
+            while handle.authority().read().await.peer_batches.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(summarized.is_ok());
+        assert_eq!(
+            handle.authority().read().await.peer_batches,
+            [(Identity::local("peer"), 1, 1)]
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Two rooms behind one authority; sessions start in the lobby.
+    struct Rooms(RouterAuthority<&'static str, TestRoom>);
+
+    impl SimpleAuthority for Rooms {
+        type Intent = Add;
+        type Snapshot = Tallies;
+        type Passport = TestPassport;
+        type Error = Refused;
+
+        fn on_connect(&mut self, session: &Session) -> Result<(), Refused> {
+            self.0.connect(&"lobby", session).map_err(|_| Refused)
+        }
+
+        fn on_transfer_in(
+            &mut self,
+            _session: &Session,
+            _passport: TestPassport,
+        ) -> Result<ImportResult<TestPassport>, Refused> {
+            Err(Refused)
+        }
+
+        fn on_disconnect(&mut self, session: &Session) {
+            self.0.disconnect(session);
+        }
+
+        fn handle_intent(&mut self, session: &Session, intent: Add) -> Result<(), Refused> {
+            let room = self.0.authority_for_mut(session.id).ok_or(Refused)?;
+            Authority::handle_intent(room, session, intent)
+        }
+
+        fn snapshot(&self) -> Tallies {
+            Vec::new()
+        }
+
+        fn snapshot_for(&self, session: &Session) -> Tallies {
+            let room = self
+                .0
+                .authority_of(session.id)
+                .and_then(|key| self.0.get(key));
+            room.map(TestRoom::tallies).unwrap_or_default()
+        }
+
+        fn emit_passport(&self, session: &Session) -> TestPassport {
+            TestPassport {
+                name: session.name.clone(),
+                items: Vec::new(),
+            }
+        }
+
+        fn validate_destination(&self, _destination: &str) -> bool {
+            false
+        }
+
+        fn on_local_transfer(
+            &mut self,
+            session: &Session,
+            destination: &str,
+        ) -> Option<Result<LocalTransferResult, TransferError<Refused>>> {
+            let key = ["lobby", "games"]
+                .into_iter()
+                .find(|key| *key == destination)?;
+            Some(self.0.local_transfer(session, &key, session.id))
+        }
+    }
+
+    #[tokio::test]
+    async fn local_transfers_keep_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut router = RouterAuthority::new();
+        router.insert("lobby", TestRoom::new());
+        router.insert("games", TestRoom::new());
+        let handle =
+            spawn_authority(Rooms(router), AuthorityConfig::new(manifest()), listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let auth = r#"{"type":"auth","identity":"local:alice"}"#;
+        ws.send(Message::text(auth)).await.unwrap();
+        let transfer = r#"{"type":"transfer_request","destination":"games"}"#;
+        ws.send(Message::text(transfer)).await.unwrap();
+
+        let complete = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(msg)) = ws.next().await {
+                let Message::Text(text) = msg else { continue };
+                let wire: ServerWire<Tallies> = from_json_str(&text).unwrap();
+                if let ServerWire::LocalTransferComplete {
+                    destination,
+                    session_id,
+                } = wire
+                {
+                    return Some((destination, session_id));
+                }
+            }
+            None
+        })
+        .await
+        .unwrap();
+        assert_eq!(complete, Some(("games".to_string(), 1)));
+
+        // Still connected, and its intents now go to the games room
+        ws.send(Message::text(r#"{"type":"intent","by":2}"#))
+            .await
+            .unwrap();
+        let games = |rooms: &Rooms| rooms.0.get(&"games").unwrap().total();
+        while games(&*handle.authority().read().await) != 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(
+            handle
+                .authority()
+                .read()
+                .await
+                .0
+                .get(&"lobby")
+                .unwrap()
+                .present
+                .is_empty()
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Each recovery, as the observer was told.
+    #[derive(Default)]
+    struct Recoveries(std::sync::Mutex<Vec<RecoveryAttempt<String>>>);
+
+    impl crate::Observer for Recoveries {
+        fn on_authority_recovered(&self, attempt: &RecoveryAttempt<String>) {
+            self.0.lock().unwrap().push(attempt.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn recovery_is_reported_when_the_pause_ends() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let room = TestRoom {
+            pause_on_error: Some(Duration::from_millis(50)),
+            ..TestRoom::new()
+        };
+        let recoveries = Arc::new(Recoveries::default());
+        let config = AuthorityConfig {
+            observer: recoveries.clone(),
+            ..AuthorityConfig::new(manifest())
+        };
+        let handle = spawn_authority(room, config, listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let (mut tx, mut rx) = ws.split();
+        let auth = r#"{"type":"auth","identity":"local:alice"}"#;
+        tx.send(Message::text(auth)).await.unwrap();
+        let intent = |by: u32| Message::text(format!(r#"{{"type":"intent","by":{by}}}"#));
+        let mut errors = Vec::new();
+        let mut next_error = async || loop {
+            let Some(Ok(Message::Text(text))) = rx.next().await else {
+                panic!("connection ended");
+            };
+            if let ServerWire::<Tallies>::Error { code, .. } = from_json_str(&text).unwrap() {
+                return code;
+            }
+        };
+
+        tx.send(intent(0)).await.unwrap();
+        errors.push(next_error().await);
+        tx.send(intent(1)).await.unwrap();
+        errors.push(next_error().await);
+        assert_eq!(errors, ["intent_error", "overloaded"]);
+        assert!(handle.authority().read().await.recovered.is_empty());
+
+        // No intent needed: the pause ending is enough
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while handle.authority().read().await.recovered.is_empty() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(handle.authority().read().await.recovered, ["refused"]);
+        let attempts = recoveries.0.lock().unwrap().clone();
+        assert!(
+            matches!(&attempts[..], [attempt] if attempt.error == "refused" && attempt.paused_for >= Duration::from_millis(50)),
+            "{attempts:?}"
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn intent_made_against_an_old_snapshot_conflicts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle =
+            spawn_authority(TestRoom::new(), AuthorityConfig::new(manifest()), listener).unwrap();
+        let url = format!("ws://{}", handle.local_addr());
+        // Pulling, alice's seq only moves when she asks
+        let (ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut alice, mut replies) = ws.split();
+        let auth = r#"{"type":"auth","identity":"local:alice","delivery":"pull"}"#;
+        alice.send(Message::text(auth)).await.unwrap();
+        let intent = |request_id: u64, if_seq: u64| {
+            Message::text(format!(
+                r#"{{"type":"tracked_intent","request_id":{request_id},"require_ack":true,"if_seq":{if_seq},"intent":{{"by":1}}}}"#
+            ))
+        };
+        let mut next_reply = async || loop {
+            let Some(Ok(Message::Text(text))) = replies.next().await else {
+                panic!("connection ended");
+            };
+            if let wire @ (ServerWire::<Tallies>::Snapshot { .. }
+            | ServerWire::IntentAck { .. }
+            | ServerWire::Error { .. }) = from_json_str(&text).unwrap()
+            {
+                return wire;
+            }
+        };
+        alice
+            .send(Message::text(r#"{"type":"resync"}"#))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_reply().await,
+            ServerWire::Snapshot { seq: 0, .. }
+        ));
+
+        let (mut bob, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let auth = r#"{"type":"auth","identity":"local:bob"}"#;
+        bob.send(Message::text(auth)).await.unwrap();
+        bob.send(Message::text(r#"{"type":"intent","by":2}"#))
+            .await
+            .unwrap();
+        while handle.authority().read().await.total() != 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // Still her latest snapshot, but no longer the room's state
+        alice.send(intent(1, 0)).await.unwrap();
+        let reply = next_reply().await;
+        assert!(
+            matches!(&reply, ServerWire::Error { code, .. } if code == ErrorCode::Conflict.as_str()),
+            "{reply:?}"
+        );
+        assert_eq!(handle.authority().read().await.total(), 2);
+
+        alice
+            .send(Message::text(r#"{"type":"resync"}"#))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_reply().await,
+            ServerWire::Snapshot { seq: 1, .. }
+        ));
+        alice.send(intent(2, 1)).await.unwrap();
+        let reply = next_reply().await;
+        assert!(
+            matches!(reply, ServerWire::IntentAck { request_id: 2, .. }),
+            "{reply:?}"
+        );
+        assert_eq!(handle.authority().read().await.total(), 3);
+        handle.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Enforcement of [`SnapshotBudget`] on one connection.
+
+use interconnect_core::SnapshotBudget;
+use std::time::{Duration, Instant};
+
+/// Token bucket tracking one session's snapshot bytes.
+#[derive(Debug, Clone)]
+pub struct SnapshotMeter {
+    budget: SnapshotBudget,
+    /// Bytes available now; negative while in debt.
+    available: f64,
+    refilled: Instant,
+}
+
+impl SnapshotMeter {
+    /// Start with a full bucket.
+    pub fn new(budget: SnapshotBudget) -> Self {
+        Self {
+            budget,
+            available: budget.burst_bytes as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// The budget being enforced.
+    pub fn budget(&self) -> &SnapshotBudget {
+        &self.budget
+    }
+
+    /// Spend `bytes` and return how long to wait before sending them.
+    ///
+    /// `Duration::ZERO` means the snapshot fits the budget.
+    pub fn reserve(&mut self, bytes: usize) -> Duration {
+        self.reserve_at(bytes, Instant::now())
+    }
+
+    fn reserve_at(&mut self, bytes: usize, now: Instant) -> Duration {
+        let rate = self.budget.bytes_per_second.max(1) as f64;
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.available = (self.available + elapsed * rate).min(self.budget.burst_bytes as f64);
+        self.refilled = now;
+
+        self.available -= bytes as f64;
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_wait_for_refill() {
+        let mut meter = SnapshotMeter::new(SnapshotBudget {
+            bytes_per_second: 1000,
+            burst_bytes: 2000,
+        });
+        let now = Instant::now();
+        assert_eq!(meter.reserve_at(1500, now), Duration::ZERO);
+        assert_eq!(meter.reserve_at(1000, now), Duration::from_millis(500));
+        // Half a second later the debt is paid off.
+        assert_eq!(
+            meter.reserve_at(0, now + Duration::from_millis(500)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn unlimited_never_waits() {
+        let mut meter = SnapshotMeter::new(SnapshotBudget::unlimited());
+        assert_eq!(meter.reserve(usize::MAX / 2), Duration::ZERO);
+    }
+}
@@ -0,0 +1,139 @@
+//! Catching snapshots that suddenly grow.
+//!
+//! A bug like recursive state or an unbounded list shows up as snapshots
+//! many times their usual size. A [`SizeTracker`] keeps a rolling average
+//! per session and flags the spikes (see
+//! [`Observer::on_snapshot_size_spike`](crate::Observer::on_snapshot_size_spike));
+//! a [`SpikeGuard`] holds back anything over a hard limit.
+
+use interconnect_core::WireError;
+use std::collections::VecDeque;
+
+/// Snapshots averaged by default.
+pub const DEFAULT_SIZE_WINDOW: usize = 32;
+
+/// A snapshot this many times the rolling average is a spike.
+pub const DEFAULT_SPIKE_THRESHOLD: f64 = 3.0;
+
+/// Rolling average of one session's snapshot sizes.
+#[derive(Debug, Clone)]
+pub struct SizeTracker {
+    window: VecDeque<usize>,
+    sum: usize,
+    capacity: usize,
+    spike_threshold: f64,
+}
+
+impl Default for SizeTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIZE_WINDOW, DEFAULT_SPIKE_THRESHOLD)
+    }
+}
+
+impl SizeTracker {
+    /// Average the last `capacity` sizes and flag any over `spike_threshold`
+    /// times that.
+    pub fn new(capacity: usize, spike_threshold: f64) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            window: VecDeque::with_capacity(capacity),
+            sum: 0,
+            capacity,
+            spike_threshold,
+        }
+    }
+
+    /// The average over the window, once there's anything in it.
+    pub fn rolling_avg(&self) -> Option<usize> {
+        (!self.window.is_empty()).then(|| self.sum / self.window.len())
+    }
+
+    /// Add a snapshot of `bytes`.
+    ///
+    /// Returns the average it's measured against if it's a spike. The spike
+    /// joins the window either way, so sustained growth soon stops counting.
+    pub fn record(&mut self, bytes: usize) -> Option<usize> {
+        let spike = self
+            .rolling_avg()
+            .filter(|&avg| avg > 0 && bytes as f64 > avg as f64 * self.spike_threshold);
+        if self.window.len() == self.capacity
+            && let Some(oldest) = self.window.pop_front()
+        {
+            self.sum -= oldest;
+        }
+        self.window.push_back(bytes);
+        self.sum += bytes;
+        spike
+    }
+}
+
+/// Holds back snapshots over a hard size limit.
+///
+/// A serialized snapshot can't be cut short without breaking it, so one over
+/// the limit isn't sent at all. It fails with [`WireError::TooLarge`] and
+/// the transport treats it as a snapshot that failed to encode (see
+/// [`SerializationFailurePolicy`](crate::SerializationFailurePolicy)). Off
+/// unless configured (see
+/// [`AuthorityConfig::snapshot_spike_guard`](crate::AuthorityConfig::snapshot_spike_guard)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpikeGuard {
+    pub hard_limit: usize,
+}
+
+impl SpikeGuard {
+    pub fn new(hard_limit: usize) -> Self {
+        Self { hard_limit }
+    }
+
+    /// No limit.
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    /// Whether a snapshot of `bytes` may be sent.
+    pub fn check(&self, bytes: usize) -> Result<(), WireError> {
+        if bytes > self.hard_limit {
+            return Err(WireError::TooLarge {
+                bytes,
+                limit: self.hard_limit,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_sudden_growth_against_the_rolling_average() {
+        let mut sizes = SizeTracker::new(4, DEFAULT_SPIKE_THRESHOLD);
+        assert_eq!(sizes.record(100), None);
+        assert_eq!(sizes.record(120), None);
+        assert_eq!(sizes.record(300), None);
+        assert_eq!(sizes.rolling_avg(), Some(173));
+        assert_eq!(sizes.record(1000), Some(173));
+
+        // Sustained growth becomes the new normal
+        for _ in 0..4 {
+            sizes.record(1000);
+        }
+        assert_eq!(sizes.rolling_avg(), Some(1000));
+        assert_eq!(sizes.record(2000), None);
+    }
+
+    #[test]
+    fn guard_refuses_over_the_hard_limit() {
+        assert!(SpikeGuard::unlimited().check(usize::MAX).is_ok());
+        let guard = SpikeGuard::new(1024);
+        assert!(guard.check(1024).is_ok());
+        assert!(matches!(
+            guard.check(4096),
+            Err(WireError::TooLarge {
+                bytes: 4096,
+                limit: 1024
+            })
+        ));
+    }
+}
@@ -0,0 +1,82 @@
+//! Spectating: one session seeing exactly what another sees.
+
+use interconnect_core::{Authority, Session};
+use std::collections::HashMap;
+
+/// Which session each spectator is watching.
+#[derive(Debug, Clone, Default)]
+pub struct SpectatorRegistry {
+    /// spectator session ID -> source session ID
+    links: HashMap<u64, u64>,
+}
+
+impl SpectatorRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `spectator` watch `source`, replacing any previous link.
+    pub fn follow(&mut self, spectator: u64, source: u64) {
+        self.links.insert(spectator, source);
+    }
+
+    /// Stop `spectator` watching anyone.
+    pub fn unfollow(&mut self, spectator: u64) {
+        self.links.remove(&spectator);
+    }
+
+    /// The session `spectator` is watching.
+    pub fn source_of(&self, spectator: u64) -> Option<u64> {
+        self.links.get(&spectator).copied()
+    }
+
+    /// Sessions watching `source`.
+    pub fn spectators_of(&self, source: u64) -> impl Iterator<Item = u64> + '_ {
+        self.links
+            .iter()
+            .filter(move |(_, s)| **s == source)
+            .map(|(spectator, _)| *spectator)
+    }
+
+    /// Drop every link to or from a session that left.
+    pub fn forget_session(&mut self, session_id: u64) {
+        self.links
+            .retain(|spectator, source| *spectator != session_id && *source != session_id);
+    }
+
+    /// The snapshot to send `session`: the watched session's view if it is
+    /// spectating and the authority supports it, otherwise its own.
+    pub fn snapshot_for<A: Authority>(&self, authority: &A, session: &Session) -> A::Snapshot {
+        self.source_of(session.id)
+            .and_then(|source| authority.clone_session_state(source, session.id))
+            .unwrap_or_else(|| authority.snapshot_for(session))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect_core::Identity;
+    use interconnect_core::testing::{Add, TestRoom};
+
+    #[test]
+    fn spectators_see_the_source_view() {
+        let mut room = TestRoom::new();
+        let alice = Session::new(1, Identity::local("alice"), "alice".into());
+        let spectator = Session::new(2, Identity::local("bob"), "bob".into());
+        Authority::handle_intent(&mut room, &alice, Add { by: 3 }).unwrap();
+        Authority::handle_intent(&mut room, &spectator, Add { by: 4 }).unwrap();
+
+        let mut registry = SpectatorRegistry::new();
+        assert_eq!(registry.snapshot_for(&room, &spectator), room.tallies());
+
+        registry.follow(2, 1);
+        assert_eq!(registry.snapshot_for(&room, &spectator), vec![(1, 3)]);
+        assert_eq!(registry.spectators_of(1).collect::<Vec<_>>(), [2]);
+
+        // The source leaving ends the link
+        registry.forget_session(1);
+        assert_eq!(registry.snapshot_for(&room, &spectator), room.tallies());
+    }
+}
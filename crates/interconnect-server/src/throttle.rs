@@ -0,0 +1,169 @@
+//! Per-topic snapshot rates.
+//!
+//! Not every change is equally urgent: presence can lag a second, game
+//! state can't. The authority tags each snapshot with a topic
+//! ([`Authority::snapshot_topic`]) and [`TopicThrottles`] gives each topic
+//! a [`BroadcastThrottle`]. A connection holds back a snapshot whose topic
+//! went out too recently and sends the latest state once the interval is
+//! up, so a burst of presence changes costs one snapshot. Snapshots are
+//! full state, so one sent for an unthrottled topic carries whatever was
+//! held back too.
+//!
+//! [`Authority::snapshot_topic`]: interconnect_core::Authority::snapshot_topic
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The least time between snapshots of one topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BroadcastThrottle {
+    pub min_interval: Duration,
+}
+
+impl BroadcastThrottle {
+    /// Send every snapshot at once.
+    pub const IMMEDIATE: Self = Self {
+        min_interval: Duration::ZERO,
+    };
+
+    pub fn new(min_interval: Duration) -> Self {
+        Self { min_interval }
+    }
+}
+
+/// A [`BroadcastThrottle`] per snapshot topic; untagged snapshots and
+/// topics without one are sent at once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicThrottles {
+    topics: HashMap<String, BroadcastThrottle>,
+}
+
+impl TopicThrottles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// With snapshots tagged `topic` sent at most once per `throttle`.
+    pub fn with_topic(mut self, topic: impl Into<String>, throttle: BroadcastThrottle) -> Self {
+        self.topics.insert(topic.into(), throttle);
+        self
+    }
+
+    /// The throttle for snapshots tagged `topic`.
+    pub fn throttle_for(&self, topic: Option<&str>) -> BroadcastThrottle {
+        topic
+            .and_then(|topic| self.topics.get(topic))
+            .copied()
+            .unwrap_or(BroadcastThrottle::IMMEDIATE)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+}
+
+/// One connection's view of [`TopicThrottles`]: when each topic last went
+/// out, and the snapshot it's holding back.
+#[derive(Debug)]
+pub struct TopicCoalescer {
+    throttles: TopicThrottles,
+    last_sent: HashMap<Option<&'static str>, Instant>,
+    held: Option<(Instant, Option<&'static str>)>,
+}
+
+impl TopicCoalescer {
+    pub fn new(throttles: TopicThrottles) -> Self {
+        Self {
+            throttles,
+            last_sent: HashMap::new(),
+            held: None,
+        }
+    }
+
+    /// Whether a snapshot tagged `topic` may go out now. If not, it's held
+    /// until [`due`](Self::due).
+    pub fn admit(&mut self, topic: Option<&'static str>) -> bool {
+        self.admit_at(topic, Instant::now())
+    }
+
+    fn admit_at(&mut self, topic: Option<&'static str>, now: Instant) -> bool {
+        let interval = self.throttles.throttle_for(topic).min_interval;
+        let Some(at) = self
+            .last_sent
+            .get(&topic)
+            .map(|last| *last + interval)
+            .filter(|at| now < *at)
+        else {
+            return true;
+        };
+        // The earliest hold wins; the snapshot sent then is the latest state
+        if self.held.is_none_or(|(held, _)| at < held) {
+            self.held = Some((at, topic));
+        }
+        false
+    }
+
+    /// When the held snapshot is due, if one is held.
+    pub fn due(&self) -> Option<Instant> {
+        self.held.map(|(at, _)| at)
+    }
+
+    /// Take the held snapshot's topic once it's due, to send it.
+    pub fn take_held(&mut self) -> Option<Option<&'static str>> {
+        self.held.take().map(|(_, topic)| topic)
+    }
+
+    /// A snapshot tagged `topic` went out. It carries the held state too.
+    pub fn sent(&mut self, topic: Option<&'static str>) {
+        self.sent_at(topic, Instant::now());
+    }
+
+    fn sent_at(&mut self, topic: Option<&'static str>, now: Instant) {
+        self.last_sent.insert(topic, now);
+        if let Some((_, held)) = self.held.take() {
+            self.last_sent.insert(held, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttles() -> TopicThrottles {
+        TopicThrottles::new().with_topic("presence", BroadcastThrottle::new(Duration::from_secs(1)))
+    }
+
+    #[test]
+    fn throttled_topic_is_held_until_its_interval_is_up() {
+        let mut coalescer = TopicCoalescer::new(throttles());
+        let now = Instant::now();
+        assert!(coalescer.admit_at(Some("presence"), now));
+        coalescer.sent_at(Some("presence"), now);
+
+        let soon = now + Duration::from_millis(200);
+        assert!(!coalescer.admit_at(Some("presence"), soon));
+        assert!(!coalescer.admit_at(Some("presence"), soon));
+        assert_eq!(coalescer.due(), Some(now + Duration::from_secs(1)));
+
+        // Game state isn't throttled and goes out at once
+        assert!(coalescer.admit_at(Some("game-state"), soon));
+        assert!(coalescer.admit_at(None, soon));
+
+        assert_eq!(coalescer.take_held(), Some(Some("presence")));
+        assert_eq!(coalescer.due(), None);
+    }
+
+    #[test]
+    fn unthrottled_snapshot_carries_the_held_one() {
+        let mut coalescer = TopicCoalescer::new(throttles());
+        let now = Instant::now();
+        coalescer.sent_at(Some("presence"), now);
+        assert!(!coalescer.admit_at(Some("presence"), now));
+
+        coalescer.sent_at(Some("game-state"), now + Duration::from_millis(10));
+        assert_eq!(coalescer.due(), None);
+        // The presence state just went out, so the interval restarts
+        assert!(!coalescer.admit_at(Some("presence"), now + Duration::from_secs(1)));
+    }
+}
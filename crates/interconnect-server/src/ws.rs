@@ -0,0 +1,225 @@
+//! WebSocket framing for wire messages.
+
+use interconnect_core::{
+    ClientWire, ConnectInfo, ErrorCode, ServerWire, SessionEncoding, WireEncoding, WireError,
+    to_json_string, wire_format_from_content_type,
+};
+use serde::Serialize;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::server::Request;
+use tokio_tungstenite::tungstenite::http::HeaderMap;
+
+/// Encode a wire message as a WebSocket frame.
+///
+/// The encoding decides whether the frame is text or binary, so transports
+/// don't repeat that decision at every send site.
+pub trait ToWsMessage {
+    /// Encode `self` as a frame using `encoding`.
+    fn to_ws_message(&self, encoding: WireEncoding) -> Result<Message, WireError>;
+}
+
+impl<S: Serialize> ToWsMessage for ServerWire<S> {
+    fn to_ws_message(&self, encoding: WireEncoding) -> Result<Message, WireError> {
+        encode(self, encoding)
+    }
+}
+
+impl<I: Serialize> ToWsMessage for ClientWire<I> {
+    fn to_ws_message(&self, encoding: WireEncoding) -> Result<Message, WireError> {
+        encode(self, encoding)
+    }
+}
+
+/// Pick `headers` out of a WebSocket upgrade request as connect-time hints
+/// for the authority (see [`ConnectInfo`]).
+///
+/// Only the named headers are taken, so credentials and cookies stay with
+/// the transport. Absent headers and values that aren't text are skipped.
+pub fn connect_info(request: &Request, headers: &[String]) -> ConnectInfo {
+    header_hints(request.headers(), headers)
+}
+
+/// The named `headers` out of `map`, as [`connect_info`] takes them.
+pub(crate) fn header_hints(map: &HeaderMap, headers: &[String]) -> ConnectInfo {
+    let mut info = ConnectInfo::new();
+    for name in headers {
+        if let Some(value) = map.get(name.as_str()).and_then(|value| value.to_str().ok()) {
+            info.insert(name, value);
+        }
+    }
+    info
+}
+
+/// Header a client reports its software version in.
+pub const CLIENT_VERSION_HEADER: &str = "x-client-version";
+
+/// The version a client reported in [`CLIENT_VERSION_HEADER`], for
+/// [`Session::client_version`](interconnect_core::Session::client_version).
+pub fn client_version(request: &Request) -> Option<String> {
+    let version = request
+        .headers()
+        .get(CLIENT_VERSION_HEADER)?
+        .to_str()
+        .ok()?
+        .trim();
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// Choose a connection's wire encoding from its WebSocket upgrade request.
+///
+/// The client's `Sec-WebSocket-Protocol` offers are tried in order, each
+/// read as a MIME type (see [`wire_format_from_content_type`]). The offer
+/// taken is returned too, since the upgrade response must echo it. Without
+/// a usable offer, `Content-Type` and then `Accept` decide, and failing
+/// those the connection uses JSON.
+pub fn negotiate_encoding(request: &Request) -> (SessionEncoding, Option<String>) {
+    for offer in header_list(request, "sec-websocket-protocol") {
+        if let Some(encoding) = wire_format_from_content_type(offer) {
+            return (SessionEncoding { encoding }, Some(offer.to_string()));
+        }
+    }
+    let encoding = ["content-type", "accept"]
+        .into_iter()
+        .flat_map(|name| header_list(request, name))
+        .find_map(wire_format_from_content_type)
+        .unwrap_or_default();
+    (SessionEncoding { encoding }, None)
+}
+
+/// The comma-separated entries of every `name` header.
+fn header_list<'a>(request: &'a Request, name: &'a str) -> impl Iterator<Item = &'a str> {
+    request
+        .headers()
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
+/// What to do when a session's snapshot doesn't serialize.
+///
+/// Each connection encodes its own snapshots, so a failure only ever
+/// concerns that session; the others keep receiving theirs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SerializationFailurePolicy {
+    /// Send the session an `internal_error` and keep it connected; its next
+    /// snapshot that serializes goes out as usual.
+    #[default]
+    NotifySession,
+    /// Skip the snapshot without telling the session.
+    Skip,
+    /// End the session.
+    Disconnect,
+}
+
+impl SerializationFailurePolicy {
+    /// What to send the session in place of a snapshot that failed with
+    /// `error`, if anything. `Disconnect` hands the error back, for the
+    /// caller to end the session with.
+    pub fn recover<S>(&self, error: WireError) -> Result<Option<ServerWire<S>>, WireError> {
+        match self {
+            Self::NotifySession => Ok(Some(ServerWire::error(
+                ErrorCode::InternalError,
+                match error {
+                    WireError::TooLarge { .. } => "Your snapshot was too large to send",
+                    WireError::Json(_) => "Your snapshot couldn't be encoded",
+                },
+            ))),
+            Self::Skip => Ok(None),
+            Self::Disconnect => Err(error),
+        }
+    }
+}
+
+fn encode<T: Serialize>(msg: &T, encoding: WireEncoding) -> Result<Message, WireError> {
+    match encoding {
+        WireEncoding::Json => Ok(Message::Text(to_json_string(msg)?.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_is_a_text_frame() {
+        let msg: ServerWire<()> = ServerWire::system("hello");
+        match msg.to_ws_message(WireEncoding::Json).unwrap() {
+            Message::Text(text) => assert_eq!(&*text, r#"{"type":"system","message":"hello"}"#),
+            other => panic!("expected text frame, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn connect_info_takes_only_named_headers() {
+        let request = Request::builder()
+            .header("X-Client-Version", "2.3.0")
+            .header("CF-IPCountry", "NZ")
+            .header("Cookie", "secret")
+            .body(())
+            .unwrap();
+        let headers = [
+            "x-client-version".to_string(),
+            "cf-ipcountry".to_string(),
+            "x-missing".to_string(),
+        ];
+        let info = connect_info(&request, &headers);
+        assert_eq!(info.get("X-Client-Version"), Some("2.3.0"));
+        assert_eq!(info.get("cf-ipcountry"), Some("NZ"));
+        assert_eq!(info.get("cookie"), None);
+        assert_eq!(info.hints().count(), 2);
+        // Reported whether or not it's a hint
+        assert_eq!(client_version(&request).as_deref(), Some("2.3.0"));
+    }
+
+    #[test]
+    fn encoding_follows_the_first_usable_offer() {
+        let request = Request::builder()
+            .header(
+                "Sec-WebSocket-Protocol",
+                "application/cbor, application/json",
+            )
+            .body(())
+            .unwrap();
+        let (encoding, protocol) = negotiate_encoding(&request);
+        assert_eq!(encoding.encoding, WireEncoding::Json);
+        assert_eq!(protocol.as_deref(), Some("application/json"));
+
+        // Nothing to echo when the choice came from another header
+        let request = Request::builder()
+            .header("Sec-WebSocket-Protocol", "application/cbor")
+            .header("Accept", "application/msgpack, application/json;q=0.5")
+            .body(())
+            .unwrap();
+        let (encoding, protocol) = negotiate_encoding(&request);
+        assert_eq!(encoding.encoding, WireEncoding::Json);
+        assert_eq!(protocol, None);
+
+        let request = Request::builder().body(()).unwrap();
+        assert_eq!(
+            negotiate_encoding(&request),
+            (SessionEncoding::default(), None)
+        );
+    }
+
+    #[test]
+    fn unserializable_snapshot_is_an_error() {
+        use std::collections::HashMap;
+        // JSON object keys must be strings
+        let data: HashMap<(u8, u8), u8> = [((1, 2), 3)].into();
+        let msg = ServerWire::snapshot(0, data);
+        let error = msg.to_ws_message(WireEncoding::Json).unwrap_err();
+
+        let notice = SerializationFailurePolicy::NotifySession
+            .recover::<()>(error)
+            .unwrap();
+        assert!(matches!(notice, Some(ServerWire::Error { code, .. }) if code == "internal_error"));
+        let error = msg.to_ws_message(WireEncoding::Json).unwrap_err();
+        assert!(
+            SerializationFailurePolicy::Disconnect
+                .recover::<()>(error)
+                .is_err()
+        );
+    }
+}
@@ -8,10 +8,30 @@
 //! Run two servers:
 //!   cargo run --example chat -- --port 8001 --name "Server A" --peer ws://localhost:8002
 //!   cargo run --example chat -- --port 8002 --name "Server B" --peer ws://localhost:8001
+//!
+//! Add `--federate` to push passports to the peer server-side instead of
+//! handing them to the client.
+//!
+//! `--schema` prints the JSON Schema of the wire protocol, for generating
+//! clients in other languages.
+//!
+//! Give servers the same `--session-key <id>:<secret>` to let clients
+//! reconnect to any of them with a signed token; the ID is published in the
+//! manifest, so pick one that gives nothing away (e.g. `2026-10`). To change
+//! the key without logging anyone out, restart them one at a time with the
+//! new `--session-key` and the old one as `--previous-session-key`.
+//!
+//! `--checkpoint <path>` saves the room's history to a file every 30 seconds
+//! and restores it on startup, so a crashed server comes back with its room.
+//!
+//! `INTERCONNECT_*` environment variables override the server's limits
+//! (e.g. `INTERCONNECT_MAX_SESSIONS=200` in a container); see
+//! `interconnect_server::AuthorityConfig` for the list.
 
 mod protocol;
 mod server;
 
+use protocol::{ChatIntent, ChatSnapshot};
 use std::net::SocketAddr;
 use tracing_subscriber::EnvFilter;
 
@@ -22,9 +42,18 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|a| a == "--schema") {
+        let schema = interconnect_core::wire_schema::<ChatIntent, ChatSnapshot>();
+        println!("{}", serde_json::to_string_pretty(&schema)?);
+        return Ok(());
+    }
     let port = parse_arg(&args, "--port").unwrap_or(8001);
     let name = parse_arg_string(&args, "--name").unwrap_or_else(|| format!("Server:{port}"));
     let peer = parse_arg_string(&args, "--peer");
+    let federate = args.iter().any(|a| a == "--federate");
+    let session_key = parse_arg_string(&args, "--session-key");
+    let previous_session_key = parse_arg_string(&args, "--previous-session-key");
+    let checkpoint = parse_arg_string(&args, "--checkpoint");
 
     let addr: SocketAddr = ([127, 0, 0, 1], port).into();
 
@@ -33,7 +62,16 @@ async fn main() -> anyhow::Result<()> {
         tracing::info!("Peer server: {}", p);
     }
 
-    server::run(addr, name, peer).await
+    server::run(
+        addr,
+        name,
+        peer,
+        federate,
+        session_key,
+        previous_session_key,
+        checkpoint,
+    )
+    .await
 }
 
 fn parse_arg(args: &[String], flag: &str) -> Option<u16> {
@@ -2,34 +2,64 @@
 //!
 //! Uses interconnect_core's wire types for the transport layer.
 
+use interconnect_core::{ByteSize, Ephemeral, Timestamp};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Chat intents (what clients can request).
 ///
 /// Note: Transfer is handled by the wire protocol's `TransferRequest`,
 /// not as an intent. Intents are domain-specific actions.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "action", rename_all = "snake_case")]
 pub enum ChatIntent {
     /// Send a message to the room.
     Message { text: String },
+    /// Signal that the user is typing (expires on its own).
+    Typing,
 }
 
-/// Chat snapshot (current room state).
+/// Chat server metadata, advertised in the manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMeta {
+    /// Always `"chat"`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Most users the room admits at once.
+    pub max_users: usize,
+}
+
+/// Read-only queries a chat server answers (in `ClientWire::Query`).
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "query", rename_all = "snake_case")]
+pub enum ChatQuery {
+    /// The room's message history, oldest first.
+    History,
+}
+
+/// Chat snapshot (current room state).
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ChatSnapshot {
     /// Recent messages (newest last).
     pub messages: Vec<ChatMessage>,
     /// Users currently in the room.
     pub users: Vec<String>,
+    /// Users currently typing. Clients drop entries once they expire.
+    pub typing: Vec<Ephemeral<String>>,
 }
 
 /// A chat message.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ChatMessage {
     pub from: String,
     pub text: String,
-    pub timestamp: u64,
+    pub timestamp: Timestamp,
+}
+
+impl ByteSize for ChatMessage {
+    fn byte_size(&self) -> usize {
+        self.from.len() + self.text.len()
+    }
 }
 
 /// Chat passport (what transfers between servers).
@@ -1,25 +1,145 @@
 //! Chat server implementation using interconnect-core abstractions.
 
-use crate::protocol::{ChatIntent, ChatMessage, ChatPassport, ChatSnapshot};
-use futures_util::{SinkExt, StreamExt};
+use crate::protocol::{ChatIntent, ChatMessage, ChatMeta, ChatPassport, ChatQuery, ChatSnapshot};
+use futures_util::{Sink, SinkExt, StreamExt};
 use interconnect_core::{
-    from_json_str, to_json_string, ClientWire, Identity, ImportResult, Manifest, ServerWire,
-    Session, SimpleAuthority,
+    AliasError, Authority, AuthorityMiddleware, ClientPrediction, ClientWire, ConnectInfo,
+    ConnectionQuality, Delivery, DisconnectReason, Ephemeral, ErrorCode, ExportedSession,
+    FieldPresenceValidator, Identity, IdentityKeyring, ImportResult, ImportSessionError,
+    IntentAliasRegistry, InvariantViolation, Layered, LoopbackAction, Manifest, MemoryBudget,
+    Passport, PassportDecodeAction, PassportValidationChain, PassportValidationError, Persistable,
+    QueryError, QueryPage, RecordingAuthority, RingLog, ServerName, ServerWire, Session,
+    SigningKey, SimpleAuthority, SnapshotBudget, Timestamp, TransferSnapshot, WireEncoding,
+    WireError, WireErrorAction, from_json_str, split_transfer_snapshot, to_json_string, unexpired,
 };
+use interconnect_server::{
+    AcceptLimiter, AcceptPolicy, AuthorityConfig, CoalesceConfig, ConsistencyPolicy, DedupCache,
+    FederationClient, FederationError, FederationRequest, FileCheckpointStore, FrameBatcher,
+    LatencyProber, LoggingObserver, ManifestCache, Observer, OwnWrites, PanicGuard, PanicPolicy,
+    PauseBuffer, PeerTransferBatcher, PendingConnection, PendingTransfers,
+    PeriodicInvariantChecker, QualityEstimator, Received, ReconnectGrace, ResumeStore, Resumed,
+    SerializationFailurePolicy, SizeTracker, SnapshotMeter, SpikeGuard, StateBroadcast,
+    TicketStore, ToWsMessage, YieldBudget, accept_push, connect_info, debug_assert_invariants,
+};
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, RwLock};
-use tokio_tungstenite::tungstenite::Message;
+use tokio::sync::RwLock;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// Messages kept per room.
+const ROOM_HISTORY: usize = 100;
+
+/// Message bytes kept across all rooms on this server.
+const HISTORY_BUDGET_BYTES: usize = 1 << 20;
+
+/// How long a typing indicator lasts without being refreshed.
+const TYPING_TTL: Duration = Duration::from_secs(3);
+
+/// How long a dropped session is held for the client to resume.
+const RECONNECT_GRACE: ReconnectGrace = ReconnectGrace::new(Duration::from_secs(10));
+
+/// Arrivals from the same peer within this window are summarized together.
+const PEER_TRANSFER_WINDOW: Duration = Duration::from_secs(5);
+
+/// Outbound broadcast bytes each session may receive.
+const SNAPSHOT_BUDGET: SnapshotBudget = SnapshotBudget {
+    bytes_per_second: 256 * 1024,
+    burst_bytes: 1 << 20,
+};
+
+/// How long a passport pushed by a peer waits for its client.
+const TICKET_TTL: Duration = Duration::from_secs(30);
+
+/// Display names that can't be carried in from another server.
+const RESERVED_NAMES: &[&str] = &["admin", "system"];
+
+/// Longest display name accepted on transfer-in, in characters.
+const MAX_NAME_LEN: usize = 32;
+
+/// How often every session is pinged for latency.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Sessions that don't answer a ping within this are reported as silent.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Round trips slower than this are reported as lagging.
+const LAGGING_RTT: Duration = Duration::from_millis(500);
+
+/// Connections slower than this (bytes per second) get short snapshots.
+const SLOW_BANDWIDTH: u64 = 32 * 1024;
+
+/// Connections losing more pings than this get short snapshots.
+const LOSSY_CONNECTION: f32 = 0.25;
+
+/// Messages in a snapshot for a lagging, slow, or lossy connection.
+const DEGRADED_HISTORY: usize = 10;
+
+/// Connection events kept for auditing.
+const CONNECTION_LOG_CAPACITY: usize = 1024;
+
+/// Words masked out of chat messages.
+const BLOCKED_WORDS: &[&str] = &["darn", "heck"];
+
+/// Messages per page of a history query.
+const HISTORY_PAGE_SIZE: usize = 20;
+
+/// Users the room admits at once.
+const MAX_USERS: usize = 64;
+
+/// Waiting broadcasts sent to a session in one frame.
+const MAX_BROADCAST_BATCH: usize = 32;
+
+/// A panicking intent ends its session; the room carries on.
+const PANIC_POLICY: PanicPolicy = PanicPolicy::KillSession;
+
+/// Tracked intents remembered so retries aren't applied twice.
+const APPLIED_INTENTS: usize = 4096;
+
+/// Broadcasts held for a paused session before it gets a full snapshot on
+/// resume instead.
+const MAX_PAUSED_BROADCASTS: usize = 16;
+
+/// Runs of held broadcasts this long go out as one snapshot on resume.
+const COALESCE_PAUSED_FROM: usize = 4;
+
+/// Consecutive malformed messages before a session is disconnected.
+const MAX_MALFORMED: u32 = 10;
+
+/// Connection limits per remote address, applied before the upgrade.
+const ACCEPT_POLICY: AcceptPolicy = AcceptPolicy {
+    max_pending: 8,
+    per_ip_rate: 10,
+};
+
+/// Senders see their own messages land without waiting behind broadcasts.
+const CONSISTENCY: ConsistencyPolicy = ConsistencyPolicy {
+    read_your_writes: true,
+};
+
+/// How often the room's invariants are checked in the background.
+const INVARIANT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the room is checkpointed when `--checkpoint` is given.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long reconnect tokens signed with `--previous-session-key` are still
+/// accepted, while other instances move to the new key.
+const KEY_OVERLAP: Duration = Duration::from_secs(60 * 60);
 
 /// The chat room authority.
 pub struct ChatRoom {
-    name: String,
-    peer: Option<String>,
-    messages: Vec<ChatMessage>,
+    name: ServerName,
+    peer: Option<ServerName>,
+    messages: RingLog<ChatMessage>,
+    max_users: usize,
     users: HashMap<u64, (Identity, String)>, // session_id -> (identity, name)
+    typing: HashMap<u64, Ephemeral<String>>, // session_id -> name
+    malformed: HashMap<u64, u32>,            // session_id -> consecutive bad messages
 }
 
 /// Error type for chat operations.
@@ -31,32 +151,76 @@ pub enum ChatError {
 }
 
 impl ChatRoom {
-    pub fn new(name: String, peer: Option<String>) -> Self {
+    pub fn new(
+        name: ServerName,
+        peer: Option<ServerName>,
+        max_users: usize,
+        budget: &MemoryBudget,
+    ) -> Self {
         Self {
             name,
             peer,
-            messages: Vec::new(),
+            messages: RingLog::with_budget(ROOM_HISTORY, budget),
+            max_users,
             users: HashMap::new(),
+            typing: HashMap::new(),
+            malformed: HashMap::new(),
         }
     }
 
+    fn is_full(&self) -> bool {
+        self.users.len() >= self.max_users
+    }
+
     fn add_message(&mut self, from: &str, text: String) {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
         self.messages.push(ChatMessage {
             from: from.to_string(),
             text,
-            timestamp,
+            timestamp: Timestamp::now(),
         });
-        // Keep last 100 messages
-        if self.messages.len() > 100 {
-            self.messages.remove(0);
+    }
+}
+
+// Only the history survives a restart; users and typing belong to connections.
+impl Persistable for ChatRoom {
+    type Error = serde_json::Error;
+
+    fn checkpoint(&self) -> Result<Vec<u8>, Self::Error> {
+        let history: Vec<&ChatMessage> = self.messages.iter().collect();
+        serde_json::to_vec(&history)
+    }
+
+    fn restore(&mut self, checkpoint: &[u8]) -> Result<(), Self::Error> {
+        let history: Vec<ChatMessage> = serde_json::from_slice(checkpoint)?;
+        for message in history {
+            self.messages.push(message);
         }
+        Ok(())
     }
 }
 
+/// Import policy: reserved names fall back to the session name, and
+/// over-long names are truncated.
+fn import_name(session: &Session, passport: ChatPassport) -> ImportResult<ChatPassport> {
+    let arrived = passport.name.clone();
+    let reserved = RESERVED_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(&arrived));
+    let wanted = if reserved { &session.name } else { &arrived };
+    let name: String = wanted.chars().take(MAX_NAME_LEN).collect();
+    ImportResult::builder(ChatPassport {
+        name: name.clone(),
+        ..passport
+    })
+    .when(reserved, |b| {
+        b.reject_code("name", "reserved_name", format!("{arrived} is reserved"))
+    })
+    .when(name != *wanted, |b| {
+        b.transform("name", wanted, name.as_str())
+    })
+    .build()
+}
+
 impl SimpleAuthority for ChatRoom {
     type Intent = ChatIntent;
     type Snapshot = ChatSnapshot;
@@ -76,20 +240,52 @@ impl SimpleAuthority for ChatRoom {
         passport: Self::Passport,
     ) -> Result<ImportResult<Self::Passport>, Self::Error> {
         tracing::info!("{} arrived from {}", passport.name, passport.origin);
-        self.users
-            .insert(session.id, (session.identity.clone(), passport.name.clone()));
 
-        // Accept everything for chat - no import policy needed
-        Ok(ImportResult::accept(passport))
+        let import = import_name(session, passport);
+        self.users.insert(
+            session.id,
+            (session.identity.clone(), import.passport.name.clone()),
+        );
+        Ok(import)
+    }
+
+    fn on_transfer_context(&mut self, session: &Session, context: Self::Snapshot) {
+        // Another server's room: what they were reading there doesn't carry over
+        tracing::debug!(
+            "{} arrived with {} messages of context",
+            session.name,
+            context.messages.len()
+        );
+    }
+
+    fn validate_passport(
+        &self,
+        _session: &Session,
+        passport: &Self::Passport,
+    ) -> Result<(), Vec<PassportValidationError>> {
+        PassportValidationChain::new()
+            .with(
+                FieldPresenceValidator::new()
+                    .require("name", |p: &ChatPassport| p.name.as_str())
+                    .require("origin", |p: &ChatPassport| p.origin.as_str()),
+            )
+            .validate(passport)
     }
 
     fn on_disconnect(&mut self, session: &Session) {
+        self.typing.remove(&session.id);
+        self.malformed.remove(&session.id);
         if let Some((_, name)) = self.users.remove(&session.id) {
             tracing::info!("{} left", name);
         }
     }
 
-    fn handle_intent(&mut self, session: &Session, intent: Self::Intent) -> Result<(), Self::Error> {
+    fn handle_intent(
+        &mut self,
+        session: &Session,
+        intent: Self::Intent,
+    ) -> Result<(), Self::Error> {
+        self.malformed.remove(&session.id);
         let name = self
             .users
             .get(&session.id)
@@ -98,163 +294,856 @@ impl SimpleAuthority for ChatRoom {
 
         match intent {
             ChatIntent::Message { text } => {
+                self.typing.remove(&session.id);
                 self.add_message(&name, text);
             }
+            ChatIntent::Typing => {
+                self.typing
+                    .insert(session.id, Ephemeral::new(name, TYPING_TTL));
+            }
         }
         Ok(())
     }
 
+    fn on_budget_exceeded(
+        &mut self,
+        session: &Session,
+        snapshot_size: usize,
+        budget: &SnapshotBudget,
+    ) {
+        tracing::warn!(
+            "Snapshot of {} bytes puts {} over budget ({} B/s)",
+            snapshot_size,
+            session.name,
+            budget.bytes_per_second
+        );
+    }
+
+    fn on_peer_transfer_complete(
+        &mut self,
+        src_identity: &Identity,
+        accepted: u64,
+        rejected: u64,
+        elapsed: Duration,
+    ) {
+        tracing::info!(
+            "{} users arrived from {} over {:?} ({} refused)",
+            accepted,
+            src_identity,
+            elapsed,
+            rejected
+        );
+    }
+
+    fn intent_type_name(intent: &Self::Intent) -> &'static str {
+        match intent {
+            ChatIntent::Message { .. } => "message",
+            ChatIntent::Typing => "typing",
+        }
+    }
+
     fn snapshot(&self) -> Self::Snapshot {
         ChatSnapshot {
             messages: self.messages.iter().rev().take(50).rev().cloned().collect(),
             users: self.users.values().map(|(_, name)| name.clone()).collect(),
+            typing: unexpired(self.typing.values()).cloned().collect(),
         }
     }
 
+    fn snapshot_for(&self, session: &Session) -> Self::Snapshot {
+        let mut snapshot = self.snapshot();
+        // Poor connections get recent messages only; the rest is a history query away
+        if session
+            .quality
+            .is_worse_than(LAGGING_RTT, SLOW_BANDWIDTH, LOSSY_CONNECTION)
+        {
+            let skip = snapshot.messages.len().saturating_sub(DEGRADED_HISTORY);
+            snapshot.messages.drain(..skip);
+        }
+        snapshot
+    }
+
     fn emit_passport(&self, session: &Session) -> Self::Passport {
         let name = self
             .users
             .get(&session.id)
             .map(|(_, n)| n.clone())
             .unwrap_or_else(|| session.name.clone());
-        ChatPassport::new(name, self.name.clone())
+        ChatPassport::new(name, self.name.to_string())
     }
 
     fn validate_destination(&self, destination: &str) -> bool {
-        self.peer.as_ref() == Some(&destination.to_string())
+        self.peer
+            .as_ref()
+            .is_some_and(|peer| peer.matches(destination))
     }
+
+    fn assert_invariants(&self) -> Result<(), InvariantViolation> {
+        // Typing indicators belong to connected users
+        let strays: Vec<u64> = self
+            .typing
+            .keys()
+            .filter(|id| !self.users.contains_key(id))
+            .copied()
+            .collect();
+        if !strays.is_empty() {
+            return Err(InvariantViolation::new(
+                "typing indicator for a session that isn't in the room",
+                serde_json::json!({ "sessions": strays }),
+            ));
+        }
+        Ok(())
+    }
+
+    fn query_page(
+        &self,
+        _session: &Session,
+        query: &serde_json::Value,
+        cursor: Option<&str>,
+    ) -> Result<QueryPage, QueryError> {
+        let query =
+            ChatQuery::deserialize(query).map_err(|e| QueryError::Invalid(e.to_string()))?;
+        match query {
+            ChatQuery::History => {
+                let history: Vec<&ChatMessage> = self.messages.iter().collect();
+                QueryPage::paginate(&history, HISTORY_PAGE_SIZE, cursor)
+            }
+        }
+    }
+
+    fn export_sessions(&self) -> Vec<ExportedSession> {
+        self.users
+            .iter()
+            .map(|(&id, (identity, name))| ExportedSession {
+                session: Session::new(id, identity.clone(), name.clone()),
+                authority_state: name.clone().into_bytes(),
+            })
+            .collect()
+    }
+
+    fn import_sessions(
+        &mut self,
+        sessions: Vec<ExportedSession>,
+    ) -> Result<(), ImportSessionError> {
+        // Check everything first so a bad batch leaves the room untouched
+        let mut users = Vec::with_capacity(sessions.len());
+        for exported in sessions {
+            let session_id = exported.session.id;
+            if self.users.contains_key(&session_id) {
+                return Err(ImportSessionError::Duplicate { session_id });
+            }
+            let name = String::from_utf8(exported.authority_state).map_err(|e| {
+                ImportSessionError::InvalidState {
+                    session_id,
+                    reason: e.to_string(),
+                }
+            })?;
+            users.push((session_id, (exported.session.identity, name)));
+        }
+        self.users.extend(users);
+        Ok(())
+    }
+
+    fn on_wire_error(
+        &mut self,
+        session: Option<&Session>,
+        _raw: &str,
+        error: &serde_json::Error,
+    ) -> WireErrorAction {
+        // Unauthenticated garbage just gets dropped
+        let Some(session) = session else {
+            return WireErrorAction::Ignore;
+        };
+        let count = self.malformed.entry(session.id).or_default();
+        *count += 1;
+        if *count >= MAX_MALFORMED {
+            tracing::info!(
+                "Disconnecting {}: {} malformed messages",
+                session.name,
+                count
+            );
+            WireErrorAction::Disconnect
+        } else {
+            WireErrorAction::WarnClient {
+                message: format!("Malformed message: {}", error),
+            }
+        }
+    }
+}
+
+/// Masks blocked words in messages before the room sees them.
+pub struct TextSanitizingMiddleware {
+    blocked: &'static [&'static str],
 }
 
+impl TextSanitizingMiddleware {
+    pub fn new(blocked: &'static [&'static str]) -> Self {
+        Self { blocked }
+    }
+
+    fn sanitize(&self, text: &str) -> String {
+        text.split(' ')
+            .map(|word| {
+                let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+                if self.blocked.iter().any(|b| b.eq_ignore_ascii_case(bare)) {
+                    word.replace(bare, &"*".repeat(bare.len()))
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl AuthorityMiddleware<ChatRoom> for TextSanitizingMiddleware {
+    fn before_validate(&mut self, _session: &Session, intent: &mut ChatIntent) {
+        if let ChatIntent::Message { text } = intent {
+            *text = self.sanitize(text);
+        }
+    }
+}
+
+/// Decode the pre-intent `{"type":"chat_message","text":..}` form.
+fn legacy_chat_message(raw: serde_json::Value) -> Result<ChatIntent, serde_json::Error> {
+    #[derive(Deserialize)]
+    struct LegacyMessage {
+        text: String,
+    }
+    let legacy: LegacyMessage = serde_json::from_value(raw)?;
+    Ok(ChatIntent::Message { text: legacy.text })
+}
+
+/// The room, behind its middleware, with a log of who came and went.
+type Room = RecordingAuthority<Layered<ChatRoom>>;
+
+/// A frame sent to every connection.
+#[derive(Clone)]
+struct Broadcast {
+    text: String,
+    /// The session whose intent produced this snapshot.
+    origin: Option<u64>,
+}
+
+impl Broadcast {
+    fn system(msg: &ServerWire<ChatSnapshot>) -> anyhow::Result<Self> {
+        Ok(Self {
+            text: to_json_string(msg)?,
+            origin: None,
+        })
+    }
+
+    fn snapshot(
+        msg: &ServerWire<ChatSnapshot>,
+        origin: u64,
+        guard: SpikeGuard,
+    ) -> Result<Self, WireError> {
+        let text = to_json_string(msg)?;
+        guard.check(text.len())?;
+        Ok(Self {
+            text,
+            origin: Some(origin),
+        })
+    }
+}
+
+/// Held broadcasts as one snapshot, reduced by the room and sent at the
+/// seq of the last one held. The joins and leaves among them are in its
+/// user list; `None` if none of them was a snapshot.
+fn coalesce_broadcasts(room: &Room, texts: Vec<String>) -> Option<String> {
+    let mut seq = 0;
+    let snapshots = texts
+        .iter()
+        .filter_map(|text| match from_json_str(text) {
+            Ok(ServerWire::<ChatSnapshot>::Snapshot { seq: at, data, .. }) => {
+                seq = at;
+                Some(data)
+            }
+            _ => None,
+        })
+        .collect();
+    let snapshot = room.coalesce_snapshots(snapshots)?;
+    let msg: ServerWire<ChatSnapshot> = ServerWire::snapshot(seq, snapshot);
+    to_json_string(&msg).ok()
+}
+
+/// Snapshots coalesce per connection; joins, leaves and the like all arrive.
+type Broadcasts = StateBroadcast<Broadcast, Broadcast>;
+
 // Server state shared across connections
 struct ServerState {
-    room: ChatRoom,
-    manifest: Manifest,
+    room: Room,
+    config: AuthorityConfig,
+    /// `config.manifest`, encoded once for every connection.
+    manifest: ManifestCache,
     next_session_id: u64,
+    observer: Box<dyn Observer>,
+    resume: ResumeStore,
+    peer_transfers: PeerTransferBatcher,
+    tickets: TicketStore,
+    federation: Option<FederationClient>,
+    pending_transfers: PendingTransfers,
+    /// Tracked intents already applied, by sender and request ID.
+    applied_intents: DedupCache<(Identity, u64)>,
+    prober: Arc<LatencyProber>,
+    /// Signs reconnect tokens and our manifest, and checks the source
+    /// manifests clients relay with transfers; `None` issues no tokens and
+    /// refuses transfers the client carries.
+    keyring: Option<IdentityKeyring>,
 }
 
 type SharedState = Arc<RwLock<ServerState>>;
 
-pub async fn run(addr: SocketAddr, name: String, peer: Option<String>) -> anyhow::Result<()> {
-    let identity = Identity::local(&name);
-    let manifest = Manifest {
-        identity: identity.clone(),
-        name: name.clone(),
-        substrate: None,
-        metadata: serde_json::json!({ "type": "chat" }),
+pub async fn run(
+    addr: SocketAddr,
+    name: String,
+    peer: Option<String>,
+    federate: bool,
+    session_key: Option<String>,
+    previous_session_key: Option<String>,
+    checkpoint: Option<String>,
+) -> anyhow::Result<()> {
+    let name = ServerName::new(&name);
+    // Docker deployments tune these through INTERCONNECT_* variables
+    let mut config = AuthorityConfig {
+        accept: ACCEPT_POLICY,
+        reconnect: RECONNECT_GRACE,
+        snapshot_budget: SNAPSHOT_BUDGET,
+        panic_policy: PANIC_POLICY,
+        max_sessions: Some(MAX_USERS),
+        pause_buffer: CoalesceConfig::new(MAX_PAUSED_BROADCASTS)
+            .coalesce_from(COALESCE_PAUSED_FROM),
+        ..AuthorityConfig::new(Manifest {
+            identity: name.identity(),
+            name,
+            substrate: None,
+            metadata: serde_json::Value::Null,
+            snapshot_type: None,
+            intent_type: None,
+            // As given to --peer on the other server
+            endpoint: Some(format!("ws://localhost:{}", addr.port())),
+            signing_keys: Vec::new(),
+            signature: None,
+        })
+    }
+    .with_env_override()?;
+    let max_users = config.max_sessions.unwrap_or(usize::MAX);
+    let budget = MemoryBudget::new(HISTORY_BUDGET_BYTES);
+    let peer = peer.as_deref().map(ServerName::new);
+    let mut room = ChatRoom::new(config.manifest.name.clone(), peer, max_users, &budget);
+    let checkpoints = checkpoint.map(FileCheckpointStore::new);
+    if let Some(store) = &checkpoints
+        && store.load(&mut room)?
+    {
+        tracing::info!(
+            "Restored {} messages from {}",
+            room.messages.len(),
+            store.path().display()
+        );
+    }
+    config.manifest = config
+        .manifest
+        .with_types(&room)
+        .with_typed_metadata(&ChatMeta {
+            kind: "chat".to_string(),
+            max_users,
+        })?;
+    // During a rotation, sign with the new key and still accept the old one
+    let keyring = match (session_key, previous_session_key) {
+        (Some(key), Some(previous)) => {
+            let mut keyring = IdentityKeyring::new(signing_key(&previous)?, KEY_OVERLAP);
+            keyring.rotate(signing_key(&key)?);
+            Some(keyring)
+        }
+        (Some(key), None) => Some(IdentityKeyring::new(signing_key(&key)?, KEY_OVERLAP)),
+        (None, _) => None,
     };
+    // Signed, so peers sharing the key trust it when clients relay it
+    if let Some(keyring) = &keyring {
+        config.manifest = config.manifest.with_keyring(keyring).sign(keyring);
+    }
+    let room = Layered::new(room).layer(TextSanitizingMiddleware::new(BLOCKED_WORDS));
+    let room = RecordingAuthority::new(room, CONNECTION_LOG_CAPACITY);
+    let federation = federate.then(|| FederationClient::new(config.manifest.clone()));
+    let manifest = ManifestCache::new(config.manifest.clone())?;
+    let limiter = AcceptLimiter::new(config.accept);
 
     let state = Arc::new(RwLock::new(ServerState {
-        room: ChatRoom::new(name, peer),
-        manifest,
+        room,
         next_session_id: 1,
+        observer: Box::new(LoggingObserver),
+        resume: ResumeStore::new(config.reconnect),
+        peer_transfers: PeerTransferBatcher::new(PEER_TRANSFER_WINDOW),
+        config,
+        manifest,
+        tickets: TicketStore::new(TICKET_TTL),
+        federation,
+        pending_transfers: PendingTransfers::new(),
+        applied_intents: DedupCache::new(APPLIED_INTENTS),
+        prober: Arc::new(LatencyProber::new(PING_TIMEOUT)),
+        keyring,
     }));
 
-    let (broadcast_tx, _) = broadcast::channel::<String>(100);
+    // Summarize bursts of arrivals from each peer once their window closes
+    {
+        let state = state.clone();
+        let limiter = limiter.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(PEER_TRANSFER_WINDOW);
+            loop {
+                tick.tick().await;
+                // Forget addresses that have gone quiet
+                limiter.prune();
+                let mut s = state.write().await;
+                // Reclaim tickets whose clients never showed up
+                s.tickets.expire();
+                for stats in s.peer_transfers.flush_due() {
+                    s.room.on_peer_transfer_complete(
+                        &stats.src_identity,
+                        stats.accepted,
+                        stats.rejected,
+                        stats.elapsed,
+                    );
+                }
+            }
+        });
+    }
+
+    // Probe round-trip times so lagging clients show up in the logs
+    {
+        let prober = state.read().await.prober.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(PING_INTERVAL);
+            loop {
+                tick.tick().await;
+                for (session_id, rtt) in prober.ping_all().await {
+                    match rtt {
+                        Some(rtt) if rtt > LAGGING_RTT => {
+                            tracing::info!("Session {} is lagging ({:?} RTT)", session_id, rtt)
+                        }
+                        None => tracing::info!("Session {} didn't answer ping", session_id),
+                        _ => {}
+                    }
+                }
+            }
+        });
+    }
+
+    // Save the room periodically so a crash loses at most one interval
+    if let Some(store) = checkpoints {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(CHECKPOINT_INTERVAL);
+            loop {
+                tick.tick().await;
+                let result = store.save(state.read().await.room.inner().inner());
+                if let Err(e) = result {
+                    tracing::warn!("Checkpoint to {} failed: {}", store.path().display(), e);
+                }
+            }
+        });
+    }
+
+    // Catch room state drifting in release builds too
+    {
+        let state = state.clone();
+        let checker = PeriodicInvariantChecker::new(INVARIANT_CHECK_INTERVAL);
+        tokio::spawn(checker.run(
+            move || {
+                let state = state.clone();
+                async move { state.read().await.room.assert_invariants() }
+            },
+            |violation| tracing::error!("{} ({})", violation, violation.context),
+        ));
+    }
+
+    let broadcast_tx = Broadcasts::new(100);
 
     let listener = TcpListener::bind(addr).await?;
     tracing::info!("Listening on ws://{}", addr);
 
     loop {
         let (stream, client_addr) = listener.accept().await?;
+        // Refuse floods before spending anything on the upgrade
+        let pending = match limiter.admit(client_addr.ip()) {
+            Ok(pending) => pending,
+            Err(e) => {
+                tracing::debug!("Refused connection from {}: {}", client_addr, e);
+                continue;
+            }
+        };
         let state = state.clone();
         let broadcast_tx = broadcast_tx.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, client_addr, state, broadcast_tx).await {
+            if let Err(e) =
+                handle_connection(stream, client_addr, pending, state, broadcast_tx).await
+            {
                 tracing::warn!("Connection error from {}: {}", client_addr, e);
             }
         });
     }
 }
 
+/// A `--session-key` given as `<id>:<secret>`.
+fn signing_key(arg: &str) -> anyhow::Result<SigningKey> {
+    // Never echo the argument: it holds the secret
+    let (id, secret) = arg
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Session keys are given as <id>:<secret>"))?;
+    Ok(SigningKey::new(id, secret))
+}
+
+/// Decode an incoming transfer payload.
+///
+/// Accepts a full `TransferSnapshot`, or a bare passport from older servers,
+/// which comes without the session's view at the origin.
+fn decode_transfer(data: &[u8]) -> Result<(Option<ChatSnapshot>, ChatPassport), serde_json::Error> {
+    let error = match serde_json::from_slice::<TransferSnapshot<ChatSnapshot, ChatPassport>>(data) {
+        Ok(ts) => {
+            let (context, passport) = split_transfer_snapshot(ts);
+            return Ok((Some(context), passport));
+        }
+        Err(e) => e,
+    };
+    // Report the current format's error, not the legacy one's
+    let passport = serde_json::from_slice(data).map_err(|_| error)?;
+    Ok((None, passport))
+}
+
 async fn handle_connection(
     stream: TcpStream,
     addr: SocketAddr,
+    pending: PendingConnection,
     state: SharedState,
-    broadcast_tx: broadcast::Sender<String>,
+    broadcast_tx: Broadcasts,
 ) -> anyhow::Result<()> {
-    let ws = tokio_tungstenite::accept_async(stream).await?;
+    // Headers the room sees at connect, e.g. a proxy's client version
+    let hint_headers = state.read().await.config.hint_headers.clone();
+    let mut info = ConnectInfo::new();
+    // The callback's signature is fixed by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let ws = tokio_tungstenite::accept_hdr_async(
+        stream,
+        |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+            info = connect_info(request, &hint_headers);
+            Ok(response)
+        },
+    )
+    .await?;
     let (mut sink, mut stream) = ws.split();
 
     tracing::debug!("New connection from {}", addr);
 
-    // Wait for auth
-    let session = loop {
+    // Wait for auth (or a resume of a held session)
+    let (mut session, resumed, delivery) = loop {
         let msg = stream
             .next()
             .await
             .ok_or(anyhow::anyhow!("Connection closed"))??;
 
         if let Message::Text(text) = msg {
-            let wire: ClientWire<ChatIntent> = from_json_str(&text)?;
+            // A peer pushing a transfer rather than a client
+            if let Ok(request) = from_json_str::<FederationRequest>(&text) {
+                let response = {
+                    let mut s = state.write().await;
+                    let s = &mut *s;
+                    accept_push(&s.room, &mut s.tickets, request)
+                };
+                sink.send(Message::Text(to_json_string(&response)?.into()))
+                    .await?;
+                return Ok(());
+            }
 
-            if let ClientWire::Auth {
+            let wire: ClientWire<ChatIntent> = match from_json_str(&text) {
+                Ok(wire) => wire,
+                Err(e) => {
+                    let action = state.write().await.room.on_wire_error(None, &text, &e);
+                    match action {
+                        WireErrorAction::Ignore => tracing::warn!("Invalid message: {}", e),
+                        WireErrorAction::WarnClient { message } => {
+                            let msg: ServerWire<ChatSnapshot> =
+                                ServerWire::error(ErrorCode::MalformedMessage, message);
+                            sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                        }
+                        WireErrorAction::Disconnect => return Ok(()),
+                    }
+                    continue;
+                }
+            };
+
+            if let ClientWire::ResumeSession { token, delivery } = wire {
+                let resumed = state.write().await.resume.resume(&token);
+                if let Some(session) = resumed {
+                    tracing::info!("{} resumed", session.name);
+                    break (session, true, delivery);
+                }
+                let msg: ServerWire<ChatSnapshot> = ServerWire::error(
+                    ErrorCode::ResumeExpired,
+                    "Session expired; authenticate again",
+                );
+                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+            } else if let ClientWire::Auth {
                 identity,
                 name,
                 passport,
+                source,
+                ticket,
+                delivery,
+                reconnect_token,
             } = wire
             {
+                // Fast path: refuse transfers from blocked sources before
+                // decoding the passport. The client only relays the source's
+                // manifest, so it counts only signed with our key.
+                if passport.is_some() {
+                    let refused = {
+                        let s = state.read().await;
+                        let trusted = source.as_deref().filter(|src| {
+                            s.keyring
+                                .as_ref()
+                                .is_some_and(|keyring| src.verify(keyring))
+                        });
+                        match trusted {
+                            None => Some(
+                                "Transfers need a source manifest signed by a trusted key"
+                                    .to_string(),
+                            ),
+                            Some(src) if !s.room.can_accept_transfer_from(src) => {
+                                tracing::info!("Refused transfer from {}", src.identity);
+                                Some(format!("Transfers from {} are not accepted", src.name))
+                            }
+                            Some(_) => None,
+                        }
+                    };
+                    if let Some(message) = refused {
+                        let msg: ServerWire<ChatSnapshot> =
+                            ServerWire::error(ErrorCode::SourceBlocked, message);
+                        sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                        return Ok(());
+                    }
+                }
+
                 let mut s = state.write().await;
+
+                if let Err(e) = s.room.validate_connect(&identity, &info) {
+                    tracing::info!("Refused identity {}: {}", identity, e);
+                    let msg: ServerWire<ChatSnapshot> =
+                        ServerWire::error(ErrorCode::InvalidIdentity, e.to_string());
+                    sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                    continue;
+                }
+
+                // A signed reconnect token restores the session's name, even
+                // one issued by another instance sharing the key
+                let restored = match (&reconnect_token, &s.keyring) {
+                    (Some(token), Some(keyring)) => {
+                        match keyring.verify_session_token(&s.room, token) {
+                            Some(restored) if restored.identity == identity => Some(restored),
+                            _ => {
+                                let msg: ServerWire<ChatSnapshot> = ServerWire::error(
+                                    ErrorCode::ResumeExpired,
+                                    "Invalid or expired reconnect token",
+                                );
+                                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                continue;
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+
+                if s.room.inner().inner().is_full() {
+                    let msg: ServerWire<ChatSnapshot> =
+                        ServerWire::error(ErrorCode::Overloaded, "The room is full");
+                    sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                    return Ok(());
+                }
+
+                // A passport pushed by the origin (federation) stands in for
+                // one carried by the client
+                let passport = match ticket {
+                    Some(token) => match s.tickets.redeem(&token) {
+                        Some(pushed) if pushed.identity == identity => Some(pushed.data),
+                        _ => {
+                            let msg: ServerWire<ChatSnapshot> = ServerWire::error(
+                                ErrorCode::InvalidTicket,
+                                "Unknown or expired ticket",
+                            );
+                            sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                            continue;
+                        }
+                    },
+                    None => passport,
+                };
+
                 let session_id = s.next_session_id;
                 s.next_session_id += 1;
 
-                let display_name = name.unwrap_or_else(|| identity.payload().to_string());
+                let display_name = restored
+                    .map(|r| r.name)
+                    .or(name)
+                    .unwrap_or_else(|| identity.display_name());
                 let session = Session::new(session_id, identity, display_name);
 
                 // Handle transfer-in or regular connect
                 if let Some(passport_data) = passport {
-                    if let Ok(passport) = serde_json::from_slice::<ChatPassport>(&passport_data) {
-                        let result = s.room.on_transfer_in(&session, passport)?;
-
-                        // Send rejection info if any
-                        if !result.rejected.is_empty() {
-                            let msg: ServerWire<ChatSnapshot> = ServerWire::system(format!(
-                                "Import: {} items rejected",
-                                result.rejected.len()
-                            ));
-                            sink.send(Message::Text(to_json_string(&msg)?.into()))
-                                .await?;
+                    match decode_transfer(&passport_data) {
+                        Ok((context, passport)) => {
+                            if let Err(errors) = s.room.validate_passport(&session, &passport) {
+                                let errors: Vec<String> =
+                                    errors.iter().map(ToString::to_string).collect();
+                                tracing::warn!(
+                                    "Invalid passport from {}: {}",
+                                    session.identity,
+                                    errors.join("; ")
+                                );
+                                let msg: ServerWire<ChatSnapshot> = ServerWire::error(
+                                    ErrorCode::InvalidPassport,
+                                    format!("Invalid passport: {}", errors.join("; ")),
+                                );
+                                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                continue;
+                            }
+                            let origin = ServerName::new(&passport.origin).identity();
+                            s.room
+                                .set_transfer_source(session.id, passport.origin.as_str());
+                            let result = s.room.on_transfer_in(&session, passport);
+                            s.peer_transfers.record(&origin, result.is_ok());
+                            let result = result?;
+                            if let Some(context) = context {
+                                s.room.on_transfer_context(&session, context);
+                            }
+
+                            // Send rejection/transform info if any
+                            if !result.is_clean() {
+                                let msg: ServerWire<ChatSnapshot> = ServerWire::system(format!(
+                                    "Import: {} items rejected, {} transformed",
+                                    result.rejected.len(),
+                                    result.transformed.len()
+                                ));
+                                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                            }
+                        }
+                        Err(e) => {
+                            match s
+                                .room
+                                .on_passport_decode_error(&session, &passport_data, &e)
+                            {
+                                PassportDecodeAction::ConnectFresh => {
+                                    tracing::warn!(
+                                        "Corrupt passport from {}, joining fresh: {}",
+                                        session.identity,
+                                        e
+                                    );
+                                    s.room.on_connect_with_info(&session, &info)?;
+                                }
+                                PassportDecodeAction::Reject => {
+                                    tracing::warn!(
+                                        "Corrupt passport from {}: {}",
+                                        session.identity,
+                                        e
+                                    );
+                                    let msg: ServerWire<ChatSnapshot> = ServerWire::error(
+                                        ErrorCode::ProtocolError,
+                                        format!("Corrupt passport: {}", e),
+                                    );
+                                    sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                    continue;
+                                }
+                            }
                         }
-                    } else {
-                        s.room.on_connect(&session)?;
                     }
                 } else {
-                    s.room.on_connect(&session)?;
+                    s.room.on_connect_with_info(&session, &info)?;
                 }
 
-                break session;
+                break (session, false, delivery);
             }
         }
     };
+    // Authenticated: no longer counts against the address
+    drop(pending);
 
     // Send manifest
     {
-        let s = state.read().await;
-        let msg: ServerWire<ChatSnapshot> = ServerWire::Manifest(s.manifest.clone());
-        sink.send(Message::Text(to_json_string(&msg)?.into()))
-            .await?;
+        let msg = state
+            .read()
+            .await
+            .manifest
+            .to_ws_message(WireEncoding::Json)?;
+        sink.send(msg).await?;
     }
 
-    // Broadcast join
+    // Issue a resume token
     {
-        let msg: ServerWire<ChatSnapshot> =
-            ServerWire::system(format!("{} joined", session.name));
-        let _ = broadcast_tx.send(to_json_string(&msg)?);
+        let token = state.write().await.resume.issue(session.id);
+        let msg: ServerWire<ChatSnapshot> = ServerWire::ResumeToken { token };
+        sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
     }
 
-    // Send initial snapshot
+    // Issue a signed token for reconnecting to any instance with the key
     {
         let s = state.read().await;
-        let snapshot = s.room.snapshot();
-        let msg: ServerWire<ChatSnapshot> = ServerWire::Snapshot { seq: 0, data: snapshot };
-        sink.send(Message::Text(to_json_string(&msg)?.into()))
-            .await?;
+        if let Some(keyring) = &s.keyring {
+            let token = keyring.sign_session_token(&s.room, &session);
+            let msg: ServerWire<ChatSnapshot> = ServerWire::ReconnectToken { token };
+            sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+        }
+    }
+
+    // Broadcast join (a resumed session never left)
+    if !resumed {
+        let msg: ServerWire<ChatSnapshot> = ServerWire::system(format!("{} joined", session.name));
+        broadcast_tx.send(Broadcast::system(&msg)?);
+    }
+
+    // Measure the connection so snapshot_for can scale detail to it
+    let mut quality = QualityEstimator::new(PING_TIMEOUT);
+    // Flag snapshots far larger than this session's usual ones
+    let mut sizes = SizeTracker::default();
+
+    // Send initial snapshot (pull-mode clients ask when they want one)
+    if delivery == Delivery::Push {
+        let s = state.read().await;
+        let mut snapshot = s.room.snapshot_for(&session);
+        s.room.redact_snapshot(&session, &mut snapshot);
+        let policy = s.config.serialization_failure;
+        let guard = s.config.snapshot_spike_guard;
+        drop(s);
+        let msg: ServerWire<ChatSnapshot> = ServerWire::snapshot(0, snapshot);
+        match encode_snapshot(&msg, guard) {
+            Ok(msg) => {
+                quality.snapshot_sent(0, msg.len());
+                record_snapshot_size(&state, &mut sizes, &session, msg.len()).await;
+                sink.send(msg).await?;
+            }
+            Err(e) => snapshot_failed(&mut sink, policy, &session, e).await?,
+        }
     }
 
+    // Resolve what this session may do (guests can chat but not transfer)
+    let capabilities = {
+        let s = state.read().await;
+        s.config.capabilities.resolve(&s.room, &session)
+    };
+    let mut last_intent: Option<Instant> = None;
+    let mut snapshot_meter = SnapshotMeter::new(state.read().await.config.snapshot_budget);
+    let prober = state.read().await.prober.clone();
+    let mut pings = prober.register(session.id);
+
     // Subscribe to broadcasts
     let mut broadcast_rx = broadcast_tx.subscribe();
+    let mut batcher = FrameBatcher::new(MAX_BROADCAST_BATCH);
+    let mut paused = PauseBuffer::with_config(state.read().await.config.pause_buffer);
+    let mut own_writes = OwnWrites::new();
+    let panic_guard = PanicGuard::new(state.read().await.config.panic_policy);
+    let mut fairness = YieldBudget::new(state.read().await.config.yield_every);
     let mut seq = 1u64;
 
+    // Accept intents from older clients under their legacy names
+    let aliases = IntentAliasRegistry::new().register("chat_message", legacy_chat_message);
+
     // Main loop
     loop {
         tokio::select! {
@@ -267,53 +1156,296 @@ async fn handle_connection(
                     }
                     None => break,
                 };
+                // A burst from this client mustn't hold up other connections
+                fairness.message_received().await;
 
                 if let Message::Text(text) = msg {
-                    let wire: ClientWire<ChatIntent> = match from_json_str(&text) {
+                    let wire = match aliases.decode(&text) {
                         Ok(w) => w,
                         Err(e) => {
-                            tracing::warn!("Invalid message: {}", e);
+                            let error = match e {
+                                AliasError::Json(e) => e,
+                                e => serde::de::Error::custom(e),
+                            };
+                            let action = state.write().await.room.on_wire_error(Some(&session), &text, &error);
+                            match action {
+                                WireErrorAction::Ignore => tracing::warn!("Invalid message: {}", error),
+                                WireErrorAction::WarnClient { message } => {
+                                    let msg: ServerWire<ChatSnapshot> = ServerWire::error(ErrorCode::MalformedMessage, message);
+                                    sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                }
+                                WireErrorAction::Disconnect => {
+                                    // Kicked, so no grace window
+                                    state.write().await.resume.revoke(session.id);
+                                    break;
+                                }
+                            }
                             continue;
                         }
                     };
 
+                    // A tracked intent is applied once; a retry only gets its ack again.
+                    // Chat clients don't predict messages, so `base_seq` is ignored.
+                    let (wire, tracked) = match wire {
+                        ClientWire::TrackedIntent { request_id, require_ack, intent, .. } => {
+                            let applied = state.read().await.applied_intents.get(&(session.identity.clone(), request_id));
+                            if let Some(seq) = applied {
+                                if require_ack {
+                                    let msg: ServerWire<ChatSnapshot> = ServerWire::IntentAck { request_id, seq };
+                                    sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                }
+                                continue;
+                            }
+                            (ClientWire::Intent(intent), Some((request_id, require_ack)))
+                        }
+                        wire => (wire, None),
+                    };
+
                     match wire {
                         ClientWire::Intent(intent) => {
+                            let now = Instant::now();
+                            if !capabilities.intent_allowed(last_intent, now) {
+                                let msg: ServerWire<ChatSnapshot> = ServerWire::error(ErrorCode::RateLimited, "Too many messages; slow down");
+                                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                continue;
+                            }
+                            last_intent = Some(now);
+
                             let mut s = state.write().await;
-                            if let Err(e) = s.room.handle_intent(&session, intent) {
-                                let msg: ServerWire<ChatSnapshot> = ServerWire::error("intent_error", e.to_string());
-                                sink.send(Message::Text(to_json_string(&msg)?.into())).await?;
+                            let intent_type = Room::intent_type_name(&intent);
+                            s.observer.on_intent_received(&session, intent_type, &s.room.redacted_intent_to_json(&intent));
+                            let started = Instant::now();
+                            let result = match panic_guard.call(|| s.room.handle_intent(&session, intent)) {
+                                Ok(result) => result,
+                                Err(panic) => {
+                                    drop(s);
+                                    let msg: ServerWire<ChatSnapshot> = ServerWire::error(ErrorCode::InternalError, "The server failed handling that");
+                                    sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                    if panic.ends_session() {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                            };
+                            s.observer.on_intent_handled(&session, intent_type, started.elapsed(), result.is_ok());
+                            debug_assert_invariants(&s.room);
+                            if let Err(e) = result {
+                                let msg: ServerWire<ChatSnapshot> = ServerWire::error(ErrorCode::IntentError, e.to_string());
+                                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
                             } else {
-                                // Broadcast updated snapshot
-                                let snapshot = s.room.snapshot();
-                                let msg: ServerWire<ChatSnapshot> = ServerWire::Snapshot { seq, data: snapshot };
+                                // Broadcast updated snapshot, at full detail since it goes to everyone
+                                let everyone = Session { quality: ConnectionQuality::default(), ..session.clone() };
+                                let snapshot = s.room.snapshot_for(&everyone);
+                                let msg: ServerWire<ChatSnapshot> = ServerWire::snapshot(seq, snapshot);
+                                // One that doesn't encode is never broadcast; the room carries on
+                                let broadcast = Broadcast::snapshot(&msg, session.id, s.config.snapshot_spike_guard);
+                                // Show the sender their write now; their broadcast copy is skipped
+                                let direct = broadcast.is_ok() && CONSISTENCY.read_your_writes && !paused.is_paused();
+                                if direct {
+                                    own_writes.sent_directly();
+                                }
+                                if let Ok(broadcast) = &broadcast {
+                                    broadcast_tx.send_snapshot(broadcast.clone());
+                                }
+                                let tracked = tracked.inspect(|&(request_id, _)| {
+                                    s.applied_intents.insert((session.identity.clone(), request_id), seq);
+                                });
+                                let policy = s.config.serialization_failure;
+                                drop(s);
+                                match broadcast {
+                                    Ok(broadcast) if direct => {
+                                        // Charged to the budget, but never held back
+                                        snapshot_meter.reserve(broadcast.text.len());
+                                        record_snapshot_size(&state, &mut sizes, &session, broadcast.text.len()).await;
+                                        sink.send(Message::Text(broadcast.text.into())).await?;
+                                    }
+                                    Ok(_) => {}
+                                    Err(e) => snapshot_failed(&mut sink, policy, &session, e).await?,
+                                }
+                                if let Some((request_id, true)) = tracked {
+                                    let msg: ServerWire<ChatSnapshot> = ServerWire::IntentAck { request_id, seq };
+                                    sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                    state.write().await.room.on_intent_ack(&session, request_id);
+                                }
                                 seq += 1;
-                                let _ = broadcast_tx.send(to_json_string(&msg)?);
                             }
                         }
 
-                        ClientWire::TransferRequest { destination } => {
+                        ClientWire::TransferRequest { mut destination } => {
+                            if !capabilities.transfer {
+                                let msg: ServerWire<ChatSnapshot> = ServerWire::error(ErrorCode::TransferForbidden, "This session can't transfer");
+                                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                continue;
+                            }
+                            // e.g. --peer pointing back at this server
+                            let loopback = state.read().await.config.manifest.is_endpoint(&destination);
+                            if loopback {
+                                let action = state.write().await.room.on_transfer_loopback(&session, &destination);
+                                match action {
+                                    LoopbackAction::Allow => {}
+                                    LoopbackAction::Reject => {
+                                        let msg: ServerWire<ChatSnapshot> = ServerWire::error(ErrorCode::TransferLoopback, format!("Already connected to {}", destination));
+                                        sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                                        continue;
+                                    }
+                                    LoopbackAction::Redirect { url } => destination = url,
+                                }
+                            }
+
                             let s = state.read().await;
                             if s.room.validate_destination(&destination) {
-                                let passport = s.room.emit_passport(&session);
-                                let msg: ServerWire<ChatSnapshot> = ServerWire::Transfer {
-                                    destination,
-                                    passport: serde_json::to_vec(&passport)?,
+                                let transfer = s.room.emit_transfer_snapshot(&session, &destination);
+                                let passport = serde_json::to_vec(&transfer)?;
+                                let federation = s.federation.clone();
+                                drop(s);
+                                let to = destination.clone();
+
+                                let msg: ServerWire<ChatSnapshot> = match federation {
+                                    // Push the passport server-side; the client only sees a ticket
+                                    Some(federation) => {
+                                        let passport = Passport::new(session.identity.clone(), passport);
+                                        match federation.push_transfer(&destination, passport).await {
+                                            Ok(token) => {
+                                                // Tracked until the client has it, so it can be withdrawn
+                                                state.write().await.pending_transfers.insert(session.id, destination.clone(), token.clone());
+                                                ServerWire::TransferTicket { destination, token }
+                                            }
+                                            // Pass the destination's refusal (e.g. too_many_transfers) through
+                                            Err(FederationError::Rejected { code, message }) => ServerWire::error(code, message),
+                                            Err(e) => ServerWire::error(ErrorCode::TransferFailed, e.to_string()),
+                                        }
+                                    }
+                                    None => ServerWire::Transfer { destination, passport },
+                                };
+                                let transferred = !matches!(msg, ServerWire::Error { .. });
+                                let ticket = match &msg {
+                                    ServerWire::TransferTicket { token, .. } => Some(token.clone()),
+                                    _ => None,
                                 };
-                                sink.send(Message::Text(to_json_string(&msg)?.into())).await?;
-                                tracing::info!("{} transferred out", session.name);
+                                if let Err(e) = sink.send(msg.to_ws_message(WireEncoding::Json)?).await {
+                                    // Gone before the ticket arrived; it's withdrawn below
+                                    tracing::debug!("WebSocket error: {}", e);
+                                    break;
+                                }
+                                if transferred {
+                                    // Leaving for good: no grace window on disconnect
+                                    let mut s = state.write().await;
+                                    if let Some(token) = ticket {
+                                        s.pending_transfers.delivered(session.id, &token);
+                                    }
+                                    s.resume.revoke(session.id);
+                                    s.room.record_transfer_out(&session, to);
+                                    s.room.set_disconnect_reason(session.id, DisconnectReason::Transferred);
+                                    drop(s);
+                                    tracing::info!("{} transferred out", session.name);
+                                }
                             } else {
                                 let msg: ServerWire<ChatSnapshot> = ServerWire::error(
-                                    "invalid_destination",
+                                    ErrorCode::InvalidDestination,
                                     format!("Unknown destination: {}", destination)
                                 );
-                                sink.send(Message::Text(to_json_string(&msg)?.into())).await?;
+                                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                            }
+                        }
+
+                        ClientWire::Pause => {
+                            // Backgrounded (e.g. a mobile app): hold broadcasts rather than disconnect
+                            paused.pause();
+                            state.write().await.room.on_session_paused(&session);
+                        }
+
+                        ClientWire::Resume => {
+                            let resumed = {
+                                let mut s = state.write().await;
+                                s.room.on_session_resumed(&session);
+                                paused.resume_with(|texts| coalesce_broadcasts(&s.room, texts))
+                            };
+                            match resumed {
+                                Resumed::Queued(texts) => {
+                                    for text in texts {
+                                        batcher.push(text);
+                                        if batcher.is_full() && let Some((text, _)) = batcher.take() {
+                                            sink.send(Message::Text(text.into())).await?;
+                                        }
+                                    }
+                                    if let Some((text, _)) = batcher.take() {
+                                        sink.send(Message::Text(text.into())).await?;
+                                    }
+                                }
+                                Resumed::FullSnapshot => {
+                                    let s = state.read().await;
+                                    let mut snapshot = s.room.snapshot_for(&session);
+                                    s.room.redact_snapshot(&session, &mut snapshot);
+                                    let policy = s.config.serialization_failure;
+                                    let guard = s.config.snapshot_spike_guard;
+                                    drop(s);
+                                    let msg: ServerWire<ChatSnapshot> = ServerWire::snapshot(seq, snapshot);
+                                    match encode_snapshot(&msg, guard) {
+                                        Ok(msg) => {
+                                            quality.snapshot_sent(seq, msg.len());
+                                            record_snapshot_size(&state, &mut sizes, &session, msg.len()).await;
+                                            seq += 1;
+                                            sink.send(msg).await?;
+                                        }
+                                        Err(e) => snapshot_failed(&mut sink, policy, &session, e).await?,
+                                    }
+                                }
+                            }
+                        }
+
+                        ClientWire::Resync => {
+                            let s = state.read().await;
+                            let mut snapshot = s.room.snapshot_for(&session);
+                            s.room.redact_snapshot(&session, &mut snapshot);
+                            let policy = s.config.serialization_failure;
+                            let guard = s.config.snapshot_spike_guard;
+                            drop(s);
+                            let msg: ServerWire<ChatSnapshot> = ServerWire::snapshot(seq, snapshot);
+                            match encode_snapshot(&msg, guard) {
+                                Ok(msg) => {
+                                    quality.snapshot_sent(seq, msg.len());
+                                    record_snapshot_size(&state, &mut sizes, &session, msg.len()).await;
+                                    seq += 1;
+                                    sink.send(msg).await?;
+                                }
+                                Err(e) => snapshot_failed(&mut sink, policy, &session, e).await?,
                             }
                         }
 
+                        ClientWire::Query { id, query, cursor } => {
+                            // One page per request, so a long history never holds up the connection
+                            let page = state.read().await.room.query_page(&session, &query, cursor.as_deref());
+                            let msg: ServerWire<ChatSnapshot> = match page {
+                                Ok(page) => ServerWire::query_page(id, page),
+                                Err(e) => ServerWire::error(ErrorCode::InvalidQuery, format!("Query {}: {}", id, e)),
+                            };
+                            sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                        }
+
                         ClientWire::Ping => {
                             let msg: ServerWire<ChatSnapshot> = ServerWire::Pong;
-                            sink.send(Message::Text(to_json_string(&msg)?.into())).await?;
+                            sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+                        }
+
+                        ClientWire::Pong { nonce } => {
+                            prober.on_pong(nonce);
+                            quality.pong(nonce);
+                            session.quality = quality.quality();
+                        }
+
+                        ClientWire::Ack { seq, confirmed_seq, prediction_horizon } => {
+                            quality.acked(seq);
+                            session.prediction = ClientPrediction { confirmed_seq, horizon: prediction_horizon };
+                            session.quality = quality.quality();
+                        }
+
+                        ClientWire::TypeMismatch { expected_snapshot, got, expected_intent, got_intent } => {
+                            // A different app's client reached this server:
+                            // a deployment problem, not a client bug.
+                            tracing::error!(
+                                "Configuration error: {} expects snapshot type {} and intent type {}, server has {} and {}",
+                                addr, got, got_intent, expected_snapshot, expected_intent
+                            );
                         }
 
                         _ => {}
@@ -321,26 +1453,201 @@ async fn handle_connection(
                 }
             }
 
-            msg = broadcast_rx.recv() => {
-                if let Ok(msg) = msg {
-                    sink.send(Message::Text(msg.into())).await?;
+            Some(nonce) = pings.recv() => {
+                quality.ping_sent(nonce);
+                // Pings that went unanswered count as lost from here
+                session.quality = quality.quality();
+                let msg: ServerWire<ChatSnapshot> = ServerWire::Ping { nonce };
+                sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+            }
+
+            received = broadcast_rx.recv() => {
+                let Some(received) = received else { break };
+                // Take whatever else is already waiting, so a burst goes out as one frame
+                let mut next = Some(received);
+                while let Some(received) = next {
+                    let text = match received {
+                        Received::Message(msg) => Some(msg.text),
+                        Received::Snapshot { snapshot: msg, replaced } => {
+                            // Our own copies may be among the replaced
+                            if replaced > 0 {
+                                own_writes.reset();
+                            }
+                            // Pull-mode sessions ask for snapshots with Resync; direct sends supersede queued ones
+                            let superseded = !own_writes.deliver(msg.origin == Some(session.id));
+                            let text = (!superseded && delivery == Delivery::Push).then_some(msg.text);
+                            if let Some(text) = &text {
+                                record_snapshot_size(&state, &mut sizes, &session, text.len()).await;
+                            }
+                            text
+                        }
+                        Received::Resync { missed } => {
+                            // Joins and leaves were among them; a full snapshot has the user list
+                            tracing::debug!("{} missed {} broadcasts, resyncing", session.name, missed);
+                            own_writes.reset();
+                            if delivery == Delivery::Push {
+                                let s = state.read().await;
+                                let mut snapshot = s.room.snapshot_for(&session);
+                                s.room.redact_snapshot(&session, &mut snapshot);
+                                let policy = s.config.serialization_failure;
+                                let guard = s.config.snapshot_spike_guard;
+                                drop(s);
+                                let msg: ServerWire<ChatSnapshot> = ServerWire::snapshot(seq, snapshot);
+                                let text = to_json_string(&msg)?;
+                                match guard.check(text.len()) {
+                                    Ok(()) => {
+                                        record_snapshot_size(&state, &mut sizes, &session, text.len()).await;
+                                        seq += 1;
+                                        Some(text)
+                                    }
+                                    Err(e) => {
+                                        snapshot_failed(&mut sink, policy, &session, e).await?;
+                                        None
+                                    }
+                                }
+                            } else {
+                                None
+                            }
+                        }
+                    };
+                    if let Some(text) = text {
+                        if paused.is_paused() {
+                            // Joins and leaves are lost with the snapshots on overflow; the full snapshot has the user list
+                            paused.push(text);
+                        } else {
+                            batcher.push(text);
+                        }
+                    }
+                    next = if batcher.is_full() { None } else { broadcast_rx.try_recv() };
+                }
+                let Some((text, stats)) = batcher.take() else { continue };
+                // Over budget: let the room react, then deliver late
+                let wait = snapshot_meter.reserve(text.len());
+                if !wait.is_zero() {
+                    state.write().await.room.on_budget_exceeded(&session, text.len(), snapshot_meter.budget());
+                    tokio::time::sleep(wait).await;
+                }
+                if stats.count > 1 {
+                    state.read().await.observer.on_batch_sent(&session, stats);
                 }
+                sink.send(Message::Text(text.into())).await?;
             }
         }
     }
 
-    // Disconnect
-    {
+    prober.unregister(session.id);
+
+    // Withdraw tickets pushed for this session that it never received
+    let (pending, federation) = {
         let mut s = state.write().await;
-        s.room.on_disconnect(&session);
+        (
+            s.pending_transfers.cancel_for_session(session.id),
+            s.federation.clone(),
+        )
+    };
+    if let Some(federation) = federation
+        && !pending.is_empty()
+    {
+        tokio::spawn(async move {
+            for transfer in pending {
+                if let Err(e) = federation
+                    .cancel_transfer(&transfer.destination, transfer.token)
+                    .await
+                {
+                    tracing::warn!(
+                        "Cancelling transfer to {} failed: {}",
+                        transfer.destination,
+                        e
+                    );
+                }
+            }
+        });
     }
 
-    // Broadcast leave
-    {
-        let msg: ServerWire<ChatSnapshot> = ServerWire::system(format!("{} left", session.name));
-        let _ = broadcast_tx.send(to_json_string(&msg)?);
+    // Hold the session for the grace window, then finalize the disconnect
+    let held = state.write().await.resume.hold(session.clone());
+    if held.is_some() {
+        let state = state.clone();
+        let broadcast_tx = broadcast_tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(state.read().await.resume.window()).await;
+            let expired = {
+                let mut s = state.write().await;
+                let expired = s.resume.finalize(session.id);
+                if expired.is_some() {
+                    s.room
+                        .set_disconnect_reason(session.id, DisconnectReason::GraceExpired);
+                }
+                expired
+            };
+            if let Some(session) = expired
+                && let Err(e) = finish_disconnect(&state, &broadcast_tx, &session).await
+            {
+                tracing::warn!("Disconnect of {} failed: {}", session.name, e);
+            }
+        });
+    } else {
+        finish_disconnect(&state, &broadcast_tx, &session).await?;
     }
 
     tracing::debug!("Connection closed: {}", addr);
     Ok(())
 }
+
+/// Encode a snapshot for one session, holding it back if it's over the hard
+/// limit.
+fn encode_snapshot(
+    msg: &ServerWire<ChatSnapshot>,
+    guard: SpikeGuard,
+) -> Result<Message, WireError> {
+    let msg = msg.to_ws_message(WireEncoding::Json)?;
+    guard.check(msg.len())?;
+    Ok(msg)
+}
+
+/// Note the size of a snapshot going to `session`, reporting it if it spiked.
+async fn record_snapshot_size(
+    state: &SharedState,
+    sizes: &mut SizeTracker,
+    session: &Session,
+    bytes: usize,
+) {
+    if let Some(rolling_avg) = sizes.record(bytes) {
+        state
+            .read()
+            .await
+            .observer
+            .on_snapshot_size_spike(session, bytes, rolling_avg);
+    }
+}
+
+/// Log a snapshot that couldn't be sent and tell the session, per `policy`.
+/// Only [`SerializationFailurePolicy::Disconnect`] fails.
+async fn snapshot_failed<S>(
+    sink: &mut S,
+    policy: SerializationFailurePolicy,
+    session: &Session,
+    error: WireError,
+) -> anyhow::Result<()>
+where
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    tracing::error!("Snapshot for {} couldn't be sent: {}", session.name, error);
+    if let Some(msg) = policy.recover::<ChatSnapshot>(error)? {
+        sink.send(msg.to_ws_message(WireEncoding::Json)?).await?;
+    }
+    Ok(())
+}
+
+/// Run `on_disconnect` and broadcast the leave.
+async fn finish_disconnect(
+    state: &SharedState,
+    broadcast_tx: &Broadcasts,
+    session: &Session,
+) -> anyhow::Result<()> {
+    state.write().await.room.on_disconnect(session);
+
+    let msg: ServerWire<ChatSnapshot> = ServerWire::system(format!("{} left", session.name));
+    broadcast_tx.send(Broadcast::system(&msg)?);
+    Ok(())
+}
@@ -9,7 +9,7 @@ use axum::{
     routing::{get, post},
     Json, Router,
 };
-use interconnect_core::{Identity, Manifest};
+use interconnect_core::{Identity, Manifest, ServerName};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -106,14 +106,19 @@ fn now() -> u64 {
 async fn get_manifest(State(state): State<AppState>) -> Json<Manifest> {
     let s = state.read().await;
     Json(Manifest {
-        identity: Identity::local(&s.name),
-        name: s.name.clone(),
+        identity: ServerName::new(&s.name).identity(),
+        name: s.name.as_str().into(),
         substrate: None,
         metadata: serde_json::json!({
             "type": "forum",
             "version": "0.1",
             "thread_count": s.threads.len()
         }),
+        snapshot_type: None,
+        intent_type: None,
+        endpoint: None,
+        signing_keys: Vec::new(),
+        signature: None,
     })
 }
 
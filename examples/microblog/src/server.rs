@@ -67,12 +67,17 @@ async fn get_manifest(State(state): State<AppState>) -> Json<Manifest> {
     let s = state.read().await;
     Json(Manifest {
         identity: s.identity.clone(),
-        name: format!("{}@localhost:{}", s.name, s.port),
+        name: format!("{}@localhost:{}", s.name, s.port).into(),
         substrate: None,
         metadata: serde_json::json!({
             "type": "microblog",
             "version": "0.1"
         }),
+        snapshot_type: None,
+        intent_type: None,
+        endpoint: None,
+        signing_keys: Vec::new(),
+        signature: None,
     })
 }
 